@@ -12,6 +12,24 @@ fi
 
 echo "✅ Bun is installed"
 
+# The reward math is a WASM build of the program's Rust crate
+if ! command -v wasm-pack &> /dev/null; then
+    echo "❌ wasm-pack is not installed. Please install Rust, then run:"
+    echo "   rustup target add wasm32-unknown-unknown"
+    echo "   cargo install wasm-pack"
+    exit 1
+fi
+
+echo "🦀 Building the reward math package..."
+bun run build:wasm
+
+if [ $? -eq 0 ]; then
+    echo "✅ Reward math built"
+else
+    echo "❌ Failed to build the reward math package"
+    exit 1
+fi
+
 # Install dependencies
 echo "📦 Installing dependencies..."
 bun install
@@ -0,0 +1,51 @@
+import { test } from 'node:test';
+import assert from 'node:assert/strict';
+import { readFileSync } from 'node:fs';
+import { simulatePurchase } from './rewardMath.js';
+
+interface RewardVector {
+  name: string;
+  base_rate: number;
+  holding_ratio: number;
+  referrer_holdings: string;
+  purchase_amount: string;
+  min_holdings: string;
+  holdings_multipliers: [number, number, number];
+  expected: {
+    base_reward: string;
+    ratio_penalty: string;
+    holding_adjusted_reward: string;
+    holdings_multiplier: number;
+    final_reward: string;
+  } | null;
+}
+
+// The vectors the Rust crate is tested on, run here through its WASM build
+const vectors: RewardVector[] = JSON.parse(
+  readFileSync(
+    new URL('../../../crates/otto-reward-math/tests/reward_vectors.json', import.meta.url),
+    'utf8'
+  )
+);
+
+for (const vector of vectors) {
+  test(vector.name, () => {
+    const breakdown = simulatePurchase(
+      vector.base_rate,
+      vector.holding_ratio,
+      BigInt(vector.referrer_holdings),
+      BigInt(vector.purchase_amount),
+      BigInt(vector.min_holdings),
+      vector.holdings_multipliers
+    );
+
+    const expected = vector.expected && {
+      baseReward: BigInt(vector.expected.base_reward),
+      ratioPenalty: BigInt(vector.expected.ratio_penalty),
+      holdingAdjustedReward: BigInt(vector.expected.holding_adjusted_reward),
+      holdingsMultiplier: vector.expected.holdings_multiplier,
+      finalReward: BigInt(vector.expected.final_reward),
+    };
+    assert.deepEqual(breakdown, expected);
+  });
+}
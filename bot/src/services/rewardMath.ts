@@ -0,0 +1,57 @@
+/**
+ * Referral reward math, run through the WASM build of the program's
+ * `otto-reward-math` crate so the bot can't drift from the on-chain integer
+ * math. The build is the local `otto-reward-math-wasm` dependency; see the
+ * README for building it before `npm install`.
+ *
+ * Both the crate and this wrapper are checked against the golden vectors in
+ * `crates/otto-reward-math/tests/reward_vectors.json`.
+ */
+
+import { simulatePurchase as simulate } from 'otto-reward-math-wasm';
+
+/** Holdings multipliers in basis points for [2x, 5x, 10x] the minimum holdings */
+export const DEFAULT_HOLDINGS_MULTIPLIERS: readonly [number, number, number] = [11000, 12500, 15000];
+
+export interface RewardBreakdown {
+  baseReward: bigint;
+  ratioPenalty: bigint; // Taken off the base reward for a holding ratio below 100%
+  holdingAdjustedReward: bigint;
+  holdingsMultiplier: number; // Basis points
+  finalReward: bigint;
+}
+
+/**
+ * Simulate the referral reward for a purchase, step by step.
+ * Returns null where the program would fail the purchase with an overflow.
+ */
+export function simulatePurchase(
+  baseRate: number, // Basis points
+  holdingRatio: number, // Basis points
+  referrerHoldings: bigint,
+  purchaseAmount: bigint,
+  minHoldings: bigint,
+  holdingsMultipliers: readonly [number, number, number] = DEFAULT_HOLDINGS_MULTIPLIERS
+): RewardBreakdown | null {
+  const breakdown = simulate(
+    baseRate,
+    holdingRatio,
+    referrerHoldings,
+    purchaseAmount,
+    minHoldings,
+    Uint16Array.from(holdingsMultipliers)
+  );
+  if (!breakdown) return null;
+
+  try {
+    return {
+      baseReward: breakdown.base_reward,
+      ratioPenalty: breakdown.ratio_penalty,
+      holdingAdjustedReward: breakdown.holding_adjusted_reward,
+      holdingsMultiplier: breakdown.holdings_multiplier,
+      finalReward: breakdown.final_reward,
+    };
+  } finally {
+    breakdown.free();
+  }
+}
@@ -3,6 +3,8 @@ import { getAssociatedTokenAddress, getAccount } from '@solana/spl-token';
 import { AnchorProvider, Wallet, Program } from '@coral-xyz/anchor';
 import bs58 from 'bs58';
 import type { ReferralStats, TierThresholds, GeneratedWallet } from '../types/index.js';
+import { simulatePurchase, type RewardBreakdown } from './rewardMath.js';
+import { getReferrerSummary, type ReferrerSummary } from './views.js';
 
 export class SolanaService {
   private connection: Connection;
@@ -136,7 +138,8 @@ To participate in OTTO referrals, you need a non-custodial Solana wallet.
   }
 
   /**
-   * Calculate dynamic reward based on new mechanics
+   * Calculate dynamic reward based on new mechanics, using the same integer
+   * math as the program (0 where the program would reject the purchase)
    */
   calculateDynamicReward(
     baseRate: number, // Basis points
@@ -144,24 +147,26 @@ To participate in OTTO referrals, you need a non-custodial Solana wallet.
     purchaseAmount: number,
     holdingRatio: number = 10000 // Default 100%
   ): number {
-    // Base reward calculation
-    const baseReward = (purchaseAmount * baseRate) / 10000;
-    
-    // Apply holding ratio penalty (if user sold tokens)
-    const holdingAdjustedReward = (baseReward * holdingRatio) / 10000;
-    
-    // Apply holdings multiplier (more holdings = higher rewards)
-    let holdingsMultiplier = 10000; // 1x base
-    
-    if (referrerHoldings >= this.minHoldingsForReferral * 10) {
-      holdingsMultiplier = 15000; // 1.5x for 10x minimum holdings
-    } else if (referrerHoldings >= this.minHoldingsForReferral * 5) {
-      holdingsMultiplier = 12500; // 1.25x for 5x minimum holdings
-    } else if (referrerHoldings >= this.minHoldingsForReferral * 2) {
-      holdingsMultiplier = 11000; // 1.1x for 2x minimum holdings
-    }
-    
-    return Math.floor((holdingAdjustedReward * holdingsMultiplier) / 10000);
+    const breakdown = this.simulatePurchase(baseRate, referrerHoldings, purchaseAmount, holdingRatio);
+    return breakdown ? Number(breakdown.finalReward) : 0;
+  }
+
+  /**
+   * Full reward breakdown (base, ratio penalty, multiplier, final) for a purchase
+   */
+  simulatePurchase(
+    baseRate: number, // Basis points
+    referrerHoldings: number,
+    purchaseAmount: number,
+    holdingRatio: number = 10000 // Default 100%
+  ): RewardBreakdown | null {
+    return simulatePurchase(
+      baseRate,
+      holdingRatio,
+      BigInt(Math.trunc(referrerHoldings)),
+      BigInt(Math.trunc(purchaseAmount)),
+      BigInt(Math.trunc(this.minHoldingsForReferral))
+    );
   }
 
   /**
@@ -293,6 +298,18 @@ To participate in OTTO referrals, you need a non-custodial Solana wallet.
     return result;
   }
 
+  /**
+   * Referrer stats and live holdings as the program computes them, read by
+   * simulating its `get_referrer_summary` view. Null if the code is unknown.
+   */
+  async getReferrerSummary(referralCode: string): Promise<ReferrerSummary | null> {
+    if (!this.programId || !this.ottTokenMint) {
+      console.warn('Program or token mint not configured, no referrer summary');
+      return null;
+    }
+    return getReferrerSummary(this.connection, this.programId, this.ottTokenMint, referralCode);
+  }
+
   /**
    * Get comprehensive referral stats with new mechanics
    */
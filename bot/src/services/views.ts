@@ -0,0 +1,198 @@
+/**
+ * Calls to the program's read-only view instructions.
+ *
+ * Views write nothing and return a Borsh-encoded struct as return data, so
+ * they are run with `simulateTransaction` instead of being sent.
+ */
+
+import { createHash } from 'node:crypto';
+import {
+  PublicKey,
+  TransactionInstruction,
+  TransactionMessage,
+  VersionedTransaction,
+  type Connection,
+} from '@solana/web3.js';
+import { getAssociatedTokenAddressSync } from '@solana/spl-token';
+
+/** Largest return data a program can set */
+const MAX_RETURN_DATA = 1024;
+
+export interface ReferrerSummary {
+  authority: PublicKey;
+  referralCode: string;
+  isActive: boolean;
+  tier: number; // 0 = None, 1 = Bronze, 2 = Silver, 3 = Gold
+  holdings: bigint; // Live balance across the ATA and linked accounts
+  twab: bigint;
+  holdingRatio: number; // Basis points
+  holdingsMultiplier: number; // Basis points
+  totalReferrals: number;
+  uniqueReferees: number;
+  totalReferredVolume: bigint;
+  totalRewardsEarned: bigint;
+  clawbackOwed: bigint;
+  milestonesReached: number;
+}
+
+/** Anchor's 8-byte discriminator for the instruction `name` */
+export function instructionDiscriminator(name: string): Buffer {
+  return createHash('sha256').update(`global:${name}`).digest().subarray(0, 8);
+}
+
+/** Reads little-endian Borsh values in order */
+export class BorshReader {
+  private offset = 0;
+
+  constructor(private readonly data: Buffer) {}
+
+  skip(bytes: number): void {
+    this.offset += bytes;
+  }
+
+  u8(): number {
+    return this.data.readUInt8(this.offset++);
+  }
+
+  bool(): boolean {
+    return this.u8() !== 0;
+  }
+
+  u16(): number {
+    const value = this.data.readUInt16LE(this.offset);
+    this.offset += 2;
+    return value;
+  }
+
+  u32(): number {
+    const value = this.data.readUInt32LE(this.offset);
+    this.offset += 4;
+    return value;
+  }
+
+  u64(): bigint {
+    const value = this.data.readBigUInt64LE(this.offset);
+    this.offset += 8;
+    return value;
+  }
+
+  pubkey(): PublicKey {
+    const value = new PublicKey(this.data.subarray(this.offset, this.offset + 32));
+    this.offset += 32;
+    return value;
+  }
+
+  string(): string {
+    const length = this.u32();
+    const value = this.data.subarray(this.offset, this.offset + length).toString('utf8');
+    this.offset += length;
+    return value;
+  }
+}
+
+/**
+ * Simulate a view instruction with `payer` as the fee payer and return its
+ * return data. Nothing is signed or sent.
+ */
+export async function simulateView(
+  connection: Connection,
+  payer: PublicKey,
+  instruction: TransactionInstruction
+): Promise<Buffer> {
+  const message = new TransactionMessage({
+    payerKey: payer,
+    // Replaced by the node, as the transaction is never signed
+    recentBlockhash: PublicKey.default.toBase58(),
+    instructions: [instruction],
+  }).compileToV0Message();
+  const { value } = await connection.simulateTransaction(new VersionedTransaction(message), {
+    sigVerify: false,
+    replaceRecentBlockhash: true,
+  });
+  if (value.err) {
+    throw new Error(`View failed: ${JSON.stringify(value.err)}\n${(value.logs ?? []).join('\n')}`);
+  }
+  if (!value.returnData || value.returnData.programId !== instruction.programId.toBase58()) {
+    throw new Error('View returned no data');
+  }
+
+  // The runtime drops trailing zero bytes from return data, so put them back
+  const data = Buffer.alloc(MAX_RETURN_DATA);
+  Buffer.from(value.returnData.data[0], 'base64').copy(data);
+  return data;
+}
+
+/** The extra holdings accounts a referrer linked, read from their referrer account */
+function linkedTokenAccounts(data: Buffer): PublicKey[] {
+  const reader = new BorshReader(data);
+  reader.skip(8 + 1 + 32); // Discriminator, version, authority
+  reader.string(); // Referral code
+  reader.skip(4 + 8 + 8 + 4 + 2 + 1 + 8 + 1 + 8 + 8 + 2 + 8 + 8); // Stats up to `twab_last_update`
+  return Array.from({ length: reader.u32() }, () => reader.pubkey());
+}
+
+/**
+ * A referrer's standing at their live balance, from `get_referrer_summary`.
+ * Null when no referrer has `referralCode`.
+ */
+export async function getReferrerSummary(
+  connection: Connection,
+  programId: PublicKey,
+  ottoMint: PublicKey,
+  referralCode: string
+): Promise<ReferrerSummary | null> {
+  const [programState] = PublicKey.findProgramAddressSync([Buffer.from('program_state')], programId);
+  const [referrerAccount] = PublicKey.findProgramAddressSync(
+    [Buffer.from('referrer'), Buffer.from(referralCode)],
+    programId
+  );
+  const [referrerInfo, mintInfo] = await connection.getMultipleAccountsInfo([referrerAccount, ottoMint]);
+  if (!referrerInfo || !mintInfo) {
+    return null;
+  }
+
+  const authority = new PublicKey(referrerInfo.data.subarray(8, 40));
+  const tokenProgram = mintInfo.owner;
+  const [stakeAccount] = PublicKey.findProgramAddressSync(
+    [Buffer.from('stake'), authority.toBuffer()],
+    programId
+  );
+  const instruction = new TransactionInstruction({
+    programId,
+    keys: [
+      { pubkey: programState, isSigner: false, isWritable: false },
+      { pubkey: referrerAccount, isSigner: false, isWritable: false },
+      {
+        pubkey: getAssociatedTokenAddressSync(ottoMint, authority, true, tokenProgram),
+        isSigner: false,
+        isWritable: false,
+      },
+      { pubkey: stakeAccount, isSigner: false, isWritable: false },
+      { pubkey: tokenProgram, isSigner: false, isWritable: false },
+      ...linkedTokenAccounts(referrerInfo.data).map((pubkey) => ({
+        pubkey,
+        isSigner: false,
+        isWritable: false,
+      })),
+    ],
+    data: instructionDiscriminator('get_referrer_summary'),
+  });
+
+  const reader = new BorshReader(await simulateView(connection, authority, instruction));
+  return {
+    authority: reader.pubkey(),
+    referralCode: reader.string(),
+    isActive: reader.bool(),
+    tier: reader.u8(),
+    holdings: reader.u64(),
+    twab: reader.u64(),
+    holdingRatio: reader.u16(),
+    holdingsMultiplier: reader.u16(),
+    totalReferrals: reader.u32(),
+    uniqueReferees: reader.u32(),
+    totalReferredVolume: reader.u64(),
+    totalRewardsEarned: reader.u64(),
+    clawbackOwed: reader.u64(),
+    milestonesReached: reader.u16(),
+  };
+}
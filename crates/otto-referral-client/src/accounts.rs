@@ -0,0 +1,110 @@
+//! Decoding program accounts, and fetching them with the `rpc` feature.
+
+use anchor_lang::AccountDeserialize;
+
+pub use otto_referral_rewards::{
+    BuyerAccount, Campaign, Leaderboard, MerkleDistribution, PriceOracle, ProgramState,
+    PurchaseAccount, RefereeRecord, ReferrerAccount, StakeAccount, UserSettings,
+};
+
+use crate::Result;
+
+/// Decode a program account, checking its discriminator
+pub fn decode<T: AccountDeserialize>(mut data: &[u8]) -> Result<T> {
+    Ok(T::try_deserialize(&mut data)?)
+}
+
+#[cfg(feature = "rpc")]
+pub use rpc::*;
+
+#[cfg(feature = "rpc")]
+mod rpc {
+    use anchor_lang::{prelude::Pubkey, Discriminator, Owner};
+    use solana_account_decoder::UiAccountEncoding;
+    use solana_rpc_client::rpc_client::RpcClient;
+    use solana_rpc_client_api::{
+        config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+        filter::{Memcmp, RpcFilterType},
+    };
+
+    use super::*;
+    use crate::{pda, ClientError, TokenMint};
+
+    /// Fetch and decode the program account at `address`
+    pub fn fetch<T: AccountDeserialize + Owner>(client: &RpcClient, address: &Pubkey) -> Result<T> {
+        fetch_optional(client, address)?.ok_or(ClientError::AccountNotFound(*address))
+    }
+
+    /// Like [`fetch`], but `None` when the account does not exist
+    pub fn fetch_optional<T: AccountDeserialize + Owner>(
+        client: &RpcClient,
+        address: &Pubkey,
+    ) -> Result<Option<T>> {
+        let Some(account) = client
+            .get_account_with_commitment(address, client.commitment())?
+            .value
+        else {
+            return Ok(None);
+        };
+        if account.owner != T::owner() {
+            return Err(ClientError::InvalidOwner(*address));
+        }
+        decode(&account.data).map(Some)
+    }
+
+    pub fn fetch_program_state(client: &RpcClient) -> Result<ProgramState> {
+        fetch(client, &pda::program_state())
+    }
+
+    pub fn fetch_referrer(client: &RpcClient, referral_code: &str) -> Result<ReferrerAccount> {
+        fetch(client, &pda::referrer(referral_code))
+    }
+
+    pub fn fetch_buyer(client: &RpcClient, buyer: &Pubkey) -> Result<BuyerAccount> {
+        fetch(client, &pda::buyer(buyer))
+    }
+
+    pub fn fetch_user_settings(client: &RpcClient, owner: &Pubkey) -> Result<Option<UserSettings>> {
+        fetch_optional(client, &pda::user_settings(owner))
+    }
+
+    /// A mint along with its owning token program
+    pub fn fetch_token_mint(client: &RpcClient, mint: &Pubkey) -> Result<TokenMint> {
+        let account = client
+            .get_account_with_commitment(mint, client.commitment())?
+            .value
+            .ok_or(ClientError::AccountNotFound(*mint))?;
+        Ok(TokenMint {
+            mint: *mint,
+            token_program: account.owner,
+        })
+    }
+
+    /// Every purchase account belonging to `buyer`
+    pub fn fetch_purchases(
+        client: &RpcClient,
+        buyer: &Pubkey,
+    ) -> Result<Vec<(Pubkey, PurchaseAccount)>> {
+        // `buyer` follows the discriminator and the version byte
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                    0,
+                    &PurchaseAccount::DISCRIMINATOR,
+                )),
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(9, buyer.as_ref())),
+            ]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                commitment: Some(client.commitment()),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+        client
+            .get_program_accounts_with_config(&crate::ID, config)?
+            .into_iter()
+            .map(|(address, account)| Ok((address, decode(&account.data)?)))
+            .collect()
+    }
+}
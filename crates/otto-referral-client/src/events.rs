@@ -0,0 +1,216 @@
+//! Typed decoding of the program's events.
+//!
+//! The program emits events with `emit_cpi!`, as a self-invocation recorded
+//! in the transaction's inner instructions, so they survive log truncation.
+//! Every event starts with a schema version; only events of the current
+//! [`EVENT_VERSION`] are decoded.
+
+use anchor_lang::{event::EVENT_IX_TAG_LE, prelude::Pubkey, AnchorDeserialize, Discriminator};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+pub use otto_referral_rewards::EVENT_VERSION;
+
+use crate::ID;
+
+macro_rules! events {
+    ($($name:ident),* $(,)?) => {
+        pub use otto_referral_rewards::{$($name),*};
+
+        /// Any event the program emits
+        pub enum Event {
+            $($name($name)),*
+        }
+
+        impl Event {
+            /// Decode an event from its discriminator-prefixed data. `None`
+            /// for unknown or malformed data, or another schema version.
+            pub fn decode(data: &[u8]) -> Option<Self> {
+                let (discriminator, mut payload) = data.split_at_checked(8)?;
+                if payload.first() != Some(&EVENT_VERSION) {
+                    return None;
+                }
+                $(
+                    if discriminator == $name::DISCRIMINATOR {
+                        return $name::deserialize(&mut payload).ok().map(Event::$name);
+                    }
+                )*
+                None
+            }
+
+            /// Decode an event from the data of the self-invocation
+            /// `emit_cpi!` makes
+            pub fn decode_cpi(data: &[u8]) -> Option<Self> {
+                Self::decode(data.strip_prefix(&EVENT_IX_TAG_LE)?)
+            }
+
+            /// Serialize the event back to its discriminator-prefixed data
+            pub fn data(&self) -> Vec<u8> {
+                match self {
+                    $(Event::$name(event) => anchor_lang::Event::data(event)),*
+                }
+            }
+
+            /// The event's name, as declared in the program
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Event::$name(_) => stringify!($name)),*
+                }
+            }
+        }
+    };
+}
+
+events!(
+    ProgramInitialized,
+    ReferrerRegistered,
+    PurchaseProcessed,
+    DynamicReferralProcessed,
+    TierUpdated,
+    RewardsClaimed,
+    ProgramParamsUpdated,
+    HoldingParamsUpdated,
+    HoldingsRatioUpdated,
+    ReferrerRefreshed,
+    Staked,
+    UnstakeRequested,
+    StakeWithdrawn,
+    MilestoneReached,
+    MilestonesUpdated,
+    RefereeBonusUpdated,
+    RewardPoolConfigured,
+    PayoutMintUpdated,
+    PriceOracleInitialized,
+    PriceOracleUpdated,
+    CampaignCreated,
+    CampaignUpdated,
+    PayoutAddressUpdated,
+    PurchaseReversed,
+    ReferralPayoutHeld,
+    HeldPayoutClaimed,
+    MerkleDistributionCreated,
+    MerkleClaimed,
+    LeaderboardCreated,
+    LeaderboardPrizePaid,
+    LeaderboardSettled,
+    StatsShardInitialized,
+    StatsAggregated,
+    AccountMigrated,
+);
+
+/// Decode the events this program emitted, in order, from a transaction's
+/// inner instructions, given as `(program_id, data)` pairs. Instructions of
+/// other programs are skipped.
+pub fn parse_inner_instructions<D: AsRef<[u8]>>(instructions: &[(Pubkey, D)]) -> Vec<Event> {
+    instructions
+        .iter()
+        .filter(|(program_id, _)| *program_id == ID)
+        .filter_map(|(_, data)| Event::decode_cpi(data.as_ref()))
+        .collect()
+}
+
+/// Decode events logged with `emit!` from a transaction's log messages.
+/// Events emitted with `emit_cpi!` do not appear in the logs; use
+/// [`parse_inner_instructions`] or `fetch_transaction_events` for those. `Program data:` lines logged by other programs, including
+/// ones the program invoked, are skipped.
+pub fn parse_logs<S: AsRef<str>>(logs: &[S]) -> Vec<Event> {
+    let program_id = ID.to_string();
+    let mut call_stack: Vec<&str> = Vec::new();
+    let mut events = Vec::new();
+
+    for log in logs {
+        let log = log.as_ref();
+        if let Some(data) = log.strip_prefix("Program data: ") {
+            if call_stack.last() == Some(&program_id.as_str()) {
+                if let Some(event) = STANDARD
+                    .decode(data)
+                    .ok()
+                    .and_then(|data| Event::decode(&data))
+                {
+                    events.push(event);
+                }
+            }
+        } else if let Some(rest) = log.strip_prefix("Program ") {
+            let mut words = rest.split_whitespace();
+            let (Some(program), Some(status)) = (words.next(), words.next()) else {
+                continue;
+            };
+            match status {
+                "invoke" => call_stack.push(program),
+                "success" | "failed:" => {
+                    call_stack.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    events
+}
+
+#[cfg(feature = "rpc")]
+pub use rpc::{fetch_transaction_events, transaction_events};
+
+#[cfg(feature = "rpc")]
+mod rpc {
+    use solana_rpc_client::rpc_client::RpcClient;
+    use solana_rpc_client_api::config::RpcTransactionConfig;
+    use solana_sdk::signature::Signature;
+    use solana_transaction_status::{
+        option_serializer::OptionSerializer, EncodedTransactionWithStatusMeta, UiInstruction,
+        UiTransactionEncoding,
+    };
+
+    use super::*;
+    use crate::{ClientError, Result};
+
+    /// Fetch a confirmed transaction and decode the events the program
+    /// emitted in it, in order
+    pub fn fetch_transaction_events(client: &RpcClient, signature: &Signature) -> Result<Vec<Event>> {
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Base64),
+            commitment: Some(client.commitment()),
+            max_supported_transaction_version: Some(0),
+        };
+        let transaction = client
+            .get_transaction_with_config(signature, config)?
+            .transaction;
+        transaction_events(&transaction).ok_or(ClientError::InvalidTransaction(*signature))
+    }
+
+    /// Decode the events the program emitted in a transaction fetched with a
+    /// binary encoding, in order. Events come from the inner instructions,
+    /// or from the logs for transactions that carry none there. `None` when
+    /// the transaction or its metadata cannot be decoded.
+    pub fn transaction_events(transaction: &EncodedTransactionWithStatusMeta) -> Option<Vec<Event>> {
+        let message = transaction.transaction.decode()?.message;
+        let meta = transaction.meta.as_ref()?;
+
+        // Inner instructions index into the static keys, then any keys
+        // loaded from lookup tables
+        let mut account_keys = message.static_account_keys().to_vec();
+        if let OptionSerializer::Some(loaded) = &meta.loaded_addresses {
+            for key in loaded.writable.iter().chain(&loaded.readonly) {
+                account_keys.push(key.parse().ok()?);
+            }
+        }
+
+        let mut instructions = Vec::new();
+        if let OptionSerializer::Some(inner_instructions) = &meta.inner_instructions {
+            for instruction in inner_instructions.iter().flat_map(|inner| &inner.instructions) {
+                let UiInstruction::Compiled(instruction) = instruction else {
+                    continue;
+                };
+                let program_id = account_keys.get(instruction.program_id_index as usize)?;
+                let data = bs58::decode(&instruction.data).into_vec().ok()?;
+                instructions.push((*program_id, data));
+            }
+        }
+
+        let events = parse_inner_instructions(&instructions);
+        if events.is_empty() {
+            if let OptionSerializer::Some(logs) = &meta.log_messages {
+                return Some(parse_logs(logs));
+            }
+        }
+        Some(events)
+    }
+}
@@ -0,0 +1,998 @@
+//! Instruction builders, one per program handler.
+//!
+//! Builders derive every PDA and associated token account they can, so
+//! callers only pass wallets, mints and instruction arguments. Referrer
+//! holdings spread over linked token accounts are passed back in as
+//! `linked_token_accounts`, in the order stored on the referrer account.
+
+use anchor_lang::{
+    prelude::Pubkey,
+    solana_program::{ed25519_program, instruction::AccountMeta, sysvar},
+    system_program, InstructionData, ToAccountMetas,
+};
+use anchor_spl::associated_token;
+use otto_referral_rewards::{
+    accounts, instruction, stats_shard_index, CampaignParams, Leaderboard, Milestone,
+    PurchaseAccount, LEADERBOARD_SIZE, STATS_SHARDS,
+};
+
+use crate::{pda, Instruction, TokenMint, ID};
+
+fn build(
+    accounts: impl ToAccountMetas,
+    data: impl InstructionData,
+    remaining_accounts: impl IntoIterator<Item = AccountMeta>,
+) -> Instruction {
+    let mut metas = accounts.to_account_metas(None);
+    metas.extend(remaining_accounts);
+    Instruction {
+        program_id: ID,
+        accounts: metas,
+        data: data.data(),
+    }
+}
+
+fn readonly(keys: &[Pubkey]) -> impl Iterator<Item = AccountMeta> + '_ {
+    keys.iter()
+        .map(|key| AccountMeta::new_readonly(*key, false))
+}
+
+pub fn initialize(
+    authority: &Pubkey,
+    otto: &TokenMint,
+    rewards_percentage: u16,
+    min_purchase_amount: u64,
+    min_holdings_for_referral: u64,
+    base_reward_rate: u16,
+) -> Instruction {
+    build(
+        accounts::Initialize {
+            program_state: pda::program_state(),
+            rewards_pool: pda::rewards_pool(otto),
+            otto_token_mint: otto.mint,
+            authority: *authority,
+            system_program: system_program::ID,
+            token_program: otto.token_program,
+            associated_token_program: associated_token::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::Initialize {
+            rewards_percentage,
+            min_purchase_amount,
+            min_holdings_for_referral,
+            base_reward_rate,
+        },
+        [],
+    )
+}
+
+pub fn register_referrer(
+    authority: &Pubkey,
+    otto: &TokenMint,
+    referral_code: &str,
+    linked_token_accounts: &[Pubkey],
+) -> Instruction {
+    build(
+        accounts::RegisterReferrer {
+            program_state: pda::program_state(),
+            referrer_account: pda::referrer(referral_code),
+            referrer_lookup: pda::referrer_lookup(authority),
+            token_account: otto.ata(authority),
+            stake_account: pda::stake(authority),
+            authority: *authority,
+            system_program: system_program::ID,
+            token_program: otto.token_program,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::RegisterReferrer {
+            referral_code: referral_code.to_string(),
+        },
+        readonly(linked_token_accounts),
+    )
+}
+
+pub fn update_holdings_ratio(
+    authority: &Pubkey,
+    otto: &TokenMint,
+    referral_code: &str,
+    linked_token_accounts: &[Pubkey],
+) -> Instruction {
+    build(
+        accounts::UpdateHoldingsRatio {
+            program_state: pda::program_state(),
+            referrer_account: pda::referrer(referral_code),
+            token_account: otto.ata(authority),
+            stake_account: pda::stake(authority),
+            authority: *authority,
+            token_program: otto.token_program,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::UpdateHoldingsRatio {},
+        readonly(linked_token_accounts),
+    )
+}
+
+/// The referrer credited with a purchase
+#[derive(Clone, Debug)]
+pub struct PurchaseReferrer {
+    pub referral_code: String,
+    pub authority: Pubkey,
+    pub linked_token_accounts: Vec<Pubkey>,
+    /// The referrer's configured payout wallet, if the reward should go there
+    pub payout_wallet: Option<Pubkey>,
+    /// Set when the payout wallet has no payout mint account, to hold the
+    /// reward for [`claim_held_payout`] instead
+    pub hold_payout: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct PurchaseArgs {
+    pub buyer: Pubkey,
+    pub otto: TokenMint,
+    /// The campaign's payout mint when a campaign applies, else the global one
+    pub payout: TokenMint,
+    pub purchase_amount: u64,
+    /// Cluster time the purchase lands at, part of the purchase PDA's seeds
+    pub timestamp: i64,
+    pub referrer: Option<PurchaseReferrer>,
+    /// Required when the payout mint's reward pool is priced by an oracle
+    pub price_oracle: Option<Pubkey>,
+    /// The program's active campaign, required whenever one is set
+    pub campaign_id: Option<u64>,
+    /// The program's active leaderboard, required whenever one is set
+    pub leaderboard_id: Option<u64>,
+}
+
+/// Returns the instruction and the purchase account it creates
+pub fn process_purchase(args: &PurchaseArgs) -> (Instruction, Pubkey) {
+    let purchase_account = pda::purchase(&args.buyer, args.timestamp);
+    let referrer = args.referrer.as_ref();
+    let referrer_account = referrer.map(|referrer| pda::referrer(&referrer.referral_code));
+    let instruction = build(
+        accounts::ProcessPurchase {
+            program_state: pda::program_state(),
+            purchase_account,
+            buyer_account: pda::buyer(&args.buyer),
+            referrer_account,
+            referee_record: referrer_account
+                .map(|referrer_account| pda::referee_record(&referrer_account, &args.buyer)),
+            referrer_token_account: referrer.map(|referrer| args.otto.ata(&referrer.authority)),
+            referrer_stake_account: referrer.map(|referrer| pda::stake(&referrer.authority)),
+            payout_mint: args.payout.mint,
+            rewards_pool: pda::rewards_pool(&args.payout),
+            referrer_payout_account: referrer
+                .filter(|referrer| !referrer.hold_payout)
+                .map(|referrer| {
+                    args.payout.ata(
+                        referrer
+                            .payout_wallet
+                            .as_ref()
+                            .unwrap_or(&referrer.authority),
+                    )
+                }),
+            referrer_settings: referrer
+                .filter(|referrer| referrer.payout_wallet.is_some() && !referrer.hold_payout)
+                .map(|referrer| pda::user_settings(&referrer.authority)),
+            held_payout: referrer
+                .filter(|referrer| referrer.hold_payout)
+                .zip(referrer_account)
+                .map(|(_, referrer_account)| pda::held_payout(&referrer_account, &args.payout.mint)),
+            price_oracle: args.price_oracle,
+            campaign: args.campaign_id.map(pda::campaign),
+            leaderboard: args.leaderboard_id.map(pda::leaderboard),
+            leaderboard_volume: args
+                .leaderboard_id
+                .zip(referrer_account)
+                .map(|(leaderboard_id, referrer_account)| {
+                    pda::leaderboard_volume(&pda::leaderboard(leaderboard_id), &referrer_account)
+                }),
+            stats_shard: pda::stats_shard(stats_shard_index(&args.buyer)),
+            buyer: args.buyer,
+            system_program: system_program::ID,
+            token_program: args.otto.token_program,
+            payout_token_program: args.payout.token_program,
+            associated_token_program: associated_token::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::ProcessPurchase {
+            purchase_amount: args.purchase_amount,
+            referral_code: referrer.map(|referrer| referrer.referral_code.clone()),
+        },
+        readonly(referrer.map_or(&[], |referrer| &referrer.linked_token_accounts)),
+    );
+    (instruction, purchase_account)
+}
+
+/// Quotes the purchase `args` describes; `timestamp` and `leaderboard_id`
+/// are ignored. Run through simulation and decode the
+/// [`ReferralQuote`](otto_referral_rewards::ReferralQuote) from the return data.
+pub fn quote_referral_reward(args: &PurchaseArgs) -> Instruction {
+    let referrer = args.referrer.as_ref();
+    let referrer_account = referrer.map(|referrer| pda::referrer(&referrer.referral_code));
+    build(
+        accounts::QuoteReferralReward {
+            program_state: pda::program_state(),
+            buyer_account: pda::buyer(&args.buyer),
+            referrer_account,
+            referee_record: pda::referee_record(&referrer_account.unwrap_or_default(), &args.buyer),
+            referrer_token_account: referrer.map(|referrer| args.otto.ata(&referrer.authority)),
+            referrer_stake_account: referrer.map(|referrer| pda::stake(&referrer.authority)),
+            payout_mint: args.payout.mint,
+            price_oracle: args.price_oracle,
+            campaign: args.campaign_id.map(pda::campaign),
+            buyer: args.buyer,
+            token_program: args.otto.token_program,
+        },
+        instruction::QuoteReferralReward {
+            purchase_amount: args.purchase_amount,
+            referral_code: referrer.map(|referrer| referrer.referral_code.clone()),
+        },
+        readonly(referrer.map_or(&[], |referrer| &referrer.linked_token_accounts)),
+    )
+}
+
+pub fn update_tier(
+    authority: &Pubkey,
+    otto: &TokenMint,
+    referral_code: &str,
+    linked_token_accounts: &[Pubkey],
+) -> Instruction {
+    build(
+        accounts::UpdateTier {
+            program_state: pda::program_state(),
+            referrer_account: pda::referrer(referral_code),
+            token_account: otto.ata(authority),
+            stake_account: pda::stake(authority),
+            authority: *authority,
+            token_program: otto.token_program,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::UpdateTier {},
+        readonly(linked_token_accounts),
+    )
+}
+
+/// `cranker_token_account` receives the bounty if the refresh catches a sale
+pub fn refresh_referrer(
+    cranker: &Pubkey,
+    otto: &TokenMint,
+    referral_code: &str,
+    referrer_authority: &Pubkey,
+    linked_token_accounts: &[Pubkey],
+    cranker_token_account: Option<Pubkey>,
+) -> Instruction {
+    build(
+        accounts::RefreshReferrer {
+            program_state: pda::program_state(),
+            referrer_account: pda::referrer(referral_code),
+            otto_token_mint: otto.mint,
+            token_account: otto.ata(referrer_authority),
+            stake_account: pda::stake(referrer_authority),
+            rewards_pool: pda::rewards_pool(otto),
+            cranker_token_account,
+            cranker: *cranker,
+            token_program: otto.token_program,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::RefreshReferrer {},
+        readonly(linked_token_accounts),
+    )
+}
+
+/// Run through simulation and decode the
+/// [`ReferrerSummary`](otto_referral_rewards::ReferrerSummary) from the return data
+pub fn get_referrer_summary(
+    otto: &TokenMint,
+    referral_code: &str,
+    referrer_authority: &Pubkey,
+    linked_token_accounts: &[Pubkey],
+) -> Instruction {
+    build(
+        accounts::GetReferrerSummary {
+            program_state: pda::program_state(),
+            referrer_account: pda::referrer(referral_code),
+            token_account: otto.ata(referrer_authority),
+            stake_account: pda::stake(referrer_authority),
+            token_program: otto.token_program,
+        },
+        instruction::GetReferrerSummary {},
+        readonly(linked_token_accounts),
+    )
+}
+
+pub fn stake(owner: &Pubkey, otto: &TokenMint, amount: u64) -> Instruction {
+    build(
+        accounts::Stake {
+            program_state: pda::program_state(),
+            stake_account: pda::stake(owner),
+            stake_vault: pda::stake_vault(owner, otto),
+            otto_token_mint: otto.mint,
+            owner_token_account: otto.ata(owner),
+            owner: *owner,
+            system_program: system_program::ID,
+            token_program: otto.token_program,
+            associated_token_program: associated_token::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::Stake { amount },
+        [],
+    )
+}
+
+/// The owner's referral code is required when they are a referrer, so the
+/// unbonding amount is taken off their holdings
+pub fn request_unstake(
+    owner: &Pubkey,
+    otto: &TokenMint,
+    amount: u64,
+    referral_code: Option<&str>,
+    linked_token_accounts: &[Pubkey],
+) -> Instruction {
+    build(
+        accounts::RequestUnstake {
+            program_state: pda::program_state(),
+            stake_account: pda::stake(owner),
+            referrer_account: referral_code.map(pda::referrer),
+            referrer_lookup: pda::referrer_lookup(owner),
+            token_account: referral_code.map(|_| otto.ata(owner)),
+            owner: *owner,
+            token_program: otto.token_program,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::RequestUnstake { amount },
+        readonly(linked_token_accounts),
+    )
+}
+
+pub fn withdraw(owner: &Pubkey, otto: &TokenMint) -> Instruction {
+    build(
+        accounts::Withdraw {
+            program_state: pda::program_state(),
+            stake_account: pda::stake(owner),
+            otto_token_mint: otto.mint,
+            stake_vault: pda::stake_vault(owner, otto),
+            owner_token_account: otto.ata(owner),
+            owner: *owner,
+            token_program: otto.token_program,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::Withdraw {},
+        [],
+    )
+}
+
+/// Claims to `destination` when given, else to the buyer's ATA, which is
+/// created if needed
+pub fn claim_rewards(
+    buyer: &Pubkey,
+    purchase_account: &Pubkey,
+    payout: &TokenMint,
+    destination: Option<Pubkey>,
+    price_oracle: Option<Pubkey>,
+) -> Instruction {
+    build(
+        accounts::ClaimRewards {
+            program_state: pda::program_state(),
+            purchase_account: *purchase_account,
+            payout_mint: payout.mint,
+            rewards_pool: pda::rewards_pool(payout),
+            buyer_token_account: destination.is_none().then(|| payout.ata(buyer)),
+            destination,
+            price_oracle,
+            stats_shard: pda::stats_shard(stats_shard_index(buyer)),
+            buyer: *buyer,
+            system_program: system_program::ID,
+            token_program: payout.token_program,
+            associated_token_program: associated_token::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::ClaimRewards {},
+        [],
+    )
+}
+
+/// Claims on the buyer's behalf, paid for by `relayer`. Must directly follow
+/// [`claim_signature`] for the same purchase. Rewards go to the buyer's
+/// configured payout wallet when given, else to the buyer's ATA.
+pub fn claim_rewards_delegated(
+    relayer: &Pubkey,
+    buyer: &Pubkey,
+    purchase_account: &Pubkey,
+    payout: &TokenMint,
+    payout_wallet: Option<Pubkey>,
+    price_oracle: Option<Pubkey>,
+) -> Instruction {
+    build(
+        accounts::ClaimRewardsDelegated {
+            program_state: pda::program_state(),
+            purchase_account: *purchase_account,
+            payout_mint: payout.mint,
+            rewards_pool: pda::rewards_pool(payout),
+            buyer_token_account: payout_wallet.is_none().then(|| payout.ata(buyer)),
+            destination: payout_wallet.map(|wallet| payout.ata(&wallet)),
+            user_settings: payout_wallet.map(|_| pda::user_settings(buyer)),
+            price_oracle,
+            stats_shard: pda::stats_shard(stats_shard_index(buyer)),
+            buyer: *buyer,
+            relayer: *relayer,
+            instructions_sysvar: sysvar::instructions::ID,
+            system_program: system_program::ID,
+            token_program: payout.token_program,
+            associated_token_program: associated_token::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::ClaimRewardsDelegated {},
+        [],
+    )
+}
+
+/// Run through simulation and decode the
+/// [`ClaimQuote`](otto_referral_rewards::ClaimQuote) from the return data
+pub fn quote_claim(
+    purchase_account: &Pubkey,
+    payout_mint: &Pubkey,
+    price_oracle: Option<Pubkey>,
+) -> Instruction {
+    build(
+        accounts::QuoteClaim {
+            program_state: pda::program_state(),
+            purchase_account: *purchase_account,
+            payout_mint: *payout_mint,
+            price_oracle,
+        },
+        instruction::QuoteClaim {},
+        [],
+    )
+}
+
+/// The ed25519 program instruction carrying the buyer's `signature` over
+/// [`program::claim_message`](otto_referral_rewards::claim_message)
+pub fn claim_signature(
+    buyer: &Pubkey,
+    purchase_account: &Pubkey,
+    signature: &[u8; 64],
+) -> Instruction {
+    const HEADER_LEN: u16 = 16;
+    const PUBLIC_KEY_OFFSET: u16 = HEADER_LEN;
+    const SIGNATURE_OFFSET: u16 = PUBLIC_KEY_OFFSET + 32;
+    const MESSAGE_OFFSET: u16 = SIGNATURE_OFFSET + 64;
+
+    let message = otto_referral_rewards::claim_message(purchase_account);
+    // One signature, with every offset pointing into this instruction
+    let mut data = vec![1, 0];
+    for field in [
+        SIGNATURE_OFFSET,
+        u16::MAX,
+        PUBLIC_KEY_OFFSET,
+        u16::MAX,
+        MESSAGE_OFFSET,
+        message.len() as u16,
+        u16::MAX,
+    ] {
+        data.extend_from_slice(&field.to_le_bytes());
+    }
+    data.extend_from_slice(buyer.as_ref());
+    data.extend_from_slice(signature);
+    data.extend_from_slice(&message);
+
+    Instruction {
+        program_id: ed25519_program::ID,
+        accounts: vec![],
+        data,
+    }
+}
+
+pub fn set_payout_address(owner: &Pubkey, payout_wallet: Option<Pubkey>) -> Instruction {
+    build(
+        accounts::SetPayoutAddress {
+            user_settings: pda::user_settings(owner),
+            owner: *owner,
+            system_program: system_program::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::SetPayoutAddress { payout_wallet },
+        [],
+    )
+}
+
+/// Every account the purchase credited is derived from `purchase`, so its
+/// referral, campaign and leaderboard credit can be taken back
+pub fn reverse_purchase(
+    authority: &Pubkey,
+    purchase_account: &Pubkey,
+    purchase: &PurchaseAccount,
+) -> Instruction {
+    build(
+        accounts::ReversePurchase {
+            program_state: pda::program_state(),
+            purchase_account: *purchase_account,
+            referrer_account: purchase.referrer,
+            referee_record: purchase
+                .referrer
+                .map(|referrer_account| pda::referee_record(&referrer_account, &purchase.buyer)),
+            buyer_account: purchase.referrer.map(|_| pda::buyer(&purchase.buyer)),
+            campaign: purchase.campaign,
+            leaderboard: purchase.leaderboard_id.map(pda::leaderboard),
+            leaderboard_volume: purchase
+                .leaderboard_id
+                .zip(purchase.referrer)
+                .map(|(leaderboard_id, referrer_account)| {
+                    pda::leaderboard_volume(&pda::leaderboard(leaderboard_id), &referrer_account)
+                }),
+            held_payout: purchase
+                .referrer
+                .filter(|_| purchase.referral_held > 0)
+                .map(|referrer_account| pda::held_payout(&referrer_account, &purchase.payout_mint)),
+            stats_shard: pda::stats_shard(stats_shard_index(&purchase.buyer)),
+            authority: *authority,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::ReversePurchase {},
+        [],
+    )
+}
+
+fn update_program_params_accounts(authority: &Pubkey) -> accounts::UpdateProgramParams {
+    accounts::UpdateProgramParams {
+        program_state: pda::program_state(),
+        authority: *authority,
+        event_authority: pda::event_authority(),
+        program: ID,
+    }
+}
+
+pub fn update_program_params(
+    authority: &Pubkey,
+    rewards_percentage: Option<u16>,
+    min_purchase_amount: Option<u64>,
+    tier_thresholds: Option<[u64; 3]>,
+) -> Instruction {
+    build(
+        update_program_params_accounts(authority),
+        instruction::UpdateProgramParams {
+            rewards_percentage,
+            min_purchase_amount,
+            tier_thresholds,
+        },
+        [],
+    )
+}
+
+pub fn update_holding_params(
+    authority: &Pubkey,
+    holding_baseline: Option<u64>,
+    twab_window: Option<i64>,
+    ratio_recovery_rate: Option<u16>,
+    crank_bounty: Option<u64>,
+    unbonding_period: Option<i64>,
+) -> Instruction {
+    build(
+        update_program_params_accounts(authority),
+        instruction::UpdateHoldingParams {
+            holding_baseline,
+            twab_window,
+            ratio_recovery_rate,
+            crank_bounty,
+            unbonding_period,
+        },
+        [],
+    )
+}
+
+pub fn update_referee_bonus(
+    authority: &Pubkey,
+    bonus_bps: Option<u16>,
+    first_purchase_only: Option<bool>,
+) -> Instruction {
+    build(
+        update_program_params_accounts(authority),
+        instruction::UpdateRefereeBonus {
+            bonus_bps,
+            first_purchase_only,
+        },
+        [],
+    )
+}
+
+pub fn set_milestones(authority: &Pubkey, milestones: Vec<Milestone>) -> Instruction {
+    build(
+        update_program_params_accounts(authority),
+        instruction::SetMilestones { milestones },
+        [],
+    )
+}
+
+pub fn set_payout_mint(authority: &Pubkey, payout_mint: Pubkey) -> Instruction {
+    build(
+        update_program_params_accounts(authority),
+        instruction::SetPayoutMint { payout_mint },
+        [],
+    )
+}
+
+/// Adds or updates the reward pool for `payout`, creating the pool's ATA
+pub fn configure_reward_pool(
+    authority: &Pubkey,
+    payout: &TokenMint,
+    rate_numerator: u64,
+    rate_denominator: u64,
+    oracle: Option<Pubkey>,
+) -> Instruction {
+    build(
+        accounts::ConfigureRewardPool {
+            program_state: pda::program_state(),
+            payout_mint: payout.mint,
+            rewards_pool: pda::rewards_pool(payout),
+            authority: *authority,
+            system_program: system_program::ID,
+            token_program: payout.token_program,
+            associated_token_program: associated_token::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::ConfigureRewardPool {
+            rate_numerator,
+            rate_denominator,
+            oracle,
+        },
+        [],
+    )
+}
+
+pub fn init_price_oracle(
+    authority: &Pubkey,
+    payout_mint: &Pubkey,
+    updater: Pubkey,
+    max_staleness: i64,
+) -> Instruction {
+    build(
+        accounts::InitPriceOracle {
+            program_state: pda::program_state(),
+            payout_mint: *payout_mint,
+            price_oracle: pda::price_oracle(payout_mint),
+            authority: *authority,
+            system_program: system_program::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::InitPriceOracle {
+            updater,
+            max_staleness,
+        },
+        [],
+    )
+}
+
+pub fn update_price_oracle(
+    updater: &Pubkey,
+    payout_mint: &Pubkey,
+    rate_numerator: u64,
+    rate_denominator: u64,
+) -> Instruction {
+    build(
+        accounts::UpdatePriceOracle {
+            price_oracle: pda::price_oracle(payout_mint),
+            updater: *updater,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::UpdatePriceOracle {
+            rate_numerator,
+            rate_denominator,
+        },
+        [],
+    )
+}
+
+pub fn create_campaign(
+    authority: &Pubkey,
+    campaign_id: u64,
+    params: CampaignParams,
+) -> Instruction {
+    build(
+        accounts::CreateCampaign {
+            program_state: pda::program_state(),
+            campaign: pda::campaign(campaign_id),
+            authority: *authority,
+            system_program: system_program::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::CreateCampaign {
+            campaign_id,
+            params,
+        },
+        [],
+    )
+}
+
+pub fn update_campaign(
+    authority: &Pubkey,
+    campaign_id: u64,
+    end_timestamp: Option<i64>,
+    budget: Option<u64>,
+) -> Instruction {
+    build(
+        accounts::UpdateCampaign {
+            program_state: pda::program_state(),
+            campaign: pda::campaign(campaign_id),
+            authority: *authority,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::UpdateCampaign {
+            end_timestamp,
+            budget,
+        },
+        [],
+    )
+}
+
+/// Creates a leaderboard and moves `prize_pool` from the prize mint's reward
+/// pool into its prize vault
+pub fn create_leaderboard(
+    authority: &Pubkey,
+    leaderboard_id: u64,
+    start_timestamp: i64,
+    end_timestamp: i64,
+    prize: &TokenMint,
+    prize_pool: u64,
+    prize_splits: [u16; LEADERBOARD_SIZE],
+) -> Instruction {
+    let leaderboard = pda::leaderboard(leaderboard_id);
+    build(
+        accounts::CreateLeaderboard {
+            program_state: pda::program_state(),
+            leaderboard,
+            prize_mint: prize.mint,
+            rewards_pool: pda::rewards_pool(prize),
+            prize_vault: prize.ata(&leaderboard),
+            authority: *authority,
+            system_program: system_program::ID,
+            token_program: prize.token_program,
+            associated_token_program: associated_token::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::CreateLeaderboard {
+            leaderboard_id,
+            start_timestamp,
+            end_timestamp,
+            prize_mint: prize.mint,
+            prize_pool,
+            prize_splits,
+        },
+        [],
+    )
+}
+
+/// Pays out a fetched `leaderboard`, passing each ranked referrer's prize ATA
+/// in rank order. Prizes for ATAs that don't exist yet are held for
+/// [`claim_leaderboard_prize`].
+pub fn settle_leaderboard(leaderboard: &Leaderboard, prize_token_program: &Pubkey) -> Instruction {
+    let prize = TokenMint {
+        mint: leaderboard.prize_mint,
+        token_program: *prize_token_program,
+    };
+    let prize_accounts = leaderboard
+        .entries
+        .iter()
+        .filter(|entry| entry.volume > 0)
+        .map(|entry| AccountMeta::new(prize.ata(&entry.referrer), false));
+    let leaderboard_key = pda::leaderboard(leaderboard.id);
+    build(
+        accounts::SettleLeaderboard {
+            program_state: pda::program_state(),
+            leaderboard: leaderboard_key,
+            prize_mint: prize.mint,
+            rewards_pool: pda::rewards_pool(&prize),
+            prize_vault: prize.ata(&leaderboard_key),
+            token_program: prize.token_program,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::SettleLeaderboard {},
+        prize_accounts,
+    )
+}
+
+/// Claims `referrer`'s held prize from a settled leaderboard, creating their
+/// prize ATA if needed
+pub fn claim_leaderboard_prize(
+    referrer: &Pubkey,
+    leaderboard_id: u64,
+    prize: &TokenMint,
+) -> Instruction {
+    let leaderboard = pda::leaderboard(leaderboard_id);
+    build(
+        accounts::ClaimLeaderboardPrize {
+            leaderboard,
+            prize_mint: prize.mint,
+            prize_vault: prize.ata(&leaderboard),
+            prize_account: prize.ata(referrer),
+            referrer: *referrer,
+            system_program: system_program::ID,
+            token_program: prize.token_program,
+            associated_token_program: associated_token::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::ClaimLeaderboardPrize {},
+        [],
+    )
+}
+
+/// Claims the referral payouts held for `authority`'s referral code,
+/// creating their payout mint ATA if needed
+pub fn claim_held_payout(authority: &Pubkey, referral_code: &str, payout: &TokenMint) -> Instruction {
+    let referrer_account = pda::referrer(referral_code);
+    build(
+        accounts::ClaimHeldPayout {
+            program_state: pda::program_state(),
+            referrer_account,
+            held_payout: pda::held_payout(&referrer_account, &payout.mint),
+            payout_mint: payout.mint,
+            rewards_pool: pda::rewards_pool(payout),
+            payout_account: payout.ata(authority),
+            authority: *authority,
+            system_program: system_program::ID,
+            token_program: payout.token_program,
+            associated_token_program: associated_token::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::ClaimHeldPayout {},
+        [],
+    )
+}
+
+pub fn create_merkle_distribution(
+    authority: &Pubkey,
+    distribution_id: u64,
+    mint: Pubkey,
+    root: [u8; 32],
+    max_claims: u32,
+    total_amount: u64,
+) -> Instruction {
+    build(
+        accounts::CreateMerkleDistribution {
+            program_state: pda::program_state(),
+            distribution: pda::merkle_distribution(distribution_id),
+            authority: *authority,
+            system_program: system_program::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::CreateMerkleDistribution {
+            distribution_id,
+            mint,
+            root,
+            max_claims,
+            total_amount,
+        },
+        [],
+    )
+}
+
+pub fn claim_merkle(
+    claimant: &Pubkey,
+    distribution_id: u64,
+    mint: &TokenMint,
+    index: u32,
+    amount: u64,
+    proof: Vec<[u8; 32]>,
+) -> Instruction {
+    build(
+        accounts::ClaimMerkle {
+            program_state: pda::program_state(),
+            distribution: pda::merkle_distribution(distribution_id),
+            mint: mint.mint,
+            rewards_pool: pda::rewards_pool(mint),
+            claimant_token_account: mint.ata(claimant),
+            claimant: *claimant,
+            system_program: system_program::ID,
+            token_program: mint.token_program,
+            associated_token_program: associated_token::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::ClaimMerkle {
+            index,
+            amount,
+            proof,
+        },
+        [],
+    )
+}
+
+/// Creates stats shard `index`; every shard below
+/// [`STATS_SHARDS`] must exist before purchases and claims hashed to it
+pub fn init_stats_shard(payer: &Pubkey, index: u8) -> Instruction {
+    build(
+        accounts::InitStatsShard {
+            stats_shard: pda::stats_shard(index),
+            payer: *payer,
+            system_program: system_program::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::InitStatsShard { index },
+        [],
+    )
+}
+
+/// Sums every stats shard into the program state's global totals
+pub fn aggregate_stats() -> Instruction {
+    let stats_shards: Vec<Pubkey> = (0..STATS_SHARDS).map(pda::stats_shard).collect();
+    build(
+        accounts::AggregateStats {
+            program_state: pda::program_state(),
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::AggregateStats {},
+        readonly(&stats_shards),
+    )
+}
+
+/// Upgrades the program state from the unversioned layout
+pub fn migrate_program_state(authority: &Pubkey) -> Instruction {
+    build(
+        accounts::MigrateProgramState {
+            program_state: pda::program_state(),
+            authority: *authority,
+            system_program: system_program::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::MigrateProgramState {},
+        [],
+    )
+}
+
+/// Upgrades a referrer account from the unversioned layout, creating the
+/// lookup of its `authority`; `payer` covers the extra rent
+pub fn migrate_referrer(payer: &Pubkey, referral_code: &str, authority: &Pubkey) -> Instruction {
+    build(
+        accounts::MigrateReferrer {
+            referrer_account: pda::referrer(referral_code),
+            referrer_lookup: pda::referrer_lookup(authority),
+            payer: *payer,
+            system_program: system_program::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::MigrateReferrer {},
+        [],
+    )
+}
+
+/// Upgrades a purchase account from the unversioned layout; `payer` covers
+/// the extra rent. The program state must be migrated first.
+pub fn migrate_purchase(payer: &Pubkey, purchase_account: &Pubkey) -> Instruction {
+    build(
+        accounts::MigratePurchase {
+            program_state: pda::program_state(),
+            purchase_account: *purchase_account,
+            payer: *payer,
+            system_program: system_program::ID,
+            event_authority: pda::event_authority(),
+            program: ID,
+        },
+        instruction::MigratePurchase {},
+        [],
+    )
+}
@@ -0,0 +1,91 @@
+//! Typed client for the OTTO Referral+ Rewards program.
+//!
+//! - [`pda`] derives the program's accounts from their seeds
+//! - [`instructions`] builds an [`Instruction`] for every handler, deriving
+//!   PDAs and associated token accounts
+//! - [`accounts`] decodes program accounts and, with the `rpc` feature,
+//!   fetches them from a cluster
+//! - [`events`] decodes the program's versioned events from inner
+//!   instructions or logs
+//! - [`views`] decodes the results of the read-only view instructions and,
+//!   with the `rpc` feature, runs them through simulation
+//! - [`reward_math`] simulates referral rewards exactly as the program does
+
+use anchor_lang::prelude::Pubkey;
+use anchor_spl::associated_token::get_associated_token_address_with_program_id;
+
+pub use anchor_lang::solana_program::instruction::Instruction;
+pub use otto_referral_rewards::{self as program, ID};
+/// Off-chain reward simulation, sharing the program's math
+pub use otto_reward_math as reward_math;
+
+pub mod accounts;
+pub mod events;
+pub mod instructions;
+pub mod pda;
+pub mod views;
+
+/// A mint and the token program that owns it, needed to derive its ATAs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TokenMint {
+    pub mint: Pubkey,
+    pub token_program: Pubkey,
+}
+
+impl TokenMint {
+    /// A mint owned by the SPL Token program
+    pub fn spl(mint: Pubkey) -> Self {
+        Self {
+            mint,
+            token_program: anchor_spl::token::ID,
+        }
+    }
+
+    /// A mint owned by the Token-2022 program
+    pub fn token_2022(mint: Pubkey) -> Self {
+        Self {
+            mint,
+            token_program: anchor_spl::token_2022::ID,
+        }
+    }
+
+    /// `owner`'s associated token account for this mint
+    pub fn ata(&self, owner: &Pubkey) -> Pubkey {
+        get_associated_token_address_with_program_id(owner, &self.mint, &self.token_program)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("account {0} not found")]
+    AccountNotFound(Pubkey),
+    #[error("account {0} is not owned by the expected program")]
+    InvalidOwner(Pubkey),
+    #[error("failed to decode account: {0}")]
+    Decode(#[from] anchor_lang::error::Error),
+    #[error("view returned no data from the program")]
+    MissingReturnData,
+    #[error("failed to decode return data: {0}")]
+    ReturnData(#[from] std::io::Error),
+    #[cfg(feature = "rpc")]
+    #[error("simulation failed: {error}")]
+    Simulation {
+        error: solana_sdk::transaction::TransactionError,
+        logs: Vec<String>,
+    },
+    #[cfg(feature = "rpc")]
+    #[error("transaction {0} could not be decoded")]
+    InvalidTransaction(solana_sdk::signature::Signature),
+    #[cfg(feature = "rpc")]
+    #[error(transparent)]
+    Rpc(Box<solana_rpc_client_api::client_error::Error>),
+}
+
+#[cfg(feature = "rpc")]
+impl From<solana_rpc_client_api::client_error::Error> for ClientError {
+    fn from(error: solana_rpc_client_api::client_error::Error) -> Self {
+        Self::Rpc(Box::new(error))
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
@@ -0,0 +1,90 @@
+//! Program-derived addresses, using the same seeds as the program.
+
+use anchor_lang::prelude::Pubkey;
+
+use crate::{TokenMint, ID};
+
+fn find(seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, &ID).0
+}
+
+pub fn program_state() -> Pubkey {
+    find(&[b"program_state"])
+}
+
+/// The program's pool for `mint`, its associated token account
+pub fn rewards_pool(mint: &TokenMint) -> Pubkey {
+    mint.ata(&program_state())
+}
+
+pub fn referrer(referral_code: &str) -> Pubkey {
+    find(&[b"referrer", referral_code.as_bytes()])
+}
+
+pub fn buyer(buyer: &Pubkey) -> Pubkey {
+    find(&[b"buyer", buyer.as_ref()])
+}
+
+/// A purchase made by `buyer` at cluster time `timestamp`
+pub fn purchase(buyer: &Pubkey, timestamp: i64) -> Pubkey {
+    find(&[b"purchase", buyer.as_ref(), &timestamp.to_le_bytes()])
+}
+
+/// `referrer_account` is the referrer's PDA, not their wallet
+pub fn referee_record(referrer_account: &Pubkey, buyer: &Pubkey) -> Pubkey {
+    find(&[b"referee", referrer_account.as_ref(), buyer.as_ref()])
+}
+
+/// Shard `index` of the global stats; a buyer's is at
+/// [`stats_shard_index`](otto_referral_rewards::stats_shard_index)
+pub fn stats_shard(index: u8) -> Pubkey {
+    find(&[b"stats_shard", &[index]])
+}
+
+pub fn stake(owner: &Pubkey) -> Pubkey {
+    find(&[b"stake", owner.as_ref()])
+}
+
+/// The referrer account `authority` registered
+pub fn referrer_lookup(authority: &Pubkey) -> Pubkey {
+    find(&[b"referrer_lookup", authority.as_ref()])
+}
+
+/// The stake account's vault, its associated token account for $OTTO
+pub fn stake_vault(owner: &Pubkey, otto: &TokenMint) -> Pubkey {
+    otto.ata(&stake(owner))
+}
+
+pub fn user_settings(owner: &Pubkey) -> Pubkey {
+    find(&[b"user_settings", owner.as_ref()])
+}
+
+pub fn price_oracle(payout_mint: &Pubkey) -> Pubkey {
+    find(&[b"price_oracle", payout_mint.as_ref()])
+}
+
+pub fn campaign(campaign_id: u64) -> Pubkey {
+    find(&[b"campaign", &campaign_id.to_le_bytes()])
+}
+
+pub fn leaderboard(leaderboard_id: u64) -> Pubkey {
+    find(&[b"leaderboard", &leaderboard_id.to_le_bytes()])
+}
+
+pub fn leaderboard_volume(leaderboard: &Pubkey, referrer_account: &Pubkey) -> Pubkey {
+    find(&[b"leaderboard_volume", leaderboard.as_ref(), referrer_account.as_ref()])
+}
+
+/// `referrer_account` is the referrer's PDA, not their wallet
+pub fn held_payout(referrer_account: &Pubkey, payout_mint: &Pubkey) -> Pubkey {
+    find(&[b"held_payout", referrer_account.as_ref(), payout_mint.as_ref()])
+}
+
+pub fn merkle_distribution(distribution_id: u64) -> Pubkey {
+    find(&[b"merkle_distribution", &distribution_id.to_le_bytes()])
+}
+
+/// Signs the program's self-invocations that carry its events
+pub fn event_authority() -> Pubkey {
+    find(&[b"__event_authority"])
+}
@@ -0,0 +1,117 @@
+//! Read-only view instructions, which return their result as return data
+//! and are run through transaction simulation.
+
+use anchor_lang::{prelude::Pubkey, AnchorDeserialize};
+
+pub use otto_referral_rewards::{ClaimQuote, ReferralQuote, ReferrerSummary};
+
+use crate::{ClientError, Result, ID};
+
+/// Largest return data a program can set
+const MAX_RETURN_DATA: usize = 1024;
+
+/// Decode a view's result from the return data `program_id` set
+pub fn decode_return_data<T: AnchorDeserialize>(program_id: &Pubkey, data: &[u8]) -> Result<T> {
+    if *program_id != ID {
+        return Err(ClientError::MissingReturnData);
+    }
+    // The runtime drops trailing zero bytes from return data, so put them back
+    let mut padded = data.to_vec();
+    padded.resize(MAX_RETURN_DATA.max(data.len()), 0);
+    Ok(T::deserialize(&mut padded.as_slice())?)
+}
+
+#[cfg(feature = "rpc")]
+pub use rpc::*;
+
+#[cfg(feature = "rpc")]
+mod rpc {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use solana_rpc_client::rpc_client::RpcClient;
+    use solana_rpc_client_api::config::RpcSimulateTransactionConfig;
+    use solana_sdk::{message::Message, transaction::Transaction};
+
+    use super::*;
+    use crate::{
+        accounts::{fetch, fetch_program_state, fetch_referrer, fetch_token_mint, PurchaseAccount},
+        instructions::{self, PurchaseArgs},
+        Instruction,
+    };
+
+    /// Simulate a view instruction with `payer` as the fee payer and decode
+    /// its result. Nothing is signed or sent.
+    pub fn simulate_view<T: AnchorDeserialize>(
+        client: &RpcClient,
+        payer: &Pubkey,
+        instruction: Instruction,
+    ) -> Result<T> {
+        let transaction = Transaction::new_unsigned(Message::new(&[instruction], Some(payer)));
+        let config = RpcSimulateTransactionConfig {
+            sig_verify: false,
+            replace_recent_blockhash: true,
+            commitment: Some(client.commitment()),
+            ..RpcSimulateTransactionConfig::default()
+        };
+        let result = client
+            .simulate_transaction_with_config(&transaction, config)?
+            .value;
+        if let Some(error) = result.err {
+            return Err(ClientError::Simulation {
+                error,
+                logs: result.logs.unwrap_or_default(),
+            });
+        }
+
+        let return_data = result.return_data.ok_or(ClientError::MissingReturnData)?;
+        let program_id = return_data
+            .program_id
+            .parse()
+            .map_err(|_| ClientError::MissingReturnData)?;
+        let data = STANDARD
+            .decode(return_data.data.0)
+            .map_err(|_| ClientError::MissingReturnData)?;
+        decode_return_data(&program_id, &data)
+    }
+
+    /// Quote the purchase `args` describes, paid for by the buyer
+    pub fn quote_referral_reward(client: &RpcClient, args: &PurchaseArgs) -> Result<ReferralQuote> {
+        simulate_view(
+            client,
+            &args.buyer,
+            instructions::quote_referral_reward(args),
+        )
+    }
+
+    /// Quote claiming `purchase_account`, paid for by its buyer
+    pub fn quote_claim(client: &RpcClient, purchase_account: &Pubkey) -> Result<ClaimQuote> {
+        let purchase: PurchaseAccount = fetch(client, purchase_account)?;
+        let price_oracle = fetch_program_state(client)?
+            .reward_pool(&purchase.payout_mint)
+            .and_then(|pool| pool.oracle);
+        simulate_view(
+            client,
+            &purchase.buyer,
+            instructions::quote_claim(purchase_account, &purchase.payout_mint, price_oracle),
+        )
+    }
+
+    /// Summarise the referrer behind `referral_code`, paid for by `payer`
+    pub fn get_referrer_summary(
+        client: &RpcClient,
+        payer: &Pubkey,
+        referral_code: &str,
+    ) -> Result<ReferrerSummary> {
+        let referrer = fetch_referrer(client, referral_code)?;
+        let otto = fetch_token_mint(client, &fetch_program_state(client)?.otto_token_mint)?;
+        simulate_view(
+            client,
+            payer,
+            instructions::get_referrer_summary(
+                &otto,
+                referral_code,
+                &referrer.authority,
+                &referrer.linked_token_accounts,
+            ),
+        )
+    }
+}
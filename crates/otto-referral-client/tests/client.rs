@@ -0,0 +1,461 @@
+#[path = "../../../programs/otto-referral-rewards/tests/common/mod.rs"]
+mod common;
+
+use anchor_lang::{prelude::Pubkey, solana_program::instruction::Instruction};
+use common::*;
+use ed25519_dalek::Signer as _;
+use otto_referral_client::{
+    events::{
+        parse_inner_instructions, parse_logs, Event, PurchaseProcessed, RewardsClaimed,
+        EVENT_VERSION,
+    },
+    instructions::{self, PurchaseArgs, PurchaseReferrer},
+    pda, program,
+    views::{decode_return_data, ClaimQuote, ReferralQuote, ReferrerSummary},
+    TokenMint, ID,
+};
+use solana_sdk::ed25519_instruction::new_ed25519_instruction;
+
+fn client_otto() -> (Otto, TokenMint) {
+    let mut otto = Otto::uninitialized();
+    let mint = TokenMint::spl(otto.otto_mint);
+    let initialize = instructions::initialize(
+        &otto.authority,
+        &mint,
+        REWARDS_PERCENTAGE,
+        MIN_PURCHASE_AMOUNT,
+        MIN_HOLDINGS_FOR_REFERRAL,
+        BASE_REWARD_RATE,
+    );
+    let init_stats_shards = (0..program::STATS_SHARDS)
+        .map(|index| instructions::init_stats_shard(&otto.authority, index));
+    otto.context
+        .process(
+            &[initialize]
+                .into_iter()
+                .chain(init_stats_shards)
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+    let (mint_authority, rewards_pool) = (otto.mint_authority, otto.rewards_pool);
+    otto.context.mint_to(
+        &mint.mint,
+        &mint_authority,
+        &rewards_pool,
+        REWARDS_POOL_FUNDING,
+    );
+    (otto, mint)
+}
+
+fn purchase_args(otto: &Otto, mint: TokenMint, buyer: Pubkey) -> PurchaseArgs {
+    PurchaseArgs {
+        buyer,
+        otto: mint,
+        payout: mint,
+        purchase_amount: 100_000,
+        timestamp: otto.context.now(),
+        referrer: None,
+        price_oracle: None,
+        campaign_id: None,
+        leaderboard_id: None,
+    }
+}
+
+#[test]
+fn pdas_match_the_program_seeds() {
+    let buyer = Pubkey::new_unique();
+    assert_eq!(pda::program_state(), program_state_address());
+    assert_eq!(pda::referrer("OTTO1"), referrer_address("OTTO1"));
+    assert_eq!(pda::buyer(&buyer), buyer_address(&buyer));
+    assert_eq!(
+        pda::purchase(&buyer, GENESIS_TIMESTAMP),
+        purchase_address(&buyer, GENESIS_TIMESTAMP)
+    );
+    assert_eq!(
+        pda::referee_record(&pda::referrer("OTTO1"), &buyer),
+        referee_record_address(&referrer_address("OTTO1"), &buyer)
+    );
+    assert_eq!(pda::event_authority(), event_authority_address());
+    assert_eq!(
+        pda::stats_shard(program::stats_shard_index(&buyer)),
+        buyer_stats_shard(&buyer)
+    );
+}
+
+#[test]
+fn builders_drive_a_referred_purchase_and_claim() {
+    let (mut otto, mint) = client_otto();
+    let referrer = otto.holder(2 * MIN_HOLDINGS_FOR_REFERRAL);
+    let register = instructions::register_referrer(&referrer, &mint, "OTTO1", &[]);
+    otto.context.process(&[register]).unwrap();
+
+    let buyer = otto.context.new_wallet();
+    let (purchase, purchase_account) = instructions::process_purchase(&PurchaseArgs {
+        referrer: Some(PurchaseReferrer {
+            referral_code: "OTTO1".to_string(),
+            authority: referrer,
+            linked_token_accounts: vec![],
+            payout_wallet: None,
+            hold_payout: false,
+        }),
+        ..purchase_args(&otto, mint, buyer)
+    });
+    otto.context.process(&[purchase]).unwrap();
+    assert_eq!(
+        otto.context.token_balance(&mint.ata(&referrer)),
+        2 * MIN_HOLDINGS_FOR_REFERRAL + 5_500
+    );
+
+    // The buyer has no ATA yet; the claim creates it
+    let claim = instructions::claim_rewards(&buyer, &purchase_account, &mint, None, None);
+    otto.context.process(&[claim]).unwrap();
+    assert_eq!(otto.context.token_balance(&mint.ata(&buyer)), 2_000);
+
+    otto.context
+        .process(&[instructions::aggregate_stats()])
+        .unwrap();
+    let state: program::ProgramState = otto.context.account(&otto.program_state);
+    assert_eq!(state.total_purchases, 1);
+    assert_eq!(state.total_referrals, 1);
+    assert_eq!(state.total_rewards_distributed, 5_500 + 2_000);
+}
+
+#[test]
+fn referral_rewards_follow_the_payout_wallet() {
+    let (mut otto, mint) = client_otto();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    let cold_wallet = otto.holder(0);
+    otto.context
+        .process(&[
+            instructions::register_referrer(&referrer, &mint, "OTTO1", &[]),
+            instructions::set_payout_address(&referrer, Some(cold_wallet)),
+        ])
+        .unwrap();
+
+    let buyer = otto.context.new_wallet();
+    let (purchase, _) = instructions::process_purchase(&PurchaseArgs {
+        referrer: Some(PurchaseReferrer {
+            referral_code: "OTTO1".to_string(),
+            authority: referrer,
+            linked_token_accounts: vec![],
+            payout_wallet: Some(cold_wallet),
+            hold_payout: false,
+        }),
+        ..purchase_args(&otto, mint, buyer)
+    });
+    otto.context.process(&[purchase]).unwrap();
+
+    assert_eq!(
+        otto.context.token_balance(&mint.ata(&referrer)),
+        MIN_HOLDINGS_FOR_REFERRAL
+    );
+    assert_eq!(otto.context.token_balance(&mint.ata(&cold_wallet)), 5_000);
+}
+
+#[test]
+fn held_payouts_are_claimed_by_the_referrer() {
+    let (mut otto, mint) = client_otto();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    // A payout wallet that has no token account yet
+    let cold_wallet = otto.context.new_wallet();
+    otto.context
+        .process(&[
+            instructions::register_referrer(&referrer, &mint, "OTTO1", &[]),
+            instructions::set_payout_address(&referrer, Some(cold_wallet)),
+        ])
+        .unwrap();
+
+    let buyer = otto.context.new_wallet();
+    let (purchase, _) = instructions::process_purchase(&PurchaseArgs {
+        referrer: Some(PurchaseReferrer {
+            referral_code: "OTTO1".to_string(),
+            authority: referrer,
+            linked_token_accounts: vec![],
+            payout_wallet: Some(cold_wallet),
+            hold_payout: true,
+        }),
+        ..purchase_args(&otto, mint, buyer)
+    });
+    otto.context.process(&[purchase]).unwrap();
+    assert!(otto.context.get_account(&mint.ata(&cold_wallet)).is_none());
+
+    otto.context
+        .process(&[instructions::claim_held_payout(&referrer, "OTTO1", &mint)])
+        .unwrap();
+    assert_eq!(
+        otto.context.token_balance(&mint.ata(&referrer)),
+        MIN_HOLDINGS_FOR_REFERRAL + 5_000
+    );
+}
+
+#[test]
+fn claim_to_destination_skips_the_buyer_ata() {
+    let (mut otto, mint) = client_otto();
+    let buyer = otto.context.new_wallet();
+    let destination = otto.holder(0);
+    let (purchase, purchase_account) =
+        instructions::process_purchase(&purchase_args(&otto, mint, buyer));
+    otto.context.process(&[purchase]).unwrap();
+
+    let claim = instructions::claim_rewards(
+        &buyer,
+        &purchase_account,
+        &mint,
+        Some(mint.ata(&destination)),
+        None,
+    );
+    otto.context.process(&[claim]).unwrap();
+
+    assert_eq!(otto.context.token_balance(&mint.ata(&destination)), 2_000);
+    assert!(otto.context.get_account(&mint.ata(&buyer)).is_none());
+}
+
+fn view<T: anchor_lang::AnchorDeserialize>(otto: &mut Otto, instruction: Instruction) -> T {
+    otto.context.process(&[instruction]).unwrap();
+    let (program_id, data) = otto.context.return_data().expect("view set no return data");
+    decode_return_data(&program_id, &data).unwrap()
+}
+
+#[test]
+fn quotes_match_what_the_purchase_and_claim_pay() {
+    let (mut otto, mint) = client_otto();
+    let referrer = otto.holder(2 * MIN_HOLDINGS_FOR_REFERRAL);
+    let register = instructions::register_referrer(&referrer, &mint, "OTTO1", &[]);
+    otto.context.process(&[register]).unwrap();
+
+    let buyer = otto.context.new_wallet();
+    let args = PurchaseArgs {
+        referrer: Some(PurchaseReferrer {
+            referral_code: "OTTO1".to_string(),
+            authority: referrer,
+            linked_token_accounts: vec![],
+            payout_wallet: None,
+            hold_payout: false,
+        }),
+        ..purchase_args(&otto, mint, buyer)
+    };
+    let referrer_data = |otto: &Otto| {
+        otto.context
+            .get_account(&pda::referrer("OTTO1"))
+            .unwrap()
+            .data
+            .clone()
+    };
+    let referrer_before = referrer_data(&otto);
+    let quote: ReferralQuote = view(&mut otto, instructions::quote_referral_reward(&args));
+    assert_eq!(quote.referrer, Some(referrer));
+    assert_eq!(quote.final_reward, 5_500);
+    assert_eq!(quote.referral_reward, 5_500);
+    assert_eq!(quote.cashback, 2_000);
+    // Quoting writes nothing
+    assert_eq!(referrer_data(&otto), referrer_before);
+
+    let (purchase, purchase_account) = instructions::process_purchase(&args);
+    otto.context.process(&[purchase]).unwrap();
+    assert_eq!(
+        otto.context.token_balance(&mint.ata(&referrer)),
+        2 * MIN_HOLDINGS_FOR_REFERRAL + quote.referral_reward + quote.milestone_bonus
+    );
+
+    let claim_quote: ClaimQuote = view(
+        &mut otto,
+        instructions::quote_claim(&purchase_account, &mint.mint, None),
+    );
+    assert!(claim_quote.claimable);
+    assert_eq!(claim_quote.amount, quote.cashback);
+    let claim = instructions::claim_rewards(&buyer, &purchase_account, &mint, None, None);
+    otto.context.process(&[claim]).unwrap();
+    assert_eq!(
+        otto.context.token_balance(&mint.ata(&buyer)),
+        claim_quote.amount
+    );
+
+    let claim_quote: ClaimQuote = view(
+        &mut otto,
+        instructions::quote_claim(&purchase_account, &mint.mint, None),
+    );
+    assert!(!claim_quote.claimable);
+}
+
+#[test]
+fn quote_reads_a_returning_buyers_history() {
+    let (mut otto, mint) = client_otto();
+    otto.update_referee_bonus(Some(100), Some(true)).unwrap();
+    let referrer = otto.holder(2 * MIN_HOLDINGS_FOR_REFERRAL);
+    let register = instructions::register_referrer(&referrer, &mint, "OTTO1", &[]);
+    otto.context.process(&[register]).unwrap();
+
+    let buyer = otto.context.new_wallet();
+    let args = PurchaseArgs {
+        referrer: Some(PurchaseReferrer {
+            referral_code: "OTTO1".to_string(),
+            authority: referrer,
+            linked_token_accounts: vec![],
+            payout_wallet: None,
+            hold_payout: false,
+        }),
+        ..purchase_args(&otto, mint, buyer)
+    };
+    let quote: ReferralQuote = view(&mut otto, instructions::quote_referral_reward(&args));
+    assert_eq!(quote.referee_bonus, 1_000);
+
+    let (purchase, _) = instructions::process_purchase(&args);
+    otto.context.process(&[purchase]).unwrap();
+
+    // The first-purchase bonus is spent, and the buyer's accounts are always
+    // passed so a quote can't omit them to be offered it again
+    let quote: ReferralQuote = view(&mut otto, instructions::quote_referral_reward(&args));
+    assert_eq!(quote.referee_bonus, 0);
+    assert_eq!(quote.cashback, 2_000);
+}
+
+#[test]
+fn referrer_summary_reads_live_holdings() {
+    let (mut otto, mint) = client_otto();
+    let referrer = otto.holder(2 * MIN_HOLDINGS_FOR_REFERRAL);
+    let register = instructions::register_referrer(&referrer, &mint, "OTTO1", &[]);
+    otto.context.process(&[register]).unwrap();
+    let buyer = otto.context.new_wallet();
+    let (purchase, _) = instructions::process_purchase(&PurchaseArgs {
+        referrer: Some(PurchaseReferrer {
+            referral_code: "OTTO1".to_string(),
+            authority: referrer,
+            linked_token_accounts: vec![],
+            payout_wallet: None,
+            hold_payout: false,
+        }),
+        ..purchase_args(&otto, mint, buyer)
+    });
+    otto.context.process(&[purchase]).unwrap();
+
+    let summary: ReferrerSummary = view(
+        &mut otto,
+        instructions::get_referrer_summary(&mint, "OTTO1", &referrer, &[]),
+    );
+    assert_eq!(summary.authority, referrer);
+    assert_eq!(summary.referral_code, "OTTO1");
+    assert_eq!(summary.total_referrals, 1);
+    assert_eq!(summary.unique_referees, 1);
+    assert_eq!(summary.total_referred_volume, 100_000);
+    assert_eq!(summary.holdings, 2 * MIN_HOLDINGS_FOR_REFERRAL + 5_500);
+}
+
+#[test]
+fn claim_signature_matches_the_sdk_encoding() {
+    let buyer = solana_sdk::signer::keypair::Keypair::new();
+    let keypair = ed25519_dalek::Keypair::from_bytes(&buyer.to_bytes()).unwrap();
+    let purchase_account = Pubkey::new_unique();
+    let message = program::claim_message(&purchase_account);
+    let signature = keypair.sign(&message).to_bytes();
+
+    let expected = new_ed25519_instruction(&keypair, &message);
+    let instruction = instructions::claim_signature(
+        &Pubkey::new_from_array(keypair.public.to_bytes()),
+        &purchase_account,
+        &signature,
+    );
+    assert_eq!(instruction.program_id, expected.program_id);
+    assert_eq!(instruction.data, expected.data);
+}
+
+fn purchase_and_claim_events(buyer: Pubkey) -> (PurchaseProcessed, RewardsClaimed) {
+    let purchase_account = Pubkey::new_unique();
+    let payout_mint = Pubkey::new_unique();
+    let purchase = PurchaseProcessed {
+        version: EVENT_VERSION,
+        purchase: purchase_account,
+        buyer,
+        amount: 100_000,
+        referral_code: Some("OTTO1".to_string()),
+        referrer_account: Some(referrer_address("OTTO1")),
+        payout_mint,
+        campaign: None,
+        referee_bonus: 0,
+        referral_reward: 5_000,
+        buyer_total_purchases: 1,
+        slot: 7,
+        timestamp: GENESIS_TIMESTAMP,
+    };
+    let claim = RewardsClaimed {
+        version: EVENT_VERSION,
+        purchase: purchase_account,
+        buyer,
+        destination: Pubkey::new_unique(),
+        payout_mint,
+        amount: 2_000,
+        transfer_fee: 0,
+        pool_balance: 93_000,
+        slot: 8,
+        timestamp: GENESIS_TIMESTAMP,
+    };
+    (purchase, claim)
+}
+
+/// Data of the self-invocation `emit_cpi!` makes for `event`
+fn cpi_data(event: &impl anchor_lang::Event) -> Vec<u8> {
+    [anchor_lang::event::EVENT_IX_TAG_LE.as_ref(), &event.data()].concat()
+}
+
+fn data_log(event: &impl anchor_lang::Event) -> String {
+    use base64::Engine;
+    format!(
+        "Program data: {}",
+        base64::engine::general_purpose::STANDARD.encode(event.data())
+    )
+}
+
+#[test]
+fn parse_logs_only_decodes_this_programs_events() {
+    let buyer = Pubkey::new_unique();
+    let (purchase, claim) = purchase_and_claim_events(buyer);
+    let other_program = Pubkey::new_unique();
+    let logs = vec![
+        format!("Program {other_program} invoke [1]"),
+        data_log(&claim),
+        format!("Program {other_program} success"),
+        format!("Program {ID} invoke [1]"),
+        "Program log: Instruction: ProcessPurchase".to_string(),
+        format!("Program {other_program} invoke [2]"),
+        data_log(&claim),
+        format!("Program {other_program} success"),
+        data_log(&purchase),
+        "Program data: bm90IGFuIGV2ZW50".to_string(),
+        format!("Program {ID} consumed 42000 of 200000 compute units"),
+        format!("Program {ID} success"),
+    ];
+
+    let events = parse_logs(&logs);
+    assert_eq!(events.len(), 1);
+    let Event::PurchaseProcessed(event) = &events[0] else {
+        panic!("expected PurchaseProcessed, got {}", events[0].name());
+    };
+    assert_eq!(event.buyer, buyer);
+    assert_eq!(event.amount, 100_000);
+}
+
+#[test]
+fn parse_inner_instructions_skips_other_programs_and_versions() {
+    let buyer = Pubkey::new_unique();
+    let (purchase, claim) = purchase_and_claim_events(buyer);
+    let mut future_claim = cpi_data(&claim);
+    future_claim[16] = EVENT_VERSION + 1; // Tag, discriminator, then the version
+    let instructions = vec![
+        (Pubkey::new_unique(), cpi_data(&purchase)),
+        (ID, cpi_data(&purchase)),
+        (ID, anchor_lang::Event::data(&claim)), // Not a self-invocation
+        (ID, future_claim),
+        (ID, cpi_data(&claim)),
+    ];
+
+    let events = parse_inner_instructions(&instructions);
+    assert_eq!(events.len(), 2);
+    let Event::PurchaseProcessed(event) = &events[0] else {
+        panic!("expected PurchaseProcessed, got {}", events[0].name());
+    };
+    assert_eq!(event.purchase, purchase.purchase);
+    assert_eq!(event.referral_code.as_deref(), Some("OTTO1"));
+    let Event::RewardsClaimed(event) = &events[1] else {
+        panic!("expected RewardsClaimed, got {}", events[1].name());
+    };
+    assert_eq!(event.pool_balance, 93_000);
+}
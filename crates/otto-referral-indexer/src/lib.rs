@@ -0,0 +1,42 @@
+//! Indexes the OTTO Referral+ Rewards program's events into SQLite.
+//!
+//! - [`source`] turns confirmed transactions, from a JSON-lines replay file
+//!   or an RPC node, into [`IndexedTransaction`]s
+//! - [`store`] keeps every event and materialises referrers, purchases,
+//!   claims and tier changes from them
+//!
+//! Each transaction is applied atomically together with the cursor, so a
+//! crash never leaves one half indexed, and indexing a transaction twice is
+//! a no-op. Rolling back to a slot drops everything after it and rebuilds
+//! the tables from the events that remain.
+
+pub mod source;
+pub mod store;
+
+pub use source::IndexedTransaction;
+pub use store::{Cursor, Store};
+
+#[derive(Debug, thiserror::Error)]
+pub enum IndexerError {
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("replay line {line}: {error}")]
+    Replay {
+        line: usize,
+        error: serde_json::Error,
+    },
+    #[error("transaction {0} could not be decoded")]
+    InvalidTransaction(String),
+    #[error(transparent)]
+    Rpc(Box<solana_rpc_client_api::client_error::Error>),
+}
+
+impl From<solana_rpc_client_api::client_error::Error> for IndexerError {
+    fn from(error: solana_rpc_client_api::client_error::Error) -> Self {
+        Self::Rpc(Box::new(error))
+    }
+}
+
+pub type Result<T> = std::result::Result<T, IndexerError>;
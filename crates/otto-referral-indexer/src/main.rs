@@ -0,0 +1,72 @@
+//! Index the program's events into a SQLite database.
+//!
+//! ```text
+//! otto-referral-indexer <database> replay <transactions.jsonl>
+//! otto-referral-indexer <database> follow <rpc-url> [poll-seconds]
+//! otto-referral-indexer <database> rollback <slot>
+//! ```
+
+use std::{fs::File, io::BufReader, process::ExitCode, thread, time::Duration};
+
+use otto_referral_indexer::{
+    source::{replay, RpcSource},
+    Store,
+};
+use solana_rpc_client::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+
+const USAGE: &str = "usage:
+  otto-referral-indexer <database> replay <transactions.jsonl>
+  otto-referral-indexer <database> follow <rpc-url> [poll-seconds]
+  otto-referral-indexer <database> rollback <slot>";
+
+const DEFAULT_POLL_SECONDS: u64 = 2;
+
+fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let [database, command, rest @ ..] = args else {
+        return Err(USAGE.into());
+    };
+    let mut store = Store::open(database)?;
+
+    match (command.as_str(), rest) {
+        ("replay", [path]) => {
+            let indexed = replay(&mut store, BufReader::new(File::open(path)?))?;
+            println!("indexed {indexed} transactions");
+        }
+        ("follow", [rpc_url, poll @ ..]) => {
+            let poll_seconds = match poll {
+                [] => DEFAULT_POLL_SECONDS,
+                [seconds] => seconds.parse()?,
+                _ => return Err(USAGE.into()),
+            };
+            let source = RpcSource::new(RpcClient::new_with_commitment(
+                rpc_url.clone(),
+                CommitmentConfig::confirmed(),
+            ));
+            loop {
+                let indexed = source.poll(&mut store)?;
+                if indexed > 0 {
+                    println!("indexed {indexed} transactions");
+                }
+                thread::sleep(Duration::from_secs(poll_seconds));
+            }
+        }
+        ("rollback", [slot]) => {
+            let removed = store.rollback(slot.parse()?)?;
+            println!("rolled back {removed} transactions");
+        }
+        _ => return Err(USAGE.into()),
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Confirmed transactions to index, from a replay file or an RPC node.
+
+use std::io::BufRead;
+
+use otto_referral_client::{
+    events::{transaction_events, Event},
+    ID,
+};
+use solana_rpc_client::rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient};
+use solana_rpc_client_api::config::RpcTransactionConfig;
+use solana_sdk::{commitment_config::CommitmentConfig, signature::Signature};
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding};
+
+use crate::{IndexerError, Result, Store};
+
+/// Most signatures the RPC returns per page, and accepts per status query
+const SIGNATURES_PAGE: usize = 1000;
+const STATUSES_PAGE: usize = 256;
+
+/// A successful transaction and the program events it emitted
+pub struct IndexedTransaction {
+    pub signature: String,
+    pub slot: u64,
+    pub block_time: Option<i64>,
+    pub events: Vec<Event>,
+}
+
+impl IndexedTransaction {
+    /// Decode a `getTransaction` result fetched with a binary encoding.
+    /// `None` for failed transactions, whose events never took effect.
+    pub fn decode(transaction: &EncodedConfirmedTransactionWithStatusMeta) -> Result<Option<Self>> {
+        let inner = &transaction.transaction;
+        let signature = inner
+            .transaction
+            .decode()
+            .and_then(|decoded| decoded.signatures.first().copied())
+            .ok_or_else(|| {
+                IndexerError::InvalidTransaction(format!("in slot {}", transaction.slot))
+            })?
+            .to_string();
+        let invalid = || IndexerError::InvalidTransaction(signature.clone());
+        if inner.meta.as_ref().ok_or_else(invalid)?.err.is_some() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            events: transaction_events(inner).ok_or_else(invalid)?,
+            signature,
+            slot: transaction.slot,
+            block_time: transaction.block_time,
+        }))
+    }
+}
+
+/// Index a replay file: one `getTransaction` result per line, fetched with
+/// the base64 encoding, in chain order. Blank lines are skipped. Returns the
+/// number of transactions newly indexed.
+pub fn replay(store: &mut Store, reader: impl BufRead) -> Result<usize> {
+    let mut indexed = 0;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let transaction = serde_json::from_str(&line).map_err(|error| IndexerError::Replay {
+            line: index + 1,
+            error,
+        })?;
+        if let Some(transaction) = IndexedTransaction::decode(&transaction)? {
+            indexed += store.apply(&transaction)? as usize;
+        }
+    }
+    Ok(indexed)
+}
+
+/// Follows the program's transactions on an RPC node
+pub struct RpcSource {
+    client: RpcClient,
+}
+
+impl RpcSource {
+    /// Transactions are read at the client's commitment; anything not yet
+    /// finalized is rechecked on every poll
+    pub fn new(client: RpcClient) -> Self {
+        Self { client }
+    }
+
+    /// Roll back transactions a fork dropped, then index every program
+    /// transaction after the cursor. Returns the number newly indexed.
+    pub fn poll(&self, store: &mut Store) -> Result<usize> {
+        self.roll_back_dropped(store)?;
+
+        let mut indexed = 0;
+        for signature in self.signatures_after(store.cursor()?.map(|cursor| cursor.signature))? {
+            let config = RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(self.client.commitment()),
+                max_supported_transaction_version: Some(0),
+            };
+            let transaction = self
+                .client
+                .get_transaction_with_config(&signature, config)?;
+            if let Some(transaction) = IndexedTransaction::decode(&transaction)? {
+                indexed += store.apply(&transaction)? as usize;
+            }
+        }
+        Ok(indexed)
+    }
+
+    /// Successful program transactions after `until`, oldest first
+    fn signatures_after(&self, until: Option<String>) -> Result<Vec<Signature>> {
+        let until = until.as_deref().map(parse_signature).transpose()?;
+
+        // Pages come newest first
+        let mut signatures = Vec::new();
+        let mut before = None;
+        loop {
+            let page = self.client.get_signatures_for_address_with_config(
+                &ID,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    until,
+                    limit: Some(SIGNATURES_PAGE),
+                    commitment: Some(self.client.commitment()),
+                },
+            )?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            before = Some(parse_signature(&last.signature)?);
+            for status in page.iter().filter(|status| status.err.is_none()) {
+                signatures.push(parse_signature(&status.signature)?);
+            }
+        }
+        signatures.reverse();
+        Ok(signatures)
+    }
+
+    /// Transactions indexed after the last finalized slot can still be
+    /// dropped by a fork. Roll back to just before the first one that was.
+    fn roll_back_dropped(&self, store: &mut Store) -> Result<()> {
+        let finalized = self
+            .client
+            .get_slot_with_commitment(CommitmentConfig::finalized())?;
+        let pending = store.transactions_after(finalized)?;
+
+        for chunk in pending.chunks(STATUSES_PAGE) {
+            let signatures = chunk
+                .iter()
+                .map(|cursor| parse_signature(&cursor.signature))
+                .collect::<Result<Vec<_>>>()?;
+            let statuses = self.client.get_signature_statuses(&signatures)?.value;
+            if let Some((dropped, _)) = chunk
+                .iter()
+                .zip(statuses)
+                .find(|(_, status)| status.is_none())
+            {
+                store.rollback(dropped.slot.saturating_sub(1))?;
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_signature(signature: &str) -> Result<Signature> {
+    signature
+        .parse()
+        .map_err(|_| IndexerError::InvalidTransaction(signature.to_string()))
+}
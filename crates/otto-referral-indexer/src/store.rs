@@ -0,0 +1,387 @@
+//! SQLite store: the raw event log, and the tables materialised from it.
+
+use std::path::Path;
+
+use otto_referral_client::events::Event;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{IndexedTransaction, Result};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS transactions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT, -- Order indexed in
+    signature TEXT NOT NULL UNIQUE,
+    slot INTEGER NOT NULL,
+    block_time INTEGER
+);
+CREATE INDEX IF NOT EXISTS transactions_slot ON transactions (slot);
+
+CREATE TABLE IF NOT EXISTS events (
+    transaction_id INTEGER NOT NULL REFERENCES transactions (id),
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    data BLOB NOT NULL, -- Discriminator-prefixed Borsh, as emitted
+    PRIMARY KEY (transaction_id, position)
+);
+
+CREATE TABLE IF NOT EXISTS referrers (
+    referrer_account TEXT PRIMARY KEY,
+    authority TEXT NOT NULL,
+    referral_code TEXT NOT NULL UNIQUE,
+    initial_holdings INTEGER NOT NULL,
+    current_holdings INTEGER NOT NULL,
+    twab INTEGER NOT NULL,
+    holding_ratio INTEGER NOT NULL, -- Basis points
+    tier INTEGER NOT NULL, -- 0 = None, 1 = Bronze, 2 = Silver, 3 = Gold
+    total_referrals INTEGER NOT NULL DEFAULT 0,
+    unique_referees INTEGER NOT NULL DEFAULT 0,
+    total_referred_volume INTEGER NOT NULL DEFAULT 0,
+    total_rewards_earned INTEGER NOT NULL DEFAULT 0,
+    clawback_owed INTEGER NOT NULL DEFAULT 0,
+    registered_slot INTEGER NOT NULL,
+    updated_slot INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS purchases (
+    purchase TEXT PRIMARY KEY,
+    buyer TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    referral_code TEXT,
+    referrer_account TEXT,
+    payout_mint TEXT NOT NULL,
+    campaign TEXT,
+    referee_bonus INTEGER NOT NULL,
+    referral_reward INTEGER NOT NULL, -- Received by the referrer
+    reversed INTEGER NOT NULL DEFAULT 0,
+    slot INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS claims (
+    purchase TEXT PRIMARY KEY,
+    buyer TEXT NOT NULL,
+    destination TEXT NOT NULL,
+    payout_mint TEXT NOT NULL,
+    amount INTEGER NOT NULL, -- Received by the destination
+    transfer_fee INTEGER NOT NULL,
+    pool_balance INTEGER NOT NULL, -- After the claim
+    slot INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS tier_changes (
+    transaction_id INTEGER NOT NULL,
+    referrer_account TEXT NOT NULL,
+    old_tier INTEGER NOT NULL,
+    new_tier INTEGER NOT NULL,
+    balance INTEGER NOT NULL,
+    slot INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+";
+
+/// The tables derived from events, emptied and refilled on rollback
+const MATERIALISED_TABLES: [&str; 4] = ["referrers", "purchases", "claims", "tier_changes"];
+
+/// An indexed transaction; the last one indexed is where polling resumes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub signature: String,
+    pub slot: u64,
+}
+
+pub struct Store {
+    connection: Connection,
+}
+
+impl Store {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self { connection })
+    }
+
+    /// For querying the materialised tables
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// The latest indexed transaction by slot
+    pub fn cursor(&self) -> Result<Option<Cursor>> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT signature, slot FROM transactions ORDER BY slot DESC, id DESC LIMIT 1",
+                [],
+                |row| {
+                    Ok(Cursor {
+                        signature: row.get(0)?,
+                        slot: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    /// Indexed transactions in slots after `slot`, oldest first
+    pub fn transactions_after(&self, slot: u64) -> Result<Vec<Cursor>> {
+        let mut statement = self.connection.prepare(
+            "SELECT signature, slot FROM transactions WHERE slot > ?1 ORDER BY slot, id",
+        )?;
+        let rows = statement.query_map([slot], |row| {
+            Ok(Cursor {
+                signature: row.get(0)?,
+                slot: row.get(1)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Index `transaction` and its events in one database transaction.
+    /// Returns false, changing nothing, if it was already indexed.
+    pub fn apply(&mut self, transaction: &IndexedTransaction) -> Result<bool> {
+        let db = self.connection.transaction()?;
+        let inserted = db.execute(
+            "INSERT OR IGNORE INTO transactions (signature, slot, block_time) VALUES (?1, ?2, ?3)",
+            params![
+                transaction.signature,
+                transaction.slot,
+                transaction.block_time
+            ],
+        )?;
+        if inserted == 0 {
+            return Ok(false);
+        }
+
+        let transaction_id = db.last_insert_rowid();
+        for (position, event) in transaction.events.iter().enumerate() {
+            db.execute(
+                "INSERT INTO events (transaction_id, position, name, data) VALUES (?1, ?2, ?3, ?4)",
+                params![transaction_id, position, event.name(), event.data()],
+            )?;
+            materialise(&db, transaction_id, transaction.slot, event)?;
+        }
+        db.commit()?;
+        Ok(true)
+    }
+
+    /// Forget every transaction in a slot after `slot`, e.g. when a fork
+    /// drops them, and rebuild the materialised tables from the events left.
+    /// Returns the number of transactions forgotten.
+    pub fn rollback(&mut self, slot: u64) -> Result<usize> {
+        let db = self.connection.transaction()?;
+        db.execute(
+            "DELETE FROM events WHERE transaction_id IN (SELECT id FROM transactions WHERE slot > ?1)",
+            [slot],
+        )?;
+        let removed = db.execute("DELETE FROM transactions WHERE slot > ?1", [slot])?;
+
+        for table in MATERIALISED_TABLES {
+            db.execute(&format!("DELETE FROM {table}"), [])?;
+        }
+        {
+            let mut statement = db.prepare(
+                "SELECT events.transaction_id, transactions.slot, events.data
+                 FROM events JOIN transactions ON transactions.id = events.transaction_id
+                 ORDER BY events.transaction_id, events.position",
+            )?;
+            let mut rows = statement.query([])?;
+            while let Some(row) = rows.next()? {
+                let data: Vec<u8> = row.get(2)?;
+                // Stored events were decoded once, so they decode again
+                if let Some(event) = Event::decode(&data) {
+                    materialise(&db, row.get(0)?, row.get(1)?, &event)?;
+                }
+            }
+        }
+        db.commit()?;
+        Ok(removed)
+    }
+}
+
+/// Fold one event into the materialised tables. Events that change nothing
+/// tracked here are only kept in the event log.
+fn materialise(db: &Connection, transaction_id: i64, slot: u64, event: &Event) -> Result<()> {
+    match event {
+        Event::ReferrerRegistered(event) => {
+            db.execute(
+                "INSERT INTO referrers (referrer_account, authority, referral_code, initial_holdings,
+                     current_holdings, twab, holding_ratio, tier, registered_slot, updated_slot)
+                 VALUES (?1, ?2, ?3, ?4, ?4, ?4, 10000, 0, ?5, ?5)",
+                params![
+                    event.referrer_account.to_string(),
+                    event.authority.to_string(),
+                    event.referral_code,
+                    event.initial_holdings,
+                    slot,
+                ],
+            )?;
+        }
+        Event::HoldingsRatioUpdated(event) => {
+            db.execute(
+                "UPDATE referrers SET current_holdings = ?2, twab = ?3, holding_ratio = ?4, updated_slot = ?5
+                 WHERE referrer_account = ?1",
+                params![
+                    event.referrer_account.to_string(),
+                    event.new_holdings,
+                    event.twab,
+                    event.holding_ratio,
+                    slot,
+                ],
+            )?;
+        }
+        Event::ReferrerRefreshed(event) => {
+            db.execute(
+                "UPDATE referrers SET current_holdings = ?2, twab = ?3, holding_ratio = ?4, tier = ?5,
+                     updated_slot = ?6
+                 WHERE referrer_account = ?1",
+                params![
+                    event.referrer_account.to_string(),
+                    event.new_holdings,
+                    event.twab,
+                    event.holding_ratio,
+                    event.new_tier as u8,
+                    slot,
+                ],
+            )?;
+            if event.old_tier != event.new_tier {
+                record_tier_change(
+                    db,
+                    transaction_id,
+                    slot,
+                    &event.referrer_account.to_string(),
+                    (event.old_tier as u8, event.new_tier as u8),
+                    event.new_holdings,
+                    event.timestamp,
+                )?;
+            }
+        }
+        Event::TierUpdated(event) => {
+            db.execute(
+                "UPDATE referrers SET tier = ?2, updated_slot = ?3 WHERE referrer_account = ?1",
+                params![
+                    event.referrer_account.to_string(),
+                    event.new_tier as u8,
+                    slot
+                ],
+            )?;
+            if event.old_tier != event.new_tier {
+                record_tier_change(
+                    db,
+                    transaction_id,
+                    slot,
+                    &event.referrer_account.to_string(),
+                    (event.old_tier as u8, event.new_tier as u8),
+                    event.balance,
+                    event.timestamp,
+                )?;
+            }
+        }
+        Event::DynamicReferralProcessed(event) => {
+            db.execute(
+                "UPDATE referrers SET current_holdings = ?2, twab = ?3, holding_ratio = ?4,
+                     total_referrals = ?5, unique_referees = ?6, total_referred_volume = ?7,
+                     total_rewards_earned = ?8, clawback_owed = ?9, updated_slot = ?10
+                 WHERE referrer_account = ?1",
+                params![
+                    event.referrer_account.to_string(),
+                    event.referrer_holdings,
+                    event.twab,
+                    event.holding_ratio,
+                    event.total_referrals,
+                    event.unique_referees,
+                    event.total_referred_volume,
+                    event.total_rewards_earned,
+                    event.clawback_owed,
+                    slot,
+                ],
+            )?;
+        }
+        Event::PurchaseProcessed(event) => {
+            db.execute(
+                "INSERT INTO purchases (purchase, buyer, amount, referral_code, referrer_account,
+                     payout_mint, campaign, referee_bonus, referral_reward, slot, timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    event.purchase.to_string(),
+                    event.buyer.to_string(),
+                    event.amount,
+                    event.referral_code,
+                    event.referrer_account.map(|key| key.to_string()),
+                    event.payout_mint.to_string(),
+                    event.campaign.map(|key| key.to_string()),
+                    event.referee_bonus,
+                    event.referral_reward,
+                    slot,
+                    event.timestamp,
+                ],
+            )?;
+        }
+        Event::RewardsClaimed(event) => {
+            db.execute(
+                "INSERT INTO claims (purchase, buyer, destination, payout_mint, amount, transfer_fee,
+                     pool_balance, slot, timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    event.purchase.to_string(),
+                    event.buyer.to_string(),
+                    event.destination.to_string(),
+                    event.payout_mint.to_string(),
+                    event.amount,
+                    event.transfer_fee,
+                    event.pool_balance,
+                    slot,
+                    event.timestamp,
+                ],
+            )?;
+        }
+        Event::PurchaseReversed(event) => {
+            db.execute(
+                "UPDATE purchases SET reversed = 1 WHERE purchase = ?1",
+                [event.purchase.to_string()],
+            )?;
+            if let Some(referrer_account) = event.referrer {
+                db.execute(
+                    "UPDATE referrers SET clawback_owed = ?2, total_referrals = ?3, unique_referees = ?4,
+                         total_referred_volume = ?5, total_rewards_earned = ?6, updated_slot = ?7
+                     WHERE referrer_account = ?1",
+                    params![
+                        referrer_account.to_string(),
+                        event.clawback_owed,
+                        event.total_referrals,
+                        event.unique_referees,
+                        event.total_referred_volume,
+                        event.total_rewards_earned,
+                        slot,
+                    ],
+                )?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn record_tier_change(
+    db: &Connection,
+    transaction_id: i64,
+    slot: u64,
+    referrer_account: &str,
+    (old_tier, new_tier): (u8, u8),
+    balance: u64,
+    timestamp: i64,
+) -> Result<()> {
+    db.execute(
+        "INSERT INTO tier_changes (transaction_id, referrer_account, old_tier, new_tier, balance, slot, timestamp)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![transaction_id, referrer_account, old_tier, new_tier, balance, slot, timestamp],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,257 @@
+#[path = "../../../programs/otto-referral-rewards/tests/common/mod.rs"]
+mod common;
+
+use anchor_lang::prelude::Pubkey;
+use common::*;
+use otto_referral_client::{events::Event, ID};
+use otto_referral_indexer::{source::replay, Cursor, IndexedTransaction, Store};
+use otto_referral_rewards::ReferrerAccount;
+use rusqlite::OptionalExtension;
+use solana_sdk::{
+    instruction::CompiledInstruction,
+    message::Message,
+    signature::Signature,
+    transaction::{Transaction, TransactionError},
+};
+use solana_transaction_status::{
+    Encodable, EncodedConfirmedTransactionWithStatusMeta, EncodedTransactionWithStatusMeta,
+    InnerInstruction, InnerInstructions, TransactionStatusMeta, UiTransactionEncoding,
+};
+
+/// The events of the harness's last transaction, as landed in `slot`
+fn landed(otto: &Otto, slot: u64) -> IndexedTransaction {
+    IndexedTransaction {
+        signature: Signature::new_unique().to_string(),
+        slot,
+        block_time: Some(otto.context.now()),
+        events: otto
+            .context
+            .event_data()
+            .iter()
+            .map(|data| Event::decode(data).expect("undecodable event"))
+            .collect(),
+    }
+}
+
+/// Register a referrer, make a referred purchase and claim its cashback,
+/// one transaction per slot from 1
+fn referred_purchase_and_claim() -> (Otto, Pubkey, Pubkey, Vec<IndexedTransaction>) {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(2 * MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let registered = landed(&otto, 1);
+
+    let buyer = otto.context.new_wallet();
+    let purchase_account = otto
+        .purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+    let purchased = landed(&otto, 2);
+
+    otto.claim_rewards(&buyer, &purchase_account).unwrap();
+    let claimed = landed(&otto, 3);
+
+    (
+        otto,
+        buyer,
+        purchase_account,
+        vec![registered, purchased, claimed],
+    )
+}
+
+fn count(store: &Store, table: &str) -> u64 {
+    store
+        .connection()
+        .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+}
+
+/// (total_referrals, unique_referees, total_referred_volume, total_rewards_earned)
+fn referrer_totals(store: &Store) -> Option<(u32, u32, u64, u64)> {
+    store
+        .connection()
+        .query_row(
+            "SELECT total_referrals, unique_referees, total_referred_volume, total_rewards_earned
+             FROM referrers WHERE referral_code = 'OTTO1'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()
+        .unwrap()
+}
+
+#[test]
+fn materialises_referrers_purchases_and_claims() {
+    let (otto, buyer, purchase_account, transactions) = referred_purchase_and_claim();
+    let mut store = Store::open_in_memory().unwrap();
+    for transaction in &transactions {
+        assert!(store.apply(transaction).unwrap());
+    }
+
+    let onchain: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(
+        referrer_totals(&store),
+        Some((
+            onchain.total_referrals,
+            onchain.unique_referees,
+            onchain.total_referred_volume,
+            onchain.total_rewards_earned,
+        ))
+    );
+
+    let (purchase_buyer, referral_code, referral_reward): (String, Option<String>, u64) = store
+        .connection()
+        .query_row(
+            "SELECT buyer, referral_code, referral_reward FROM purchases WHERE purchase = ?1",
+            [purchase_account.to_string()],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(purchase_buyer, buyer.to_string());
+    assert_eq!(referral_code.as_deref(), Some("OTTO1"));
+    assert_eq!(referral_reward, 5_500);
+
+    let (amount, pool_balance, slot): (u64, u64, u64) = store
+        .connection()
+        .query_row(
+            "SELECT amount, pool_balance, slot FROM claims WHERE purchase = ?1",
+            [purchase_account.to_string()],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(amount, 2_000);
+    assert_eq!(pool_balance, otto.context.token_balance(&otto.rewards_pool));
+    assert_eq!(slot, 3);
+
+    assert_eq!(
+        store.cursor().unwrap(),
+        Some(Cursor {
+            signature: transactions[2].signature.clone(),
+            slot: 3,
+        })
+    );
+}
+
+#[test]
+fn applying_a_transaction_twice_is_a_no_op() {
+    let (_, _, _, transactions) = referred_purchase_and_claim();
+    let mut store = Store::open_in_memory().unwrap();
+    for transaction in &transactions {
+        store.apply(transaction).unwrap();
+    }
+    let events = count(&store, "events");
+
+    for transaction in &transactions {
+        assert!(!store.apply(transaction).unwrap());
+    }
+    assert_eq!(count(&store, "events"), events);
+    assert_eq!(count(&store, "purchases"), 1);
+    assert_eq!(referrer_totals(&store), Some((1, 1, 100_000, 5_500)));
+}
+
+#[test]
+fn rollback_rebuilds_tables_from_the_remaining_events() {
+    let (_, _, _, transactions) = referred_purchase_and_claim();
+    let mut store = Store::open_in_memory().unwrap();
+    for transaction in &transactions {
+        store.apply(transaction).unwrap();
+    }
+
+    assert_eq!(store.rollback(2).unwrap(), 1);
+    assert_eq!(count(&store, "claims"), 0);
+    assert_eq!(count(&store, "purchases"), 1);
+    assert_eq!(store.cursor().unwrap().unwrap().slot, 2);
+
+    assert_eq!(store.rollback(1).unwrap(), 1);
+    assert_eq!(count(&store, "purchases"), 0);
+    assert_eq!(referrer_totals(&store), Some((0, 0, 0, 0)));
+
+    // The dropped transactions can land again, e.g. on the surviving fork
+    assert!(store.apply(&transactions[1]).unwrap());
+    assert_eq!(referrer_totals(&store), Some((1, 1, 100_000, 5_500)));
+}
+
+/// A `getTransaction` result, base64 encoded, whose inner instructions
+/// carry `events` as the program's self-invocations
+fn get_transaction_json(
+    slot: u64,
+    events: &[Event],
+    status: Result<(), TransactionError>,
+) -> (Signature, String) {
+    let payer = Pubkey::new_unique();
+    let mut transaction = Transaction::new_unsigned(Message::new_with_compiled_instructions(
+        1,
+        0,
+        1,
+        vec![payer, ID],
+        Default::default(),
+        vec![],
+    ));
+    let signature = Signature::new_unique();
+    transaction.signatures = vec![signature];
+
+    let instructions = events
+        .iter()
+        .map(|event| InnerInstruction {
+            instruction: CompiledInstruction::new_from_raw_parts(
+                1,
+                [anchor_lang::event::EVENT_IX_TAG_LE.as_ref(), &event.data()].concat(),
+                vec![],
+            ),
+            stack_height: Some(2),
+        })
+        .collect();
+    let meta = TransactionStatusMeta {
+        status,
+        inner_instructions: Some(vec![InnerInstructions {
+            index: 0,
+            instructions,
+        }]),
+        ..TransactionStatusMeta::default()
+    };
+    let result = EncodedConfirmedTransactionWithStatusMeta {
+        slot,
+        transaction: EncodedTransactionWithStatusMeta {
+            transaction: transaction.encode(UiTransactionEncoding::Base64),
+            meta: Some(meta.into()),
+            version: None,
+        },
+        block_time: None,
+    };
+    (signature, serde_json::to_string(&result).unwrap())
+}
+
+#[test]
+fn replay_indexes_successful_transactions_from_json_lines() {
+    let (_, _, _, transactions) = referred_purchase_and_claim();
+    let (registered, registered_json) = get_transaction_json(1, &transactions[0].events, Ok(()));
+    let (_, failed_json) = get_transaction_json(
+        2,
+        &transactions[1].events,
+        Err(TransactionError::AccountInUse),
+    );
+    let (purchased, purchased_json) = get_transaction_json(3, &transactions[1].events, Ok(()));
+    let file = [registered_json, failed_json, String::new(), purchased_json].join("\n");
+
+    let mut store = Store::open_in_memory().unwrap();
+    assert_eq!(replay(&mut store, file.as_bytes()).unwrap(), 2);
+    assert_eq!(
+        store.transactions_after(0).unwrap(),
+        vec![
+            Cursor {
+                signature: registered.to_string(),
+                slot: 1,
+            },
+            Cursor {
+                signature: purchased.to_string(),
+                slot: 3,
+            },
+        ]
+    );
+    assert_eq!(count(&store, "purchases"), 1);
+    assert_eq!(referrer_totals(&store), Some((1, 1, 100_000, 5_500)));
+
+    // Replaying the same file again indexes nothing new
+    assert_eq!(replay(&mut store, file.as_bytes()).unwrap(), 0);
+}
@@ -0,0 +1,56 @@
+//! JavaScript bindings for `otto-reward-math`, built with
+//! `wasm-pack build crates/otto-reward-math-wasm`.
+
+use otto_reward_math::DEFAULT_HOLDINGS_MULTIPLIERS;
+use wasm_bindgen::prelude::*;
+
+/// Each step of a referral reward. Amounts are `bigint`s in $OTTO base units.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub struct RewardBreakdown {
+    pub base_reward: u64,
+    pub ratio_penalty: u64,
+    pub holding_adjusted_reward: u64,
+    pub holdings_multiplier: u16, // Basis points
+    pub final_reward: u64,
+}
+
+impl From<otto_reward_math::RewardBreakdown> for RewardBreakdown {
+    fn from(breakdown: otto_reward_math::RewardBreakdown) -> Self {
+        Self {
+            base_reward: breakdown.base_reward,
+            ratio_penalty: breakdown.ratio_penalty,
+            holding_adjusted_reward: breakdown.holding_adjusted_reward,
+            holdings_multiplier: breakdown.holdings_multiplier,
+            final_reward: breakdown.final_reward,
+        }
+    }
+}
+
+/// Simulate the referral reward for a purchase, or `undefined` where the
+/// program would fail it with an overflow. `holdings_multipliers` defaults
+/// to the program's [2x, 5x, 10x] multipliers.
+#[wasm_bindgen(js_name = simulatePurchase)]
+pub fn simulate_purchase(
+    base_rate: u16,
+    holding_ratio: u16,
+    referrer_holdings: u64,
+    purchase_amount: u64,
+    min_holdings: u64,
+    holdings_multipliers: Option<Vec<u16>>,
+) -> Result<Option<RewardBreakdown>, JsError> {
+    let holdings_multipliers = match holdings_multipliers {
+        Some(multipliers) => <[u16; 3]>::try_from(multipliers)
+            .map_err(|_| JsError::new("holdings_multipliers must have 3 entries"))?,
+        None => DEFAULT_HOLDINGS_MULTIPLIERS,
+    };
+    Ok(otto_reward_math::simulate_purchase(
+        base_rate,
+        holding_ratio,
+        referrer_holdings,
+        purchase_amount,
+        min_holdings,
+        holdings_multipliers,
+    )
+    .map(RewardBreakdown::from))
+}
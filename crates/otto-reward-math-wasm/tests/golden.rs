@@ -0,0 +1,57 @@
+//! The golden vectors run through the bindings the bot calls, so a mistake
+//! in the conversion shows up without a WASM build.
+
+use otto_reward_math_wasm::simulate_purchase;
+use serde_json::Value;
+
+fn amount(value: &Value) -> u64 {
+    value.as_str().unwrap().parse().unwrap()
+}
+
+#[test]
+fn bindings_match_golden_vectors() {
+    let vectors: Vec<Value> = serde_json::from_str(include_str!(
+        "../../otto-reward-math/tests/reward_vectors.json"
+    ))
+    .unwrap();
+    assert!(!vectors.is_empty());
+
+    for vector in &vectors {
+        let multipliers: Vec<u16> = vector["holdings_multipliers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|multiplier| multiplier.as_u64().unwrap() as u16)
+            .collect();
+        let breakdown = simulate_purchase(
+            vector["base_rate"].as_u64().unwrap() as u16,
+            vector["holding_ratio"].as_u64().unwrap() as u16,
+            amount(&vector["referrer_holdings"]),
+            amount(&vector["purchase_amount"]),
+            amount(&vector["min_holdings"]),
+            Some(multipliers),
+        )
+        .unwrap();
+
+        let expected = &vector["expected"];
+        let breakdown = breakdown.map(|breakdown| {
+            (
+                breakdown.base_reward,
+                breakdown.ratio_penalty,
+                breakdown.holding_adjusted_reward,
+                breakdown.holdings_multiplier,
+                breakdown.final_reward,
+            )
+        });
+        let expected = (!expected.is_null()).then(|| {
+            (
+                amount(&expected["base_reward"]),
+                amount(&expected["ratio_penalty"]),
+                amount(&expected["holding_adjusted_reward"]),
+                expected["holdings_multiplier"].as_u64().unwrap() as u16,
+                amount(&expected["final_reward"]),
+            )
+        });
+        assert_eq!(breakdown, expected, "{}", vector["name"]);
+    }
+}
@@ -0,0 +1,102 @@
+//! Referral reward math used on-chain by the OTTO Referral+ program.
+//!
+//! `no_std` so the program, the Rust client and, through
+//! `otto-reward-math-wasm`, the bot all run the exact same integer math.
+
+#![no_std]
+
+/// 100% in basis points
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Holdings multipliers in basis points for [2x, 5x, 10x] the minimum holdings
+pub const DEFAULT_HOLDINGS_MULTIPLIERS: [u16; 3] = [11000, 12500, 15000];
+
+/// Each step of a referral reward, all in $OTTO base units except the multiplier
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RewardBreakdown {
+    pub base_reward: u64,
+    pub ratio_penalty: u64, // Taken off the base reward for a holding ratio below 100%
+    pub holding_adjusted_reward: u64,
+    pub holdings_multiplier: u16, // Basis points
+    pub final_reward: u64,
+}
+
+/// Simulate the referral reward for a purchase, step by step.
+/// `None` if any step overflows, where the program fails the purchase.
+pub fn simulate_purchase(
+    base_rate: u16,
+    holding_ratio: u16,
+    referrer_holdings: u64,
+    purchase_amount: u64,
+    min_holdings: u64,
+    holdings_multipliers: [u16; 3], // [2x, 5x, 10x] minimum holdings
+) -> Option<RewardBreakdown> {
+    // Base reward calculation
+    let base_reward = apply_bps(purchase_amount, base_rate as u64)?;
+
+    // Apply holding ratio penalty (if user sold tokens)
+    let holding_adjusted_reward = apply_bps(base_reward, holding_ratio as u64)?;
+
+    // Apply holdings multiplier (more holdings = higher rewards)
+    let holdings_multiplier =
+        holdings_multiplier(referrer_holdings, min_holdings, holdings_multipliers);
+
+    Some(RewardBreakdown {
+        base_reward,
+        ratio_penalty: base_reward.saturating_sub(holding_adjusted_reward),
+        holding_adjusted_reward,
+        holdings_multiplier,
+        final_reward: apply_bps(holding_adjusted_reward, holdings_multiplier as u64)?,
+    })
+}
+
+/// Calculate dynamic reward based on holdings and purchase volume
+pub fn calculate_dynamic_reward(
+    base_rate: u16,
+    holding_ratio: u16,
+    referrer_holdings: u64,
+    purchase_amount: u64,
+    min_holdings: u64,
+    holdings_multipliers: [u16; 3],
+) -> Option<u64> {
+    simulate_purchase(
+        base_rate,
+        holding_ratio,
+        referrer_holdings,
+        purchase_amount,
+        min_holdings,
+        holdings_multipliers,
+    )
+    .map(|breakdown| breakdown.final_reward)
+}
+
+/// The multiplier, in basis points, for a referrer holding `referrer_holdings`
+pub fn holdings_multiplier(
+    referrer_holdings: u64,
+    min_holdings: u64,
+    holdings_multipliers: [u16; 3],
+) -> u16 {
+    if meets_holdings_multiple(referrer_holdings, min_holdings, 10) {
+        holdings_multipliers[2] // 1.5x by default for 10x minimum holdings
+    } else if meets_holdings_multiple(referrer_holdings, min_holdings, 5) {
+        holdings_multipliers[1] // 1.25x by default for 5x minimum holdings
+    } else if meets_holdings_multiple(referrer_holdings, min_holdings, 2) {
+        holdings_multipliers[0] // 1.1x by default for 2x minimum holdings
+    } else {
+        BPS_DENOMINATOR as u16 // 1x for minimum holdings
+    }
+}
+
+/// Multiply `amount` by `bps / 10_000`, `None` instead of truncating on overflow
+pub fn apply_bps(amount: u64, bps: u64) -> Option<u64> {
+    let scaled = (amount as u128) * (bps as u128) / BPS_DENOMINATOR as u128;
+    u64::try_from(scaled).ok()
+}
+
+/// Whether `holdings` is at least `multiple` times `min_holdings`.
+/// A threshold that does not fit in a u64 can never be met.
+fn meets_holdings_multiple(holdings: u64, min_holdings: u64, multiple: u64) -> bool {
+    min_holdings
+        .checked_mul(multiple)
+        .is_some_and(|threshold| holdings >= threshold)
+}
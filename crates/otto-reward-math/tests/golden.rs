@@ -0,0 +1,42 @@
+//! Golden vectors shared with the WASM bindings and the bot, which runs the
+//! same file through them in `bot/src/services/rewardMath.test.ts`.
+
+use otto_reward_math::{simulate_purchase, RewardBreakdown};
+use serde_json::Value;
+
+fn amount(value: &Value) -> u64 {
+    value.as_str().unwrap().parse().unwrap()
+}
+
+#[test]
+fn simulate_purchase_matches_golden_vectors() {
+    let vectors: Vec<Value> = serde_json::from_str(include_str!("reward_vectors.json")).unwrap();
+    assert!(!vectors.is_empty());
+
+    for vector in &vectors {
+        let multipliers: Vec<u16> = vector["holdings_multipliers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|multiplier| multiplier.as_u64().unwrap() as u16)
+            .collect();
+        let breakdown = simulate_purchase(
+            vector["base_rate"].as_u64().unwrap() as u16,
+            vector["holding_ratio"].as_u64().unwrap() as u16,
+            amount(&vector["referrer_holdings"]),
+            amount(&vector["purchase_amount"]),
+            amount(&vector["min_holdings"]),
+            multipliers.try_into().unwrap(),
+        );
+
+        let expected = &vector["expected"];
+        let expected = (!expected.is_null()).then(|| RewardBreakdown {
+            base_reward: amount(&expected["base_reward"]),
+            ratio_penalty: amount(&expected["ratio_penalty"]),
+            holding_adjusted_reward: amount(&expected["holding_adjusted_reward"]),
+            holdings_multiplier: expected["holdings_multiplier"].as_u64().unwrap() as u16,
+            final_reward: amount(&expected["final_reward"]),
+        });
+        assert_eq!(breakdown, expected, "{}", vector["name"]);
+    }
+}
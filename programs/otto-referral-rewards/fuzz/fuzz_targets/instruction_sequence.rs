@@ -0,0 +1,18 @@
+#![no_main]
+
+//! Random purchase, claim, sell and warp sequences must never create or lose
+//! $OTTO, and the rewards pool must only shrink by the rewards it paid out.
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+use common::sequence::{Action, Scenario};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut scenario = Scenario::new();
+    for action in Action::decode_all(data).iter().take(64) {
+        scenario.apply(action);
+        scenario.check_invariants();
+    }
+});
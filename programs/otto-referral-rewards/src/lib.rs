@@ -1,7 +1,21 @@
-use anchor_lang::prelude::*;
+use anchor_lang::{
+    prelude::*,
+    solana_program::{
+        ed25519_program, keccak,
+        sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
+    },
+};
 use anchor_spl::{
-    associated_token::AssociatedToken,
-    token::{self, Mint, Token, TokenAccount, Transfer},
+    associated_token::{get_associated_token_address_with_program_id, AssociatedToken},
+    token,
+    token_2022::{
+        self,
+        spl_token_2022::{
+            self,
+            extension::{transfer_fee::TransferFeeConfig, BaseStateWithExtensions, StateWithExtensions},
+        },
+    },
+    token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked},
 };
 
 declare_id!("Fg6PaFpoGXkYsidMpWTK6W2BeZ7FEfcYkg476zPFsLnS");
@@ -19,9 +33,21 @@ pub mod otto_referral_rewards {
         base_reward_rate: u16, // Base reward percentage in basis points
     ) -> Result<()> {
         let program_state = &mut ctx.accounts.program_state;
+        program_state.version = ACCOUNT_VERSION;
         program_state.authority = ctx.accounts.authority.key();
         program_state.otto_token_mint = ctx.accounts.otto_token_mint.key();
         program_state.rewards_pool = ctx.accounts.rewards_pool.key();
+        program_state.reward_pools = vec![RewardPoolConfig {
+            mint: program_state.otto_token_mint,
+            pool: program_state.rewards_pool,
+            rate_numerator: 1,
+            rate_denominator: 1,
+            oracle: None,
+        }];
+        program_state.payout_mint = program_state.otto_token_mint;
+        program_state.referee_bonus_bps = 0;
+        program_state.referee_bonus_first_purchase_only = true;
+        program_state.milestones = Vec::new();
         program_state.rewards_percentage = rewards_percentage;
         program_state.min_purchase_amount = min_purchase_amount;
         program_state.min_holdings_for_referral = min_holdings_for_referral;
@@ -31,14 +57,26 @@ pub mod otto_referral_rewards {
         program_state.bronze_threshold = 1_000_000; // 0.1% of total supply
         program_state.silver_threshold = 5_000_000; // 0.5% of total supply
         program_state.gold_threshold = 10_000_000; // 1% of total supply
+        program_state.holding_baseline = 0; // Use each referrer's initial holdings
+        program_state.twab_window = 7 * SECONDS_PER_DAY;
+        program_state.ratio_recovery_rate = 1000; // 10% of ratio per day
+        program_state.crank_bounty = 0;
+        program_state.unbonding_period = 7 * SECONDS_PER_DAY;
         program_state.bump = ctx.bumps.program_state;
 
-        emit!(ProgramInitialized {
+        let clock = Clock::get()?;
+        emit_cpi!(ProgramInitialized {
+            version: EVENT_VERSION,
+            program_state: program_state.key(),
             authority: program_state.authority,
             otto_token_mint: program_state.otto_token_mint,
+            rewards_pool: program_state.rewards_pool,
             rewards_percentage,
             min_purchase_amount,
             min_holdings_for_referral,
+            base_reward_rate,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
         });
 
         Ok(())
@@ -53,66 +91,101 @@ pub mod otto_referral_rewards {
         require!(referral_code.len() >= 4, ErrorCode::ReferralCodeTooShort);
 
         let program_state = &ctx.accounts.program_state;
-        let token_account = &ctx.accounts.token_account;
+        let authority = ctx.accounts.authority.key();
+
+        // Holdings span the ATA and stake account plus any extra token
+        // accounts passed in remaining_accounts, which become the referrer's
+        // linked accounts
+        let (holdings, linked_token_accounts) = sum_holdings(
+            &ctx.accounts.token_account,
+            staked_amount(&ctx.accounts.stake_account)?,
+            ctx.remaining_accounts,
+            &program_state.otto_token_mint,
+            &authority,
+        )?;
 
         // Verify minimum holdings requirement
         require!(
-            token_account.amount >= program_state.min_holdings_for_referral,
+            holdings >= program_state.min_holdings_for_referral,
             ErrorCode::InsufficientHoldingsForReferral
         );
 
         let referrer_account = &mut ctx.accounts.referrer_account;
-        referrer_account.authority = ctx.accounts.authority.key();
+        referrer_account.version = ACCOUNT_VERSION;
+        referrer_account.authority = authority;
         referrer_account.referral_code = referral_code.clone();
         referrer_account.total_referrals = 0;
         referrer_account.total_rewards_earned = 0;
-        referrer_account.initial_holdings = token_account.amount;
-        referrer_account.current_holdings = token_account.amount;
+        referrer_account.total_referred_volume = 0;
+        referrer_account.unique_referees = 0;
+        referrer_account.milestones_reached = 0;
+        referrer_account.clawback_owed = 0;
+        referrer_account.initial_holdings = holdings;
+        referrer_account.current_holdings = holdings;
         referrer_account.tier = Tier::None;
-        referrer_account.last_activity = Clock::get()?.unix_timestamp;
+        let clock = Clock::get()?;
+        referrer_account.last_activity = clock.unix_timestamp;
         referrer_account.is_active = true;
         referrer_account.holding_ratio = 10000; // Start at 100% (basis points)
+        referrer_account.twab = holdings;
+        referrer_account.twab_last_update = referrer_account.last_activity;
+        referrer_account.ratio_recovery_at = referrer_account.last_activity;
+        referrer_account.last_bounty_at = 0;
+        referrer_account.linked_token_accounts = linked_token_accounts;
         referrer_account.bump = ctx.bumps.referrer_account;
 
-        emit!(ReferrerRegistered {
+        let referrer_lookup = &mut ctx.accounts.referrer_lookup;
+        referrer_lookup.referrer_account = referrer_account.key();
+        referrer_lookup.bump = ctx.bumps.referrer_lookup;
+
+        emit_cpi!(ReferrerRegistered {
+            version: EVENT_VERSION,
+            referrer_account: referrer_account.key(),
             authority: referrer_account.authority,
             referral_code,
-            initial_holdings: token_account.amount,
-            timestamp: referrer_account.last_activity,
+            initial_holdings: holdings,
+            linked_token_accounts: referrer_account.linked_token_accounts.clone(),
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
 
-    /// Update referrer holdings ratio (called when tokens are sold)
+    /// Update referrer holdings ratio (called when tokens are sold or rebought)
     pub fn update_holdings_ratio(ctx: Context<UpdateHoldingsRatio>) -> Result<()> {
+        let program_state = &ctx.accounts.program_state;
         let referrer_account = &mut ctx.accounts.referrer_account;
-        let token_account = &ctx.accounts.token_account;
+
+        // The signed update re-links whichever extra token accounts are passed
+        let (new_holdings, linked_token_accounts) = sum_holdings(
+            &ctx.accounts.token_account,
+            staked_amount(&ctx.accounts.stake_account)?,
+            ctx.remaining_accounts,
+            &program_state.otto_token_mint,
+            &referrer_account.authority,
+        )?;
+        referrer_account.linked_token_accounts = linked_token_accounts;
         
         // Store previous holdings before updating
         let previous_holdings = referrer_account.current_holdings;
-        let new_holdings = token_account.amount;
-        
-        // Update current holdings
-        referrer_account.current_holdings = new_holdings;
-        
-        // Calculate new holding ratio (affects reward rates)
-        if referrer_account.initial_holdings > 0 {
-            referrer_account.holding_ratio = ((new_holdings as u128 * 10000) / referrer_account.initial_holdings as u128) as u16;
-            // Cap at 100% if they've accumulated more tokens
-            if referrer_account.holding_ratio > 10000 {
-                referrer_account.holding_ratio = 10000;
-            }
-        }
         
-        referrer_account.last_activity = Clock::get()?.unix_timestamp;
+        // Fold the new balance into the TWAB and recompute the ratio
+        let clock = Clock::get()?;
+        referrer_account.record_holdings(program_state, new_holdings, clock.unix_timestamp)?;
+        referrer_account.last_activity = clock.unix_timestamp;
 
-        emit!(HoldingsRatioUpdated {
+        emit_cpi!(HoldingsRatioUpdated {
+            version: EVENT_VERSION,
+            referrer_account: referrer_account.key(),
             authority: referrer_account.authority,
             previous_holdings,
             new_holdings,
+            twab: referrer_account.twab,
             holding_ratio: referrer_account.holding_ratio,
-            timestamp: referrer_account.last_activity,
+            linked_token_accounts: referrer_account.linked_token_accounts.clone(),
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
         });
 
         Ok(())
@@ -133,157 +206,1199 @@ pub mod otto_referral_rewards {
         );
 
         let buyer = &ctx.accounts.buyer;
-        let current_time = Clock::get()?.unix_timestamp;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        // Purchases are ranked on the active leaderboard, which can't be left
+        // out to keep a referral off it
+        require!(
+            ctx.accounts.leaderboard.as_ref().map(|leaderboard| leaderboard.key())
+                == program_state.active_leaderboard,
+            ErrorCode::LeaderboardMismatch
+        );
+
+        // Accounts only a referral uses are created on first use, so they
+        // must be left out otherwise rather than created under placeholder
+        // seeds at the buyer's expense
+        let referred = referral_code.is_some() && ctx.accounts.referrer_account.is_some();
+        require!(
+            referred
+                || (ctx.accounts.referee_record.is_none()
+                    && ctx.accounts.held_payout.is_none()
+                    && ctx.accounts.leaderboard_volume.is_none()),
+            ErrorCode::UnexpectedReferralAccount
+        );
+        require!(
+            ctx.accounts.leaderboard_volume.is_none() || ctx.accounts.leaderboard.is_some(),
+            ErrorCode::UnexpectedLeaderboardVolume
+        );
+
+        // The active campaign only applies while it is live, has budget left
+        // and, if it has an allowlist, lists the code of the referrer account
+        // credited; otherwise global terms are used
+        require!(
+            ctx.accounts.campaign.as_ref().map(|campaign| campaign.key()) == program_state.active_campaign,
+            ErrorCode::CampaignMismatch
+        );
+        let referrer_code = referral_code
+            .as_ref()
+            .and(ctx.accounts.referrer_account.as_ref())
+            .map(|referrer_account| referrer_account.referral_code.clone());
+        let mut campaign = ctx
+            .accounts
+            .campaign
+            .as_deref_mut()
+            .filter(|campaign| campaign.is_live(current_time, referrer_code.as_deref()));
+        let payout_mint = campaign
+            .as_ref()
+            .map_or(program_state.payout_mint, |campaign| campaign.payout_mint);
+        require_keys_eq!(
+            ctx.accounts.payout_mint.key(),
+            payout_mint,
+            ErrorCode::PayoutMintMismatch
+        );
 
         // Process purchase
         let purchase_account = &mut ctx.accounts.purchase_account;
+        purchase_account.version = ACCOUNT_VERSION;
         purchase_account.buyer = buyer.key();
         purchase_account.amount = purchase_amount;
         purchase_account.timestamp = current_time;
         purchase_account.referral_code = referral_code.clone();
+        purchase_account.payout_mint = payout_mint;
+        purchase_account.cashback = None;
+        purchase_account.referee_bonus = 0;
+        purchase_account.campaign = campaign.as_ref().map(|campaign| campaign.key());
+        purchase_account.campaign_rewards = 0;
+        purchase_account.referrer = None;
+        purchase_account.referral_reward = 0;
+        purchase_account.referral_held = 0;
+        purchase_account.referral_held_reward = 0;
+        purchase_account.milestones_reached = 0;
+        purchase_account.leaderboard_id = None;
         purchase_account.rewards_claimed = false;
+        purchase_account.reversed = false;
         purchase_account.bump = ctx.bumps.purchase_account;
 
+        // Track the buyer's purchase history for first-purchase bonuses
+        let buyer_account = &mut ctx.accounts.buyer_account;
+        buyer_account.buyer = buyer.key();
+        buyer_account.total_purchases = buyer_account
+            .total_purchases
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        buyer_account.bump = ctx.bumps.buyer_account;
+
         // Process referral if provided
         if let Some(ref_code) = referral_code {
             if let Some(referrer_account) = ctx.accounts.referrer_account.as_mut() {
+                require!(
+                    referrer_account.referral_code == ref_code,
+                    ErrorCode::ReferralCodeMismatch
+                );
+
                 // Anti-abuse: Prevent self-referral
                 require!(
                     referrer_account.authority != buyer.key(),
                     ErrorCode::SelfReferralNotAllowed
                 );
 
-                // Calculate dynamic reward based on:
-                // 1. Referrer's holding ratio (penalized if they sold tokens)
-                // 2. Referrer's current holdings (more holdings = higher multiplier)
-                // 3. Purchase volume
-                let dynamic_reward = calculate_dynamic_reward(
-                    program_state.base_reward_rate,
-                    referrer_account.holding_ratio,
-                    referrer_account.current_holdings,
-                    purchase_amount,
-                    program_state.min_holdings_for_referral,
+                // Read the referrer's live balance instead of trusting the
+                // holdings cached by the last update
+                let referrer_token_account = ctx
+                    .accounts
+                    .referrer_token_account
+                    .as_ref()
+                    .ok_or(ErrorCode::MissingReferrerTokenAccount)?;
+                require_keys_eq!(
+                    referrer_token_account.key(),
+                    get_associated_token_address_with_program_id(
+                        &referrer_account.authority,
+                        &program_state.otto_token_mint,
+                        &ctx.accounts.token_program.key(),
+                    ),
+                    ErrorCode::InvalidReferrerTokenAccount
                 );
+                let referrer_stake_account = ctx
+                    .accounts
+                    .referrer_stake_account
+                    .as_ref()
+                    .ok_or(ErrorCode::MissingReferrerStakeAccount)?;
+                let (referrer_holdings, gone) = sum_linked_holdings(
+                    referrer_account,
+                    referrer_token_account,
+                    staked_amount(referrer_stake_account)?,
+                    ctx.remaining_accounts,
+                    &program_state.otto_token_mint,
+                )?;
+                referrer_account.unlink(&gone);
+                referrer_account.record_holdings(program_state, referrer_holdings, current_time)?;
+
+                // Rewards are computed in $OTTO and paid in the payout asset
+                let payout_rate = program_state.payout_rate(
+                    &payout_mint,
+                    ctx.accounts.price_oracle.as_ref(),
+                    current_time,
+                )?;
+                let reward = referral_reward(
+                    program_state,
+                    referrer_account,
+                    campaign.as_deref(),
+                    payout_rate,
+                    purchase_amount,
+                )?;
+                let clawback = reward.clawback;
+                referrer_account.clawback_owed = referrer_account
+                    .clawback_owed
+                    .checked_sub(clawback)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                purchase_account.referrer = Some(referrer_account.key());
+                purchase_account.referral_reward = reward.chargeable;
+                let dynamic_reward = reward.payout;
+
+                // Campaign rewards come out of its budget
+                if let Some(campaign) = campaign.as_mut() {
+                    campaign.spent = campaign
+                        .spent
+                        .checked_add(dynamic_reward)
+                        .ok_or(ErrorCode::MathOverflow)?;
+                    purchase_account.campaign_rewards = dynamic_reward;
+                }
+
+                // A referrer without a payout account has their payouts
+                // held for claim_held_payout instead of failing the purchase
+                if let Some(held_payout) = ctx.accounts.held_payout.as_mut() {
+                    held_payout.referrer_account = referrer_account.key();
+                    held_payout.payout_mint = payout_mint;
+                    held_payout.bump = ctx.bumps.held_payout.ok_or(ErrorCode::MissingReferrerPayoutAccount)?;
+                }
+
+                // Transfer dynamic rewards to referrer; with a transfer-fee
+                // mint the referrer receives less than the pool sends
+                let mut received = 0;
+                let mut held = 0;
+                if dynamic_reward > 0 {
+                    (received, held) = pay_referrer(
+                        program_state,
+                        &mut ctx.accounts.rewards_pool,
+                        &ctx.accounts.referrer_payout_account,
+                        &mut ctx.accounts.held_payout,
+                        referrer_account,
+                        ctx.accounts.referrer_settings.as_ref(),
+                        &ctx.accounts.payout_mint,
+                        &ctx.accounts.payout_token_program,
+                        dynamic_reward,
+                        &clock,
+                    )?;
+                }
+                let mut held_reward = if held > 0 {
+                    paid_share(reward.chargeable, held, dynamic_reward)?
+                } else {
+                    0
+                };
+
+                // Buyers using a valid code earn a bonus on top of their
+                // cashback, paid alongside it in claim_rewards. Purchases
+                // made without a code don't use up a first-purchase bonus.
+                let referee_bonus =
+                    program_state.referee_bonus(purchase_amount, buyer_account.referred_purchases == 0)?;
+                purchase_account.referee_bonus = referee_bonus;
+                buyer_account.referred_purchases = buyer_account
+                    .referred_purchases
+                    .checked_add(1)
+                    .ok_or(ErrorCode::MathOverflow)?;
+
+                // Track the (referrer, buyer) pair; the first referred purchase
+                // creates the record and counts a new unique referee
+                let referee_record = ctx
+                    .accounts
+                    .referee_record
+                    .as_mut()
+                    .ok_or(ErrorCode::MissingRefereeRecord)?;
+                let new_referee = referee_record.purchases == 0;
+                if new_referee {
+                    referee_record.referrer = referrer_account.key();
+                    referee_record.buyer = buyer.key();
+                    referee_record.first_purchase_at = current_time;
+                    referee_record.bump = ctx.bumps.referee_record.ok_or(ErrorCode::MissingRefereeRecord)?;
+                }
+                referee_record.purchases = referee_record
+                    .purchases
+                    .checked_add(1)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                referee_record.volume = referee_record
+                    .volume
+                    .checked_add(purchase_amount)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                referee_record.last_purchase_at = current_time;
 
                 // Update referrer stats
-                referrer_account.total_referrals += 1;
-                referrer_account.total_rewards_earned += dynamic_reward;
-                referrer_account.last_activity = current_time;
-
-                // Transfer dynamic rewards to referrer
-                if dynamic_reward > 0 && ctx.accounts.referrer_token_account.is_some() {
-                    let cpi_accounts = Transfer {
-                        from: ctx.accounts.rewards_pool.to_account_info(),
-                        to: ctx.accounts.referrer_token_account.as_ref().unwrap().to_account_info(),
-                        authority: ctx.accounts.program_state.to_account_info(),
-                    };
-                    let seeds = &[
-                        b"program_state".as_ref(),
-                        &[program_state.bump],
-                    ];
-                    let signer = &[&seeds[..]];
-                    let cpi_program = ctx.accounts.token_program.to_account_info();
-                    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-                    token::transfer(cpi_ctx, dynamic_reward)?;
+                referrer_account.record_referral(purchase_amount, new_referee, current_time)?;
+
+                // Rank the referrer on the open leaderboard by the volume they
+                // referred during its window, kept per leaderboard so a new
+                // leaderboard starts from zero while the last awaits settlement
+                if let Some(leaderboard) = ctx
+                    .accounts
+                    .leaderboard
+                    .as_mut()
+                    .filter(|leaderboard| leaderboard.is_open(current_time))
+                {
+                    let leaderboard_volume = ctx
+                        .accounts
+                        .leaderboard_volume
+                        .as_mut()
+                        .ok_or(ErrorCode::MissingLeaderboardVolume)?;
+                    if leaderboard_volume.volume == 0 {
+                        leaderboard_volume.leaderboard = leaderboard.key();
+                        leaderboard_volume.referrer = referrer_account.key();
+                        leaderboard_volume.bump = ctx
+                            .bumps
+                            .leaderboard_volume
+                            .ok_or(ErrorCode::MissingLeaderboardVolume)?;
+                    }
+                    leaderboard_volume.volume = leaderboard_volume
+                        .volume
+                        .checked_add(purchase_amount)
+                        .ok_or(ErrorCode::MathOverflow)?;
+                    leaderboard.record(referrer_account.authority, leaderboard_volume.volume);
+                    purchase_account.leaderboard_id = Some(leaderboard.id);
+                }
+
+                // Pay each milestone this purchase crossed, once per referrer
+                let mut earned = received.checked_add(held).ok_or(ErrorCode::MathOverflow)?;
+                for (index, milestone) in referrer_account.reach_milestones(&program_state.milestones) {
+                    let mut bonus = payout_rate.convert(milestone.bonus)?;
+                    let mut chargeable = milestone.bonus;
+                    // Campaign milestones share its budget, paying only
+                    // what is left of it
+                    if let Some(campaign) = campaign.as_mut() {
+                        let charged = campaign.charge(bonus)?;
+                        chargeable = paid_share(chargeable, charged, bonus)?;
+                        bonus = charged;
+                        purchase_account.campaign_rewards = purchase_account
+                            .campaign_rewards
+                            .checked_add(bonus)
+                            .ok_or(ErrorCode::MathOverflow)?;
+                    }
+                    purchase_account.milestones_reached |= 1 << index;
+                    purchase_account.referral_reward = purchase_account
+                        .referral_reward
+                        .checked_add(chargeable)
+                        .ok_or(ErrorCode::MathOverflow)?;
+                    let mut bonus_received = 0;
+                    let mut bonus_held = 0;
+                    if bonus > 0 {
+                        (bonus_received, bonus_held) = pay_referrer(
+                            program_state,
+                            &mut ctx.accounts.rewards_pool,
+                            &ctx.accounts.referrer_payout_account,
+                            &mut ctx.accounts.held_payout,
+                            referrer_account,
+                            ctx.accounts.referrer_settings.as_ref(),
+                            &ctx.accounts.payout_mint,
+                            &ctx.accounts.payout_token_program,
+                            bonus,
+                            &clock,
+                        )?;
+                    }
+                    held = held.checked_add(bonus_held).ok_or(ErrorCode::MathOverflow)?;
+                    if bonus_held > 0 {
+                        held_reward = held_reward
+                            .checked_add(paid_share(chargeable, bonus_held, bonus)?)
+                            .ok_or(ErrorCode::MathOverflow)?;
+                    }
+                    earned = earned
+                        .checked_add(bonus_received)
+                        .and_then(|earned| earned.checked_add(bonus_held))
+                        .ok_or(ErrorCode::MathOverflow)?;
+
+                    emit_cpi!(MilestoneReached {
+                        version: EVENT_VERSION,
+                        referrer_account: referrer_account.key(),
+                        referrer: referrer_account.authority,
+                        referral_code: referrer_account.referral_code.clone(),
+                        purchase: purchase_account.key(),
+                        milestone_index: index,
+                        kind: milestone.kind,
+                        threshold: milestone.threshold,
+                        payout_mint,
+                        bonus: bonus_received,
+                        transfer_fee: bonus - bonus_received - bonus_held,
+                        pool_balance: ctx.accounts.rewards_pool.amount,
+                        slot: clock.slot,
+                        timestamp: current_time,
+                    });
                 }
 
-                emit!(DynamicReferralProcessed {
+                purchase_account.referral_held = held;
+                purchase_account.referral_held_reward = held_reward;
+
+                referrer_account.total_rewards_earned = referrer_account
+                    .total_rewards_earned
+                    .checked_add(earned)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                ctx.accounts
+                    .stats_shard
+                    .load_mut()?
+                    .record_referral(purchase_amount, earned)?;
+
+                emit_cpi!(DynamicReferralProcessed {
+                    version: EVENT_VERSION,
+                    purchase: purchase_account.key(),
                     buyer: buyer.key(),
+                    referrer_account: referrer_account.key(),
                     referrer: referrer_account.authority,
                     referral_code: ref_code,
                     purchase_amount,
-                    dynamic_reward,
+                    payout_mint,
+                    campaign: campaign.as_ref().map(|campaign| campaign.key()),
+                    campaign_spent: campaign.as_ref().map_or(0, |campaign| campaign.spent),
+                    dynamic_reward: received,
+                    transfer_fee: dynamic_reward - received - held,
+                    clawback,
+                    referee_bonus,
                     holding_ratio: referrer_account.holding_ratio,
                     referrer_holdings: referrer_account.current_holdings,
+                    twab: referrer_account.twab,
+                    total_referrals: referrer_account.total_referrals,
+                    unique_referees: referrer_account.unique_referees,
+                    total_referred_volume: referrer_account.total_referred_volume,
+                    total_rewards_earned: referrer_account.total_rewards_earned,
+                    clawback_owed: referrer_account.clawback_owed,
+                    pool_balance: ctx.accounts.rewards_pool.amount,
+                    slot: clock.slot,
                     timestamp: current_time,
                 });
+
+                if let Some(held_payout) = ctx.accounts.held_payout.as_ref().filter(|_| held > 0) {
+                    emit_cpi!(ReferralPayoutHeld {
+                        version: EVENT_VERSION,
+                        purchase: purchase_account.key(),
+                        referrer_account: referrer_account.key(),
+                        referrer: referrer_account.authority,
+                        payout_mint,
+                        amount: held,
+                        total_held: held_payout.amount,
+                        slot: clock.slot,
+                        timestamp: current_time,
+                    });
+                }
             }
         }
 
-        emit!(PurchaseProcessed {
+        // Cashback, with any referee bonus, is fixed in the payout mint at
+        // today's rate, so the claim pays exactly what a campaign budgeted,
+        // capped at what is left of the budget
+        let payout_rate = program_state.payout_rate(
+            &payout_mint,
+            ctx.accounts.price_oracle.as_ref(),
+            current_time,
+        )?;
+        let mut cashback = payout_rate.convert(program_state.cashback(
+            purchase_amount,
+            ctx.accounts.purchase_account.referee_bonus,
+        )?)?;
+        if let Some(campaign) = campaign.as_mut() {
+            cashback = campaign.charge(cashback)?;
+        }
+        ctx.accounts.purchase_account.cashback = Some(cashback);
+
+        ctx.accounts
+            .stats_shard
+            .load_mut()?
+            .record_purchase(purchase_amount)?;
+
+        let purchase_account = &ctx.accounts.purchase_account;
+        emit_cpi!(PurchaseProcessed {
+            version: EVENT_VERSION,
+            purchase: purchase_account.key(),
             buyer: buyer.key(),
             amount: purchase_amount,
+            referral_code: purchase_account.referral_code.clone(),
+            referrer_account: purchase_account.referrer,
+            payout_mint: purchase_account.payout_mint,
+            campaign: purchase_account.campaign,
+            referee_bonus: purchase_account.referee_bonus,
+            referral_reward: purchase_account.referral_reward,
+            buyer_total_purchases: ctx.accounts.buyer_account.total_purchases,
+            slot: clock.slot,
             timestamp: current_time,
         });
 
         Ok(())
     }
 
+    /// Quote what `process_purchase` would pay without sending it. Nothing is
+    /// written; the quote is returned as return data, so callers read it by
+    /// simulating the transaction. Takes the same accounts as the purchase,
+    /// minus the ones only needed to pay and record it.
+    pub fn quote_referral_reward(
+        ctx: Context<QuoteReferralReward>,
+        purchase_amount: u64,
+        referral_code: Option<String>,
+    ) -> Result<ReferralQuote> {
+        let program_state = &ctx.accounts.program_state;
+        require!(
+            purchase_amount >= program_state.min_purchase_amount,
+            ErrorCode::PurchaseAmountTooLow
+        );
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        require!(
+            ctx.accounts.campaign.as_ref().map(|campaign| campaign.key()) == program_state.active_campaign,
+            ErrorCode::CampaignMismatch
+        );
+        let referrer_code = referral_code
+            .as_ref()
+            .and(ctx.accounts.referrer_account.as_ref())
+            .map(|referrer_account| referrer_account.referral_code.as_str());
+        let campaign = ctx
+            .accounts
+            .campaign
+            .as_deref()
+            .filter(|campaign| campaign.is_live(current_time, referrer_code));
+        let payout_mint = campaign.map_or(program_state.payout_mint, |campaign| campaign.payout_mint);
+        require_keys_eq!(
+            ctx.accounts.payout_mint.key(),
+            payout_mint,
+            ErrorCode::PayoutMintMismatch
+        );
+        let payout_rate = program_state.payout_rate(
+            &payout_mint,
+            ctx.accounts.price_oracle.as_ref(),
+            current_time,
+        )?;
+
+        // A buyer without a buyer account has never purchased
+        let is_first_referred_purchase = read_if_created::<BuyerAccount>(&ctx.accounts.buyer_account)?
+            .is_none_or(|buyer_account| buyer_account.referred_purchases == 0);
+
+        let mut quote = ReferralQuote {
+            purchase_amount,
+            payout_mint,
+            campaign: campaign.map(|campaign| campaign.key()),
+            ..ReferralQuote::default()
+        };
+        // Charge a copy of the campaign, as process_purchase would the
+        // campaign itself
+        let mut budget = campaign.map(|campaign| Campaign::clone(campaign));
+
+        if let (Some(ref_code), Some(referrer_account)) =
+            (referral_code, ctx.accounts.referrer_account.as_ref())
+        {
+            require!(
+                referrer_account.referral_code == ref_code,
+                ErrorCode::ReferralCodeMismatch
+            );
+            require!(
+                referrer_account.authority != ctx.accounts.buyer.key(),
+                ErrorCode::SelfReferralNotAllowed
+            );
+
+            let referrer_token_account = ctx
+                .accounts
+                .referrer_token_account
+                .as_ref()
+                .ok_or(ErrorCode::MissingReferrerTokenAccount)?;
+            require_keys_eq!(
+                referrer_token_account.key(),
+                get_associated_token_address_with_program_id(
+                    &referrer_account.authority,
+                    &program_state.otto_token_mint,
+                    &ctx.accounts.token_program.key(),
+                ),
+                ErrorCode::InvalidReferrerTokenAccount
+            );
+            let referrer_stake_account = ctx
+                .accounts
+                .referrer_stake_account
+                .as_ref()
+                .ok_or(ErrorCode::MissingReferrerStakeAccount)?;
+            let (referrer_holdings, _) = sum_linked_holdings(
+                referrer_account,
+                referrer_token_account,
+                staked_amount(referrer_stake_account)?,
+                ctx.remaining_accounts,
+                &program_state.otto_token_mint,
+            )?;
+
+            // Apply the purchase to a copy of the referrer, as process_purchase
+            // would to the account itself
+            let mut referrer = (**referrer_account).clone();
+            referrer.record_holdings(program_state, referrer_holdings, current_time)?;
+            let reward = referral_reward(program_state, &referrer, campaign, payout_rate, purchase_amount)?;
+            if let Some(budget) = budget.as_mut() {
+                budget.charge(reward.payout)?;
+            }
+            let referral_received = amount_after_transfer_fee(&ctx.accounts.payout_mint, reward.payout, &clock)?;
+
+            let new_referee = read_if_created::<RefereeRecord>(&ctx.accounts.referee_record)?
+                .is_none_or(|referee_record| referee_record.purchases == 0);
+            referrer.record_referral(purchase_amount, new_referee, current_time)?;
+            let mut milestone_bonus: u64 = 0;
+            for (_, milestone) in referrer.reach_milestones(&program_state.milestones) {
+                let mut bonus = payout_rate.convert(milestone.bonus)?;
+                if let Some(budget) = budget.as_mut() {
+                    bonus = budget.charge(bonus)?;
+                }
+                let bonus = amount_after_transfer_fee(&ctx.accounts.payout_mint, bonus, &clock)?;
+                milestone_bonus = milestone_bonus
+                    .checked_add(bonus)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+
+            quote.referrer = Some(referrer.authority);
+            quote.holding_ratio = referrer.holding_ratio;
+            quote.referrer_holdings = referrer.current_holdings;
+            quote.base_reward = reward.breakdown.base_reward;
+            quote.ratio_penalty = reward.breakdown.ratio_penalty;
+            quote.holding_adjusted_reward = reward.breakdown.holding_adjusted_reward;
+            quote.holdings_multiplier = reward.breakdown.holdings_multiplier;
+            quote.final_reward = reward.breakdown.final_reward;
+            quote.clawback = reward.clawback;
+            quote.referral_reward = referral_received;
+            quote.transfer_fee = reward.payout - referral_received;
+            quote.milestone_bonus = milestone_bonus;
+            quote.referee_bonus = program_state.referee_bonus(purchase_amount, is_first_referred_purchase)?;
+        }
+
+        quote.cashback = payout_rate.convert(program_state.cashback(purchase_amount, quote.referee_bonus)?)?;
+        if let Some(budget) = budget.as_mut() {
+            quote.cashback = budget.charge(quote.cashback)?;
+        }
+
+        Ok(quote)
+    }
+
 
 
     /// Update user tier based on token holdings
     pub fn update_tier(ctx: Context<UpdateTier>) -> Result<()> {
         let program_state = &ctx.accounts.program_state;
         let referrer_account = &mut ctx.accounts.referrer_account;
-        let token_account = &ctx.accounts.token_account;
 
-        // Get current token balance
-        let balance = token_account.amount;
+        // Get current token balance across the ATA and linked accounts
+        let (balance, gone) = sum_linked_holdings(
+            referrer_account,
+            &ctx.accounts.token_account,
+            staked_amount(&ctx.accounts.stake_account)?,
+            ctx.remaining_accounts,
+            &program_state.otto_token_mint,
+        )?;
+        referrer_account.unlink(&gone);
 
         // Determine tier based on holdings
-        let new_tier = if balance >= program_state.gold_threshold {
-            Tier::Gold
-        } else if balance >= program_state.silver_threshold {
-            Tier::Silver
-        } else if balance >= program_state.bronze_threshold {
-            Tier::Bronze
-        } else {
-            Tier::None
-        };
+        let new_tier = program_state.tier_for_balance(balance);
 
         let old_tier = referrer_account.tier;
+        let clock = Clock::get()?;
         referrer_account.tier = new_tier;
-        referrer_account.last_activity = Clock::get()?.unix_timestamp;
+        referrer_account.last_activity = clock.unix_timestamp;
 
-        emit!(TierUpdated {
+        emit_cpi!(TierUpdated {
+            version: EVENT_VERSION,
+            referrer_account: referrer_account.key(),
             authority: referrer_account.authority,
             old_tier,
             new_tier,
             balance,
-            timestamp: referrer_account.last_activity,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
 
-    /// Claim pending rewards
-    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
-        let purchase_account = &mut ctx.accounts.purchase_account;
+    /// Permissionless crank: re-read a referrer's balance, refresh their
+    /// holding ratio and lower their tier, and pay the cranker a bounty if it
+    /// caught a sale. Tiers only go back up through the signed `update_tier`.
+    pub fn refresh_referrer(ctx: Context<RefreshReferrer>) -> Result<()> {
+        let program_state = &ctx.accounts.program_state;
+        let referrer_account = &mut ctx.accounts.referrer_account;
+        let (balance, gone) = sum_linked_holdings(
+            referrer_account,
+            &ctx.accounts.token_account,
+            staked_amount(&ctx.accounts.stake_account)?,
+            ctx.remaining_accounts,
+            &program_state.otto_token_mint,
+        )?;
+        // Closed or reassigned accounts would fail every later read
+        referrer_account.unlink(&gone);
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let previous_holdings = referrer_account.current_holdings;
+        let previous_ratio = referrer_account.holding_ratio;
+        let old_tier = referrer_account.tier;
+
+        referrer_account.record_holdings(program_state, balance, current_time)?;
+        referrer_account.tier = old_tier.min(program_state.tier_for_balance(balance));
+
+        // Only penalising refreshes earn a bounty, so honest referrers can't be
+        // farmed and referrers can't crank themselves. The balance must still
+        // sit below the TWAB, so a sale already bought back doesn't count, and
+        // a referrer pays out at most one bounty per TWAB window, so selling
+        // and rebuying to an accomplice's crank doesn't drain the pool.
+        let caught_sale = (referrer_account.holding_ratio < previous_ratio
+            || referrer_account.tier < old_tier)
+            && balance < referrer_account.twab;
+        let bounty_due = referrer_account.last_bounty_at == 0
+            || current_time.saturating_sub(referrer_account.last_bounty_at)
+                >= program_state.twab_window;
+        let mut bounty = 0;
+        if caught_sale
+            && bounty_due
+            && program_state.crank_bounty > 0
+            && ctx.accounts.cranker.key() != referrer_account.authority
+        {
+            if let Some(cranker_token_account) = ctx.accounts.cranker_token_account.as_ref() {
+                bounty = program_state.crank_bounty.min(ctx.accounts.rewards_pool.amount);
+                if bounty > 0 {
+                    bounty = pay_from_rewards_pool(
+                        program_state,
+                        &mut ctx.accounts.rewards_pool,
+                        cranker_token_account,
+                        &ctx.accounts.otto_token_mint,
+                        &ctx.accounts.token_program,
+                        bounty,
+                        &clock,
+                    )?;
+                    referrer_account.last_bounty_at = current_time;
+                }
+            }
+        }
+
+        emit_cpi!(ReferrerRefreshed {
+            version: EVENT_VERSION,
+            referrer_account: referrer_account.key(),
+            authority: referrer_account.authority,
+            cranker: ctx.accounts.cranker.key(),
+            previous_holdings,
+            new_holdings: balance,
+            twab: referrer_account.twab,
+            holding_ratio: referrer_account.holding_ratio,
+            old_tier,
+            new_tier: referrer_account.tier,
+            bounty,
+            pool_balance: ctx.accounts.rewards_pool.amount,
+            slot: clock.slot,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /// Summarise a referrer's standing as of their live balance, i.e. as
+    /// `refresh_referrer` would leave it. Read-only; the summary is returned
+    /// as return data.
+    pub fn get_referrer_summary(ctx: Context<GetReferrerSummary>) -> Result<ReferrerSummary> {
+        let program_state = &ctx.accounts.program_state;
+        let (balance, _) = sum_linked_holdings(
+            &ctx.accounts.referrer_account,
+            &ctx.accounts.token_account,
+            staked_amount(&ctx.accounts.stake_account)?,
+            ctx.remaining_accounts,
+            &program_state.otto_token_mint,
+        )?;
+
+        let mut referrer = (*ctx.accounts.referrer_account).clone();
+        referrer.record_holdings(program_state, balance, Clock::get()?.unix_timestamp)?;
+
+        Ok(ReferrerSummary {
+            tier: referrer.tier.min(program_state.tier_for_balance(balance)),
+            holdings_multiplier: otto_reward_math::holdings_multiplier(
+                balance,
+                program_state.min_holdings_for_referral,
+                DEFAULT_HOLDINGS_MULTIPLIERS,
+            ),
+            holdings: balance,
+            twab: referrer.twab,
+            holding_ratio: referrer.holding_ratio,
+            is_active: referrer.is_active,
+            total_referrals: referrer.total_referrals,
+            unique_referees: referrer.unique_referees,
+            total_referred_volume: referrer.total_referred_volume,
+            total_rewards_earned: referrer.total_rewards_earned,
+            clawback_owed: referrer.clawback_owed,
+            milestones_reached: referrer.milestones_reached,
+            authority: referrer.authority,
+            referral_code: referrer.referral_code,
+        })
+    }
+
+    /// Stake $OTTO into the caller's program-owned vault. Staked tokens count
+    /// towards referral eligibility, tiers and the holdings multiplier.
+    pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidStakeAmount);
+
+        let mint = &ctx.accounts.otto_token_mint;
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.owner_token_account.to_account_info(),
+            mint: mint.to_account_info(),
+            to: ctx.accounts.stake_vault.to_account_info(),
+            authority: ctx.accounts.owner.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)?;
+
+        // Only what actually reached the vault is staked
+        let clock = Clock::get()?;
+        let staked = amount_after_transfer_fee(mint, amount, &clock)?;
+
+        let stake_account = &mut ctx.accounts.stake_account;
+        stake_account.owner = ctx.accounts.owner.key();
+        stake_account.staked_amount = stake_account
+            .staked_amount
+            .checked_add(staked)
+            .ok_or(ErrorCode::MathOverflow)?;
+        stake_account.bump = ctx.bumps.stake_account;
+
+        emit_cpi!(Staked {
+            version: EVENT_VERSION,
+            stake_account: stake_account.key(),
+            owner: stake_account.owner,
+            amount: staked,
+            transfer_fee: amount - staked,
+            staked_amount: stake_account.staked_amount,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Start unbonding staked tokens. Unbonding tokens stop counting towards
+    /// holdings immediately, and a referrer must pass their referrer account so
+    /// the drop is recorded against their holding ratio like a sale.
+    pub fn request_unstake(ctx: Context<RequestUnstake>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidStakeAmount);
+
         let program_state = &ctx.accounts.program_state;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        let stake_account = &mut ctx.accounts.stake_account;
+        require!(stake_account.staked_amount >= amount, ErrorCode::InsufficientStake);
+        stake_account.staked_amount -= amount;
+        stake_account.pending_unstake = stake_account
+            .pending_unstake
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        // Adding to a pending unstake restarts the unbonding period
+        stake_account.unlock_timestamp = current_time
+            .checked_add(program_state.unbonding_period)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Leaving the referrer account out would keep the drop off their ratio
+        let registered = read_if_created::<ReferrerLookup>(&ctx.accounts.referrer_lookup)?
+            .map(|referrer_lookup| referrer_lookup.referrer_account);
+        require!(
+            ctx.accounts.referrer_account.as_ref().map(|referrer_account| referrer_account.key()) == registered,
+            ErrorCode::ReferrerAccountMismatch
+        );
+
+        if let Some(referrer_account) = ctx.accounts.referrer_account.as_mut() {
+            let token_account = ctx
+                .accounts
+                .token_account
+                .as_ref()
+                .ok_or(ErrorCode::MissingReferrerTokenAccount)?;
+            let (holdings, gone) = sum_linked_holdings(
+                referrer_account,
+                token_account,
+                stake_account.staked_amount,
+                ctx.remaining_accounts,
+                &program_state.otto_token_mint,
+            )?;
+            referrer_account.unlink(&gone);
+
+            let previous_holdings = referrer_account.current_holdings;
+            referrer_account.record_holdings(program_state, holdings, current_time)?;
+            referrer_account.last_activity = current_time;
 
-        require!(!purchase_account.rewards_claimed, ErrorCode::RewardsAlreadyClaimed);
+            emit_cpi!(HoldingsRatioUpdated {
+                version: EVENT_VERSION,
+                referrer_account: referrer_account.key(),
+                authority: referrer_account.authority,
+                previous_holdings,
+                new_holdings: holdings,
+                twab: referrer_account.twab,
+                holding_ratio: referrer_account.holding_ratio,
+                linked_token_accounts: referrer_account.linked_token_accounts.clone(),
+                slot: clock.slot,
+                timestamp: current_time,
+            });
+        }
+
+        emit_cpi!(UnstakeRequested {
+            version: EVENT_VERSION,
+            stake_account: stake_account.key(),
+            owner: stake_account.owner,
+            amount,
+            staked_amount: stake_account.staked_amount,
+            pending_unstake: stake_account.pending_unstake,
+            unlock_timestamp: stake_account.unlock_timestamp,
+            slot: clock.slot,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /// Withdraw unbonded tokens back to the owner once the unbonding period ends
+    pub fn withdraw(ctx: Context<Withdraw>) -> Result<()> {
+        let stake_account = &mut ctx.accounts.stake_account;
+        let amount = stake_account.pending_unstake;
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+
+        require!(amount > 0, ErrorCode::NothingToWithdraw);
+        require!(
+            current_time >= stake_account.unlock_timestamp,
+            ErrorCode::UnbondingNotElapsed
+        );
+
+        stake_account.pending_unstake = 0;
+
+        let mint = &ctx.accounts.otto_token_mint;
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.stake_vault.to_account_info(),
+            mint: mint.to_account_info(),
+            to: ctx.accounts.owner_token_account.to_account_info(),
+            authority: stake_account.to_account_info(),
+        };
+        let seeds = &[
+            b"stake".as_ref(),
+            stake_account.owner.as_ref(),
+            &[stake_account.bump],
+        ];
+        let signer = &[&seeds[..]];
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)?;
+        let received = amount_after_transfer_fee(mint, amount, &clock)?;
+
+        emit_cpi!(StakeWithdrawn {
+            version: EVENT_VERSION,
+            stake_account: stake_account.key(),
+            owner: stake_account.owner,
+            amount: received,
+            transfer_fee: amount - received,
+            staked_amount: stake_account.staked_amount,
+            slot: clock.slot,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /// Claim pending rewards
+    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
         require!(
-            purchase_account.buyer == ctx.accounts.buyer.key(),
+            ctx.accounts.purchase_account.buyer == ctx.accounts.buyer.key(),
             ErrorCode::UnauthorizedClaim
         );
 
-        // Calculate rewards
-        let rewards_amount = (purchase_account.amount as u128 * program_state.rewards_percentage as u128 / 10_000) as u64;
+        // The buyer signed, so they may send the cashback to any account
+        let destination = match ctx.accounts.destination.as_ref() {
+            Some(destination) => destination,
+            None => ctx
+                .accounts
+                .buyer_token_account
+                .as_ref()
+                .ok_or(ErrorCode::MissingClaimDestination)?,
+        };
+
+        let claimed = pay_cashback(
+            &ctx.accounts.program_state,
+            &mut ctx.accounts.purchase_account,
+            ctx.accounts.price_oracle.as_ref(),
+            &mut ctx.accounts.rewards_pool,
+            destination,
+            &ctx.accounts.payout_mint,
+            &ctx.accounts.token_program,
+            &Clock::get()?,
+        )?;
+        ctx.accounts
+            .stats_shard
+            .load_mut()?
+            .record_rewards(claimed.amount)?;
+        emit_cpi!(claimed);
 
-        // Mark as claimed
-        purchase_account.rewards_claimed = true;
+        Ok(())
+    }
 
-        // Transfer rewards
-        if rewards_amount > 0 {
-            let cpi_accounts = Transfer {
-                from: ctx.accounts.rewards_pool.to_account_info(),
-                to: ctx.accounts.buyer_token_account.to_account_info(),
-                authority: ctx.accounts.program_state.to_account_info(),
-            };
-            let seeds = &[
-                b"program_state".as_ref(),
-                &[program_state.bump],
-            ];
-            let signer = &[&seeds[..]];
-            let cpi_program = ctx.accounts.token_program.to_account_info();
-            let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
-            token::transfer(cpi_ctx, rewards_amount)?;
+    /// Claim cashback on the buyer's behalf. Any relayer can submit and pay
+    /// for the transaction; the buyer authorises it by signing
+    /// `claim_message(purchase_account)` in a preceding ed25519 instruction,
+    /// and the rewards go to the buyer's ATA or their configured payout wallet.
+    pub fn claim_rewards_delegated(ctx: Context<ClaimRewardsDelegated>) -> Result<()> {
+        verify_ed25519_signature(
+            &ctx.accounts.instructions_sysvar,
+            &ctx.accounts.buyer.key(),
+            &claim_message(&ctx.accounts.purchase_account.key()),
+        )?;
+
+        // The relayer picks the accounts, so only the buyer's own settings
+        // can route the cashback away from their ATA
+        let destination = match ctx.accounts.destination.as_ref() {
+            Some(destination) => {
+                let payout_wallet = ctx
+                    .accounts
+                    .user_settings
+                    .as_ref()
+                    .and_then(|settings| settings.payout_wallet);
+                require!(
+                    Some(destination.owner) == payout_wallet,
+                    ErrorCode::InvalidClaimDestination
+                );
+                destination
+            }
+            None => ctx
+                .accounts
+                .buyer_token_account
+                .as_ref()
+                .ok_or(ErrorCode::MissingClaimDestination)?,
+        };
+
+        let claimed = pay_cashback(
+            &ctx.accounts.program_state,
+            &mut ctx.accounts.purchase_account,
+            ctx.accounts.price_oracle.as_ref(),
+            &mut ctx.accounts.rewards_pool,
+            destination,
+            &ctx.accounts.payout_mint,
+            &ctx.accounts.token_program,
+            &Clock::get()?,
+        )?;
+        ctx.accounts
+            .stats_shard
+            .load_mut()?
+            .record_rewards(claimed.amount)?;
+        emit_cpi!(claimed);
+
+        Ok(())
+    }
+
+    /// Quote what claiming a purchase's cashback would pay right now.
+    /// Read-only; the quote is returned as return data.
+    pub fn quote_claim(ctx: Context<QuoteClaim>) -> Result<ClaimQuote> {
+        let program_state = &ctx.accounts.program_state;
+        let purchase_account = &ctx.accounts.purchase_account;
+
+        let clock = Clock::get()?;
+        let cashback = program_state.cashback(purchase_account.amount, purchase_account.referee_bonus)?;
+        let amount = purchase_account.cashback_owed(
+            program_state,
+            ctx.accounts.price_oracle.as_ref(),
+            clock.unix_timestamp,
+        )?;
+        let received = amount_after_transfer_fee(&ctx.accounts.payout_mint, amount, &clock)?;
+
+        Ok(ClaimQuote {
+            purchase: purchase_account.key(),
+            buyer: purchase_account.buyer,
+            payout_mint: purchase_account.payout_mint,
+            cashback,
+            amount: received,
+            transfer_fee: amount - received,
+            claimable: !purchase_account.rewards_claimed && !purchase_account.reversed,
+        })
+    }
+
+    /// Set or clear the wallet that referral payouts and delegated claims may
+    /// be sent to instead of the user's own ATA
+    pub fn set_payout_address(
+        ctx: Context<SetPayoutAddress>,
+        payout_wallet: Option<Pubkey>,
+    ) -> Result<()> {
+        let user_settings = &mut ctx.accounts.user_settings;
+        user_settings.owner = ctx.accounts.owner.key();
+        user_settings.payout_wallet = payout_wallet;
+        user_settings.bump = ctx.bumps.user_settings;
+
+        let clock = Clock::get()?;
+        emit_cpi!(PayoutAddressUpdated {
+            version: EVENT_VERSION,
+            user_settings: user_settings.key(),
+            owner: user_settings.owner,
+            payout_wallet,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to reverse a refunded or fraudulent purchase. Blocks the
+    /// buyer's cashback, charges the referral reward and milestone bonuses
+    /// back to the referrer and takes back the credit the purchase gave them:
+    /// referral counts, referee and leaderboard volume, reopened milestones,
+    /// campaign budget, the buyer's referred purchases and stats shard.
+    /// Rewards still held for the referrer are released from their
+    /// `HeldPayout` instead of being charged back.
+    pub fn reverse_purchase(ctx: Context<ReversePurchase>) -> Result<()> {
+        let purchase_account = &mut ctx.accounts.purchase_account;
+        require!(!purchase_account.reversed, ErrorCode::PurchaseAlreadyReversed);
+        let amount = purchase_account.amount;
+
+        // Referral rewards were paid out immediately, so they are recorded as
+        // owed and withheld from the referrer's future rewards
+        let mut clawback = 0;
+        let mut clawback_owed = 0;
+        let mut payout_released = 0;
+        let mut total_rewards_earned = 0;
+        let mut totals = (0, 0, 0);
+        if let Some(referrer) = purchase_account.referrer {
+            let referrer_account = ctx
+                .accounts
+                .referrer_account
+                .as_mut()
+                .ok_or(ErrorCode::MissingReferrerAccount)?;
+            require_keys_eq!(referrer_account.key(), referrer, ErrorCode::MissingReferrerAccount);
+
+            // The buyer stops counting as a referee once none of their
+            // purchases with this referrer stand
+            let referee_record = ctx
+                .accounts
+                .referee_record
+                .as_mut()
+                .ok_or(ErrorCode::MissingRefereeRecord)?;
+            referee_record.purchases = referee_record
+                .purchases
+                .checked_sub(1)
+                .ok_or(ErrorCode::MathOverflow)?;
+            referee_record.volume = referee_record
+                .volume
+                .checked_sub(amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+            referrer_account.reverse_referral(amount, referee_record.purchases == 0)?;
+
+            // The first-purchase referee bonus goes to the buyer's first
+            // referred purchase that stands
+            let buyer_account = ctx
+                .accounts
+                .buyer_account
+                .as_mut()
+                .ok_or(ErrorCode::MissingBuyerAccount)?;
+            buyer_account.referred_purchases = buyer_account
+                .referred_purchases
+                .checked_sub(1)
+                .ok_or(ErrorCode::MathOverflow)?;
+
+            // Milestones the purchase crossed are reopened and their bonuses
+            // charged back; one still met pays again on the next referral
+            referrer_account.milestones_reached &= !purchase_account.milestones_reached;
+            clawback = purchase_account.referral_reward;
+
+            // Whatever is still held for the referrer is taken back out of
+            // their HeldPayout; only what they were actually paid is owed
+            if purchase_account.referral_held > 0 {
+                let held_payout = ctx
+                    .accounts
+                    .held_payout
+                    .as_mut()
+                    .ok_or(ErrorCode::MissingHeldPayout)?;
+                payout_released = purchase_account.referral_held.min(held_payout.amount);
+                held_payout.amount -= payout_released;
+                let released_reward = paid_share(
+                    purchase_account.referral_held_reward,
+                    payout_released,
+                    purchase_account.referral_held,
+                )?;
+                clawback = clawback
+                    .checked_sub(released_reward)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                referrer_account.total_rewards_earned = referrer_account
+                    .total_rewards_earned
+                    .checked_sub(payout_released)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+            referrer_account.clawback_owed = referrer_account
+                .clawback_owed
+                .checked_add(clawback)
+                .ok_or(ErrorCode::MathOverflow)?;
+            clawback_owed = referrer_account.clawback_owed;
+            total_rewards_earned = referrer_account.total_rewards_earned;
+            totals = (
+                referrer_account.total_referrals,
+                referrer_account.unique_referees,
+                referrer_account.total_referred_volume,
+            );
+
+            // Re-rank the referrer on the leaderboard the volume counted
+            // towards; prizes already settled stand
+            if let Some(leaderboard_id) = purchase_account.leaderboard_id {
+                let leaderboard = ctx
+                    .accounts
+                    .leaderboard
+                    .as_mut()
+                    .filter(|leaderboard| leaderboard.id == leaderboard_id)
+                    .ok_or(ErrorCode::ReversalAccountMismatch)?;
+                if !leaderboard.settled {
+                    let leaderboard_volume = ctx
+                        .accounts
+                        .leaderboard_volume
+                        .as_mut()
+                        .ok_or(ErrorCode::MissingLeaderboardVolume)?;
+                    leaderboard_volume.volume = leaderboard_volume
+                        .volume
+                        .checked_sub(amount)
+                        .ok_or(ErrorCode::MathOverflow)?;
+                    leaderboard.record(referrer_account.authority, leaderboard_volume.volume);
+                }
+            }
+        }
+
+        // Hand back the campaign budget for everything charged back or never
+        // paid; cashback already claimed can't be recovered
+        let mut campaign_refund = 0;
+        if let Some(campaign_key) = purchase_account.campaign {
+            let campaign = ctx
+                .accounts
+                .campaign
+                .as_mut()
+                .filter(|campaign| campaign.key() == campaign_key)
+                .ok_or(ErrorCode::ReversalAccountMismatch)?;
+            campaign_refund = purchase_account.campaign_rewards;
+            if !purchase_account.rewards_claimed {
+                campaign_refund = campaign_refund
+                    .checked_add(purchase_account.cashback.unwrap_or(0))
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+            campaign.spent = campaign
+                .spent
+                .checked_sub(campaign_refund)
+                .ok_or(ErrorCode::MathOverflow)?;
         }
 
-        emit!(RewardsClaimed {
+        ctx.accounts
+            .stats_shard
+            .load_mut()?
+            .reverse_purchase(amount, purchase_account.referrer.is_some(), payout_released)?;
+
+        purchase_account.reversed = true;
+
+        let clock = Clock::get()?;
+        emit_cpi!(PurchaseReversed {
+            version: EVENT_VERSION,
+            purchase: purchase_account.key(),
             buyer: purchase_account.buyer,
-            amount: rewards_amount,
-            timestamp: Clock::get()?.unix_timestamp,
+            referrer: purchase_account.referrer,
+            clawback,
+            clawback_owed,
+            payout_released,
+            total_rewards_earned,
+            total_referrals: totals.0,
+            unique_referees: totals.1,
+            total_referred_volume: totals.2,
+            milestones_reopened: purchase_account.milestones_reached,
+            campaign_refund,
+            cashback_blocked: !purchase_account.rewards_claimed,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
         });
 
         Ok(())
@@ -313,291 +1428,3706 @@ pub mod otto_referral_rewards {
             program_state.gold_threshold = thresholds[2];
         }
 
-        emit!(ProgramParamsUpdated {
+        let clock = Clock::get()?;
+        emit_cpi!(ProgramParamsUpdated {
+            version: EVENT_VERSION,
             authority: ctx.accounts.authority.key(),
             rewards_percentage: program_state.rewards_percentage,
             min_purchase_amount: program_state.min_purchase_amount,
-            timestamp: Clock::get()?.unix_timestamp,
+            tier_thresholds: [
+                program_state.bronze_threshold,
+                program_state.silver_threshold,
+                program_state.gold_threshold,
+            ],
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
-}
 
-/// Calculate dynamic reward based on holdings and purchase volume
-pub fn calculate_dynamic_reward(
-    base_rate: u16,
-    holding_ratio: u16,
-    referrer_holdings: u64,
-    purchase_amount: u64,
-    min_holdings: u64,
-) -> u64 {
-    // Base reward calculation
-    let base_reward = (purchase_amount as u128 * base_rate as u128 / 10_000) as u64;
-    
-    // Apply holding ratio penalty (if user sold tokens)
-    let holding_adjusted_reward = (base_reward as u128 * holding_ratio as u128 / 10_000) as u64;
-    
-    // Apply holdings multiplier (more holdings = higher rewards)
-    let holdings_multiplier = if referrer_holdings >= min_holdings * 10 {
-        15000 // 1.5x for 10x minimum holdings
-    } else if referrer_holdings >= min_holdings * 5 {
-        12500 // 1.25x for 5x minimum holdings
-    } else if referrer_holdings >= min_holdings * 2 {
-        11000 // 1.1x for 2x minimum holdings
-    } else {
-        10000 // 1x for minimum holdings
-    };
-    
-    (holding_adjusted_reward as u128 * holdings_multiplier / 10_000) as u64
-}
+    /// Admin function to update holding ratio and keeper parameters
+    pub fn update_holding_params(
+        ctx: Context<UpdateProgramParams>,
+        holding_baseline: Option<u64>, // 0 = referrer's initial holdings
+        twab_window: Option<i64>, // Seconds
+        ratio_recovery_rate: Option<u16>, // Basis points of ratio per day
+        crank_bounty: Option<u64>, // Paid per penalising refresh_referrer
+        unbonding_period: Option<i64>, // Seconds before unstaked tokens can be withdrawn
+    ) -> Result<()> {
+        let program_state = &mut ctx.accounts.program_state;
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + ProgramState::INIT_SPACE,
-        seeds = [b"program_state"],
-        bump
-    )]
-    pub program_state: Account<'info, ProgramState>,
+        if let Some(baseline) = holding_baseline {
+            program_state.holding_baseline = baseline;
+        }
 
-    #[account(
-        init,
-        payer = authority,
-        associated_token::mint = otto_token_mint,
-        associated_token::authority = program_state,
-    )]
-    pub rewards_pool: Account<'info, TokenAccount>,
+        if let Some(window) = twab_window {
+            require!(window > 0, ErrorCode::InvalidTwabWindow);
+            program_state.twab_window = window;
+        }
 
-    pub otto_token_mint: Account<'info, Mint>,
+        if let Some(rate) = ratio_recovery_rate {
+            program_state.ratio_recovery_rate = rate;
+        }
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+        if let Some(bounty) = crank_bounty {
+            program_state.crank_bounty = bounty;
+        }
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-}
+        if let Some(period) = unbonding_period {
+            require!(period >= 0, ErrorCode::InvalidUnbondingPeriod);
+            program_state.unbonding_period = period;
+        }
 
-#[derive(Accounts)]
-#[instruction(referral_code: String)]
-pub struct RegisterReferrer<'info> {
-    #[account(
-        seeds = [b"program_state"],
-        bump = program_state.bump
-    )]
-    pub program_state: Account<'info, ProgramState>,
+        let clock = Clock::get()?;
+        emit_cpi!(HoldingParamsUpdated {
+            version: EVENT_VERSION,
+            authority: ctx.accounts.authority.key(),
+            holding_baseline: program_state.holding_baseline,
+            twab_window: program_state.twab_window,
+            ratio_recovery_rate: program_state.ratio_recovery_rate,
+            crank_bounty: program_state.crank_bounty,
+            unbonding_period: program_state.unbonding_period,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
 
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + ReferrerAccount::INIT_SPACE,
-        seeds = [b"referrer", referral_code.as_bytes()],
-        bump
-    )]
-    pub referrer_account: Account<'info, ReferrerAccount>,
+        Ok(())
+    }
 
-    #[account(
-        associated_token::mint = program_state.otto_token_mint,
-        associated_token::authority = authority,
-    )]
-    pub token_account: Account<'info, TokenAccount>,
+    /// Admin function to configure the bonus buyers earn for using a referral code
+    pub fn update_referee_bonus(
+        ctx: Context<UpdateProgramParams>,
+        bonus_bps: Option<u16>,
+        first_purchase_only: Option<bool>,
+    ) -> Result<()> {
+        let program_state = &mut ctx.accounts.program_state;
+
+        if let Some(bps) = bonus_bps {
+            require!(bps <= 1000, ErrorCode::InvalidRewardsPercentage); // Max 10%
+            program_state.referee_bonus_bps = bps;
+        }
+
+        if let Some(first_only) = first_purchase_only {
+            program_state.referee_bonus_first_purchase_only = first_only;
+        }
+
+        let clock = Clock::get()?;
+        emit_cpi!(RefereeBonusUpdated {
+            version: EVENT_VERSION,
+            authority: ctx.accounts.authority.key(),
+            bonus_bps: program_state.referee_bonus_bps,
+            first_purchase_only: program_state.referee_bonus_first_purchase_only,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to replace the referrer milestone table. Milestones are
+    /// tracked by position, so referrers keep the bits for indices they have
+    /// already reached.
+    pub fn set_milestones(ctx: Context<UpdateProgramParams>, milestones: Vec<Milestone>) -> Result<()> {
+        require!(milestones.len() <= MAX_MILESTONES, ErrorCode::TooManyMilestones);
+
+        let program_state = &mut ctx.accounts.program_state;
+        program_state.milestones = milestones;
+
+        let clock = Clock::get()?;
+        emit_cpi!(MilestonesUpdated {
+            version: EVENT_VERSION,
+            authority: ctx.accounts.authority.key(),
+            milestones: program_state.milestones.clone(),
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to add or update a reward pool for a payout asset.
+    /// Rewards are converted from $OTTO at `rate_numerator / rate_denominator`,
+    /// or at the rate of `oracle` when one is set.
+    pub fn configure_reward_pool(
+        ctx: Context<ConfigureRewardPool>,
+        rate_numerator: u64,
+        rate_denominator: u64,
+        oracle: Option<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            rate_numerator > 0 && rate_denominator > 0,
+            ErrorCode::InvalidConversionRate
+        );
+        // Only init_price_oracle can create the mint's oracle PDA, so a
+        // price can't be taken from an account the program doesn't control
+        if let Some(oracle) = oracle {
+            let (expected, _) = Pubkey::find_program_address(
+                &[b"price_oracle", ctx.accounts.payout_mint.key().as_ref()],
+                ctx.program_id,
+            );
+            require_keys_eq!(oracle, expected, ErrorCode::PriceOracleMismatch);
+        }
+
+        let program_state = &mut ctx.accounts.program_state;
+        let config = RewardPoolConfig {
+            mint: ctx.accounts.payout_mint.key(),
+            pool: ctx.accounts.rewards_pool.key(),
+            rate_numerator,
+            rate_denominator,
+            oracle,
+        };
+
+        match program_state
+            .reward_pools
+            .iter_mut()
+            .find(|pool| pool.mint == config.mint)
+        {
+            Some(existing) => *existing = config.clone(),
+            None => {
+                require!(
+                    program_state.reward_pools.len() < MAX_REWARD_POOLS,
+                    ErrorCode::TooManyRewardPools
+                );
+                program_state.reward_pools.push(config.clone());
+            }
+        }
+
+        let clock = Clock::get()?;
+        emit_cpi!(RewardPoolConfigured {
+            version: EVENT_VERSION,
+            authority: ctx.accounts.authority.key(),
+            mint: config.mint,
+            pool: config.pool,
+            rate_numerator,
+            rate_denominator,
+            oracle,
+            pool_balance: ctx.accounts.rewards_pool.amount,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to choose the asset new purchases are rewarded in
+    pub fn set_payout_mint(ctx: Context<UpdateProgramParams>, payout_mint: Pubkey) -> Result<()> {
+        let program_state = &mut ctx.accounts.program_state;
+        require!(
+            program_state.reward_pool(&payout_mint).is_some(),
+            ErrorCode::UnsupportedPayoutMint
+        );
+        program_state.payout_mint = payout_mint;
+
+        let clock = Clock::get()?;
+        emit_cpi!(PayoutMintUpdated {
+            version: EVENT_VERSION,
+            authority: ctx.accounts.authority.key(),
+            payout_mint,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to create a price oracle for a payout asset, updated by `updater`
+    pub fn init_price_oracle(
+        ctx: Context<InitPriceOracle>,
+        updater: Pubkey,
+        max_staleness: i64, // Seconds a price stays usable
+    ) -> Result<()> {
+        require!(max_staleness > 0, ErrorCode::InvalidOracleStaleness);
+
+        let price_oracle = &mut ctx.accounts.price_oracle;
+        price_oracle.mint = ctx.accounts.payout_mint.key();
+        price_oracle.updater = updater;
+        price_oracle.rate_numerator = 0;
+        price_oracle.rate_denominator = 0;
+        price_oracle.updated_at = 0;
+        price_oracle.max_staleness = max_staleness;
+        price_oracle.bump = ctx.bumps.price_oracle;
+
+        let clock = Clock::get()?;
+        emit_cpi!(PriceOracleInitialized {
+            version: EVENT_VERSION,
+            price_oracle: price_oracle.key(),
+            mint: price_oracle.mint,
+            updater,
+            max_staleness,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to create a time-boxed referral campaign with its own
+    /// budget, payout asset and reward rate overrides
+    pub fn create_campaign(
+        ctx: Context<CreateCampaign>,
+        campaign_id: u64,
+        params: CampaignParams,
+    ) -> Result<()> {
+        require!(
+            params.end_timestamp > params.start_timestamp,
+            ErrorCode::InvalidCampaignWindow
+        );
+        require!(
+            ctx.accounts.program_state.reward_pool(&params.payout_mint).is_some(),
+            ErrorCode::UnsupportedPayoutMint
+        );
+        require!(
+            params.eligible_codes.len() <= MAX_CAMPAIGN_CODES
+                && params.eligible_codes.iter().all(|code| code.len() <= 32),
+            ErrorCode::TooManyCampaignCodes
+        );
+        require!(
+            params.base_reward_rate.is_none_or(|rate| rate <= 10000)
+                && params
+                    .holdings_multipliers
+                    .is_none_or(|multipliers| multipliers.is_sorted()),
+            ErrorCode::InvalidCampaignRates
+        );
+
+        // Purchases can't pick their campaign; the newest one replaces any
+        // campaign still running
+        ctx.accounts.program_state.active_campaign = Some(ctx.accounts.campaign.key());
+
+        let campaign = &mut ctx.accounts.campaign;
+        campaign.id = campaign_id;
+        campaign.start_timestamp = params.start_timestamp;
+        campaign.end_timestamp = params.end_timestamp;
+        campaign.budget = params.budget;
+        campaign.spent = 0;
+        campaign.payout_mint = params.payout_mint;
+        campaign.base_reward_rate = params.base_reward_rate;
+        campaign.holdings_multipliers = params.holdings_multipliers;
+        campaign.eligible_codes = params.eligible_codes;
+        campaign.bump = ctx.bumps.campaign;
+
+        let clock = Clock::get()?;
+        emit_cpi!(CampaignCreated {
+            version: EVENT_VERSION,
+            campaign: campaign.key(),
+            campaign_id,
+            start_timestamp: campaign.start_timestamp,
+            end_timestamp: campaign.end_timestamp,
+            budget: campaign.budget,
+            payout_mint: campaign.payout_mint,
+            base_reward_rate: campaign.base_reward_rate,
+            holdings_multipliers: campaign.holdings_multipliers,
+            eligible_codes: campaign.eligible_codes.clone(),
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to extend, cut short or top up a campaign
+    pub fn update_campaign(
+        ctx: Context<UpdateCampaign>,
+        end_timestamp: Option<i64>,
+        budget: Option<u64>,
+    ) -> Result<()> {
+        let campaign = &mut ctx.accounts.campaign;
+
+        if let Some(end) = end_timestamp {
+            require!(end > campaign.start_timestamp, ErrorCode::InvalidCampaignWindow);
+            campaign.end_timestamp = end;
+        }
+
+        if let Some(budget) = budget {
+            campaign.budget = budget;
+        }
+
+        let clock = Clock::get()?;
+        emit_cpi!(CampaignUpdated {
+            version: EVENT_VERSION,
+            campaign: campaign.key(),
+            end_timestamp: campaign.end_timestamp,
+            budget: campaign.budget,
+            spent: campaign.spent,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to open a leaderboard period whose top referrers by
+    /// referred volume share `prize_pool` once it ends
+    pub fn create_leaderboard(
+        ctx: Context<CreateLeaderboard>,
+        leaderboard_id: u64,
+        start_timestamp: i64,
+        end_timestamp: i64,
+        prize_mint: Pubkey,
+        prize_pool: u64,
+        prize_splits: [u16; LEADERBOARD_SIZE],
+    ) -> Result<()> {
+        require!(
+            end_timestamp > start_timestamp,
+            ErrorCode::InvalidLeaderboardWindow
+        );
+        require!(
+            ctx.accounts.program_state.reward_pool(&prize_mint).is_some(),
+            ErrorCode::UnsupportedPayoutMint
+        );
+        require_keys_eq!(
+            ctx.accounts.prize_mint.key(),
+            prize_mint,
+            ErrorCode::UnsupportedPayoutMint
+        );
+        require!(
+            prize_splits.iter().map(|split| *split as u64).sum::<u64>() <= 10000,
+            ErrorCode::InvalidPrizeSplits
+        );
+
+        // Set the prize aside now so rewards paid during the period can't
+        // leave the pool short at settlement; the vault holds what arrives
+        let clock = Clock::get()?;
+        let mut funded = 0;
+        if prize_pool > 0 {
+            funded = pay_from_rewards_pool(
+                &ctx.accounts.program_state,
+                &mut ctx.accounts.rewards_pool,
+                &ctx.accounts.prize_vault,
+                &ctx.accounts.prize_mint,
+                &ctx.accounts.token_program,
+                prize_pool,
+                &clock,
+            )?;
+        }
+
+        // As with campaigns, purchases can't pick their leaderboard; the
+        // newest one stops ranking on any still open
+        ctx.accounts.program_state.active_leaderboard = Some(ctx.accounts.leaderboard.key());
+
+        let leaderboard = &mut ctx.accounts.leaderboard;
+        leaderboard.id = leaderboard_id;
+        leaderboard.start_timestamp = start_timestamp;
+        leaderboard.end_timestamp = end_timestamp;
+        leaderboard.prize_mint = prize_mint;
+        leaderboard.prize_pool = funded;
+        leaderboard.prize_splits = prize_splits;
+        leaderboard.entries = [LeaderboardEntry::default(); LEADERBOARD_SIZE];
+        leaderboard.settled = false;
+        leaderboard.prizes_paid = 0;
+        leaderboard.bump = ctx.bumps.leaderboard;
+
+        emit_cpi!(LeaderboardCreated {
+            version: EVENT_VERSION,
+            leaderboard: leaderboard.key(),
+            leaderboard_id,
+            start_timestamp,
+            end_timestamp,
+            prize_mint,
+            prize_pool: funded,
+            prize_splits,
+            prize_vault: ctx.accounts.prize_vault.key(),
+            pool_balance: ctx.accounts.rewards_pool.amount,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Pay out an ended leaderboard from its prize vault (permissionless),
+    /// returning whatever the splits leave over to the rewards pool. The
+    /// remaining accounts are the prize mint ATAs of the ranked referrers, in
+    /// rank order; prizes for ATAs that don't exist or are frozen are held
+    /// for `claim_leaderboard_prize`.
+    pub fn settle_leaderboard<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SettleLeaderboard<'info>>,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let current_time = clock.unix_timestamp;
+        require!(!ctx.accounts.leaderboard.settled, ErrorCode::LeaderboardAlreadySettled);
+        let leaderboard = &ctx.accounts.leaderboard;
+        require!(
+            current_time >= leaderboard.end_timestamp,
+            ErrorCode::LeaderboardNotEnded
+        );
+
+        let ranked: Vec<(usize, LeaderboardEntry)> = leaderboard
+            .entries
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, entry)| entry.volume > 0)
+            .collect();
+        require!(
+            ctx.remaining_accounts.len() == ranked.len(),
+            ErrorCode::InvalidPrizeAccount
+        );
+
+        let mut total_paid: u64 = 0;
+        let mut held: u64 = 0;
+        let mut prizes_paid: u16 = 0;
+        for ((rank, entry), info) in ranked.into_iter().zip(ctx.remaining_accounts) {
+            require_keys_eq!(
+                info.key(),
+                get_associated_token_address_with_program_id(
+                    &entry.referrer,
+                    &ctx.accounts.leaderboard.prize_mint,
+                    &ctx.accounts.token_program.key(),
+                ),
+                ErrorCode::InvalidPrizeAccount
+            );
+            let prize = apply_bps(
+                ctx.accounts.leaderboard.prize_pool,
+                ctx.accounts.leaderboard.prize_splits[rank] as u64,
+            )?;
+            let leaderboard_key = ctx.accounts.leaderboard.key();
+            let leaderboard_id = ctx.accounts.leaderboard.id;
+
+            // A missing or frozen ATA can't block everyone else's prize; the
+            // referrer's stays in the vault for claim_leaderboard_prize
+            let prize_account = (*info.owner == ctx.accounts.token_program.key() && !info.data_is_empty())
+                .then(|| InterfaceAccount::<TokenAccount>::try_from(info))
+                .transpose()?
+                .filter(|prize_account| !prize_account.is_frozen());
+            let Some(prize_account) = prize_account.filter(|_| prize > 0) else {
+                if prize > 0 {
+                    held = held.checked_add(prize).ok_or(ErrorCode::MathOverflow)?;
+                    emit_cpi!(LeaderboardPrizeHeld {
+                        version: EVENT_VERSION,
+                        leaderboard: leaderboard_key,
+                        leaderboard_id,
+                        rank: rank as u8,
+                        referrer: entry.referrer,
+                        volume: entry.volume,
+                        prize,
+                        slot: clock.slot,
+                        timestamp: current_time,
+                    });
+                } else {
+                    prizes_paid |= 1 << rank;
+                }
+                continue;
+            };
+
+            let received = pay_from_prize_vault(
+                &ctx.accounts.leaderboard,
+                &mut ctx.accounts.prize_vault,
+                &prize_account,
+                &ctx.accounts.prize_mint,
+                &ctx.accounts.token_program,
+                prize,
+                &clock,
+            )?;
+            prizes_paid |= 1 << rank;
+            total_paid = total_paid.checked_add(prize).ok_or(ErrorCode::MathOverflow)?;
+
+            emit_cpi!(LeaderboardPrizePaid {
+                version: EVENT_VERSION,
+                leaderboard: leaderboard_key,
+                leaderboard_id,
+                rank: rank as u8,
+                referrer: entry.referrer,
+                prize_account: prize_account.key(),
+                volume: entry.volume,
+                prize: received,
+                transfer_fee: prize - received,
+                vault_balance: ctx.accounts.prize_vault.amount,
+                slot: clock.slot,
+                timestamp: current_time,
+            });
+        }
+
+        // Splits under 100% and unfilled ranks go back to the pool
+        let returned = ctx
+            .accounts
+            .prize_vault
+            .amount
+            .checked_sub(held)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if returned > 0 {
+            pay_from_prize_vault(
+                &ctx.accounts.leaderboard,
+                &mut ctx.accounts.prize_vault,
+                &ctx.accounts.rewards_pool,
+                &ctx.accounts.prize_mint,
+                &ctx.accounts.token_program,
+                returned,
+                &clock,
+            )?;
+            ctx.accounts.rewards_pool.reload()?;
+        }
+
+        let leaderboard = &mut ctx.accounts.leaderboard;
+        leaderboard.settled = true;
+        leaderboard.prizes_paid = prizes_paid;
+
+        emit_cpi!(LeaderboardSettled {
+            version: EVENT_VERSION,
+            leaderboard: leaderboard.key(),
+            leaderboard_id: leaderboard.id,
+            total_paid,
+            held,
+            returned,
+            pool_balance: ctx.accounts.rewards_pool.amount,
+            slot: clock.slot,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /// Claim a leaderboard prize that settlement couldn't pay, into the
+    /// referrer's prize mint ATA
+    pub fn claim_leaderboard_prize(ctx: Context<ClaimLeaderboardPrize>) -> Result<()> {
+        let leaderboard = &ctx.accounts.leaderboard;
+        require!(leaderboard.settled, ErrorCode::LeaderboardNotSettled);
+
+        let referrer = ctx.accounts.referrer.key();
+        let (rank, entry) = leaderboard
+            .entries
+            .iter()
+            .copied()
+            .enumerate()
+            .find(|(rank, entry)| {
+                entry.volume > 0 && entry.referrer == referrer && leaderboard.prizes_paid & (1 << rank) == 0
+            })
+            .ok_or(ErrorCode::NoLeaderboardPrize)?;
+        let prize = apply_bps(leaderboard.prize_pool, leaderboard.prize_splits[rank] as u64)?;
+
+        let clock = Clock::get()?;
+        let received = pay_from_prize_vault(
+            &ctx.accounts.leaderboard,
+            &mut ctx.accounts.prize_vault,
+            &ctx.accounts.prize_account,
+            &ctx.accounts.prize_mint,
+            &ctx.accounts.token_program,
+            prize,
+            &clock,
+        )?;
+        let leaderboard = &mut ctx.accounts.leaderboard;
+        leaderboard.prizes_paid |= 1 << rank;
+
+        emit_cpi!(LeaderboardPrizePaid {
+            version: EVENT_VERSION,
+            leaderboard: leaderboard.key(),
+            leaderboard_id: leaderboard.id,
+            rank: rank as u8,
+            referrer,
+            prize_account: ctx.accounts.prize_account.key(),
+            volume: entry.volume,
+            prize: received,
+            transfer_fee: prize - received,
+            vault_balance: ctx.accounts.prize_vault.amount,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Claim the referral payouts held for a referrer who had no payout
+    /// account, into their payout mint ATA
+    pub fn claim_held_payout(ctx: Context<ClaimHeldPayout>) -> Result<()> {
+        let amount = ctx.accounts.held_payout.amount;
+        require!(amount > 0, ErrorCode::NoHeldPayout);
+
+        let clock = Clock::get()?;
+        let received = pay_from_rewards_pool(
+            &ctx.accounts.program_state,
+            &mut ctx.accounts.rewards_pool,
+            &ctx.accounts.payout_account,
+            &ctx.accounts.payout_mint,
+            &ctx.accounts.token_program,
+            amount,
+            &clock,
+        )?;
+        ctx.accounts.held_payout.amount = 0;
+
+        emit_cpi!(HeldPayoutClaimed {
+            version: EVENT_VERSION,
+            referrer_account: ctx.accounts.referrer_account.key(),
+            referrer: ctx.accounts.authority.key(),
+            payout_mint: ctx.accounts.payout_mint.key(),
+            payout_account: ctx.accounts.payout_account.key(),
+            amount: received,
+            transfer_fee: amount - received,
+            pool_balance: ctx.accounts.rewards_pool.amount,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Admin function to post the Merkle root of a batch of off-chain computed
+    /// rewards, claimable from the reward pool of `mint`
+    pub fn create_merkle_distribution(
+        ctx: Context<CreateMerkleDistribution>,
+        distribution_id: u64,
+        mint: Pubkey,
+        root: [u8; 32],
+        max_claims: u32,
+        total_amount: u64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.program_state.reward_pool(&mint).is_some(),
+            ErrorCode::UnsupportedPayoutMint
+        );
+
+        let distribution = &mut ctx.accounts.distribution;
+        distribution.id = distribution_id;
+        distribution.mint = mint;
+        distribution.root = root;
+        distribution.max_claims = max_claims;
+        distribution.total_amount = total_amount;
+        distribution.claimed_amount = 0;
+        distribution.num_claimed = 0;
+        distribution.claimed_bitmap = vec![0; MerkleDistribution::bitmap_len(max_claims)];
+        distribution.bump = ctx.bumps.distribution;
+
+        let clock = Clock::get()?;
+        emit_cpi!(MerkleDistributionCreated {
+            version: EVENT_VERSION,
+            distribution: distribution.key(),
+            distribution_id,
+            mint,
+            root,
+            max_claims,
+            total_amount,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Claim an off-chain computed reward by proving `(index, claimant, amount)`
+    /// is a leaf of the distribution's Merkle tree
+    pub fn claim_merkle(
+        ctx: Context<ClaimMerkle>,
+        index: u32,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        let distribution = &mut ctx.accounts.distribution;
+        require!(index < distribution.max_claims, ErrorCode::InvalidMerkleProof);
+        require!(!distribution.is_claimed(index), ErrorCode::MerkleAlreadyClaimed);
+
+        let leaf = merkle_leaf(index, &ctx.accounts.claimant.key(), amount);
+        require!(
+            verify_merkle_proof(&proof, distribution.root, leaf),
+            ErrorCode::InvalidMerkleProof
+        );
+
+        // Never pay out more than the root was funded for
+        distribution.claimed_amount = distribution
+            .claimed_amount
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        require!(
+            distribution.claimed_amount <= distribution.total_amount,
+            ErrorCode::MerkleDistributionExhausted
+        );
+        distribution.num_claimed = distribution
+            .num_claimed
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        distribution.set_claimed(index);
+
+        let distribution_key = distribution.key();
+        let distribution_id = distribution.id;
+        let claimed_amount = distribution.claimed_amount;
+        let num_claimed = distribution.num_claimed;
+
+        let clock = Clock::get()?;
+        let mut received = 0;
+        if amount > 0 {
+            received = pay_from_rewards_pool(
+                &ctx.accounts.program_state,
+                &mut ctx.accounts.rewards_pool,
+                &ctx.accounts.claimant_token_account,
+                &ctx.accounts.mint,
+                &ctx.accounts.token_program,
+                amount,
+                &clock,
+            )?;
+        }
+
+        emit_cpi!(MerkleClaimed {
+            version: EVENT_VERSION,
+            distribution: distribution_key,
+            distribution_id,
+            index,
+            claimant: ctx.accounts.claimant.key(),
+            destination: ctx.accounts.claimant_token_account.key(),
+            mint: ctx.accounts.mint.key(),
+            amount: received,
+            transfer_fee: amount - received,
+            claimed_amount,
+            num_claimed,
+            pool_balance: ctx.accounts.rewards_pool.amount,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Post a new $OTTO conversion rate to a price oracle
+    pub fn update_price_oracle(
+        ctx: Context<UpdatePriceOracle>,
+        rate_numerator: u64,
+        rate_denominator: u64,
+    ) -> Result<()> {
+        require!(
+            rate_numerator > 0 && rate_denominator > 0,
+            ErrorCode::InvalidConversionRate
+        );
+
+        let clock = Clock::get()?;
+        let price_oracle = &mut ctx.accounts.price_oracle;
+        price_oracle.rate_numerator = rate_numerator;
+        price_oracle.rate_denominator = rate_denominator;
+        price_oracle.updated_at = clock.unix_timestamp;
+
+        emit_cpi!(PriceOracleUpdated {
+            version: EVENT_VERSION,
+            price_oracle: price_oracle.key(),
+            mint: price_oracle.mint,
+            rate_numerator,
+            rate_denominator,
+            slot: clock.slot,
+            timestamp: price_oracle.updated_at,
+        });
+
+        Ok(())
+    }
+
+    /// Create one of the `STATS_SHARDS` global stats shards. Permissionless;
+    /// every shard must exist before purchases and claims hashed to it.
+    pub fn init_stats_shard(ctx: Context<InitStatsShard>, index: u8) -> Result<()> {
+        require!(index < STATS_SHARDS, ErrorCode::InvalidStatsShard);
+
+        let mut stats_shard = ctx.accounts.stats_shard.load_init()?;
+        stats_shard.version = ACCOUNT_VERSION;
+        stats_shard.index = index;
+        stats_shard.bump = ctx.bumps.stats_shard;
+
+        let clock = Clock::get()?;
+        emit_cpi!(StatsShardInitialized {
+            version: EVENT_VERSION,
+            stats_shard: ctx.accounts.stats_shard.key(),
+            index,
+            payer: ctx.accounts.payer.key(),
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Sum the stats shards into the program state's global totals.
+    /// Permissionless; every shard must be passed in remaining_accounts, in
+    /// index order. Runs at most once per `STATS_AGGREGATION_INTERVAL`, as it
+    /// write-locks the program state every purchase reads.
+    pub fn aggregate_stats<'info>(
+        ctx: Context<'_, '_, 'info, 'info, AggregateStats<'info>>,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        require!(
+            clock.unix_timestamp >= ctx.accounts.program_state.stats_aggregated_at + STATS_AGGREGATION_INTERVAL,
+            ErrorCode::StatsAggregatedTooRecently
+        );
+        require!(
+            ctx.remaining_accounts.len() == STATS_SHARDS as usize,
+            ErrorCode::InvalidStatsShard
+        );
+
+        let mut totals = StatsShard::default();
+        for (index, info) in ctx.remaining_accounts.iter().enumerate() {
+            let loader = AccountLoader::<StatsShard>::try_from(info)?;
+            let stats_shard = loader.load()?;
+            require!(stats_shard.index as usize == index, ErrorCode::InvalidStatsShard);
+            totals.add(&stats_shard)?;
+        }
+
+        let program_state = &mut ctx.accounts.program_state;
+        program_state.total_purchases = totals.total_purchases;
+        program_state.total_purchase_volume = totals.total_purchase_volume;
+        program_state.total_referrals = totals.total_referrals;
+        program_state.total_referred_volume = totals.total_referred_volume;
+        program_state.total_rewards_distributed = totals.total_rewards_distributed;
+        program_state.stats_aggregated_at = clock.unix_timestamp;
+
+        emit_cpi!(StatsAggregated {
+            version: EVENT_VERSION,
+            program_state: program_state.key(),
+            total_purchases: totals.total_purchases,
+            total_purchase_volume: totals.total_purchase_volume,
+            total_referrals: totals.total_referrals,
+            total_referred_volume: totals.total_referred_volume,
+            total_rewards_distributed: totals.total_rewards_distributed,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Upgrade the program state from the unversioned layout (admin only)
+    pub fn migrate_program_state(ctx: Context<MigrateProgramState>) -> Result<()> {
+        let account = ctx.accounts.program_state.to_account_info();
+        let old = read_unversioned::<ProgramState, ProgramStateV0>(&account)?;
+        require_keys_eq!(
+            old.authority,
+            ctx.accounts.authority.key(),
+            anchor_lang::error::ErrorCode::ConstraintHasOne
+        );
+        let space = write_migrated(
+            &account,
+            &ProgramState::from(old),
+            &ctx.accounts.authority,
+            &ctx.accounts.system_program,
+        )?;
+
+        let clock = Clock::get()?;
+        emit_cpi!(AccountMigrated {
+            version: EVENT_VERSION,
+            account: account.key(),
+            payer: ctx.accounts.authority.key(),
+            from_version: 0,
+            to_version: ACCOUNT_VERSION,
+            space: space as u64,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Upgrade a referrer account from the unversioned layout. Permissionless;
+    /// the payer covers the extra rent.
+    pub fn migrate_referrer(ctx: Context<MigrateReferrer>) -> Result<()> {
+        let account = ctx.accounts.referrer_account.to_account_info();
+        let old = read_unversioned::<ReferrerAccount, ReferrerAccountV0>(&account)?;
+        create_referrer_lookup(
+            &ctx.accounts.referrer_lookup,
+            &old.authority,
+            account.key(),
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+        )?;
+        let space = write_migrated(
+            &account,
+            &ReferrerAccount::from(old),
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+        )?;
+
+        let clock = Clock::get()?;
+        emit_cpi!(AccountMigrated {
+            version: EVENT_VERSION,
+            account: account.key(),
+            payer: ctx.accounts.payer.key(),
+            from_version: 0,
+            to_version: ACCOUNT_VERSION,
+            space: space as u64,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Upgrade a purchase account from the unversioned layout. Permissionless;
+    /// the payer covers the extra rent. The program state must be migrated first.
+    pub fn migrate_purchase(ctx: Context<MigratePurchase>) -> Result<()> {
+        let account = ctx.accounts.purchase_account.to_account_info();
+        let old = read_unversioned::<PurchaseAccount, PurchaseAccountV0>(&account)?;
+        let space = write_migrated(
+            &account,
+            &PurchaseAccount::from_v0(old, ctx.accounts.program_state.otto_token_mint),
+            &ctx.accounts.payer,
+            &ctx.accounts.system_program,
+        )?;
+
+        let clock = Clock::get()?;
+        emit_cpi!(AccountMigrated {
+            version: EVENT_VERSION,
+            account: account.key(),
+            payer: ctx.accounts.payer.key(),
+            from_version: 0,
+            to_version: ACCOUNT_VERSION,
+            space: space as u64,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+}
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Maximum number of extra holdings accounts a referrer can link
+pub const MAX_LINKED_TOKEN_ACCOUNTS: usize = 8;
+
+/// Sum `owner`'s balance of `mint` across their ATA, their `staked` amount
+/// and the extra holdings accounts in `extra_accounts`, returning the total
+/// and the extra keys.
+///
+/// Every extra account must be an SPL token account for `mint` owned by
+/// `owner`. No account may be counted twice.
+pub fn sum_holdings(
+    ata: &InterfaceAccount<TokenAccount>,
+    staked: u64,
+    extra_accounts: &[AccountInfo],
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Result<(u64, Vec<Pubkey>)> {
+    require!(
+        extra_accounts.len() <= MAX_LINKED_TOKEN_ACCOUNTS,
+        ErrorCode::TooManyHoldingAccounts
+    );
+
+    let mut total = ata.amount.checked_add(staked).ok_or(ErrorCode::MathOverflow)?;
+    let mut keys: Vec<Pubkey> = Vec::with_capacity(extra_accounts.len());
+    for info in extra_accounts {
+        require!(
+            info.key() != ata.key() && !keys.contains(info.key),
+            ErrorCode::DuplicateHoldingAccount
+        );
+
+        let amount = holding_amount(info, mint, owner)?.ok_or(ErrorCode::InvalidHoldingAccount)?;
+        total = total.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        keys.push(info.key());
+    }
+
+    Ok((total, keys))
+}
+
+/// Sum a referrer's holdings for an unsigned read, where the extra accounts
+/// must be exactly the ones the referrer linked so none can be left out.
+///
+/// A linked account that no longer holds the referrer's tokens, because it
+/// was closed or handed to someone else, counts as zero rather than failing
+/// the read. Returns the total and those accounts, for the caller to unlink.
+pub fn sum_linked_holdings(
+    referrer_account: &ReferrerAccount,
+    ata: &InterfaceAccount<TokenAccount>,
+    staked: u64,
+    extra_accounts: &[AccountInfo],
+    mint: &Pubkey,
+) -> Result<(u64, Vec<Pubkey>)> {
+    let linked = &referrer_account.linked_token_accounts;
+    require!(
+        extra_accounts.len() == linked.len() && extra_accounts.iter().all(|info| linked.contains(info.key)),
+        ErrorCode::LinkedAccountsMismatch
+    );
+
+    let mut total = ata.amount.checked_add(staked).ok_or(ErrorCode::MathOverflow)?;
+    let mut keys: Vec<Pubkey> = Vec::with_capacity(extra_accounts.len());
+    let mut gone = Vec::new();
+    for info in extra_accounts {
+        require!(!keys.contains(info.key), ErrorCode::DuplicateHoldingAccount);
+        keys.push(info.key());
+
+        match holding_amount(info, mint, &referrer_account.authority)? {
+            Some(amount) => total = total.checked_add(amount).ok_or(ErrorCode::MathOverflow)?,
+            None => gone.push(info.key()),
+        }
+    }
+
+    Ok((total, gone))
+}
+
+/// `owner`'s balance of `mint` in a holdings account, an SPL token account
+/// for `mint` owned by `owner`. `None` for any other account, including a
+/// closed one.
+fn holding_amount(info: &AccountInfo, mint: &Pubkey, owner: &Pubkey) -> Result<Option<u64>> {
+    if *info.owner != token::ID && *info.owner != token_2022::ID {
+        return Ok(None);
+    }
+    Ok(TokenAccount::try_deserialize(&mut &info.try_borrow_data()?[..])
+        .ok()
+        .filter(|token_account| token_account.mint == *mint && token_account.owner == *owner)
+        .map(|token_account| token_account.amount))
+}
+
+/// The staked, not unbonding, amount in a stake account PDA, which counts
+/// towards its owner's holdings without being linked. Zero before they stake.
+fn staked_amount(stake_account: &AccountInfo) -> Result<u64> {
+    Ok(read_if_created::<StakeAccount>(stake_account)?.map_or(0, |stake_account| stake_account.staked_amount))
+}
+
+/// Transfer `amount` out of the rewards pool, signed by the program state PDA.
+/// Returns the amount the destination actually receives after any transfer fee.
+/// The pool is reloaded afterwards so events can report its new balance.
+fn pay_from_rewards_pool<'info>(
+    program_state: &Account<'info, ProgramState>,
+    rewards_pool: &mut InterfaceAccount<'info, TokenAccount>,
+    destination: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+    clock: &Clock,
+) -> Result<u64> {
+    let cpi_accounts = TransferChecked {
+        from: rewards_pool.to_account_info(),
+        mint: mint.to_account_info(),
+        to: destination.to_account_info(),
+        authority: program_state.to_account_info(),
+    };
+    let seeds = &[
+        b"program_state".as_ref(),
+        &[program_state.bump],
+    ];
+    let signer = &[&seeds[..]];
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)?;
+    rewards_pool.reload()?;
+
+    amount_after_transfer_fee(mint, amount, clock)
+}
+
+/// Transfer `amount` out of a leaderboard's prize vault, signed by the
+/// leaderboard PDA. Returns the amount the destination actually receives.
+fn pay_from_prize_vault<'info>(
+    leaderboard: &Account<'info, Leaderboard>,
+    prize_vault: &mut InterfaceAccount<'info, TokenAccount>,
+    destination: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+    clock: &Clock,
+) -> Result<u64> {
+    let cpi_accounts = TransferChecked {
+        from: prize_vault.to_account_info(),
+        mint: mint.to_account_info(),
+        to: destination.to_account_info(),
+        authority: leaderboard.to_account_info(),
+    };
+    let id = leaderboard.id.to_le_bytes();
+    let seeds = &[
+        b"leaderboard".as_ref(),
+        id.as_ref(),
+        &[leaderboard.bump],
+    ];
+    let signer = &[&seeds[..]];
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+    token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)?;
+    prize_vault.reload()?;
+
+    amount_after_transfer_fee(mint, amount, clock)
+}
+
+/// Pay a purchase's cashback, plus any referee bonus, to `destination`.
+/// Returns the event for the caller to emit.
+#[allow(clippy::too_many_arguments)]
+fn pay_cashback<'info>(
+    program_state: &Account<'info, ProgramState>,
+    purchase_account: &mut Account<'info, PurchaseAccount>,
+    price_oracle: Option<&Account<'info, PriceOracle>>,
+    rewards_pool: &mut InterfaceAccount<'info, TokenAccount>,
+    destination: &InterfaceAccount<'info, TokenAccount>,
+    payout_mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    clock: &Clock,
+) -> Result<RewardsClaimed> {
+    require!(!purchase_account.rewards_claimed, ErrorCode::RewardsAlreadyClaimed);
+    require!(!purchase_account.reversed, ErrorCode::PurchaseReversed);
+
+    let current_time = clock.unix_timestamp;
+    let rewards_amount = purchase_account.cashback_owed(program_state, price_oracle, current_time)?;
+
+    // Mark as claimed
+    purchase_account.rewards_claimed = true;
+
+    // Transfer rewards
+    let mut received = 0;
+    if rewards_amount > 0 {
+        received = pay_from_rewards_pool(
+            program_state,
+            rewards_pool,
+            destination,
+            payout_mint,
+            token_program,
+            rewards_amount,
+            clock,
+        )?;
+    }
+
+    Ok(RewardsClaimed {
+        version: EVENT_VERSION,
+        purchase: purchase_account.key(),
+        buyer: purchase_account.buyer,
+        destination: destination.key(),
+        payout_mint: purchase_account.payout_mint,
+        amount: received,
+        transfer_fee: rewards_amount - received,
+        pool_balance: rewards_pool.amount,
+        slot: clock.slot,
+        timestamp: current_time,
+    })
+}
+
+/// Message a buyer signs to let a relayer claim `purchase_account` for them
+pub fn claim_message(purchase_account: &Pubkey) -> Vec<u8> {
+    [
+        b"otto-referral-rewards:claim:".as_ref(),
+        crate::ID.as_ref(),
+        purchase_account.as_ref(),
+    ]
+    .concat()
+}
+
+/// Require the instruction before this one to be an ed25519 program
+/// instruction verifying a single signature by `signer` over `message`, with
+/// all of its data inline
+fn verify_ed25519_signature(
+    instructions_sysvar: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<()> {
+    let current_index = load_current_index_checked(instructions_sysvar)?;
+    require!(current_index > 0, ErrorCode::MissingClaimSignature);
+    let ix = load_instruction_at_checked(current_index as usize - 1, instructions_sysvar)?;
+    require_keys_eq!(ix.program_id, ed25519_program::ID, ErrorCode::MissingClaimSignature);
+
+    // Layout: num_signatures u8, padding u8, then one 14-byte offsets struct
+    // of u16s: signature offset/ix, public key offset/ix, message offset/size/ix
+    let data = &ix.data;
+    require!(data.len() >= 16 && data[0] == 1, ErrorCode::InvalidClaimSignature);
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]) as usize;
+    let (signature_ix, public_key_offset, public_key_ix) = (read_u16(4), read_u16(6), read_u16(8));
+    let (message_offset, message_size, message_ix) = (read_u16(10), read_u16(12), read_u16(14));
+
+    // u16::MAX means the data lives in the ed25519 instruction itself
+    let inline = u16::MAX as usize;
+    require!(
+        signature_ix == inline && public_key_ix == inline && message_ix == inline,
+        ErrorCode::InvalidClaimSignature
+    );
+    require!(
+        data.get(public_key_offset..public_key_offset + 32) == Some(signer.as_ref())
+            && data.get(message_offset..message_offset + message_size) == Some(message),
+        ErrorCode::InvalidClaimSignature
+    );
+
+    Ok(())
+}
+
+/// Pay a referrer out of the rewards pool, into their payout token account or
+/// the payout wallet in their settings. Without an account to pay, or with a
+/// frozen one, the payout is added to their `HeldPayout` and stays in the pool
+/// until claim_held_payout. Returns the amounts received and held.
+#[allow(clippy::too_many_arguments)]
+fn pay_referrer<'info>(
+    program_state: &Account<'info, ProgramState>,
+    rewards_pool: &mut InterfaceAccount<'info, TokenAccount>,
+    referrer_payout_account: &Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+    held_payout: &mut Option<Account<'info, HeldPayout>>,
+    referrer_account: &ReferrerAccount,
+    referrer_settings: Option<&Account<'info, UserSettings>>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+    clock: &Clock,
+) -> Result<(u64, u64)> {
+    if let Some(referrer_payout_account) = referrer_payout_account {
+        let payout_wallet = referrer_settings
+            .filter(|settings| settings.owner == referrer_account.authority)
+            .and_then(|settings| settings.payout_wallet);
+        require!(
+            referrer_payout_account.owner == referrer_account.authority
+                || Some(referrer_payout_account.owner) == payout_wallet,
+            ErrorCode::InvalidReferrerPayoutAccount
+        );
+        if !referrer_payout_account.is_frozen() {
+            let received = pay_from_rewards_pool(
+                program_state,
+                rewards_pool,
+                referrer_payout_account,
+                mint,
+                token_program,
+                amount,
+                clock,
+            )?;
+            return Ok((received, 0));
+        }
+    }
+
+    let held_payout = held_payout
+        .as_mut()
+        .ok_or(ErrorCode::MissingReferrerPayoutAccount)?;
+    held_payout.amount = held_payout
+        .amount
+        .checked_add(amount)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok((0, amount))
+}
+
+/// Decode a program PDA that may not have been created yet, in which case it
+/// is still owned by the system program
+fn read_if_created<T: AccountDeserialize + Owner>(account: &AccountInfo) -> Result<Option<T>> {
+    if account.owner != &T::owner() {
+        return Ok(None);
+    }
+    T::try_deserialize(&mut &account.try_borrow_data()?[..]).map(Some)
+}
+
+/// Decode a `T` account still in its unversioned layout `V0`. Accounts are
+/// told apart by size, since `V0` was always allocated at its full `INIT_SPACE`.
+fn read_unversioned<T: anchor_lang::Discriminator + Space, V0: AnchorDeserialize + Space>(
+    account: &AccountInfo,
+) -> Result<V0> {
+    let data = account.try_borrow_data()?;
+    require!(
+        data.len() >= 8 && data[..8] == T::DISCRIMINATOR,
+        anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch
+    );
+    require_neq!(data.len(), 8 + T::INIT_SPACE, ErrorCode::AccountAlreadyMigrated);
+    require_eq!(data.len(), 8 + V0::INIT_SPACE, ErrorCode::UnknownAccountLayout);
+    V0::deserialize(&mut &data[8..])
+        .map_err(|_| error!(anchor_lang::error::ErrorCode::AccountDidNotDeserialize))
+}
+
+/// Grow `account` to the current layout, with `payer` topping up its rent,
+/// and overwrite it with `migrated`. Returns the new data length.
+fn write_migrated<'info, T: AccountSerialize + Space>(
+    account: &AccountInfo<'info>,
+    migrated: &T,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<usize> {
+    let space = 8 + T::INIT_SPACE;
+    let shortfall = Rent::get()?
+        .minimum_balance(space)
+        .saturating_sub(account.lamports());
+    if shortfall > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: payer.to_account_info(),
+                    to: account.clone(),
+                },
+            ),
+            shortfall,
+        )?;
+    }
+
+    account.realloc(space, false)?;
+    let mut data = account.try_borrow_mut_data()?;
+    data.fill(0);
+    migrated.try_serialize(&mut &mut data[..])?;
+    Ok(space)
+}
+
+/// Create `authority`'s `ReferrerLookup`, pointing at `referrer_account`,
+/// for referrers registered before lookups existed. An authority with more
+/// than one such referrer keeps the first lookup created.
+fn create_referrer_lookup<'info>(
+    referrer_lookup: &UncheckedAccount<'info>,
+    authority: &Pubkey,
+    referrer_account: Pubkey,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    let (address, bump) =
+        Pubkey::find_program_address(&[b"referrer_lookup", authority.as_ref()], &crate::ID);
+    require_keys_eq!(referrer_lookup.key(), address, ErrorCode::InvalidReferrerLookup);
+    if referrer_lookup.owner == &crate::ID {
+        return Ok(());
+    }
+
+    let space = 8 + ReferrerLookup::INIT_SPACE;
+    anchor_lang::system_program::create_account(
+        CpiContext::new_with_signer(
+            system_program.to_account_info(),
+            anchor_lang::system_program::CreateAccount {
+                from: payer.to_account_info(),
+                to: referrer_lookup.to_account_info(),
+            },
+            &[&[b"referrer_lookup", authority.as_ref(), &[bump]]],
+        ),
+        Rent::get()?.minimum_balance(space),
+        space as u64,
+        &crate::ID,
+    )?;
+    let mut data = referrer_lookup.try_borrow_mut_data()?;
+    ReferrerLookup { referrer_account, bump }.try_serialize(&mut &mut data[..])
+}
+
+/// Leaf of a Merkle distribution: `keccak(0x00 || index || claimant || amount)`,
+/// with integers little-endian. The prefix keeps leaves and nodes apart.
+pub fn merkle_leaf(index: u32, claimant: &Pubkey, amount: u64) -> [u8; 32] {
+    keccak::hashv(&[
+        &[0],
+        &index.to_le_bytes(),
+        claimant.as_ref(),
+        &amount.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+/// Verify `leaf` against `root`. Nodes are `keccak(0x01 || min(a, b) || max(a, b))`,
+/// so proofs carry no left/right flags.
+pub fn verify_merkle_proof(proof: &[[u8; 32]], root: [u8; 32], leaf: [u8; 32]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        let (first, second) = if node <= *sibling {
+            (node, *sibling)
+        } else {
+            (*sibling, node)
+        };
+        keccak::hashv(&[&[1], &first, &second]).to_bytes()
+    });
+    computed == root
+}
+
+/// Fee withheld when transferring `amount` of `mint`. Always zero for legacy
+/// SPL mints and Token-2022 mints without the transfer-fee extension.
+pub fn transfer_fee(mint: &InterfaceAccount<Mint>, amount: u64, clock: &Clock) -> Result<u64> {
+    let mint_info = mint.to_account_info();
+    if *mint_info.owner != token_2022::ID {
+        return Ok(0);
+    }
+
+    let mint_data = mint_info.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    match mint_state.get_extension::<TransferFeeConfig>() {
+        Ok(fee_config) => fee_config
+            .calculate_epoch_fee(clock.epoch, amount)
+            .ok_or_else(|| error!(ErrorCode::MathOverflow)),
+        Err(_) => Ok(0),
+    }
+}
+
+/// Amount the destination of a transfer of `amount` of `mint` receives
+pub fn amount_after_transfer_fee(
+    mint: &InterfaceAccount<Mint>,
+    amount: u64,
+    clock: &Clock,
+) -> Result<u64> {
+    amount
+        .checked_sub(transfer_fee(mint, amount, clock)?)
+        .ok_or_else(|| error!(ErrorCode::MathOverflow))
+}
+
+pub use otto_reward_math::{RewardBreakdown, DEFAULT_HOLDINGS_MULTIPLIERS};
+
+/// Calculate dynamic reward based on holdings and purchase volume. The math
+/// lives in `otto-reward-math` so off-chain simulators share it.
+pub fn calculate_dynamic_reward(
+    base_rate: u16,
+    holding_ratio: u16,
+    referrer_holdings: u64,
+    purchase_amount: u64,
+    min_holdings: u64,
+    holdings_multipliers: [u16; 3], // [2x, 5x, 10x] minimum holdings
+) -> Result<u64> {
+    otto_reward_math::calculate_dynamic_reward(
+        base_rate,
+        holding_ratio,
+        referrer_holdings,
+        purchase_amount,
+        min_holdings,
+        holdings_multipliers,
+    )
+    .ok_or_else(|| error!(ErrorCode::MathOverflow))
+}
+
+/// Multiply `amount` by `bps / 10_000`, failing instead of truncating on overflow
+pub fn apply_bps(amount: u64, bps: u64) -> Result<u64> {
+    otto_reward_math::apply_bps(amount, bps).ok_or_else(|| error!(ErrorCode::MathOverflow))
+}
+
+/// The share of `otto_amount` covered when only `paid` of its `payout` units
+/// are paid out; only that share can be clawed back later
+fn paid_share(otto_amount: u64, paid: u64, payout: u64) -> Result<u64> {
+    if paid >= payout {
+        return Ok(otto_amount);
+    }
+    let share = (otto_amount as u128)
+        .checked_mul(paid as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(payout as u128)
+        .ok_or(ErrorCode::MathOverflow)?;
+    u64::try_from(share).map_err(|_| error!(ErrorCode::MathOverflow))
+}
+
+/// A referral reward as `process_purchase` computes it, before anything is paid
+pub struct ReferralReward {
+    pub breakdown: RewardBreakdown, // In $OTTO
+    pub clawback: u64, // $OTTO netted against the referrer's owed clawbacks
+    pub chargeable: u64, // $OTTO charged back if the purchase is reversed
+    pub payout: u64, // Payout units sent to the referrer, before transfer fees
+}
+
+/// Compute the referral reward for a purchase from a referrer whose live
+/// holdings were just recorded, based on:
+/// 1. Referrer's holding ratio (penalized if they sold tokens)
+/// 2. Referrer's current holdings (more holdings = higher multiplier)
+/// 3. Purchase volume
+pub fn referral_reward(
+    program_state: &ProgramState,
+    referrer_account: &ReferrerAccount,
+    campaign: Option<&Account<Campaign>>,
+    payout_rate: PayoutRate,
+    purchase_amount: u64,
+) -> Result<ReferralReward> {
+    let (base_reward_rate, holdings_multipliers) = match campaign {
+        Some(campaign) => (
+            campaign.base_reward_rate.unwrap_or(program_state.base_reward_rate),
+            campaign.holdings_multipliers.unwrap_or(DEFAULT_HOLDINGS_MULTIPLIERS),
+        ),
+        None => (program_state.base_reward_rate, DEFAULT_HOLDINGS_MULTIPLIERS),
+    };
+    let breakdown = otto_reward_math::simulate_purchase(
+        base_reward_rate,
+        referrer_account.holding_ratio,
+        referrer_account.current_holdings,
+        purchase_amount,
+        program_state.min_holdings_for_referral,
+        holdings_multipliers,
+    )
+    .ok_or(ErrorCode::MathOverflow)?;
+
+    // Rewards clawed back from reversed purchases are netted
+    // against new rewards until the referrer has repaid them
+    let clawback = referrer_account.clawback_owed.min(breakdown.final_reward);
+    let mut chargeable = breakdown.final_reward - clawback;
+    let mut payout = payout_rate.convert(chargeable)?;
+
+    // Campaign rewards come out of its budget and stop when it runs out
+    if let Some(campaign) = campaign {
+        let capped = payout.min(campaign.remaining_budget());
+        chargeable = paid_share(chargeable, capped, payout)?;
+        payout = capped;
+    }
+
+    Ok(ReferralReward {
+        breakdown,
+        clawback,
+        chargeable,
+        payout,
+    })
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ProgramState::INIT_SPACE,
+        seeds = [b"program_state"],
+        bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    #[account(
+        init,
+        payer = authority,
+        associated_token::mint = otto_token_mint,
+        associated_token::authority = program_state,
+        associated_token::token_program = token_program,
+    )]
+    pub rewards_pool: InterfaceAccount<'info, TokenAccount>,
+
+    pub otto_token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(referral_code: String)]
+pub struct RegisterReferrer<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ReferrerAccount::INIT_SPACE,
+        seeds = [b"referrer", referral_code.as_bytes()],
+        bump
+    )]
+    pub referrer_account: Account<'info, ReferrerAccount>,
+
+    /// One referral code per wallet
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ReferrerLookup::INIT_SPACE,
+        seeds = [b"referrer_lookup", authority.key().as_ref()],
+        bump
+    )]
+    pub referrer_lookup: Account<'info, ReferrerLookup>,
+
+    #[account(
+        associated_token::mint = program_state.otto_token_mint,
+        associated_token::authority = authority,
+        associated_token::token_program = token_program,
+    )]
+    pub token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: The authority's `StakeAccount` PDA, read for holdings if it has
+    /// been created
+    #[account(
+        seeds = [b"stake", authority.key().as_ref()],
+        bump
+    )]
+    pub stake_account: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ProcessPurchase<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Box<Account<'info, ProgramState>>,
+
+    #[account(
+        init,
+        payer = buyer,
+        space = 8 + PurchaseAccount::INIT_SPACE,
+        seeds = [b"purchase", buyer.key().as_ref(), &Clock::get()?.unix_timestamp.to_le_bytes()],
+        bump
+    )]
+    pub purchase_account: Box<Account<'info, PurchaseAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = 8 + BuyerAccount::INIT_SPACE,
+        seeds = [b"buyer", buyer.key().as_ref()],
+        bump
+    )]
+    pub buyer_account: Box<Account<'info, BuyerAccount>>,
+
+    #[account(mut)]
+    pub referrer_account: Option<Box<Account<'info, ReferrerAccount>>>,
+
+    /// The buyer's record with the referrer; only passed with a referral
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = 8 + RefereeRecord::INIT_SPACE,
+        seeds = [
+            b"referee",
+            referrer_account.as_ref().map(|r| r.key()).unwrap_or_default().as_ref(),
+            buyer.key().as_ref()
+        ],
+        bump
+    )]
+    pub referee_record: Option<Account<'info, RefereeRecord>>,
+
+    /// The referrer's $OTTO ATA, read for holdings
+    pub referrer_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: The referrer's `StakeAccount` PDA, read for holdings if it has
+    /// been created; required with the referrer
+    #[account(
+        seeds = [
+            b"stake",
+            referrer_account.as_ref().map(|r| r.authority).unwrap_or_default().as_ref()
+        ],
+        bump
+    )]
+    pub referrer_stake_account: Option<UncheckedAccount<'info>>,
+
+    /// The campaign's payout mint when a campaign applies, else the global one
+    pub payout_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = payout_mint,
+        associated_token::authority = program_state,
+        associated_token::token_program = payout_token_program,
+    )]
+    pub rewards_pool: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        token::mint = payout_mint,
+        token::token_program = payout_token_program,
+    )]
+    pub referrer_payout_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Required when paying the referrer's configured payout wallet
+    pub referrer_settings: Option<Account<'info, UserSettings>>,
+
+    /// Holds the referrer's payouts when there is no payout account to pay;
+    /// only passed with a referral
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = 8 + HeldPayout::INIT_SPACE,
+        seeds = [
+            b"held_payout",
+            referrer_account.as_ref().map(|r| r.key()).unwrap_or_default().as_ref(),
+            payout_mint.key().as_ref()
+        ],
+        bump
+    )]
+    pub held_payout: Option<Account<'info, HeldPayout>>,
+
+    pub price_oracle: Option<Account<'info, PriceOracle>>,
+
+    #[account(
+        mut,
+        seeds = [b"campaign", campaign.id.to_le_bytes().as_ref()],
+        bump = campaign.bump
+    )]
+    pub campaign: Option<Box<Account<'info, Campaign>>>,
+
+    #[account(
+        mut,
+        seeds = [b"leaderboard", leaderboard.id.to_le_bytes().as_ref()],
+        bump = leaderboard.bump
+    )]
+    pub leaderboard: Option<Box<Account<'info, Leaderboard>>>,
+
+    /// The referrer's volume on `leaderboard`, required with it on a referral
+    /// and omitted otherwise
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = 8 + LeaderboardVolume::INIT_SPACE,
+        seeds = [
+            b"leaderboard_volume",
+            leaderboard.as_ref().map(|l| l.key()).unwrap_or_default().as_ref(),
+            referrer_account.as_ref().map(|r| r.key()).unwrap_or_default().as_ref()
+        ],
+        bump
+    )]
+    pub leaderboard_volume: Option<Account<'info, LeaderboardVolume>>,
+
+    /// The buyer's shard of the global stats
+    #[account(
+        mut,
+        seeds = [b"stats_shard", [stats_shard_index(&buyer.key())].as_ref()],
+        bump = stats_shard.load()?.bump
+    )]
+    pub stats_shard: AccountLoader<'info, StatsShard>,
+
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    /// Token program of the $OTTO mint
+    pub token_program: Interface<'info, TokenInterface>,
+    /// Token program of the payout mint
+    pub payout_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct QuoteReferralReward<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Box<Account<'info, ProgramState>>,
+
+    /// CHECK: The buyer's `BuyerAccount` PDA, read if it has been created so
+    /// first-purchase bonuses can't be quoted to a returning buyer
+    #[account(
+        seeds = [b"buyer", buyer.key().as_ref()],
+        bump
+    )]
+    pub buyer_account: UncheckedAccount<'info>,
+
+    pub referrer_account: Option<Box<Account<'info, ReferrerAccount>>>,
+
+    /// CHECK: The buyer's `RefereeRecord` PDA with the referrer, read if it
+    /// has been created
+    #[account(
+        seeds = [
+            b"referee",
+            referrer_account.as_ref().map(|r| r.key()).unwrap_or_default().as_ref(),
+            buyer.key().as_ref()
+        ],
+        bump
+    )]
+    pub referee_record: UncheckedAccount<'info>,
+
+    /// The referrer's $OTTO ATA, read for holdings
+    pub referrer_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// CHECK: The referrer's `StakeAccount` PDA, read for holdings if it has
+    /// been created; required with the referrer
+    #[account(
+        seeds = [
+            b"stake",
+            referrer_account.as_ref().map(|r| r.authority).unwrap_or_default().as_ref()
+        ],
+        bump
+    )]
+    pub referrer_stake_account: Option<UncheckedAccount<'info>>,
+
+    /// The campaign's payout mint when a campaign applies, else the global one
+    pub payout_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    pub price_oracle: Option<Account<'info, PriceOracle>>,
+
+    #[account(
+        seeds = [b"campaign", campaign.id.to_le_bytes().as_ref()],
+        bump = campaign.bump
+    )]
+    pub campaign: Option<Box<Account<'info, Campaign>>>,
+
+    /// CHECK: Only its key is read; quotes need no signature
+    pub buyer: UncheckedAccount<'info>,
+
+    /// Token program of the $OTTO mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UpdateHoldingsRatio<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    #[account(
+        mut,
+        seeds = [b"referrer", referrer_account.referral_code.as_bytes()],
+        bump = referrer_account.bump,
+        has_one = authority
+    )]
+    pub referrer_account: Account<'info, ReferrerAccount>,
+
+    #[account(
+        associated_token::mint = program_state.otto_token_mint,
+        associated_token::authority = authority,
+        associated_token::token_program = token_program,
+    )]
+    pub token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: The authority's `StakeAccount` PDA, read for holdings if it has
+    /// been created
+    #[account(
+        seeds = [b"stake", authority.key().as_ref()],
+        bump
+    )]
+    pub stake_account: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UpdateTier<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    #[account(
+        mut,
+        seeds = [b"referrer", referrer_account.referral_code.as_bytes()],
+        bump = referrer_account.bump,
+        has_one = authority
+    )]
+    pub referrer_account: Account<'info, ReferrerAccount>,
+
+    #[account(
+        associated_token::mint = program_state.otto_token_mint,
+        associated_token::authority = authority,
+        associated_token::token_program = token_program,
+    )]
+    pub token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: The authority's `StakeAccount` PDA, read for holdings if it has
+    /// been created
+    #[account(
+        seeds = [b"stake", authority.key().as_ref()],
+        bump
+    )]
+    pub stake_account: UncheckedAccount<'info>,
+
+    pub authority: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RefreshReferrer<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Box<Account<'info, ProgramState>>,
+
+    #[account(
+        mut,
+        seeds = [b"referrer", referrer_account.referral_code.as_bytes()],
+        bump = referrer_account.bump
+    )]
+    pub referrer_account: Box<Account<'info, ReferrerAccount>>,
+
+    #[account(address = program_state.otto_token_mint)]
+    pub otto_token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        associated_token::mint = program_state.otto_token_mint,
+        associated_token::authority = referrer_account.authority,
+        associated_token::token_program = token_program,
+    )]
+    pub token_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// CHECK: The referrer's `StakeAccount` PDA, read for holdings if it has
+    /// been created
+    #[account(
+        seeds = [b"stake", referrer_account.authority.as_ref()],
+        bump
+    )]
+    pub stake_account: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = program_state.otto_token_mint,
+        associated_token::authority = program_state,
+        associated_token::token_program = token_program,
+    )]
+    pub rewards_pool: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        token::mint = program_state.otto_token_mint,
+        token::token_program = token_program,
+    )]
+    pub cranker_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    pub cranker: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct GetReferrerSummary<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    #[account(
+        seeds = [b"referrer", referrer_account.referral_code.as_bytes()],
+        bump = referrer_account.bump
+    )]
+    pub referrer_account: Account<'info, ReferrerAccount>,
+
+    #[account(
+        associated_token::mint = program_state.otto_token_mint,
+        associated_token::authority = referrer_account.authority,
+        associated_token::token_program = token_program,
+    )]
+    pub token_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: The referrer's `StakeAccount` PDA, read for holdings if it has
+    /// been created
+    #[account(
+        seeds = [b"stake", referrer_account.authority.as_ref()],
+        bump
+    )]
+    pub stake_account: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Stake<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + StakeAccount::INIT_SPACE,
+        seeds = [b"stake", owner.key().as_ref()],
+        bump
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        associated_token::mint = otto_token_mint,
+        associated_token::authority = stake_account,
+        associated_token::token_program = token_program,
+    )]
+    pub stake_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = program_state.otto_token_mint)]
+    pub otto_token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        token::mint = otto_token_mint,
+        token::authority = owner,
+        token::token_program = token_program,
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RequestUnstake<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    #[account(
+        mut,
+        seeds = [b"stake", owner.key().as_ref()],
+        bump = stake_account.bump,
+        has_one = owner
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+
+    /// Required when the owner is a referrer
+    #[account(
+        mut,
+        constraint = referrer_account.authority == owner.key() @ ErrorCode::UnauthorizedReferrer
+    )]
+    pub referrer_account: Option<Account<'info, ReferrerAccount>>,
+
+    /// CHECK: The owner's `ReferrerLookup` PDA, read if it has been created
+    #[account(
+        seeds = [b"referrer_lookup", owner.key().as_ref()],
+        bump
+    )]
+    pub referrer_lookup: UncheckedAccount<'info>,
+
+    #[account(
+        associated_token::mint = program_state.otto_token_mint,
+        associated_token::authority = owner,
+        associated_token::token_program = token_program,
+    )]
+    pub token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub owner: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    #[account(
+        mut,
+        seeds = [b"stake", owner.key().as_ref()],
+        bump = stake_account.bump,
+        has_one = owner
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+
+    #[account(address = program_state.otto_token_mint)]
+    pub otto_token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = program_state.otto_token_mint,
+        associated_token::authority = stake_account,
+        associated_token::token_program = token_program,
+    )]
+    pub stake_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = program_state.otto_token_mint,
+        token::authority = owner,
+        token::token_program = token_program,
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub owner: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Box<Account<'info, ProgramState>>,
+
+    #[account(
+        mut,
+        has_one = buyer
+    )]
+    pub purchase_account: Box<Account<'info, PurchaseAccount>>,
+
+    #[account(address = purchase_account.payout_mint)]
+    pub payout_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = payout_mint,
+        associated_token::authority = program_state,
+        associated_token::token_program = token_program,
+    )]
+    pub rewards_pool: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// The buyer's ATA, used when no destination is given
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = payout_mint,
+        associated_token::authority = buyer,
+        associated_token::token_program = token_program,
+    )]
+    pub buyer_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Any token account for the payout mint, e.g. cold storage
+    #[account(
+        mut,
+        token::mint = payout_mint,
+        token::token_program = token_program,
+    )]
+    pub destination: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// Only read for purchases migrated from version 0, whose cashback converts at claim
+    pub price_oracle: Option<Account<'info, PriceOracle>>,
+
+    /// The buyer's shard of the global stats
+    #[account(
+        mut,
+        seeds = [b"stats_shard", [stats_shard_index(&buyer.key())].as_ref()],
+        bump = stats_shard.load()?.bump
+    )]
+    pub stats_shard: AccountLoader<'info, StatsShard>,
+
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimRewardsDelegated<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Box<Account<'info, ProgramState>>,
+
+    #[account(
+        mut,
+        has_one = buyer
+    )]
+    pub purchase_account: Box<Account<'info, PurchaseAccount>>,
+
+    #[account(address = purchase_account.payout_mint)]
+    pub payout_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = payout_mint,
+        associated_token::authority = program_state,
+        associated_token::token_program = token_program,
+    )]
+    pub rewards_pool: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// The buyer's ATA, used when no destination is given
+    #[account(
+        init_if_needed,
+        payer = relayer,
+        associated_token::mint = payout_mint,
+        associated_token::authority = buyer,
+        associated_token::token_program = token_program,
+    )]
+    pub buyer_token_account: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    /// A token account of the buyer's configured payout wallet
+    #[account(
+        mut,
+        token::mint = payout_mint,
+        token::token_program = token_program,
+    )]
+    pub destination: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
+
+    #[account(
+        seeds = [b"user_settings", buyer.key().as_ref()],
+        bump = user_settings.bump
+    )]
+    pub user_settings: Option<Account<'info, UserSettings>>,
+
+    /// Only read for purchases migrated from version 0, whose cashback converts at claim
+    pub price_oracle: Option<Account<'info, PriceOracle>>,
+
+    /// The buyer's shard of the global stats
+    #[account(
+        mut,
+        seeds = [b"stats_shard", [stats_shard_index(&buyer.key())].as_ref()],
+        bump = stats_shard.load()?.bump
+    )]
+    pub stats_shard: AccountLoader<'info, StatsShard>,
+
+    /// CHECK: The beneficiary; authorises the claim through the ed25519 instruction
+    pub buyer: UncheckedAccount<'info>,
+
+    /// Submits the transaction and pays for the buyer's ATA
+    #[account(mut)]
+    pub relayer: Signer<'info>,
+
+    /// CHECK: Instructions sysvar, read to find the ed25519 instruction
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions_sysvar: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct QuoteClaim<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    pub purchase_account: Account<'info, PurchaseAccount>,
+
+    #[account(address = purchase_account.payout_mint)]
+    pub payout_mint: InterfaceAccount<'info, Mint>,
+
+    /// Only read for purchases migrated from version 0, whose cashback converts at claim
+    pub price_oracle: Option<Account<'info, PriceOracle>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SetPayoutAddress<'info> {
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + UserSettings::INIT_SPACE,
+        seeds = [b"user_settings", owner.key().as_ref()],
+        bump
+    )]
+    pub user_settings: Account<'info, UserSettings>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ReversePurchase<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump,
+        has_one = authority
+    )]
+    pub program_state: Box<Account<'info, ProgramState>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"purchase",
+            purchase_account.buyer.as_ref(),
+            purchase_account.timestamp.to_le_bytes().as_ref()
+        ],
+        bump = purchase_account.bump
+    )]
+    pub purchase_account: Box<Account<'info, PurchaseAccount>>,
+
+    /// Required when the purchase paid a referral reward
+    #[account(mut)]
+    pub referrer_account: Option<Box<Account<'info, ReferrerAccount>>>,
+
+    /// The buyer's record with the referrer, required with it
+    #[account(
+        mut,
+        seeds = [
+            b"referee",
+            referrer_account.as_ref().map(|r| r.key()).unwrap_or_default().as_ref(),
+            purchase_account.buyer.as_ref()
+        ],
+        bump = referee_record.bump
+    )]
+    pub referee_record: Option<Account<'info, RefereeRecord>>,
+
+    /// The buyer's purchase history, required with the referrer
+    #[account(
+        mut,
+        seeds = [b"buyer", purchase_account.buyer.as_ref()],
+        bump = buyer_account.bump
+    )]
+    pub buyer_account: Option<Account<'info, BuyerAccount>>,
+
+    /// Required when the purchase applied to a campaign
+    #[account(mut)]
+    pub campaign: Option<Box<Account<'info, Campaign>>>,
+
+    /// Required when the purchase was ranked on a leaderboard
+    #[account(mut)]
+    pub leaderboard: Option<Box<Account<'info, Leaderboard>>>,
+
+    /// The referrer's volume on `leaderboard`, required with it until it settles
+    #[account(
+        mut,
+        seeds = [
+            b"leaderboard_volume",
+            leaderboard.as_ref().map(|l| l.key()).unwrap_or_default().as_ref(),
+            referrer_account.as_ref().map(|r| r.key()).unwrap_or_default().as_ref()
+        ],
+        bump = leaderboard_volume.bump
+    )]
+    pub leaderboard_volume: Option<Account<'info, LeaderboardVolume>>,
+
+    /// The referrer's held payouts, required when the purchase's reward was held
+    #[account(
+        mut,
+        seeds = [
+            b"held_payout",
+            referrer_account.as_ref().map(|r| r.key()).unwrap_or_default().as_ref(),
+            purchase_account.payout_mint.as_ref()
+        ],
+        bump = held_payout.bump
+    )]
+    pub held_payout: Option<Account<'info, HeldPayout>>,
+
+    /// The buyer's shard of the global stats
+    #[account(
+        mut,
+        seeds = [b"stats_shard", [stats_shard_index(&purchase_account.buyer)].as_ref()],
+        bump = stats_shard.load()?.bump
+    )]
+    pub stats_shard: AccountLoader<'info, StatsShard>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UpdateProgramParams<'info> {
+    #[account(
+        mut,
+        seeds = [b"program_state"],
+        bump = program_state.bump,
+        has_one = authority
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ConfigureRewardPool<'info> {
+    #[account(
+        mut,
+        seeds = [b"program_state"],
+        bump = program_state.bump,
+        has_one = authority
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    pub payout_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        associated_token::mint = payout_mint,
+        associated_token::authority = program_state,
+        associated_token::token_program = token_program,
+    )]
+    pub rewards_pool: InterfaceAccount<'info, TokenAccount>,
 
     #[account(mut)]
     pub authority: Signer<'info>,
 
     pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
+#[event_cpi]
 #[derive(Accounts)]
-pub struct ProcessPurchase<'info> {
+pub struct InitPriceOracle<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump,
+        has_one = authority
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    pub payout_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PriceOracle::INIT_SPACE,
+        seeds = [b"price_oracle", payout_mint.key().as_ref()],
+        bump
+    )]
+    pub price_oracle: Account<'info, PriceOracle>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UpdatePriceOracle<'info> {
+    #[account(
+        mut,
+        seeds = [b"price_oracle", price_oracle.mint.as_ref()],
+        bump = price_oracle.bump,
+        has_one = updater
+    )]
+    pub price_oracle: Account<'info, PriceOracle>,
+
+    pub updater: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(campaign_id: u64)]
+pub struct CreateCampaign<'info> {
+    #[account(
+        mut,
+        seeds = [b"program_state"],
+        bump = program_state.bump,
+        has_one = authority
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Campaign::INIT_SPACE,
+        seeds = [b"campaign", campaign_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub campaign: Account<'info, Campaign>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UpdateCampaign<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump,
+        has_one = authority
+    )]
+    pub program_state: Account<'info, ProgramState>,
+
+    #[account(
+        mut,
+        seeds = [b"campaign", campaign.id.to_le_bytes().as_ref()],
+        bump = campaign.bump
+    )]
+    pub campaign: Account<'info, Campaign>,
+
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(leaderboard_id: u64)]
+pub struct CreateLeaderboard<'info> {
+    #[account(
+        mut,
+        seeds = [b"program_state"],
+        bump = program_state.bump,
+        has_one = authority
+    )]
+    pub program_state: Box<Account<'info, ProgramState>>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Leaderboard::INIT_SPACE,
+        seeds = [b"leaderboard", leaderboard_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub leaderboard: Box<Account<'info, Leaderboard>>,
+
+    /// The `prize_mint` argument's mint
+    pub prize_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = prize_mint,
+        associated_token::authority = program_state,
+        associated_token::token_program = token_program,
+    )]
+    pub rewards_pool: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    /// Holds the prize pool until settlement
+    #[account(
+        init,
+        payer = authority,
+        associated_token::mint = prize_mint,
+        associated_token::authority = leaderboard,
+        associated_token::token_program = token_program,
+    )]
+    pub prize_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SettleLeaderboard<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Box<Account<'info, ProgramState>>,
+
+    #[account(
+        mut,
+        seeds = [b"leaderboard", leaderboard.id.to_le_bytes().as_ref()],
+        bump = leaderboard.bump
+    )]
+    pub leaderboard: Box<Account<'info, Leaderboard>>,
+
+    #[account(address = leaderboard.prize_mint)]
+    pub prize_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = prize_mint,
+        associated_token::authority = program_state,
+        associated_token::token_program = token_program,
+    )]
+    pub rewards_pool: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = prize_mint,
+        associated_token::authority = leaderboard,
+        associated_token::token_program = token_program,
+    )]
+    pub prize_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimLeaderboardPrize<'info> {
+    #[account(
+        mut,
+        seeds = [b"leaderboard", leaderboard.id.to_le_bytes().as_ref()],
+        bump = leaderboard.bump
+    )]
+    pub leaderboard: Account<'info, Leaderboard>,
+
+    #[account(address = leaderboard.prize_mint)]
+    pub prize_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = prize_mint,
+        associated_token::authority = leaderboard,
+        associated_token::token_program = token_program,
+    )]
+    pub prize_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = referrer,
+        associated_token::mint = prize_mint,
+        associated_token::authority = referrer,
+        associated_token::token_program = token_program,
+    )]
+    pub prize_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimHeldPayout<'info> {
     #[account(
         seeds = [b"program_state"],
         bump = program_state.bump
     )]
+    pub program_state: Box<Account<'info, ProgramState>>,
+
+    #[account(
+        seeds = [b"referrer", referrer_account.referral_code.as_bytes()],
+        bump = referrer_account.bump,
+        has_one = authority
+    )]
+    pub referrer_account: Box<Account<'info, ReferrerAccount>>,
+
+    #[account(
+        mut,
+        seeds = [b"held_payout", referrer_account.key().as_ref(), payout_mint.key().as_ref()],
+        bump = held_payout.bump
+    )]
+    pub held_payout: Box<Account<'info, HeldPayout>>,
+
+    pub payout_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        associated_token::mint = payout_mint,
+        associated_token::authority = program_state,
+        associated_token::token_program = token_program,
+    )]
+    pub rewards_pool: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = authority,
+        associated_token::mint = payout_mint,
+        associated_token::authority = authority,
+        associated_token::token_program = token_program,
+    )]
+    pub payout_account: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(distribution_id: u64, mint: Pubkey, root: [u8; 32], max_claims: u32)]
+pub struct CreateMerkleDistribution<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump,
+        has_one = authority
+    )]
     pub program_state: Account<'info, ProgramState>,
 
     #[account(
         init,
-        payer = buyer,
-        space = 8 + PurchaseAccount::INIT_SPACE,
-        seeds = [b"purchase", buyer.key().as_ref(), &Clock::get()?.unix_timestamp.to_le_bytes()],
+        payer = authority,
+        space = 8 + MerkleDistribution::INIT_SPACE + MerkleDistribution::bitmap_len(max_claims),
+        seeds = [b"merkle_distribution", distribution_id.to_le_bytes().as_ref()],
         bump
     )]
-    pub purchase_account: Account<'info, PurchaseAccount>,
+    pub distribution: Account<'info, MerkleDistribution>,
 
     #[account(mut)]
-    pub referrer_account: Option<Account<'info, ReferrerAccount>>,
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimMerkle<'info> {
+    #[account(
+        seeds = [b"program_state"],
+        bump = program_state.bump
+    )]
+    pub program_state: Account<'info, ProgramState>,
 
     #[account(
         mut,
-        associated_token::mint = program_state.otto_token_mint,
+        seeds = [b"merkle_distribution", distribution.id.to_le_bytes().as_ref()],
+        bump = distribution.bump
+    )]
+    pub distribution: Account<'info, MerkleDistribution>,
+
+    #[account(address = distribution.mint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
         associated_token::authority = program_state,
+        associated_token::token_program = token_program,
+    )]
+    pub rewards_pool: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = claimant,
+        associated_token::mint = mint,
+        associated_token::authority = claimant,
+        associated_token::token_program = token_program,
+    )]
+    pub claimant_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub claimant: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(index: u8)]
+pub struct InitStatsShard<'info> {
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + std::mem::size_of::<StatsShard>(),
+        seeds = [b"stats_shard", [index].as_ref()],
+        bump
+    )]
+    pub stats_shard: AccountLoader<'info, StatsShard>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct AggregateStats<'info> {
+    #[account(
+        mut,
+        seeds = [b"program_state"],
+        bump = program_state.bump
     )]
-    pub rewards_pool: Account<'info, TokenAccount>,
+    pub program_state: Account<'info, ProgramState>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct MigrateProgramState<'info> {
+    /// CHECK: Decoded from the unversioned layout by the handler
+    #[account(mut, seeds = [b"program_state"], bump, owner = crate::ID)]
+    pub program_state: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
 
-    pub referrer_token_account: Option<Account<'info, TokenAccount>>,
+#[event_cpi]
+#[derive(Accounts)]
+pub struct MigrateReferrer<'info> {
+    /// CHECK: Decoded from the unversioned layout by the handler
+    #[account(mut, owner = crate::ID)]
+    pub referrer_account: UncheckedAccount<'info>,
 
+    /// CHECK: The `ReferrerLookup` PDA of the referrer's authority, checked
+    /// and created by the handler if it doesn't exist yet
     #[account(mut)]
-    pub buyer: Signer<'info>,
+    pub referrer_lookup: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
 
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
+#[event_cpi]
 #[derive(Accounts)]
-pub struct UpdateHoldingsRatio<'info> {
+pub struct MigratePurchase<'info> {
     #[account(
         seeds = [b"program_state"],
         bump = program_state.bump
     )]
     pub program_state: Account<'info, ProgramState>,
 
-    #[account(
-        mut,
-        seeds = [b"referrer", referrer_account.referral_code.as_bytes()],
-        bump = referrer_account.bump,
-        has_one = authority
-    )]
-    pub referrer_account: Account<'info, ReferrerAccount>,
+    /// CHECK: Decoded from the unversioned layout by the handler
+    #[account(mut, owner = crate::ID)]
+    pub purchase_account: UncheckedAccount<'info>,
 
-    #[account(
-        associated_token::mint = program_state.otto_token_mint,
-        associated_token::authority = authority,
-    )]
-    pub token_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
 
-    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
 }
 
-#[derive(Accounts)]
-pub struct UpdateTier<'info> {
-    #[account(
-        seeds = [b"program_state"],
-        bump = program_state.bump
-    )]
-    pub program_state: Account<'info, ProgramState>,
+/// Maximum number of payout assets, including $OTTO
+pub const MAX_REWARD_POOLS: usize = 4;
 
-    #[account(
-        mut,
-        seeds = [b"referrer", referrer_account.referral_code.as_bytes()],
-        bump = referrer_account.bump,
-        has_one = authority
-    )]
-    pub referrer_account: Account<'info, ReferrerAccount>,
+#[account]
+#[derive(InitSpace)]
+pub struct ProgramState {
+    pub version: u8, // ACCOUNT_VERSION of the layout
+    pub authority: Pubkey,
+    pub otto_token_mint: Pubkey,
+    pub rewards_pool: Pubkey,
+    pub rewards_percentage: u16,        // Basis points
+    pub min_purchase_amount: u64,
+    pub min_holdings_for_referral: u64,
+    pub base_reward_rate: u16, // Base reward percentage in basis points
+    // Global totals as of the last aggregate_stats
+    pub total_referrals: u64,
+    pub total_rewards_distributed: u64,
+    pub total_purchases: u64,
+    pub total_purchase_volume: u64,
+    pub total_referred_volume: u64,
+    pub stats_aggregated_at: i64,
+    pub bronze_threshold: u64,
+    pub silver_threshold: u64,
+    pub gold_threshold: u64,
+    pub holding_baseline: u64, // Balance counted as a 100% holding ratio (0 = initial holdings)
+    pub twab_window: i64, // Averaging window for the holdings TWAB, in seconds
+    pub ratio_recovery_rate: u16, // Max ratio recovery per day, in basis points
+    pub crank_bounty: u64, // Paid to refresh_referrer crankers that catch a sale
+    pub unbonding_period: i64, // Seconds between request_unstake and withdraw
+    #[max_len(MAX_REWARD_POOLS)]
+    pub reward_pools: Vec<RewardPoolConfig>,
+    pub payout_mint: Pubkey, // Asset new purchases are rewarded in
+    pub referee_bonus_bps: u16, // Extra cashback for buyers using a referral code
+    pub referee_bonus_first_purchase_only: bool,
+    #[max_len(MAX_MILESTONES)]
+    pub milestones: Vec<Milestone>,
+    pub active_campaign: Option<Pubkey>, // The only campaign purchases can fall under
+    pub active_leaderboard: Option<Pubkey>, // The only leaderboard purchases can be ranked on
+    pub bump: u8,
+    pub reserved: [u8; 64], // Zeroed; room for new fields without a realloc
+}
 
-    #[account(
-        associated_token::mint = program_state.otto_token_mint,
-        associated_token::authority = authority,
-    )]
-    pub token_account: Account<'info, TokenAccount>,
+impl ProgramState {
+    /// Reward pool configured for `mint`, if any
+    pub fn reward_pool(&self, mint: &Pubkey) -> Option<&RewardPoolConfig> {
+        self.reward_pools.iter().find(|pool| pool.mint == *mint)
+    }
 
-    pub authority: Signer<'info>,
+    /// Rate for converting $OTTO-denominated rewards into `mint`. Pools backed by
+    /// an oracle require that oracle account and a price newer than its staleness limit.
+    pub fn payout_rate(
+        &self,
+        mint: &Pubkey,
+        price_oracle: Option<&Account<PriceOracle>>,
+        now: i64,
+    ) -> Result<PayoutRate> {
+        let pool = self.reward_pool(mint).ok_or(ErrorCode::UnsupportedPayoutMint)?;
+
+        let Some(oracle_key) = pool.oracle else {
+            return Ok(PayoutRate {
+                numerator: pool.rate_numerator,
+                denominator: pool.rate_denominator,
+            });
+        };
+
+        let price_oracle = price_oracle.ok_or(ErrorCode::MissingPriceOracle)?;
+        require_keys_eq!(price_oracle.key(), oracle_key, ErrorCode::PriceOracleMismatch);
+        require_keys_eq!(price_oracle.mint, *mint, ErrorCode::PriceOracleMismatch);
+        require!(
+            price_oracle.rate_denominator > 0
+                && now.saturating_sub(price_oracle.updated_at) <= price_oracle.max_staleness,
+            ErrorCode::StalePriceOracle
+        );
+
+        Ok(PayoutRate {
+            numerator: price_oracle.rate_numerator,
+            denominator: price_oracle.rate_denominator,
+        })
+    }
+
+    /// Extra cashback, in $OTTO, for a buyer using a referral code. With
+    /// `referee_bonus_first_purchase_only`, only the buyer's first purchase
+    /// with a referral code earns it.
+    pub fn referee_bonus(&self, purchase_amount: u64, is_first_referred_purchase: bool) -> Result<u64> {
+        if is_first_referred_purchase || !self.referee_bonus_first_purchase_only {
+            apply_bps(purchase_amount, self.referee_bonus_bps as u64)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Cashback owed on a purchase, in $OTTO, including any referee bonus
+    pub fn cashback(&self, purchase_amount: u64, referee_bonus: u64) -> Result<u64> {
+        apply_bps(purchase_amount, self.rewards_percentage as u64)?
+            .checked_add(referee_bonus)
+            .ok_or_else(|| error!(ErrorCode::MathOverflow))
+    }
+
+    /// Tier earned by a referrer holding `balance` tokens
+    pub fn tier_for_balance(&self, balance: u64) -> Tier {
+        if balance >= self.gold_threshold {
+            Tier::Gold
+        } else if balance >= self.silver_threshold {
+            Tier::Silver
+        } else if balance >= self.bronze_threshold {
+            Tier::Bronze
+        } else {
+            Tier::None
+        }
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct ReferrerAccount {
+    pub version: u8, // ACCOUNT_VERSION of the layout
+    pub authority: Pubkey,
+    #[max_len(32)]
+    pub referral_code: String,
+    pub total_referrals: u32,
+    pub total_rewards_earned: u64,
+    pub total_referred_volume: u64,
+    pub unique_referees: u32, // Distinct buyers, one per RefereeRecord
+    pub milestones_reached: u16, // Bit i set once `ProgramState::milestones[i]` has paid out
+    pub clawback_owed: u64, // $OTTO charged back from reversed purchases, netted against future rewards
+    pub tier: Tier,
+    pub last_activity: i64,
+    pub last_bounty_at: i64, // Last refresh_referrer that paid a crank bounty; 0 if none
+    pub is_active: bool,
+    pub initial_holdings: u64,
+    pub current_holdings: u64,
+    pub holding_ratio: u16, // Basis points
+    pub ratio_recovery_at: i64, // Holding ratio recovery has been credited up to here
+    pub twab: u64, // Time-weighted average holdings over `twab_window`
+    pub twab_last_update: i64,
+    #[max_len(MAX_LINKED_TOKEN_ACCOUNTS)]
+    pub linked_token_accounts: Vec<Pubkey>, // Extra token accounts counted towards holdings
+    pub bump: u8,
+    pub reserved: [u8; 64], // Zeroed; room for new fields without a realloc
+}
+
+impl ReferrerAccount {
+    /// Mark every not-yet-reached milestone the referrer now meets as reached,
+    /// returning them with their index
+    pub fn reach_milestones(&mut self, milestones: &[Milestone]) -> Vec<(u8, Milestone)> {
+        let mut reached = Vec::new();
+        for (index, milestone) in milestones.iter().enumerate().take(MAX_MILESTONES) {
+            let bit = 1u16 << index;
+            if self.milestones_reached & bit != 0 {
+                continue;
+            }
+
+            let progress = match milestone.kind {
+                MilestoneKind::ReferredPurchases => self.total_referrals as u64,
+                MilestoneKind::ReferredVolume => self.total_referred_volume,
+                MilestoneKind::UniqueReferees => self.unique_referees as u64,
+            };
+            if progress >= milestone.threshold {
+                self.milestones_reached |= bit;
+                reached.push((index as u8, *milestone));
+            }
+        }
+        reached
+    }
+
+    /// Stop reading linked holdings accounts that no longer hold the
+    /// referrer's tokens
+    pub fn unlink(&mut self, token_accounts: &[Pubkey]) {
+        self.linked_token_accounts
+            .retain(|key| !token_accounts.contains(key));
+    }
+
+    /// Count a referred purchase towards the referrer's stats; `new_referee`
+    /// when it is the buyer's first purchase with this referrer
+    pub fn record_referral(&mut self, purchase_amount: u64, new_referee: bool, now: i64) -> Result<()> {
+        if new_referee {
+            self.unique_referees = self
+                .unique_referees
+                .checked_add(1)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        self.total_referrals = self
+            .total_referrals
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.total_referred_volume = self
+            .total_referred_volume
+            .checked_add(purchase_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.last_activity = now;
+        Ok(())
+    }
+
+    /// Take a reversed referred purchase back out of the referrer's stats;
+    /// `lost_referee` when it was the buyer's last standing purchase with them
+    pub fn reverse_referral(&mut self, purchase_amount: u64, lost_referee: bool) -> Result<()> {
+        if lost_referee {
+            self.unique_referees = self
+                .unique_referees
+                .checked_sub(1)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+        self.total_referrals = self
+            .total_referrals
+            .checked_sub(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.total_referred_volume = self
+            .total_referred_volume
+            .checked_sub(purchase_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Fold the balance held since the last observation into the TWAB, then
+    /// recompute the holding ratio against the configured baseline.
+    ///
+    /// The ratio targets `min(twab, new_holdings) / baseline`, so a sale is
+    /// penalised immediately while a rebuy only counts once it has been held
+    /// for part of the window. Upward moves are further capped at
+    /// `ratio_recovery_rate` basis points per day, credited in whole basis
+    /// points from `ratio_recovery_at` so frequent updates can't stall it.
+    pub fn record_holdings(
+        &mut self,
+        program_state: &ProgramState,
+        new_holdings: u64,
+        now: i64,
+    ) -> Result<()> {
+        let window = program_state.twab_window.max(1);
+        let elapsed = now.saturating_sub(self.twab_last_update).max(0);
+        let recovering_since = if self.ratio_recovery_at > 0 {
+            self.ratio_recovery_at
+        } else {
+            self.twab_last_update
+        };
+
+        // The previous balance was held for the whole elapsed interval
+        self.twab = if elapsed >= window {
+            self.current_holdings
+        } else {
+            let weighted = (self.twab as u128)
+                .checked_mul((window - elapsed) as u128)
+                .and_then(|old| {
+                    (self.current_holdings as u128)
+                        .checked_mul(elapsed as u128)
+                        .and_then(|recent| old.checked_add(recent))
+                })
+                .ok_or(ErrorCode::MathOverflow)?;
+            u64::try_from(weighted / window as u128).map_err(|_| error!(ErrorCode::MathOverflow))?
+        };
+        self.twab_last_update = now;
+        self.current_holdings = new_holdings;
+
+        let baseline = if program_state.holding_baseline > 0 {
+            program_state.holding_baseline
+        } else {
+            self.initial_holdings
+        };
+        if baseline == 0 {
+            self.ratio_recovery_at = now;
+            return Ok(());
+        }
+
+        let effective = self.twab.min(new_holdings);
+        // Cap at 100% if they've accumulated more tokens
+        let target = ((effective as u128)
+            .checked_mul(10000)
+            .ok_or(ErrorCode::MathOverflow)?
+            / baseline as u128)
+            .min(10000) as u16;
+
+        let rate = program_state.ratio_recovery_rate as u128;
+        if target <= self.holding_ratio || rate == 0 {
+            self.holding_ratio = self.holding_ratio.min(target);
+            self.ratio_recovery_at = now;
+            return Ok(());
+        }
+
+        let recovering = now.saturating_sub(recovering_since).max(0) as u128;
+        let recoverable = rate
+            .checked_mul(recovering)
+            .ok_or(ErrorCode::MathOverflow)?
+            / SECONDS_PER_DAY as u128;
+        let gap = (target - self.holding_ratio) as u128;
+        if recoverable >= gap {
+            self.holding_ratio = target;
+            self.ratio_recovery_at = now;
+        } else {
+            // Only the time the whole basis points took is used up; the
+            // remainder carries over to the next update
+            let used = (recoverable * SECONDS_PER_DAY as u128).div_ceil(rate);
+            self.holding_ratio += recoverable as u16;
+            self.ratio_recovery_at = recovering_since + used as i64;
+        }
+
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct PurchaseAccount {
+    pub version: u8, // ACCOUNT_VERSION of the layout
+    pub buyer: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+    #[max_len(32)]
+    pub referral_code: Option<String>,
+    pub payout_mint: Pubkey, // Asset the buyer's cashback is paid in
+    pub cashback: Option<u64>, // Payout units owed on claim, fixed at purchase; None converts at the claim rate
+    pub referee_bonus: u64, // In $OTTO, paid with the cashback on claim
+    pub campaign: Option<Pubkey>,
+    pub campaign_rewards: u64, // Charged to the campaign for the referral reward and milestones
+    pub referrer: Option<Pubkey>, // Referrer account paid for this purchase
+    pub referral_reward: u64, // In $OTTO, with any milestone bonuses; charged back if the purchase is reversed
+    pub referral_held: u64, // Payout units of the referral reward added to the referrer's HeldPayout
+    pub referral_held_reward: u64, // The $OTTO share of `referral_reward` that was held rather than paid
+    pub milestones_reached: u16, // Milestones this purchase crossed, reopened if it is reversed
+    pub leaderboard_id: Option<u64>, // Leaderboard the purchase's volume was ranked on
+    pub rewards_claimed: bool,
+    pub reversed: bool, // Refunded or fraudulent; cashback can no longer be claimed
+    pub bump: u8,
+    pub reserved: [u8; 32], // Zeroed; room for new fields without a realloc
+}
+
+/// Current version of the program's versioned accounts. `ProgramState`,
+/// `ReferrerAccount` and `PurchaseAccount` written before versioning are
+/// version 0 and are upgraded in place by the `migrate_*` instructions.
+pub const ACCOUNT_VERSION: u8 = 1;
+
+/// `ProgramState` as laid out before accounts were versioned
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub struct ProgramStateV0 {
+    pub authority: Pubkey,
+    pub otto_token_mint: Pubkey,
+    pub rewards_pool: Pubkey,
+    pub rewards_percentage: u16,
+    pub min_purchase_amount: u64,
+    pub min_holdings_for_referral: u64,
+    pub base_reward_rate: u16,
+    pub total_referrals: u64,
+    pub total_rewards_distributed: u64,
+    pub bronze_threshold: u64,
+    pub silver_threshold: u64,
+    pub gold_threshold: u64,
+    pub bump: u8,
+}
+
+/// Fields added since version 0 take the defaults `initialize` sets
+impl From<ProgramStateV0> for ProgramState {
+    fn from(old: ProgramStateV0) -> Self {
+        Self {
+            version: ACCOUNT_VERSION,
+            authority: old.authority,
+            otto_token_mint: old.otto_token_mint,
+            rewards_pool: old.rewards_pool,
+            rewards_percentage: old.rewards_percentage,
+            min_purchase_amount: old.min_purchase_amount,
+            min_holdings_for_referral: old.min_holdings_for_referral,
+            base_reward_rate: old.base_reward_rate,
+            total_referrals: old.total_referrals,
+            total_rewards_distributed: old.total_rewards_distributed,
+            total_purchases: 0,
+            total_purchase_volume: 0,
+            total_referred_volume: 0,
+            stats_aggregated_at: 0,
+            bronze_threshold: old.bronze_threshold,
+            silver_threshold: old.silver_threshold,
+            gold_threshold: old.gold_threshold,
+            holding_baseline: 0,
+            twab_window: 7 * SECONDS_PER_DAY,
+            ratio_recovery_rate: 1000,
+            crank_bounty: 0,
+            unbonding_period: 7 * SECONDS_PER_DAY,
+            reward_pools: vec![RewardPoolConfig {
+                mint: old.otto_token_mint,
+                pool: old.rewards_pool,
+                rate_numerator: 1,
+                rate_denominator: 1,
+                oracle: None,
+            }],
+            payout_mint: old.otto_token_mint,
+            referee_bonus_bps: 0,
+            referee_bonus_first_purchase_only: true,
+            milestones: Vec::new(),
+            active_campaign: None,
+            active_leaderboard: None,
+            bump: old.bump,
+            reserved: [0; 64],
+        }
+    }
+}
+
+/// `ReferrerAccount` as laid out before accounts were versioned
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub struct ReferrerAccountV0 {
+    pub authority: Pubkey,
+    #[max_len(32)]
+    pub referral_code: String,
+    pub total_referrals: u32,
+    pub total_rewards_earned: u64,
+    pub tier: Tier,
+    pub last_activity: i64,
+    pub is_active: bool,
+    pub initial_holdings: u64,
+    pub current_holdings: u64,
+    pub holding_ratio: u16,
+    pub bump: u8,
+}
+
+/// Version 0 did not track referred volume or referees, so those counters
+/// start from zero; the TWAB starts at the last recorded holdings
+impl From<ReferrerAccountV0> for ReferrerAccount {
+    fn from(old: ReferrerAccountV0) -> Self {
+        Self {
+            version: ACCOUNT_VERSION,
+            authority: old.authority,
+            referral_code: old.referral_code,
+            total_referrals: old.total_referrals,
+            total_rewards_earned: old.total_rewards_earned,
+            total_referred_volume: 0,
+            unique_referees: 0,
+            milestones_reached: 0,
+            clawback_owed: 0,
+            tier: old.tier,
+            last_activity: old.last_activity,
+            last_bounty_at: 0,
+            is_active: old.is_active,
+            initial_holdings: old.initial_holdings,
+            current_holdings: old.current_holdings,
+            holding_ratio: old.holding_ratio,
+            ratio_recovery_at: old.last_activity,
+            twab: old.current_holdings,
+            twab_last_update: old.last_activity,
+            linked_token_accounts: Vec::new(),
+            bump: old.bump,
+            reserved: [0; 64],
+        }
+    }
+}
+
+/// `PurchaseAccount` as laid out before accounts were versioned
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub struct PurchaseAccountV0 {
+    pub buyer: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+    #[max_len(32)]
+    pub referral_code: Option<String>,
+    pub rewards_claimed: bool,
+    pub bump: u8,
+}
+
+impl PurchaseAccount {
+    /// Payout units the buyer's claim pays: the amount fixed at purchase, or
+    /// for purchases migrated from version 0, the $OTTO cashback at
+    /// today's rate
+    pub fn cashback_owed(
+        &self,
+        program_state: &ProgramState,
+        price_oracle: Option<&Account<PriceOracle>>,
+        now: i64,
+    ) -> Result<u64> {
+        if let Some(cashback) = self.cashback {
+            return Ok(cashback);
+        }
+        program_state
+            .payout_rate(&self.payout_mint, price_oracle, now)?
+            .convert(program_state.cashback(self.amount, self.referee_bonus)?)
+    }
+
+    /// Upgrade a version 0 purchase, whose cashback was always owed in
+    /// `otto_token_mint`. Version 0 did not record the referrer or its reward,
+    /// so a migrated purchase has nothing to claw back if reversed.
+    pub fn from_v0(old: PurchaseAccountV0, otto_token_mint: Pubkey) -> Self {
+        Self {
+            version: ACCOUNT_VERSION,
+            buyer: old.buyer,
+            amount: old.amount,
+            timestamp: old.timestamp,
+            referral_code: old.referral_code,
+            payout_mint: otto_token_mint,
+            cashback: None,
+            referee_bonus: 0,
+            campaign: None,
+            campaign_rewards: 0,
+            referrer: None,
+            referral_reward: 0,
+            referral_held: 0,
+            referral_held_reward: 0,
+            milestones_reached: 0,
+            leaderboard_id: None,
+            rewards_claimed: old.rewards_claimed,
+            reversed: false,
+            bump: old.bump,
+            reserved: [0; 32],
+        }
+    }
+}
+
+/// Number of `StatsShard` accounts the global counters are spread over.
+/// Sharding only keeps the counters off the hot path: every purchase still
+/// write-locks its payout mint's `rewards_pool` token account, so purchases
+/// paying out in the same mint are processed one at a time.
+pub const STATS_SHARDS: u8 = 16;
+
+/// Minimum seconds between `aggregate_stats` runs
+pub const STATS_AGGREGATION_INTERVAL: i64 = 60;
+
+/// Shard of the global stats a buyer's purchases and claims are counted in
+pub fn stats_shard_index(buyer: &Pubkey) -> u8 {
+    buyer.to_bytes()[0] % STATS_SHARDS
+}
+
+/// One shard of the global counters. Purchases and claims only write their
+/// buyer's shard, so they neither lock `ProgramState` nor one shared account;
+/// `aggregate_stats` sums the shards into `ProgramState`.
+#[account(zero_copy)]
+#[derive(Default)]
+pub struct StatsShard {
+    pub version: u8, // ACCOUNT_VERSION of the layout
+    pub index: u8,
+    pub bump: u8,
+    pub padding: [u8; 5],
+    pub total_purchases: u64,
+    pub total_purchase_volume: u64,
+    pub total_referrals: u64, // Purchases paying a referral reward
+    pub total_referred_volume: u64,
+    pub total_rewards_distributed: u64, // Referral rewards, milestone bonuses and cashback received
+    pub reserved: [u64; 8], // Zeroed; room for new counters without a realloc
+}
+
+impl StatsShard {
+    pub fn record_purchase(&mut self, amount: u64) -> Result<()> {
+        self.total_purchases = self
+            .total_purchases
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.total_purchase_volume = self
+            .total_purchase_volume
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Count a referred purchase and the rewards its referrer received
+    pub fn record_referral(&mut self, amount: u64, rewards: u64) -> Result<()> {
+        self.total_referrals = self
+            .total_referrals
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.total_referred_volume = self
+            .total_referred_volume
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.record_rewards(rewards)
+    }
+
+    pub fn record_rewards(&mut self, amount: u64) -> Result<()> {
+        self.total_rewards_distributed = self
+            .total_rewards_distributed
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Take a reversed purchase back out of the counters. Rewards it paid stay
+    /// counted as distributed, as the clawback nets them out of later ones;
+    /// `released` rewards were held and never paid.
+    pub fn reverse_purchase(&mut self, amount: u64, referred: bool, released: u64) -> Result<()> {
+        let sub = |a: u64, b: u64| a.checked_sub(b).ok_or(ErrorCode::MathOverflow);
+        self.total_purchases = sub(self.total_purchases, 1)?;
+        self.total_rewards_distributed = sub(self.total_rewards_distributed, released)?;
+        self.total_purchase_volume = sub(self.total_purchase_volume, amount)?;
+        if referred {
+            self.total_referrals = sub(self.total_referrals, 1)?;
+            self.total_referred_volume = sub(self.total_referred_volume, amount)?;
+        }
+        Ok(())
+    }
+
+    /// Add another shard's counters to these
+    pub fn add(&mut self, other: &StatsShard) -> Result<()> {
+        let sum = |a: u64, b: u64| a.checked_add(b).ok_or(ErrorCode::MathOverflow);
+        self.total_purchases = sum(self.total_purchases, other.total_purchases)?;
+        self.total_purchase_volume = sum(self.total_purchase_volume, other.total_purchase_volume)?;
+        self.total_referrals = sum(self.total_referrals, other.total_referrals)?;
+        self.total_referred_volume = sum(self.total_referred_volume, other.total_referred_volume)?;
+        self.total_rewards_distributed =
+            sum(self.total_rewards_distributed, other.total_rewards_distributed)?;
+        Ok(())
+    }
+}
+
+/// Maximum number of milestones, bounded by `ReferrerAccount::milestones_reached`
+pub const MAX_MILESTONES: usize = 16;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub struct Milestone {
+    pub kind: MilestoneKind,
+    pub threshold: u64,
+    pub bonus: u64, // In $OTTO, paid in the purchase's payout asset
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum MilestoneKind {
+    ReferredPurchases,
+    ReferredVolume,
+    UniqueReferees,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct RefereeRecord {
+    pub referrer: Pubkey, // Referrer account
+    pub buyer: Pubkey,
+    pub purchases: u32,
+    pub volume: u64,
+    pub first_purchase_at: i64,
+    pub last_purchase_at: i64,
+    pub bump: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct BuyerAccount {
+    pub buyer: Pubkey,
+    pub total_purchases: u32,
+    pub referred_purchases: u32,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub struct RewardPoolConfig {
+    pub mint: Pubkey,
+    pub pool: Pubkey, // Program state's ATA for `mint`
+    pub rate_numerator: u64, // Payout units per `rate_denominator` $OTTO units
+    pub rate_denominator: u64,
+    pub oracle: Option<Pubkey>, // Overrides the fixed rate when set
+}
+
+/// Conversion from $OTTO base units into a payout asset's base units
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PayoutRate {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl PayoutRate {
+    pub fn convert(&self, otto_amount: u64) -> Result<u64> {
+        let converted = (otto_amount as u128)
+            .checked_mul(self.numerator as u128)
+            .ok_or(ErrorCode::MathOverflow)?
+            .checked_div(self.denominator as u128)
+            .ok_or(ErrorCode::MathOverflow)?;
+        u64::try_from(converted).map_err(|_| error!(ErrorCode::MathOverflow))
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct PriceOracle {
+    pub mint: Pubkey,
+    pub updater: Pubkey,
+    pub rate_numerator: u64, // Payout units per `rate_denominator` $OTTO units
+    pub rate_denominator: u64,
+    pub updated_at: i64,
+    pub max_staleness: i64,
+    pub bump: u8,
+}
+
+/// Maximum number of referral codes on a campaign allowlist
+pub const MAX_CAMPAIGN_CODES: usize = 8;
+
+#[account]
+#[derive(InitSpace)]
+pub struct Campaign {
+    pub id: u64,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64, // Exclusive; the campaign expires on its own
+    pub budget: u64, // In payout mint units
+    pub spent: u64,
+    pub payout_mint: Pubkey,
+    pub base_reward_rate: Option<u16>, // Overrides the global rate when set
+    pub holdings_multipliers: Option<[u16; 3]>, // Overrides DEFAULT_HOLDINGS_MULTIPLIERS
+    #[max_len(MAX_CAMPAIGN_CODES, 32)]
+    pub eligible_codes: Vec<String>, // Empty = every referral code
+    pub bump: u8,
 }
 
-#[derive(Accounts)]
-pub struct ClaimRewards<'info> {
-    #[account(
-        seeds = [b"program_state"],
-        bump = program_state.bump
-    )]
-    pub program_state: Account<'info, ProgramState>,
-
-    #[account(
-        mut,
-        has_one = buyer
-    )]
-    pub purchase_account: Account<'info, PurchaseAccount>,
+impl Campaign {
+    pub fn remaining_budget(&self) -> u64 {
+        self.budget.saturating_sub(self.spent)
+    }
 
-    pub otto_token_mint: Account<'info, Mint>,
+    /// Charge up to `amount` to the budget, returning the amount charged
+    pub fn charge(&mut self, amount: u64) -> Result<u64> {
+        let charged = amount.min(self.remaining_budget());
+        self.spent = self
+            .spent
+            .checked_add(charged)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(charged)
+    }
 
-    #[account(
-        mut,
-        associated_token::mint = otto_token_mint,
-        associated_token::authority = program_state,
-    )]
-    pub rewards_pool: Account<'info, TokenAccount>,
+    /// Whether the campaign applies to a purchase at `now` crediting the
+    /// referrer registered as `referrer_code`, None if the purchase is not
+    /// referred. A campaign with an allowlist never applies to unreferred
+    /// purchases.
+    pub fn is_live(&self, now: i64, referrer_code: Option<&str>) -> bool {
+        let code_eligible = self.eligible_codes.is_empty()
+            || referrer_code.is_some_and(|code| self.eligible_codes.iter().any(|c| c == code));
+        now >= self.start_timestamp
+            && now < self.end_timestamp
+            && self.remaining_budget() > 0
+            && code_eligible
+    }
+}
 
-    #[account(
-        init_if_needed,
-        payer = buyer,
-        associated_token::mint = otto_token_mint,
-        associated_token::authority = buyer,
-    )]
-    pub buyer_token_account: Account<'info, TokenAccount>,
+/// Number of ranked referrers on a leaderboard
+pub const LEADERBOARD_SIZE: usize = 10;
 
-    #[account(mut)]
-    pub buyer: Signer<'info>,
+#[account]
+#[derive(InitSpace)]
+pub struct Leaderboard {
+    pub id: u64,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64, // Exclusive; settle_leaderboard is allowed from here on
+    pub prize_mint: Pubkey,
+    pub prize_pool: u64, // In prize mint units, held by the leaderboard's prize vault ATA
+    pub prize_splits: [u16; LEADERBOARD_SIZE], // Share of the prize pool per rank, in basis points
+    pub entries: [LeaderboardEntry; LEADERBOARD_SIZE], // Sorted by volume, highest first
+    pub settled: bool,
+    pub prizes_paid: u16, // Bit i set once rank i's prize is paid; unset ranks are held in the vault
+    pub bump: u8,
+}
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct LeaderboardEntry {
+    pub referrer: Pubkey, // Referrer authority
+    pub volume: u64, // Zero = empty slot
 }
 
-#[derive(Accounts)]
-pub struct UpdateProgramParams<'info> {
-    #[account(
-        mut,
-        seeds = [b"program_state"],
-        bump = program_state.bump,
-        has_one = authority
-    )]
-    pub program_state: Account<'info, ProgramState>,
+impl Leaderboard {
+    pub fn is_open(&self, now: i64) -> bool {
+        !self.settled && now >= self.start_timestamp && now < self.end_timestamp
+    }
 
-    pub authority: Signer<'info>,
+    /// Set `referrer`'s period volume, taking the lowest slot if they are not
+    /// ranked yet and beat it, and keep the entries sorted
+    pub fn record(&mut self, referrer: Pubkey, volume: u64) {
+        let ranked = self
+            .entries
+            .iter()
+            .position(|entry| entry.referrer == referrer && entry.volume > 0);
+        let slot = match ranked {
+            Some(index) => index,
+            None => {
+                let lowest = LEADERBOARD_SIZE - 1;
+                if volume <= self.entries[lowest].volume {
+                    return;
+                }
+                lowest
+            }
+        };
+        self.entries[slot] = LeaderboardEntry { referrer, volume };
+        self.entries.sort_by_key(|entry| std::cmp::Reverse(entry.volume));
+    }
 }
 
+/// Volume a referrer referred during one leaderboard's window
 #[account]
 #[derive(InitSpace)]
-pub struct ProgramState {
-    pub authority: Pubkey,
-    pub otto_token_mint: Pubkey,
-    pub rewards_pool: Pubkey,
-    pub rewards_percentage: u16,        // Basis points
-    pub min_purchase_amount: u64,
-    pub min_holdings_for_referral: u64,
-    pub base_reward_rate: u16, // Base reward percentage in basis points
-    pub total_referrals: u64,
-    pub total_rewards_distributed: u64,
-    pub bronze_threshold: u64,
-    pub silver_threshold: u64,
-    pub gold_threshold: u64,
+pub struct LeaderboardVolume {
+    pub leaderboard: Pubkey,
+    pub referrer: Pubkey, // Referrer account
+    pub volume: u64,
     pub bump: u8,
 }
 
+/// A referrer's payouts in one mint that had no payout account to go to. The
+/// tokens stay in the rewards pool until the referrer claims them.
 #[account]
 #[derive(InitSpace)]
-pub struct ReferrerAccount {
+pub struct HeldPayout {
+    pub referrer_account: Pubkey,
+    pub payout_mint: Pubkey,
+    pub amount: u64,
+    pub bump: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct MerkleDistribution {
+    pub id: u64,
+    pub mint: Pubkey,
+    pub root: [u8; 32],
+    pub max_claims: u32, // Leaf indices run from 0 to max_claims - 1
+    pub total_amount: u64, // Cap on the sum of all claims, in mint units
+    pub claimed_amount: u64,
+    pub num_claimed: u32,
+    #[max_len(0)]
+    pub claimed_bitmap: Vec<u8>, // One bit per leaf index, sized at creation
+    pub bump: u8,
+}
+
+impl MerkleDistribution {
+    pub fn bitmap_len(max_claims: u32) -> usize {
+        (max_claims as usize).div_ceil(8)
+    }
+
+    pub fn is_claimed(&self, index: u32) -> bool {
+        self.claimed_bitmap[index as usize / 8] & (1 << (index % 8)) != 0
+    }
+
+    pub fn set_claimed(&mut self, index: u32) {
+        self.claimed_bitmap[index as usize / 8] |= 1 << (index % 8);
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct CampaignParams {
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+    pub budget: u64,
+    pub payout_mint: Pubkey,
+    pub base_reward_rate: Option<u16>,
+    pub holdings_multipliers: Option<[u16; 3]>,
+    pub eligible_codes: Vec<String>,
+}
+
+/// What `process_purchase` would pay, returned by `quote_referral_reward`.
+/// The referral fields are zero when no referral applies.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default, PartialEq, Eq)]
+pub struct ReferralQuote {
+    pub purchase_amount: u64,
+    pub payout_mint: Pubkey,
+    pub campaign: Option<Pubkey>, // Campaign whose terms apply
+    pub referrer: Option<Pubkey>, // Referrer's authority
+    pub holding_ratio: u16, // Basis points, after recording live holdings
+    pub referrer_holdings: u64,
+    pub base_reward: u64, // In $OTTO, as in `RewardBreakdown`
+    pub ratio_penalty: u64,
+    pub holding_adjusted_reward: u64,
+    pub holdings_multiplier: u16, // Basis points
+    pub final_reward: u64,
+    pub clawback: u64, // $OTTO netted against owed clawbacks
+    pub referral_reward: u64, // Payout units the referrer receives
+    pub transfer_fee: u64, // Withheld from `referral_reward`
+    pub milestone_bonus: u64, // Payout units for milestones this purchase reaches
+    pub referee_bonus: u64, // In $OTTO, included in `cashback`
+    pub cashback: u64, // Payout units claimable afterwards, at today's rate and before transfer fees
+}
+
+/// What `claim_rewards` would pay, returned by `quote_claim`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct ClaimQuote {
+    pub purchase: Pubkey,
+    pub buyer: Pubkey,
+    pub payout_mint: Pubkey,
+    pub cashback: u64, // In $OTTO, including the referee bonus
+    pub amount: u64, // Payout units the destination receives
+    pub transfer_fee: u64,
+    pub claimable: bool, // False once claimed or reversed
+}
+
+/// A referrer's standing at their live balance, returned by `get_referrer_summary`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct ReferrerSummary {
     pub authority: Pubkey,
-    #[max_len(32)]
     pub referral_code: String,
-    pub total_referrals: u32,
-    pub total_rewards_earned: u64,
-    pub tier: Tier,
-    pub last_activity: i64,
     pub is_active: bool,
-    pub initial_holdings: u64,
-    pub current_holdings: u64,
+    pub tier: Tier, // Tier the live balance earns
+    pub holdings: u64, // Across the ATA and linked accounts
+    pub twab: u64,
     pub holding_ratio: u16, // Basis points
+    pub holdings_multiplier: u16, // Basis points, under the global terms
+    pub total_referrals: u32,
+    pub unique_referees: u32,
+    pub total_referred_volume: u64,
+    pub total_rewards_earned: u64,
+    pub clawback_owed: u64,
+    pub milestones_reached: u16,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct UserSettings {
+    pub owner: Pubkey,
+    pub payout_wallet: Option<Pubkey>, // Receives referral payouts and delegated claims
     pub bump: u8,
 }
 
 #[account]
 #[derive(InitSpace)]
-pub struct PurchaseAccount {
-    pub buyer: Pubkey,
-    pub amount: u64,
-    pub timestamp: i64,
-    #[max_len(32)]
-    pub referral_code: Option<String>,
-    pub rewards_claimed: bool,
+pub struct StakeAccount {
+    pub owner: Pubkey,
+    pub staked_amount: u64, // Counts towards holdings
+    pub pending_unstake: u64, // Unbonding, no longer counts towards holdings
+    pub unlock_timestamp: i64,
     pub bump: u8,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+/// The referrer account a wallet registered, so instructions that change
+/// its holdings can require it
+#[account]
+#[derive(InitSpace)]
+pub struct ReferrerLookup {
+    pub referrer_account: Pubkey,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, InitSpace)]
 pub enum Tier {
     None,
     Bronze,
@@ -605,72 +5135,489 @@ pub enum Tier {
     Gold,
 }
 
+/// Schema version carried as the first field of every event. Bump it when an
+/// event's fields change so indexers can tell old and new layouts apart.
+pub const EVENT_VERSION: u8 = 1;
+
 #[event]
 pub struct ProgramInitialized {
+    pub version: u8,
+    pub program_state: Pubkey,
     pub authority: Pubkey,
     pub otto_token_mint: Pubkey,
+    pub rewards_pool: Pubkey,
     pub rewards_percentage: u16,
     pub min_purchase_amount: u64,
     pub min_holdings_for_referral: u64,
+    pub base_reward_rate: u16,
+    pub slot: u64,
+    pub timestamp: i64,
 }
 
 #[event]
 pub struct ReferrerRegistered {
+    pub version: u8,
+    pub referrer_account: Pubkey,
     pub authority: Pubkey,
     pub referral_code: String,
     pub initial_holdings: u64,
+    pub linked_token_accounts: Vec<Pubkey>,
+    pub slot: u64,
     pub timestamp: i64,
 }
 
 #[event]
 pub struct PurchaseProcessed {
+    pub version: u8,
+    pub purchase: Pubkey,
     pub buyer: Pubkey,
     pub amount: u64,
+    pub referral_code: Option<String>,
+    pub referrer_account: Option<Pubkey>,
+    pub payout_mint: Pubkey,
+    pub campaign: Option<Pubkey>,
+    pub referee_bonus: u64,
+    pub referral_reward: u64, // Amount received by the referrer, 0 without one
+    pub buyer_total_purchases: u32,
+    pub slot: u64,
     pub timestamp: i64,
 }
 
 #[event]
 pub struct DynamicReferralProcessed {
+    pub version: u8,
+    pub purchase: Pubkey,
     pub buyer: Pubkey,
+    pub referrer_account: Pubkey,
     pub referrer: Pubkey,
     pub referral_code: String,
     pub purchase_amount: u64,
-    pub dynamic_reward: u64,
+    pub payout_mint: Pubkey,
+    pub campaign: Option<Pubkey>,
+    pub campaign_spent: u64, // Campaign budget used after this reward, 0 outside one
+    pub dynamic_reward: u64, // Amount received by the referrer
+    pub transfer_fee: u64, // Withheld by a Token-2022 transfer-fee mint
+    pub clawback: u64, // $OTTO owed from reversed purchases, withheld from this reward
+    pub referee_bonus: u64, // Buyer's bonus in $OTTO, claimable with their cashback
     pub holding_ratio: u16,
     pub referrer_holdings: u64,
+    pub twab: u64,
+    pub total_referrals: u32,
+    pub unique_referees: u32,
+    pub total_referred_volume: u64,
+    pub total_rewards_earned: u64,
+    pub clawback_owed: u64,
+    pub pool_balance: u64, // Payout pool balance after the transfer
+    pub slot: u64,
     pub timestamp: i64,
 }
 
 #[event]
 pub struct TierUpdated {
+    pub version: u8,
+    pub referrer_account: Pubkey,
     pub authority: Pubkey,
     pub old_tier: Tier,
     pub new_tier: Tier,
     pub balance: u64,
+    pub slot: u64,
     pub timestamp: i64,
 }
 
 #[event]
 pub struct RewardsClaimed {
+    pub version: u8,
+    pub purchase: Pubkey,
     pub buyer: Pubkey,
-    pub amount: u64,
+    pub destination: Pubkey,
+    pub payout_mint: Pubkey,
+    pub amount: u64, // Amount received by the buyer
+    pub transfer_fee: u64,
+    pub pool_balance: u64, // Payout pool balance after the transfer
+    pub slot: u64,
     pub timestamp: i64,
 }
 
 #[event]
 pub struct ProgramParamsUpdated {
+    pub version: u8,
     pub authority: Pubkey,
     pub rewards_percentage: u16,
     pub min_purchase_amount: u64,
+    pub tier_thresholds: [u64; 3], // Bronze, silver, gold
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct HoldingParamsUpdated {
+    pub version: u8,
+    pub authority: Pubkey,
+    pub holding_baseline: u64,
+    pub twab_window: i64,
+    pub ratio_recovery_rate: u16,
+    pub crank_bounty: u64,
+    pub unbonding_period: i64,
+    pub slot: u64,
     pub timestamp: i64,
 }
 
 #[event]
 pub struct HoldingsRatioUpdated {
+    pub version: u8,
+    pub referrer_account: Pubkey,
+    pub authority: Pubkey,
+    pub previous_holdings: u64,
+    pub new_holdings: u64,
+    pub twab: u64,
+    pub holding_ratio: u16,
+    pub linked_token_accounts: Vec<Pubkey>,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReferrerRefreshed {
+    pub version: u8,
+    pub referrer_account: Pubkey,
     pub authority: Pubkey,
+    pub cranker: Pubkey,
     pub previous_holdings: u64,
     pub new_holdings: u64,
+    pub twab: u64,
     pub holding_ratio: u16,
+    pub old_tier: Tier,
+    pub new_tier: Tier,
+    pub bounty: u64,
+    pub pool_balance: u64, // $OTTO pool balance after any bounty
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Staked {
+    pub version: u8,
+    pub stake_account: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64, // Amount received by the vault
+    pub transfer_fee: u64,
+    pub staked_amount: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct UnstakeRequested {
+    pub version: u8,
+    pub stake_account: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub staked_amount: u64,
+    pub pending_unstake: u64,
+    pub unlock_timestamp: i64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct StakeWithdrawn {
+    pub version: u8,
+    pub stake_account: Pubkey,
+    pub owner: Pubkey,
+    pub amount: u64, // Amount received by the owner
+    pub transfer_fee: u64,
+    pub staked_amount: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MilestoneReached {
+    pub version: u8,
+    pub referrer_account: Pubkey,
+    pub referrer: Pubkey,
+    pub referral_code: String,
+    pub purchase: Pubkey,
+    pub milestone_index: u8,
+    pub kind: MilestoneKind,
+    pub threshold: u64,
+    pub payout_mint: Pubkey,
+    pub bonus: u64, // Amount received by the referrer
+    pub transfer_fee: u64,
+    pub pool_balance: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MilestonesUpdated {
+    pub version: u8,
+    pub authority: Pubkey,
+    pub milestones: Vec<Milestone>,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RefereeBonusUpdated {
+    pub version: u8,
+    pub authority: Pubkey,
+    pub bonus_bps: u16,
+    pub first_purchase_only: bool,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RewardPoolConfigured {
+    pub version: u8,
+    pub authority: Pubkey,
+    pub mint: Pubkey,
+    pub pool: Pubkey,
+    pub rate_numerator: u64,
+    pub rate_denominator: u64,
+    pub oracle: Option<Pubkey>,
+    pub pool_balance: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PayoutMintUpdated {
+    pub version: u8,
+    pub authority: Pubkey,
+    pub payout_mint: Pubkey,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PriceOracleInitialized {
+    pub version: u8,
+    pub price_oracle: Pubkey,
+    pub mint: Pubkey,
+    pub updater: Pubkey,
+    pub max_staleness: i64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PriceOracleUpdated {
+    pub version: u8,
+    pub price_oracle: Pubkey,
+    pub mint: Pubkey,
+    pub rate_numerator: u64,
+    pub rate_denominator: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CampaignCreated {
+    pub version: u8,
+    pub campaign: Pubkey,
+    pub campaign_id: u64,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+    pub budget: u64,
+    pub payout_mint: Pubkey,
+    pub base_reward_rate: Option<u16>,
+    pub holdings_multipliers: Option<[u16; 3]>,
+    pub eligible_codes: Vec<String>,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct CampaignUpdated {
+    pub version: u8,
+    pub campaign: Pubkey,
+    pub end_timestamp: i64,
+    pub budget: u64,
+    pub spent: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PayoutAddressUpdated {
+    pub version: u8,
+    pub user_settings: Pubkey,
+    pub owner: Pubkey,
+    pub payout_wallet: Option<Pubkey>,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PurchaseReversed {
+    pub version: u8,
+    pub purchase: Pubkey,
+    pub buyer: Pubkey,
+    pub referrer: Option<Pubkey>,
+    pub clawback: u64,
+    pub clawback_owed: u64, // Referrer's total owed after this reversal
+    pub payout_released: u64, // Taken back out of the referrer's held payout, in payout units
+    pub total_rewards_earned: u64,
+    pub total_referrals: u32, // Referrer's totals after this reversal
+    pub unique_referees: u32,
+    pub total_referred_volume: u64,
+    pub milestones_reopened: u16,
+    pub campaign_refund: u64,
+    pub cashback_blocked: bool,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MerkleDistributionCreated {
+    pub version: u8,
+    pub distribution: Pubkey,
+    pub distribution_id: u64,
+    pub mint: Pubkey,
+    pub root: [u8; 32],
+    pub max_claims: u32,
+    pub total_amount: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MerkleClaimed {
+    pub version: u8,
+    pub distribution: Pubkey,
+    pub distribution_id: u64,
+    pub index: u32,
+    pub claimant: Pubkey,
+    pub destination: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub transfer_fee: u64,
+    pub claimed_amount: u64, // Distribution totals after this claim
+    pub num_claimed: u32,
+    pub pool_balance: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LeaderboardCreated {
+    pub version: u8,
+    pub leaderboard: Pubkey,
+    pub leaderboard_id: u64,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+    pub prize_mint: Pubkey,
+    pub prize_pool: u64, // Amount the prize vault received
+    pub prize_splits: [u16; LEADERBOARD_SIZE],
+    pub prize_vault: Pubkey,
+    pub pool_balance: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LeaderboardPrizePaid {
+    pub version: u8,
+    pub leaderboard: Pubkey,
+    pub leaderboard_id: u64,
+    pub rank: u8,
+    pub referrer: Pubkey,
+    pub prize_account: Pubkey,
+    pub volume: u64,
+    pub prize: u64, // Amount received by the referrer
+    pub transfer_fee: u64,
+    pub vault_balance: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LeaderboardPrizeHeld {
+    pub version: u8,
+    pub leaderboard: Pubkey,
+    pub leaderboard_id: u64,
+    pub rank: u8,
+    pub referrer: Pubkey,
+    pub volume: u64,
+    pub prize: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ReferralPayoutHeld {
+    pub version: u8,
+    pub purchase: Pubkey,
+    pub referrer_account: Pubkey,
+    pub referrer: Pubkey,
+    pub payout_mint: Pubkey,
+    pub amount: u64, // Referral reward and milestone bonuses held from this purchase
+    pub total_held: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct HeldPayoutClaimed {
+    pub version: u8,
+    pub referrer_account: Pubkey,
+    pub referrer: Pubkey,
+    pub payout_mint: Pubkey,
+    pub payout_account: Pubkey,
+    pub amount: u64, // Amount received by the referrer
+    pub transfer_fee: u64,
+    pub pool_balance: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct LeaderboardSettled {
+    pub version: u8,
+    pub leaderboard: Pubkey,
+    pub leaderboard_id: u64,
+    pub total_paid: u64,
+    pub held: u64, // Left in the prize vault for claim_leaderboard_prize
+    pub returned: u64, // Sent back from the prize vault to the rewards pool
+    pub pool_balance: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct StatsShardInitialized {
+    pub version: u8,
+    pub stats_shard: Pubkey,
+    pub index: u8,
+    pub payer: Pubkey,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct StatsAggregated {
+    pub version: u8,
+    pub program_state: Pubkey,
+    pub total_purchases: u64,
+    pub total_purchase_volume: u64,
+    pub total_referrals: u64,
+    pub total_referred_volume: u64,
+    pub total_rewards_distributed: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AccountMigrated {
+    pub version: u8,
+    pub account: Pubkey,
+    pub payer: Pubkey,
+    pub from_version: u8,
+    pub to_version: u8,
+    pub space: u64, // Data length after the upgrade
+    pub slot: u64,
     pub timestamp: i64,
 }
 
@@ -692,4 +5639,130 @@ pub enum ErrorCode {
     InvalidRewardsPercentage,
     #[msg("Insufficient holdings to become referrer")]
     InsufficientHoldingsForReferral,
+    #[msg("Arithmetic overflow in reward or counter calculation")]
+    MathOverflow,
+    #[msg("TWAB window must be greater than zero")]
+    InvalidTwabWindow,
+    #[msg("Referral code does not match the referrer account")]
+    ReferralCodeMismatch,
+    #[msg("Referrer token account is required to process a referral")]
+    MissingReferrerTokenAccount,
+    #[msg("Referrer token account must be the referrer's $OTTO associated token account")]
+    InvalidReferrerTokenAccount,
+    #[msg("Too many token accounts supplied for holdings")]
+    TooManyHoldingAccounts,
+    #[msg("Token account supplied more than once for holdings")]
+    DuplicateHoldingAccount,
+    #[msg("Holdings account must be an $OTTO token account owned by the referrer")]
+    InvalidHoldingAccount,
+    #[msg("Holdings accounts do not match the referrer's linked token accounts")]
+    LinkedAccountsMismatch,
+    #[msg("Stake amount must be greater than zero")]
+    InvalidStakeAmount,
+    #[msg("Insufficient staked balance")]
+    InsufficientStake,
+    #[msg("No unbonded tokens to withdraw")]
+    NothingToWithdraw,
+    #[msg("Unbonding period has not elapsed")]
+    UnbondingNotElapsed,
+    #[msg("Unbonding period cannot be negative")]
+    InvalidUnbondingPeriod,
+    #[msg("Referrer account does not belong to the signer")]
+    UnauthorizedReferrer,
+    #[msg("No reward pool is configured for this payout mint")]
+    UnsupportedPayoutMint,
+    #[msg("Maximum number of reward pools reached")]
+    TooManyRewardPools,
+    #[msg("Conversion rate numerator and denominator must be greater than zero")]
+    InvalidConversionRate,
+    #[msg("Price oracle is required for this payout mint")]
+    MissingPriceOracle,
+    #[msg("Price oracle does not match the reward pool configuration")]
+    PriceOracleMismatch,
+    #[msg("Price oracle has no recent price")]
+    StalePriceOracle,
+    #[msg("Oracle staleness limit must be greater than zero")]
+    InvalidOracleStaleness,
+    #[msg("Referrer payout account, or the held payout account, is required to pay a referral reward")]
+    MissingReferrerPayoutAccount,
+    #[msg("Referrer payout account must be owned by the referrer")]
+    InvalidReferrerPayoutAccount,
+    #[msg("Payout mint does not match the campaign or program payout asset")]
+    PayoutMintMismatch,
+    #[msg("Campaign must end after it starts")]
+    InvalidCampaignWindow,
+    #[msg("Campaign allowlist has too many or overlong referral codes")]
+    TooManyCampaignCodes,
+    #[msg("Too many milestones")]
+    TooManyMilestones,
+    #[msg("Referee record is required to process a referral")]
+    MissingRefereeRecord,
+    #[msg("Leaderboard must end after it starts")]
+    InvalidLeaderboardWindow,
+    #[msg("Prize splits must not exceed 10000 basis points")]
+    InvalidPrizeSplits,
+    #[msg("Leaderboard period has not ended yet")]
+    LeaderboardNotEnded,
+    #[msg("Leaderboard has already been settled")]
+    LeaderboardAlreadySettled,
+    #[msg("Prize accounts must be the ranked referrers' token accounts, in rank order")]
+    InvalidPrizeAccount,
+    #[msg("Purchase has been reversed")]
+    PurchaseReversed,
+    #[msg("Purchase has already been reversed")]
+    PurchaseAlreadyReversed,
+    #[msg("The purchase's referrer account is required")]
+    MissingReferrerAccount,
+    #[msg("Invalid Merkle proof")]
+    InvalidMerkleProof,
+    #[msg("This Merkle reward has already been claimed")]
+    MerkleAlreadyClaimed,
+    #[msg("Claims exceed the distribution's total amount")]
+    MerkleDistributionExhausted,
+    #[msg("Delegated claims must follow an ed25519 signature instruction")]
+    MissingClaimSignature,
+    #[msg("The ed25519 instruction does not verify the buyer's claim message")]
+    InvalidClaimSignature,
+    #[msg("Either the buyer's token account or a destination is required")]
+    MissingClaimDestination,
+    #[msg("Destination must belong to the buyer's configured payout wallet")]
+    InvalidClaimDestination,
+    #[msg("Account is already in the current layout")]
+    AccountAlreadyMigrated,
+    #[msg("Account data matches no known layout")]
+    UnknownAccountLayout,
+    #[msg("Stats shards must all be passed, in index order")]
+    InvalidStatsShard,
+    #[msg("Campaign reward rate must not exceed 10000 basis points and multipliers must not decrease")]
+    InvalidCampaignRates,
+    #[msg("Campaign must be the program's active campaign, or omitted when there is none")]
+    CampaignMismatch,
+    #[msg("Leaderboard volume account is required to rank a referral")]
+    MissingLeaderboardVolume,
+    #[msg("Leaderboard has not been settled yet")]
+    LeaderboardNotSettled,
+    #[msg("No unpaid leaderboard prize for this referrer")]
+    NoLeaderboardPrize,
+    #[msg("Campaign or leaderboard does not match the purchase being reversed")]
+    ReversalAccountMismatch,
+    #[msg("Stats were aggregated too recently")]
+    StatsAggregatedTooRecently,
+    #[msg("Buyer account is required to reverse a referred purchase")]
+    MissingBuyerAccount,
+    #[msg("Leaderboard must be the program's active leaderboard, or omitted when there is none")]
+    LeaderboardMismatch,
+    #[msg("No referral payouts are held for this referrer")]
+    NoHeldPayout,
+    #[msg("Referrer's stake account is required to read their holdings")]
+    MissingReferrerStakeAccount,
+    #[msg("Referrer account must be the one the owner registered, or omitted when there is none")]
+    ReferrerAccountMismatch,
+    #[msg("Referrer lookup must be the PDA of the referrer's authority")]
+    InvalidReferrerLookup,
+    #[msg("Held payout account is required to reverse a purchase whose referral reward was held")]
+    MissingHeldPayout,
+    #[msg("Referee record, held payout and leaderboard volume must be omitted without a referral")]
+    UnexpectedReferralAccount,
+    #[msg("Leaderboard volume must be omitted when there is no active leaderboard")]
+    UnexpectedLeaderboardVolume,
 } 
\ No newline at end of file
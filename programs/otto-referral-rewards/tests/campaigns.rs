@@ -0,0 +1,229 @@
+mod common;
+
+use common::*;
+use otto_referral_rewards::{
+    Campaign, CampaignParams, ErrorCode, Milestone, MilestoneKind, ProgramState, PurchaseAccount,
+};
+
+/// A day-long campaign paying a 10% base rate in $OTTO to every code
+fn campaign_params(otto: &Otto) -> CampaignParams {
+    CampaignParams {
+        start_timestamp: otto.context.now(),
+        end_timestamp: otto.context.now() + 86_400,
+        budget: 1_000_000,
+        payout_mint: otto.otto_mint,
+        base_reward_rate: Some(1_000),
+        holdings_multipliers: None,
+        eligible_codes: Vec::new(),
+    }
+}
+
+#[test]
+fn create_campaign_validates_rate_overrides() {
+    let mut otto = Otto::new();
+
+    let params = CampaignParams {
+        base_reward_rate: Some(10_001),
+        ..campaign_params(&otto)
+    };
+    assert_error(
+        otto.create_campaign(1, params),
+        ErrorCode::InvalidCampaignRates,
+    );
+
+    let params = CampaignParams {
+        holdings_multipliers: Some([12_500, 11_000, 15_000]),
+        ..campaign_params(&otto)
+    };
+    assert_error(
+        otto.create_campaign(1, params),
+        ErrorCode::InvalidCampaignRates,
+    );
+
+    let params = CampaignParams {
+        base_reward_rate: Some(10_000),
+        holdings_multipliers: Some([11_000, 11_000, 20_000]),
+        ..campaign_params(&otto)
+    };
+    otto.create_campaign(1, params).unwrap();
+}
+
+#[test]
+fn purchases_fall_under_the_active_campaign_only() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+
+    let params = campaign_params(&otto);
+    otto.create_campaign(1, params.clone()).unwrap();
+    // A second campaign replaces the first
+    otto.create_campaign(2, params).unwrap();
+    let state: ProgramState = otto.context.account(&otto.program_state);
+    assert_eq!(state.active_campaign, Some(campaign_address(2)));
+
+    for campaign in [None, Some(campaign_address(1))] {
+        let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO1", &referrer)));
+        accounts.campaign = campaign;
+        assert_error(
+            otto.purchase_with(accounts, 100_000, Some("OTTO1"))
+                .map(|_| ()),
+            ErrorCode::CampaignMismatch,
+        );
+    }
+
+    let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO1", &referrer)));
+    accounts.campaign = Some(campaign_address(2));
+    let purchase_account = otto
+        .purchase_with(accounts, 100_000, Some("OTTO1"))
+        .unwrap();
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.campaign, Some(campaign_address(2)));
+}
+
+#[test]
+fn allowlist_is_checked_against_the_code() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+
+    let params = CampaignParams {
+        eligible_codes: vec!["OTTO2".to_string()],
+        ..campaign_params(&otto)
+    };
+    otto.create_campaign(1, params).unwrap();
+
+    let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO1", &referrer)));
+    accounts.campaign = Some(campaign_address(1));
+    let purchase_account = otto
+        .purchase_with(accounts, 100_000, Some("OTTO1"))
+        .unwrap();
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.campaign, None);
+    otto.context.warp(1);
+
+    // Naming an allowlisted code without its referrer account leaves the
+    // purchase unreferred, which the campaign doesn't cover
+    let mut accounts = otto.purchase_accounts(&buyer, None);
+    accounts.campaign = Some(campaign_address(1));
+    let purchase_account = otto
+        .purchase_with(accounts, 100_000, Some("OTTO2"))
+        .unwrap();
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.campaign, None);
+    let campaign: Campaign = otto.context.account(&campaign_address(1));
+    assert_eq!(campaign.spent, 0);
+    otto.context.warp(1);
+
+    let listed = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&listed, "OTTO2").unwrap();
+    let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO2", &listed)));
+    accounts.campaign = Some(campaign_address(1));
+    let purchase_account = otto
+        .purchase_with(accounts, 100_000, Some("OTTO2"))
+        .unwrap();
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.campaign, Some(campaign_address(1)));
+}
+
+#[test]
+fn campaign_budget_is_charged_for_every_payout() {
+    let mut otto = Otto::new();
+    otto.update_referee_bonus(Some(100), None).unwrap();
+    otto.set_milestones(vec![Milestone {
+        kind: MilestoneKind::ReferredPurchases,
+        threshold: 1,
+        bonus: 3_000,
+    }])
+    .unwrap();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+    let params = campaign_params(&otto);
+    otto.create_campaign(1, params).unwrap();
+
+    let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO1", &referrer)));
+    accounts.campaign = Some(campaign_address(1));
+    let purchase_account = otto
+        .purchase_with(accounts, 100_000, Some("OTTO1"))
+        .unwrap();
+
+    // 10% referral reward, 2% cashback, 1% referee bonus and the milestone
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.referral_reward, 10_000 + 3_000);
+    let campaign: Campaign = otto.context.account(&campaign_address(1));
+    assert_eq!(campaign.spent, 10_000 + 2_000 + 1_000 + 3_000);
+}
+
+#[test]
+fn reward_is_capped_by_the_remaining_budget() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+    let params = CampaignParams {
+        budget: 4_000,
+        ..campaign_params(&otto)
+    };
+    otto.create_campaign(1, params).unwrap();
+
+    let referrer_ata = ata(&referrer, &otto.otto_mint);
+    let before = otto.context.token_balance(&referrer_ata);
+    let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO1", &referrer)));
+    accounts.campaign = Some(campaign_address(1));
+    let purchase_account = otto
+        .purchase_with(accounts, 100_000, Some("OTTO1"))
+        .unwrap();
+
+    // Only the 4,000 paid of the 10,000 reward can be clawed back
+    assert_eq!(otto.context.token_balance(&referrer_ata), before + 4_000);
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.referral_reward, 4_000);
+}
+
+#[test]
+fn milestones_and_cashback_stop_at_the_budget() {
+    let mut otto = Otto::new();
+    otto.update_referee_bonus(Some(100), None).unwrap();
+    otto.set_milestones(vec![Milestone {
+        kind: MilestoneKind::ReferredPurchases,
+        threshold: 1,
+        bonus: 3_000,
+    }])
+    .unwrap();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+    let params = CampaignParams {
+        budget: 12_500,
+        ..campaign_params(&otto)
+    };
+    otto.create_campaign(1, params).unwrap();
+
+    let referrer_ata = ata(&referrer, &otto.otto_mint);
+    let before = otto.context.token_balance(&referrer_ata);
+    let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO1", &referrer)));
+    accounts.campaign = Some(campaign_address(1));
+    let purchase_account = otto
+        .purchase_with(accounts, 100_000, Some("OTTO1"))
+        .unwrap();
+
+    // The 10,000 reward leaves 2,500 of the milestone's 3,000 and nothing
+    // for the 3,000 cashback and referee bonus
+    assert_eq!(
+        otto.context.token_balance(&referrer_ata),
+        before + 10_000 + 2_500
+    );
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.referral_reward, 10_000 + 2_500);
+    assert_eq!(purchase.campaign_rewards, 10_000 + 2_500);
+    assert_eq!(purchase.cashback, Some(0));
+    let campaign: Campaign = otto.context.account(&campaign_address(1));
+    assert_eq!(campaign.spent, campaign.budget);
+
+    // Reversing it hands back exactly what was charged
+    otto.reverse_purchase(&purchase_account).unwrap();
+    let campaign: Campaign = otto.context.account(&campaign_address(1));
+    assert_eq!(campaign.spent, 0);
+}
@@ -0,0 +1,1618 @@
+//! Integration test harness on solana-program-test's bank.
+//!
+//! The program runs natively inside a real bank, next to the SPL Token,
+//! Token-2022 and Associated Token Account programs that solana-program-test
+//! ships as BPF. Transactions are signed and executed as on a cluster:
+//! signatures, account privileges, rent and CPI rules all apply. Native code
+//! is not metered and has no stack frame limit, so compute and stack usage
+//! are only checked by the tests in `compiled.rs`, which load the program
+//! built by `cargo build-sbf` instead.
+
+#![allow(dead_code)]
+
+pub mod sequence;
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Mutex,
+};
+
+use anchor_lang::{
+    event::EVENT_IX_TAG_LE,
+    prelude::*,
+    solana_program::{
+        entrypoint::ProgramResult, instruction::Instruction, program_pack::Pack, sysvar,
+    },
+    system_program, AccountDeserialize, Discriminator, Event, InstructionData, ToAccountMetas,
+};
+use anchor_spl::{
+    associated_token, token,
+    token_2022::{self, spl_token_2022},
+};
+use otto_referral_rewards::{accounts, instruction};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::{Account, AccountSharedData},
+    bpf_loader,
+    compute_budget::ComputeBudgetInstruction,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+
+/// Lamports given to every wallet created by a test
+pub const WALLET_LAMPORTS: u64 = 100_000_000_000;
+
+/// Clock at the start of every test
+pub const GENESIS_TIMESTAMP: i64 = 1_700_000_000;
+
+#[derive(Clone, Debug, Default)]
+pub struct AccountState {
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+    pub executable: bool,
+}
+
+thread_local! {
+    // The bank runs on the test thread's runtime, so the program's
+    // emit_cpi! events can be collected per thread
+    static EVENTS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+fn process_instruction<'a, 'b, 'c, 'd>(
+    program_id: &'a Pubkey,
+    accounts: &'b [AccountInfo<'c>],
+    data: &'d [u8],
+) -> ProgramResult {
+    // emit_cpi! events are self-invocations, recorded like inner instructions
+    if let Some(event) = data.strip_prefix(&EVENT_IX_TAG_LE) {
+        EVENTS.with(|events| events.borrow_mut().push(event.to_vec()));
+    }
+    // Anchor's entrypoint ties the slice to the accounts' lifetime, which
+    // the builtin shim gives a shorter one. The accounts outlive the call.
+    let accounts =
+        unsafe { std::mem::transmute::<&'b [AccountInfo<'c>], &'c [AccountInfo<'c>]>(accounts) };
+    otto_referral_rewards::entry(program_id, accounts, data)
+}
+
+/// The program built by `cargo build-sbf`, from SBF_OUT_DIR when set (as by
+/// `cargo test-sbf`), else from the workspace's target/deploy if it is there
+fn compiled_program() -> Option<Vec<u8>> {
+    const PROGRAM: &str = "otto_referral_rewards.so";
+    match std::env::var_os("SBF_OUT_DIR") {
+        Some(dir) => Some(
+            std::fs::read(Path::new(&dir).join(PROGRAM))
+                .unwrap_or_else(|error| panic!("no {PROGRAM} in SBF_OUT_DIR: {error}")),
+        ),
+        None => std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../../target/deploy").join(PROGRAM),
+        )
+        .ok(),
+    }
+}
+
+/// A running bank and the runtime driving its server
+struct Bank {
+    runtime: tokio::runtime::Runtime,
+    context: ProgramTestContext,
+    transactions: u32,
+    compiled: bool,
+}
+
+impl Bank {
+    /// Start a bank running `program`'s compiled code in the BPF VM, or the
+    /// program natively without it
+    fn start(program: Option<Vec<u8>>) -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        // The runtime logs every instruction to stderr, which the test
+        // harness doesn't capture. Program logs are printed per transaction
+        // below; set RUST_LOG to see the runtime's as well.
+        if std::env::var_os("RUST_LOG").is_none() {
+            std::env::set_var("RUST_LOG", "error");
+        }
+        let compiled = program.is_some();
+        let program_test = match program {
+            Some(data) => {
+                let mut program_test = ProgramTest::default();
+                program_test.add_account(
+                    otto_referral_rewards::ID,
+                    Account {
+                        lamports: Rent::default().minimum_balance(data.len()),
+                        data,
+                        owner: bpf_loader::ID,
+                        executable: true,
+                        rent_epoch: 0,
+                    },
+                );
+                program_test
+            }
+            None => {
+                let mut program_test = ProgramTest::new(
+                    "otto_referral_rewards",
+                    otto_referral_rewards::ID,
+                    processor!(process_instruction),
+                );
+                program_test.prefer_bpf(false);
+                program_test
+            }
+        };
+        let context = runtime.block_on(program_test.start_with_context());
+        Self {
+            runtime,
+            context,
+            transactions: 0,
+            compiled,
+        }
+    }
+}
+
+// Banks are never freed (their BankForks and program cache point at each
+// other) and keep background threads busy, so tests share them. A bank goes
+// back to the pool with every account its test wrote wiped.
+static IDLE_BANKS: Mutex<Vec<Bank>> = Mutex::new(Vec::new());
+
+pub struct TestContext {
+    bank: Option<Bank>,
+    keypairs: HashMap<Pubkey, Keypair>,
+    clock: RefCell<Clock>,
+    written: HashSet<Pubkey>,
+    return_data: Option<(Pubkey, Vec<u8>)>,
+    compute_units: u64,
+}
+
+impl Drop for TestContext {
+    fn drop(&mut self) {
+        // A test that panicked may have left the runtime mid-transaction
+        if std::thread::panicking() {
+            return;
+        }
+        let mut bank = self.bank.take().unwrap();
+        for key in &self.written {
+            bank.context.set_account(key, &AccountSharedData::default());
+        }
+        IDLE_BANKS.lock().unwrap().push(bank);
+    }
+}
+
+impl TestContext {
+    pub fn new() -> Self {
+        Self::with_bank(Self::idle_bank(false).unwrap_or_else(|| Bank::start(None)))
+    }
+
+    /// A context running the program built by `cargo build-sbf`, if any.
+    /// Its events aren't recorded: banks don't capture a BPF program's CPIs.
+    pub fn compiled() -> Option<Self> {
+        let bank = match Self::idle_bank(true) {
+            Some(bank) => bank,
+            None => Bank::start(Some(compiled_program()?)),
+        };
+        Some(Self::with_bank(bank))
+    }
+
+    fn idle_bank(compiled: bool) -> Option<Bank> {
+        let mut idle = IDLE_BANKS.lock().unwrap();
+        let index = idle.iter().position(|bank| bank.compiled == compiled)?;
+        Some(idle.swap_remove(index))
+    }
+
+    fn with_bank(bank: Bank) -> Self {
+        let mut banks_client = bank.context.banks_client.clone();
+        let mut clock: Clock = bank.runtime.block_on(banks_client.get_sysvar()).unwrap();
+        clock.unix_timestamp = GENESIS_TIMESTAMP;
+        bank.context.set_sysvar(&clock);
+        Self {
+            bank: Some(bank),
+            keypairs: HashMap::new(),
+            clock: RefCell::new(clock),
+            written: HashSet::new(),
+            return_data: None,
+            compute_units: 0,
+        }
+    }
+
+    fn bank(&self) -> &Bank {
+        self.bank.as_ref().unwrap()
+    }
+
+    pub fn now(&self) -> i64 {
+        self.clock.borrow().unix_timestamp
+    }
+
+    pub fn warp(&self, seconds: i64) {
+        let mut clock = self.clock.borrow_mut();
+        clock.unix_timestamp += seconds;
+        self.bank().context.set_sysvar(&*clock);
+    }
+
+    pub fn set_account(&mut self, key: Pubkey, account: AccountState) {
+        let account = AccountSharedData::from(Account {
+            lamports: account.lamports,
+            data: account.data,
+            owner: account.owner,
+            executable: account.executable,
+            rent_epoch: 0,
+        });
+        self.bank
+            .as_mut()
+            .unwrap()
+            .context
+            .set_account(&key, &account);
+        self.written.insert(key);
+    }
+
+    pub fn get_account(&self, key: &Pubkey) -> Option<AccountState> {
+        let bank = self.bank();
+        let mut banks_client = bank.context.banks_client.clone();
+        bank.runtime
+            .block_on(banks_client.get_account(*key))
+            .unwrap()
+            .map(|account| AccountState {
+                lamports: account.lamports,
+                data: account.data,
+                owner: account.owner,
+                executable: account.executable,
+            })
+    }
+
+    /// Deserialize an Anchor account
+    pub fn account<T: AccountDeserialize>(&self, key: &Pubkey) -> T {
+        let account = self.get_account(key).expect("account does not exist");
+        T::try_deserialize(&mut account.data.as_slice()).expect("invalid account data")
+    }
+
+    /// Balance of an SPL Token or Token-2022 account, read from the base
+    /// state that precedes any Token-2022 extensions
+    pub fn token_balance(&self, key: &Pubkey) -> u64 {
+        let account = self.get_account(key).expect("token account does not exist");
+        let len = token::spl_token::state::Account::LEN;
+        token::spl_token::state::Account::unpack(&account.data[..len])
+            .unwrap()
+            .amount
+    }
+
+    /// Token program owning `mint`
+    pub fn token_program(&self, mint: &Pubkey) -> Pubkey {
+        self.get_account(mint).expect("mint does not exist").owner
+    }
+
+    /// Return data left by the last transaction, with trailing zeros
+    /// trimmed by the bank
+    pub fn return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        self.return_data.clone()
+    }
+
+    /// Compute units the last transaction consumed, its compute budget
+    /// instruction included
+    pub fn compute_units(&self) -> u64 {
+        self.compute_units
+    }
+
+    /// Events of type `T` emitted by the last transaction, in order
+    pub fn events<T: Event + Discriminator + AnchorDeserialize>(&self) -> Vec<T> {
+        EVENTS.with(|events| {
+            events
+                .borrow()
+                .iter()
+                .filter_map(|data| data.strip_prefix(&T::DISCRIMINATOR))
+                .map(|mut data| T::deserialize(&mut data).expect("invalid event data"))
+                .collect()
+        })
+    }
+
+    /// Data of every event emitted by the last transaction, discriminator
+    /// first, as an indexer sees it
+    pub fn event_data(&self) -> Vec<Vec<u8>> {
+        EVENTS.with(|events| events.borrow().clone())
+    }
+
+    pub fn new_wallet(&mut self) -> Pubkey {
+        self.add_wallet(Keypair::new())
+    }
+
+    /// A funded wallet signed for by `keypair`
+    pub fn add_wallet(&mut self, keypair: Keypair) -> Pubkey {
+        let wallet = keypair.pubkey();
+        self.set_account(
+            wallet,
+            AccountState {
+                lamports: WALLET_LAMPORTS,
+                owner: system_program::ID,
+                ..AccountState::default()
+            },
+        );
+        self.keypairs.insert(wallet, keypair);
+        wallet
+    }
+
+    /// A funded wallet whose purchases are counted in stats shard `index`
+    pub fn wallet_in_stats_shard(&mut self, index: u8) -> Pubkey {
+        loop {
+            let keypair = Keypair::new();
+            if otto_referral_rewards::stats_shard_index(&keypair.pubkey()) == index {
+                return self.add_wallet(keypair);
+            }
+        }
+    }
+
+    pub fn keypair(&self, wallet: &Pubkey) -> &Keypair {
+        self.keypairs
+            .get(wallet)
+            .unwrap_or_else(|| panic!("no keypair for {wallet}"))
+    }
+
+    /// Sign and run `instructions` as one transaction. Every signer must be
+    /// a wallet created through this context. Instruction errors are
+    /// returned; a transaction the runtime rejects outright panics.
+    pub fn process(
+        &mut self,
+        instructions: &[Instruction],
+    ) -> std::result::Result<(), ProgramError> {
+        EVENTS.with(|events| events.borrow_mut().clear());
+        self.return_data = None;
+        self.compute_units = 0;
+
+        // Identical transactions would be deduplicated by the status cache,
+        // so each one asks for a slightly different compute limit. It goes
+        // first, as builtins clear the return data of earlier instructions.
+        let bank = self.bank.as_mut().unwrap();
+        bank.transactions += 1;
+        let instructions: Vec<Instruction> = [ComputeBudgetInstruction::set_compute_unit_limit(
+            1_400_000 - bank.transactions % 100_000,
+        )]
+        .into_iter()
+        .chain(instructions.iter().cloned())
+        .collect();
+
+        let bank = self.bank();
+        let mut signers = vec![&bank.context.payer];
+        for meta in instructions
+            .iter()
+            .flat_map(|instruction| &instruction.accounts)
+        {
+            if meta.is_signer && !signers.iter().any(|signer| signer.pubkey() == meta.pubkey) {
+                signers.push(self.keypair(&meta.pubkey));
+            }
+        }
+
+        let mut banks_client = bank.context.banks_client.clone();
+        let blockhash = bank
+            .runtime
+            .block_on(banks_client.get_latest_blockhash())
+            .unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&bank.context.payer.pubkey()),
+            &signers,
+            blockhash,
+        );
+        let result = bank
+            .runtime
+            .block_on(banks_client.process_transaction_with_metadata(transaction))
+            .unwrap();
+        self.written.extend(
+            instructions
+                .iter()
+                .flat_map(|instruction| &instruction.accounts)
+                .filter(|meta| meta.is_writable)
+                .map(|meta| meta.pubkey),
+        );
+
+        let logs = result
+            .metadata
+            .map(|metadata| {
+                self.return_data = metadata
+                    .return_data
+                    .map(|return_data| (return_data.program_id, return_data.data));
+                self.compute_units = metadata.compute_units_consumed;
+                metadata.log_messages
+            })
+            .unwrap_or_default();
+        for log in &logs {
+            println!("{log}");
+        }
+        match result.result {
+            Ok(()) => Ok(()),
+            Err(TransactionError::InstructionError(_, error)) => {
+                Err(ProgramError::try_from(error.clone())
+                    .unwrap_or_else(|_| panic!("transaction violated a runtime rule: {error}")))
+            }
+            Err(error) => panic!("transaction rejected: {error}"),
+        }
+    }
+
+    pub fn create_mint(&mut self, mint_authority: &Pubkey, decimals: u8) -> Pubkey {
+        let mint = Pubkey::new_unique();
+        self.set_account(
+            mint,
+            AccountState {
+                lamports: Rent::default().minimum_balance(token::spl_token::state::Mint::LEN),
+                data: vec![0; token::spl_token::state::Mint::LEN],
+                owner: token::spl_token::ID,
+                executable: false,
+            },
+        );
+        let initialize_mint = token::spl_token::instruction::initialize_mint2(
+            &token::spl_token::ID,
+            &mint,
+            mint_authority,
+            None,
+            decimals,
+        )
+        .unwrap();
+        self.process(&[initialize_mint]).unwrap();
+        mint
+    }
+
+    /// A Token-2022 mint withholding `fee_bps` of every transfer, up to
+    /// `max_fee`
+    pub fn create_fee_mint(
+        &mut self,
+        mint_authority: &Pubkey,
+        decimals: u8,
+        fee_bps: u16,
+        max_fee: u64,
+    ) -> Pubkey {
+        use spl_token_2022::extension::{transfer_fee, ExtensionType};
+
+        let mint = Pubkey::new_unique();
+        let len = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[
+            ExtensionType::TransferFeeConfig,
+        ])
+        .unwrap();
+        self.set_account(
+            mint,
+            AccountState {
+                lamports: Rent::default().minimum_balance(len),
+                data: vec![0; len],
+                owner: token_2022::ID,
+                executable: false,
+            },
+        );
+        let initialize_fee = transfer_fee::instruction::initialize_transfer_fee_config(
+            &token_2022::ID,
+            &mint,
+            None,
+            None,
+            fee_bps,
+            max_fee,
+        )
+        .unwrap();
+        let initialize_mint = spl_token_2022::instruction::initialize_mint2(
+            &token_2022::ID,
+            &mint,
+            mint_authority,
+            None,
+            decimals,
+        )
+        .unwrap();
+        self.process(&[initialize_fee, initialize_mint]).unwrap();
+        mint
+    }
+
+    /// The wallet's ATA for `mint`, under whichever token program owns it
+    pub fn create_ata(&mut self, wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+        let payer = self.new_wallet();
+        let token_program = self.token_program(mint);
+        let create_ata = associated_token::spl_associated_token_account::instruction::create_associated_token_account(
+            &payer,
+            wallet,
+            mint,
+            &token_program,
+        );
+        self.process(&[create_ata]).unwrap();
+        ata_with_program(wallet, mint, &token_program)
+    }
+
+    /// A token account for an SPL `mint` owned by `wallet` at an address
+    /// other than its ATA
+    pub fn create_token_account(&mut self, wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+        let token_account = Pubkey::new_unique();
+        let len = token::spl_token::state::Account::LEN;
+        self.set_account(
+            token_account,
+            AccountState {
+                lamports: Rent::default().minimum_balance(len),
+                data: vec![0; len],
+                owner: token::spl_token::ID,
+                executable: false,
+            },
+        );
+        let initialize_account = token::spl_token::instruction::initialize_account3(
+            &token::spl_token::ID,
+            &token_account,
+            mint,
+            wallet,
+        )
+        .unwrap();
+        self.process(&[initialize_account]).unwrap();
+        token_account
+    }
+
+    pub fn mint_to(
+        &mut self,
+        mint: &Pubkey,
+        mint_authority: &Pubkey,
+        destination: &Pubkey,
+        amount: u64,
+    ) {
+        let mint_to = spl_token_2022::instruction::mint_to(
+            &self.token_program(mint),
+            mint,
+            destination,
+            mint_authority,
+            &[],
+            amount,
+        )
+        .unwrap();
+        self.process(&[mint_to]).unwrap();
+    }
+}
+
+pub fn ata(wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+    anchor_spl::associated_token::get_associated_token_address(wallet, mint)
+}
+
+pub fn ata_with_program(wallet: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    anchor_spl::associated_token::get_associated_token_address_with_program_id(
+        wallet,
+        mint,
+        token_program,
+    )
+}
+
+pub fn anchor_instruction(
+    accounts: impl ToAccountMetas,
+    data: impl InstructionData,
+) -> Instruction {
+    Instruction {
+        program_id: otto_referral_rewards::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+pub fn assert_error(result: std::result::Result<(), ProgramError>, error: impl Into<u32>) {
+    assert_eq!(result, Err(ProgramError::Custom(error.into())));
+}
+
+pub fn program_state_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"program_state"], &otto_referral_rewards::ID).0
+}
+
+pub fn event_authority_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"__event_authority"], &otto_referral_rewards::ID).0
+}
+
+pub fn stats_shard_address(index: u8) -> Pubkey {
+    Pubkey::find_program_address(&[b"stats_shard", &[index]], &otto_referral_rewards::ID).0
+}
+
+/// The stats shard `buyer`'s purchases and claims are counted in
+pub fn buyer_stats_shard(buyer: &Pubkey) -> Pubkey {
+    stats_shard_address(otto_referral_rewards::stats_shard_index(buyer))
+}
+
+pub fn referrer_address(referral_code: &str) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"referrer", referral_code.as_bytes()],
+        &otto_referral_rewards::ID,
+    )
+    .0
+}
+
+pub fn buyer_address(buyer: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"buyer", buyer.as_ref()], &otto_referral_rewards::ID).0
+}
+
+pub fn purchase_address(buyer: &Pubkey, timestamp: i64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"purchase", buyer.as_ref(), &timestamp.to_le_bytes()],
+        &otto_referral_rewards::ID,
+    )
+    .0
+}
+
+pub fn campaign_address(campaign_id: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"campaign", campaign_id.to_le_bytes().as_ref()],
+        &otto_referral_rewards::ID,
+    )
+    .0
+}
+
+pub fn leaderboard_address(leaderboard_id: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"leaderboard", leaderboard_id.to_le_bytes().as_ref()],
+        &otto_referral_rewards::ID,
+    )
+    .0
+}
+
+pub fn leaderboard_volume_address(leaderboard: &Pubkey, referrer_account: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"leaderboard_volume",
+            leaderboard.as_ref(),
+            referrer_account.as_ref(),
+        ],
+        &otto_referral_rewards::ID,
+    )
+    .0
+}
+
+pub fn price_oracle_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"price_oracle", mint.as_ref()],
+        &otto_referral_rewards::ID,
+    )
+    .0
+}
+
+pub fn referee_record_address(referrer_account: &Pubkey, buyer: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"referee", referrer_account.as_ref(), buyer.as_ref()],
+        &otto_referral_rewards::ID,
+    )
+    .0
+}
+
+pub fn stake_address(owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"stake", owner.as_ref()], &otto_referral_rewards::ID).0
+}
+
+pub fn referrer_lookup_address(authority: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"referrer_lookup", authority.as_ref()],
+        &otto_referral_rewards::ID,
+    )
+    .0
+}
+
+pub fn user_settings_address(owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"user_settings", owner.as_ref()],
+        &otto_referral_rewards::ID,
+    )
+    .0
+}
+
+pub fn held_payout_address(referrer_account: &Pubkey, payout_mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"held_payout",
+            referrer_account.as_ref(),
+            payout_mint.as_ref(),
+        ],
+        &otto_referral_rewards::ID,
+    )
+    .0
+}
+
+pub fn merkle_distribution_address(distribution_id: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"merkle_distribution", &distribution_id.to_le_bytes()],
+        &otto_referral_rewards::ID,
+    )
+    .0
+}
+
+pub const REWARDS_PERCENTAGE: u16 = 200;
+pub const MIN_PURCHASE_AMOUNT: u64 = 1_000;
+pub const MIN_HOLDINGS_FOR_REFERRAL: u64 = 1_000_000;
+pub const BASE_REWARD_RATE: u16 = 500;
+pub const REWARDS_POOL_FUNDING: u64 = 1_000_000_000_000;
+
+/// An initialized program with a funded $OTTO rewards pool
+pub struct Otto {
+    pub context: TestContext,
+    pub authority: Pubkey,
+    pub mint_authority: Pubkey,
+    pub otto_mint: Pubkey,
+    pub program_state: Pubkey,
+    pub rewards_pool: Pubkey,
+}
+
+impl Otto {
+    pub fn initialize_instruction(&self) -> Instruction {
+        anchor_instruction(
+            accounts::Initialize {
+                program_state: self.program_state,
+                rewards_pool: self.rewards_pool,
+                otto_token_mint: self.otto_mint,
+                authority: self.authority,
+                system_program: system_program::ID,
+                token_program: token::spl_token::ID,
+                associated_token_program: associated_token::spl_associated_token_account::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::Initialize {
+                rewards_percentage: REWARDS_PERCENTAGE,
+                min_purchase_amount: MIN_PURCHASE_AMOUNT,
+                min_holdings_for_referral: MIN_HOLDINGS_FOR_REFERRAL,
+                base_reward_rate: BASE_REWARD_RATE,
+            },
+        )
+    }
+
+    pub fn init_stats_shard_instruction(&self, index: u8) -> Instruction {
+        anchor_instruction(
+            accounts::InitStatsShard {
+                stats_shard: stats_shard_address(index),
+                payer: self.authority,
+                system_program: system_program::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::InitStatsShard { index },
+        )
+    }
+
+    /// Sum every stats shard into the program state
+    pub fn aggregate_stats(&mut self) -> std::result::Result<(), ProgramError> {
+        let aggregate = self.aggregate_stats_instruction(0..otto_referral_rewards::STATS_SHARDS);
+        self.context.process(&[aggregate])
+    }
+
+    pub fn aggregate_stats_instruction(&self, shards: impl IntoIterator<Item = u8>) -> Instruction {
+        let mut aggregate = anchor_instruction(
+            accounts::AggregateStats {
+                program_state: self.program_state,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::AggregateStats {},
+        );
+        aggregate.accounts.extend(
+            shards
+                .into_iter()
+                .map(|index| AccountMeta::new_readonly(stats_shard_address(index), false)),
+        );
+        aggregate
+    }
+
+    /// Mints and accounts set up, program not yet initialized
+    pub fn uninitialized() -> Self {
+        Self::uninitialized_in(TestContext::new())
+    }
+
+    fn uninitialized_in(mut context: TestContext) -> Self {
+        let authority = context.new_wallet();
+        let mint_authority = context.new_wallet();
+        let otto_mint = context.create_mint(&mint_authority, 6);
+        let program_state = program_state_address();
+        let rewards_pool = ata(&program_state, &otto_mint);
+        Self {
+            context,
+            authority,
+            mint_authority,
+            otto_mint,
+            program_state,
+            rewards_pool,
+        }
+    }
+
+    pub fn new() -> Self {
+        Self::initialized_in(TestContext::new())
+    }
+
+    /// Initialized on the program built by `cargo build-sbf`, if any
+    pub fn compiled() -> Option<Self> {
+        TestContext::compiled().map(Self::initialized_in)
+    }
+
+    fn initialized_in(context: TestContext) -> Self {
+        let mut otto = Self::uninitialized_in(context);
+        let initialize = otto.initialize_instruction();
+        otto.context.process(&[initialize]).unwrap();
+        let init_stats_shards: Vec<Instruction> = (0..otto_referral_rewards::STATS_SHARDS)
+            .map(|index| otto.init_stats_shard_instruction(index))
+            .collect();
+        otto.context.process(&init_stats_shards).unwrap();
+        let (mint, mint_authority, rewards_pool) =
+            (otto.otto_mint, otto.mint_authority, otto.rewards_pool);
+        otto.context
+            .mint_to(&mint, &mint_authority, &rewards_pool, REWARDS_POOL_FUNDING);
+        otto
+    }
+
+    /// A wallet with an $OTTO ATA holding `amount`
+    pub fn holder(&mut self, amount: u64) -> Pubkey {
+        let wallet = self.context.new_wallet();
+        let token_account = self.context.create_ata(&wallet, &self.otto_mint);
+        if amount > 0 {
+            let (mint, mint_authority) = (self.otto_mint, self.mint_authority);
+            self.context
+                .mint_to(&mint, &mint_authority, &token_account, amount);
+        }
+        wallet
+    }
+
+    pub fn register_referrer(
+        &mut self,
+        authority: &Pubkey,
+        referral_code: &str,
+    ) -> std::result::Result<(), ProgramError> {
+        self.register_referrer_linked(authority, referral_code, &[])
+    }
+
+    /// Register a referrer whose holdings also span `linked` accounts
+    pub fn register_referrer_linked(
+        &mut self,
+        authority: &Pubkey,
+        referral_code: &str,
+        linked: &[Pubkey],
+    ) -> std::result::Result<(), ProgramError> {
+        let mut register = anchor_instruction(
+            accounts::RegisterReferrer {
+                program_state: self.program_state,
+                referrer_account: referrer_address(referral_code),
+                referrer_lookup: referrer_lookup_address(authority),
+                token_account: ata(authority, &self.otto_mint),
+                stake_account: stake_address(authority),
+                authority: *authority,
+                system_program: system_program::ID,
+                token_program: token::spl_token::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::RegisterReferrer {
+                referral_code: referral_code.to_string(),
+            },
+        );
+        register.accounts.extend(
+            linked
+                .iter()
+                .map(|account| AccountMeta::new_readonly(*account, false)),
+        );
+        self.context.process(&[register])
+    }
+
+    /// Accounts for a purchase paid in $OTTO, for tests to adjust before
+    /// building the instruction
+    pub fn purchase_accounts(
+        &self,
+        buyer: &Pubkey,
+        referrer: Option<(&str, &Pubkey)>,
+    ) -> accounts::ProcessPurchase {
+        let referrer_account = referrer.map(|(code, _)| referrer_address(code));
+        // Every purchase must pass the active leaderboard
+        let leaderboard = self
+            .context
+            .account::<otto_referral_rewards::ProgramState>(&self.program_state)
+            .active_leaderboard;
+        accounts::ProcessPurchase {
+            program_state: self.program_state,
+            purchase_account: purchase_address(buyer, self.context.now()),
+            buyer_account: buyer_address(buyer),
+            referrer_account,
+            referee_record: referrer_account
+                .map(|referrer| referee_record_address(&referrer, buyer)),
+            referrer_token_account: referrer.map(|(_, authority)| ata(authority, &self.otto_mint)),
+            referrer_stake_account: referrer.map(|(_, authority)| stake_address(authority)),
+            payout_mint: self.otto_mint,
+            rewards_pool: self.rewards_pool,
+            referrer_payout_account: referrer.map(|(_, authority)| ata(authority, &self.otto_mint)),
+            referrer_settings: None,
+            held_payout: None,
+            price_oracle: None,
+            campaign: None,
+            leaderboard,
+            leaderboard_volume: leaderboard
+                .zip(referrer_account)
+                .map(|(leaderboard, referrer)| leaderboard_volume_address(&leaderboard, &referrer)),
+            stats_shard: buyer_stats_shard(buyer),
+            buyer: *buyer,
+            system_program: system_program::ID,
+            token_program: token::spl_token::ID,
+            payout_token_program: token::spl_token::ID,
+            associated_token_program: associated_token::spl_associated_token_account::ID,
+            event_authority: event_authority_address(),
+            program: otto_referral_rewards::ID,
+        }
+    }
+
+    /// Process a purchase at the current clock, returning its purchase account
+    pub fn purchase(
+        &mut self,
+        buyer: &Pubkey,
+        amount: u64,
+        referrer: Option<(&str, &Pubkey)>,
+    ) -> std::result::Result<Pubkey, ProgramError> {
+        let accounts = self.purchase_accounts(buyer, referrer);
+        self.purchase_with(accounts, amount, referrer.map(|(code, _)| code))
+    }
+
+    /// Process a purchase with the given accounts, returning its purchase account
+    pub fn purchase_with(
+        &mut self,
+        accounts: accounts::ProcessPurchase,
+        amount: u64,
+        referral_code: Option<&str>,
+    ) -> std::result::Result<Pubkey, ProgramError> {
+        let purchase_account = accounts.purchase_account;
+        let purchase = anchor_instruction(
+            accounts,
+            instruction::ProcessPurchase {
+                purchase_amount: amount,
+                referral_code: referral_code.map(str::to_string),
+            },
+        );
+        self.context.process(&[purchase])?;
+        Ok(purchase_account)
+    }
+
+    /// Accounts for claiming a purchase's $OTTO cashback to the buyer's ATA
+    pub fn claim_accounts(
+        &self,
+        buyer: &Pubkey,
+        purchase_account: &Pubkey,
+    ) -> accounts::ClaimRewards {
+        accounts::ClaimRewards {
+            program_state: self.program_state,
+            purchase_account: *purchase_account,
+            payout_mint: self.otto_mint,
+            rewards_pool: self.rewards_pool,
+            buyer_token_account: Some(ata(buyer, &self.otto_mint)),
+            destination: None,
+            price_oracle: None,
+            stats_shard: buyer_stats_shard(buyer),
+            buyer: *buyer,
+            system_program: system_program::ID,
+            token_program: token::spl_token::ID,
+            associated_token_program: associated_token::spl_associated_token_account::ID,
+            event_authority: event_authority_address(),
+            program: otto_referral_rewards::ID,
+        }
+    }
+
+    pub fn claim_rewards(
+        &mut self,
+        buyer: &Pubkey,
+        purchase_account: &Pubkey,
+    ) -> std::result::Result<(), ProgramError> {
+        let claim = anchor_instruction(
+            self.claim_accounts(buyer, purchase_account),
+            instruction::ClaimRewards {},
+        );
+        self.context.process(&[claim])
+    }
+
+    /// Accounts for a relayer claiming to the buyer's ATA
+    pub fn delegated_claim_accounts(
+        &self,
+        relayer: &Pubkey,
+        buyer: &Pubkey,
+        purchase_account: &Pubkey,
+    ) -> accounts::ClaimRewardsDelegated {
+        accounts::ClaimRewardsDelegated {
+            program_state: self.program_state,
+            purchase_account: *purchase_account,
+            payout_mint: self.otto_mint,
+            rewards_pool: self.rewards_pool,
+            buyer_token_account: Some(ata(buyer, &self.otto_mint)),
+            destination: None,
+            user_settings: None,
+            price_oracle: None,
+            stats_shard: buyer_stats_shard(buyer),
+            buyer: *buyer,
+            relayer: *relayer,
+            instructions_sysvar: sysvar::instructions::ID,
+            system_program: system_program::ID,
+            token_program: token::spl_token::ID,
+            associated_token_program: associated_token::spl_associated_token_account::ID,
+            event_authority: event_authority_address(),
+            program: otto_referral_rewards::ID,
+        }
+    }
+
+    pub fn set_payout_address(
+        &mut self,
+        owner: &Pubkey,
+        payout_wallet: Option<Pubkey>,
+    ) -> std::result::Result<(), ProgramError> {
+        let instruction = anchor_instruction(
+            accounts::SetPayoutAddress {
+                user_settings: user_settings_address(owner),
+                owner: *owner,
+                system_program: system_program::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::SetPayoutAddress { payout_wallet },
+        );
+        self.context.process(&[instruction])
+    }
+
+    /// Reverse a purchase, passing every account it credited
+    pub fn reverse_purchase(
+        &mut self,
+        purchase_account: &Pubkey,
+    ) -> std::result::Result<(), ProgramError> {
+        let purchase: otto_referral_rewards::PurchaseAccount =
+            self.context.account(purchase_account);
+        let reverse = anchor_instruction(
+            accounts::ReversePurchase {
+                program_state: self.program_state,
+                purchase_account: *purchase_account,
+                referrer_account: purchase.referrer,
+                referee_record: purchase.referrer.map(|referrer_account| {
+                    referee_record_address(&referrer_account, &purchase.buyer)
+                }),
+                buyer_account: purchase.referrer.map(|_| buyer_address(&purchase.buyer)),
+                campaign: purchase.campaign,
+                leaderboard: purchase.leaderboard_id.map(leaderboard_address),
+                leaderboard_volume: purchase.leaderboard_id.zip(purchase.referrer).map(
+                    |(leaderboard_id, referrer_account)| {
+                        leaderboard_volume_address(
+                            &leaderboard_address(leaderboard_id),
+                            &referrer_account,
+                        )
+                    },
+                ),
+                held_payout: purchase
+                    .referrer
+                    .filter(|_| purchase.referral_held > 0)
+                    .map(|referrer_account| {
+                        held_payout_address(&referrer_account, &purchase.payout_mint)
+                    }),
+                stats_shard: buyer_stats_shard(&purchase.buyer),
+                authority: self.authority,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::ReversePurchase {},
+        );
+        self.context.process(&[reverse])
+    }
+
+    /// Add or reconfigure the reward pool for `mint`, creating its ATA
+    pub fn configure_reward_pool(
+        &mut self,
+        mint: &Pubkey,
+        rate: (u64, u64),
+        oracle: Option<Pubkey>,
+    ) -> std::result::Result<(), ProgramError> {
+        let token_program = self.context.token_program(mint);
+        let configure = anchor_instruction(
+            accounts::ConfigureRewardPool {
+                program_state: self.program_state,
+                payout_mint: *mint,
+                rewards_pool: ata_with_program(&self.program_state, mint, &token_program),
+                authority: self.authority,
+                system_program: system_program::ID,
+                token_program,
+                associated_token_program: associated_token::spl_associated_token_account::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::ConfigureRewardPool {
+                rate_numerator: rate.0,
+                rate_denominator: rate.1,
+                oracle,
+            },
+        );
+        self.context.process(&[configure])
+    }
+
+    /// Create `mint`'s price oracle, updated by `updater`
+    pub fn init_price_oracle(
+        &mut self,
+        mint: &Pubkey,
+        updater: &Pubkey,
+        max_staleness: i64,
+    ) -> std::result::Result<(), ProgramError> {
+        let init = anchor_instruction(
+            accounts::InitPriceOracle {
+                program_state: self.program_state,
+                payout_mint: *mint,
+                price_oracle: price_oracle_address(mint),
+                authority: self.authority,
+                system_program: system_program::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::InitPriceOracle {
+                updater: *updater,
+                max_staleness,
+            },
+        );
+        self.context.process(&[init])
+    }
+
+    pub fn update_price_oracle(
+        &mut self,
+        mint: &Pubkey,
+        updater: &Pubkey,
+        rate: (u64, u64),
+    ) -> std::result::Result<(), ProgramError> {
+        let update = anchor_instruction(
+            accounts::UpdatePriceOracle {
+                price_oracle: price_oracle_address(mint),
+                updater: *updater,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::UpdatePriceOracle {
+                rate_numerator: rate.0,
+                rate_denominator: rate.1,
+            },
+        );
+        self.context.process(&[update])
+    }
+
+    pub fn create_campaign(
+        &mut self,
+        campaign_id: u64,
+        params: otto_referral_rewards::CampaignParams,
+    ) -> std::result::Result<(), ProgramError> {
+        let create = anchor_instruction(
+            accounts::CreateCampaign {
+                program_state: self.program_state,
+                campaign: campaign_address(campaign_id),
+                authority: self.authority,
+                system_program: system_program::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::CreateCampaign {
+                campaign_id,
+                params,
+            },
+        );
+        self.context.process(&[create])
+    }
+
+    /// Create a leaderboard paying `prize_pool` of `prize_mint`, half to the
+    /// top referrer and a quarter each to the next two
+    pub fn create_leaderboard(
+        &mut self,
+        leaderboard_id: u64,
+        start_timestamp: i64,
+        end_timestamp: i64,
+        prize_mint: &Pubkey,
+        prize_pool: u64,
+    ) -> std::result::Result<(), ProgramError> {
+        let mut prize_splits = [0; otto_referral_rewards::LEADERBOARD_SIZE];
+        prize_splits[..3].copy_from_slice(&[5_000, 2_500, 2_500]);
+        let create = anchor_instruction(
+            accounts::CreateLeaderboard {
+                program_state: self.program_state,
+                leaderboard: leaderboard_address(leaderboard_id),
+                prize_mint: *prize_mint,
+                rewards_pool: ata(&self.program_state, prize_mint),
+                prize_vault: ata(&leaderboard_address(leaderboard_id), prize_mint),
+                authority: self.authority,
+                system_program: system_program::ID,
+                token_program: token::spl_token::ID,
+                associated_token_program: associated_token::spl_associated_token_account::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::CreateLeaderboard {
+                leaderboard_id,
+                start_timestamp,
+                end_timestamp,
+                prize_mint: *prize_mint,
+                prize_pool,
+                prize_splits,
+            },
+        );
+        self.context.process(&[create])
+    }
+
+    /// Settle an ended leaderboard, passing the ranked referrers' prize ATAs
+    /// in rank order
+    pub fn settle_leaderboard(
+        &mut self,
+        leaderboard_id: u64,
+        prize_mint: &Pubkey,
+        referrers: &[Pubkey],
+    ) -> std::result::Result<(), ProgramError> {
+        let leaderboard = leaderboard_address(leaderboard_id);
+        let mut settle = anchor_instruction(
+            accounts::SettleLeaderboard {
+                program_state: self.program_state,
+                leaderboard,
+                prize_mint: *prize_mint,
+                rewards_pool: ata(&self.program_state, prize_mint),
+                prize_vault: ata(&leaderboard, prize_mint),
+                token_program: token::spl_token::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::SettleLeaderboard {},
+        );
+        settle.accounts.extend(
+            referrers
+                .iter()
+                .map(|referrer| AccountMeta::new(ata(referrer, prize_mint), false)),
+        );
+        self.context.process(&[settle])
+    }
+
+    pub fn claim_leaderboard_prize(
+        &mut self,
+        referrer: &Pubkey,
+        leaderboard_id: u64,
+        prize_mint: &Pubkey,
+    ) -> std::result::Result<(), ProgramError> {
+        let leaderboard = leaderboard_address(leaderboard_id);
+        let claim = anchor_instruction(
+            accounts::ClaimLeaderboardPrize {
+                leaderboard,
+                prize_mint: *prize_mint,
+                prize_vault: ata(&leaderboard, prize_mint),
+                prize_account: ata(referrer, prize_mint),
+                referrer: *referrer,
+                system_program: system_program::ID,
+                token_program: token::spl_token::ID,
+                associated_token_program: associated_token::spl_associated_token_account::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::ClaimLeaderboardPrize {},
+        );
+        self.context.process(&[claim])
+    }
+
+    /// Claim the payouts held for `referrer`'s code in `payout_mint`
+    pub fn claim_held_payout(
+        &mut self,
+        referrer: &Pubkey,
+        referral_code: &str,
+        payout_mint: &Pubkey,
+    ) -> std::result::Result<(), ProgramError> {
+        let referrer_account = referrer_address(referral_code);
+        let claim = anchor_instruction(
+            accounts::ClaimHeldPayout {
+                program_state: self.program_state,
+                referrer_account,
+                held_payout: held_payout_address(&referrer_account, payout_mint),
+                payout_mint: *payout_mint,
+                rewards_pool: ata(&self.program_state, payout_mint),
+                payout_account: ata(referrer, payout_mint),
+                authority: *referrer,
+                system_program: system_program::ID,
+                token_program: token::spl_token::ID,
+                associated_token_program: associated_token::spl_associated_token_account::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::ClaimHeldPayout {},
+        );
+        self.context.process(&[claim])
+    }
+
+    pub fn set_payout_mint(&mut self, mint: &Pubkey) -> std::result::Result<(), ProgramError> {
+        let set = anchor_instruction(
+            accounts::UpdateProgramParams {
+                program_state: self.program_state,
+                authority: self.authority,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::SetPayoutMint { payout_mint: *mint },
+        );
+        self.context.process(&[set])
+    }
+
+    pub fn update_referee_bonus(
+        &mut self,
+        bonus_bps: Option<u16>,
+        first_purchase_only: Option<bool>,
+    ) -> std::result::Result<(), ProgramError> {
+        let update = anchor_instruction(
+            accounts::UpdateProgramParams {
+                program_state: self.program_state,
+                authority: self.authority,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::UpdateRefereeBonus {
+                bonus_bps,
+                first_purchase_only,
+            },
+        );
+        self.context.process(&[update])
+    }
+
+    pub fn set_milestones(
+        &mut self,
+        milestones: Vec<otto_referral_rewards::Milestone>,
+    ) -> std::result::Result<(), ProgramError> {
+        let set = anchor_instruction(
+            accounts::UpdateProgramParams {
+                program_state: self.program_state,
+                authority: self.authority,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::SetMilestones { milestones },
+        );
+        self.context.process(&[set])
+    }
+
+    /// Move $OTTO between two wallets' ATAs, signed by `from`
+    pub fn transfer(&mut self, from: &Pubkey, to: &Pubkey, amount: u64) {
+        let transfer = token::spl_token::instruction::transfer(
+            &token::spl_token::ID,
+            &ata(from, &self.otto_mint),
+            &ata(to, &self.otto_mint),
+            from,
+            &[],
+            amount,
+        )
+        .unwrap();
+        self.context.process(&[transfer]).unwrap();
+    }
+
+    /// Re-read a referrer's ATA balance into their TWAB and holding ratio,
+    /// signed by the referrer
+    pub fn update_holdings_ratio(
+        &mut self,
+        authority: &Pubkey,
+        referral_code: &str,
+    ) -> std::result::Result<(), ProgramError> {
+        let update = anchor_instruction(
+            accounts::UpdateHoldingsRatio {
+                program_state: self.program_state,
+                referrer_account: referrer_address(referral_code),
+                token_account: ata(authority, &self.otto_mint),
+                stake_account: stake_address(authority),
+                authority: *authority,
+                token_program: token::spl_token::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::UpdateHoldingsRatio {},
+        );
+        self.context.process(&[update])
+    }
+
+    /// Recompute a referrer's tier from their ATA balance, signed by the referrer
+    pub fn update_tier(
+        &mut self,
+        authority: &Pubkey,
+        referral_code: &str,
+    ) -> std::result::Result<(), ProgramError> {
+        let update = anchor_instruction(
+            accounts::UpdateTier {
+                program_state: self.program_state,
+                referrer_account: referrer_address(referral_code),
+                token_account: ata(authority, &self.otto_mint),
+                stake_account: stake_address(authority),
+                authority: *authority,
+                token_program: token::spl_token::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::UpdateTier {},
+        );
+        self.context.process(&[update])
+    }
+
+    /// Crank `referral_code`'s holdings, any bounty paid to `cranker`'s ATA
+    pub fn refresh_referrer(
+        &mut self,
+        cranker: &Pubkey,
+        authority: &Pubkey,
+        referral_code: &str,
+    ) -> std::result::Result<(), ProgramError> {
+        self.refresh_referrer_linked(cranker, authority, referral_code, &[])
+    }
+
+    /// Crank a referrer whose holdings also span `linked` accounts
+    pub fn refresh_referrer_linked(
+        &mut self,
+        cranker: &Pubkey,
+        authority: &Pubkey,
+        referral_code: &str,
+        linked: &[Pubkey],
+    ) -> std::result::Result<(), ProgramError> {
+        let mut refresh = anchor_instruction(
+            accounts::RefreshReferrer {
+                program_state: self.program_state,
+                referrer_account: referrer_address(referral_code),
+                otto_token_mint: self.otto_mint,
+                token_account: ata(authority, &self.otto_mint),
+                stake_account: stake_address(authority),
+                rewards_pool: self.rewards_pool,
+                cranker_token_account: Some(ata(cranker, &self.otto_mint)),
+                cranker: *cranker,
+                token_program: token::spl_token::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::RefreshReferrer {},
+        );
+        refresh.accounts.extend(
+            linked
+                .iter()
+                .map(|account| AccountMeta::new_readonly(*account, false)),
+        );
+        self.context.process(&[refresh])
+    }
+
+    pub fn update_holding_params(
+        &mut self,
+        holding_baseline: Option<u64>,
+        twab_window: Option<i64>,
+        ratio_recovery_rate: Option<u16>,
+        crank_bounty: Option<u64>,
+        unbonding_period: Option<i64>,
+    ) -> std::result::Result<(), ProgramError> {
+        let update = anchor_instruction(
+            accounts::UpdateProgramParams {
+                program_state: self.program_state,
+                authority: self.authority,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::UpdateHoldingParams {
+                holding_baseline,
+                twab_window,
+                ratio_recovery_rate,
+                crank_bounty,
+                unbonding_period,
+            },
+        );
+        self.context.process(&[update])
+    }
+
+    pub fn update_program_params(
+        &mut self,
+        authority: &Pubkey,
+        rewards_percentage: Option<u16>,
+        min_purchase_amount: Option<u64>,
+        tier_thresholds: Option<[u64; 3]>,
+    ) -> std::result::Result<(), ProgramError> {
+        let update = anchor_instruction(
+            accounts::UpdateProgramParams {
+                program_state: self.program_state,
+                authority: *authority,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::UpdateProgramParams {
+                rewards_percentage,
+                min_purchase_amount,
+                tier_thresholds,
+            },
+        );
+        self.context.process(&[update])
+    }
+
+    /// Stake `amount` from the owner's $OTTO ATA
+    pub fn stake(&mut self, owner: &Pubkey, amount: u64) -> std::result::Result<(), ProgramError> {
+        let stake_account = stake_address(owner);
+        let stake = anchor_instruction(
+            accounts::Stake {
+                program_state: self.program_state,
+                stake_account,
+                stake_vault: ata(&stake_account, &self.otto_mint),
+                otto_token_mint: self.otto_mint,
+                owner_token_account: ata(owner, &self.otto_mint),
+                owner: *owner,
+                system_program: system_program::ID,
+                token_program: token::spl_token::ID,
+                associated_token_program: associated_token::spl_associated_token_account::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::Stake { amount },
+        );
+        self.context.process(&[stake])
+    }
+
+    /// Start unbonding `amount`. With a referral code, the owner's referrer
+    /// account is updated too.
+    pub fn request_unstake(
+        &mut self,
+        owner: &Pubkey,
+        amount: u64,
+        referral_code: Option<&str>,
+    ) -> std::result::Result<(), ProgramError> {
+        let stake_account = stake_address(owner);
+        let request = anchor_instruction(
+            accounts::RequestUnstake {
+                program_state: self.program_state,
+                stake_account,
+                referrer_account: referral_code.map(referrer_address),
+                referrer_lookup: referrer_lookup_address(owner),
+                token_account: referral_code.map(|_| ata(owner, &self.otto_mint)),
+                owner: *owner,
+                token_program: token::spl_token::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::RequestUnstake { amount },
+        );
+        self.context.process(&[request])
+    }
+
+    /// Withdraw unbonded stake back to the owner's $OTTO ATA
+    pub fn withdraw_stake(&mut self, owner: &Pubkey) -> std::result::Result<(), ProgramError> {
+        let stake_account = stake_address(owner);
+        let withdraw = anchor_instruction(
+            accounts::Withdraw {
+                program_state: self.program_state,
+                stake_account,
+                otto_token_mint: self.otto_mint,
+                stake_vault: ata(&stake_account, &self.otto_mint),
+                owner_token_account: ata(owner, &self.otto_mint),
+                owner: *owner,
+                token_program: token::spl_token::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::Withdraw {},
+        );
+        self.context.process(&[withdraw])
+    }
+
+    pub fn create_merkle_distribution(
+        &mut self,
+        distribution_id: u64,
+        mint: &Pubkey,
+        root: [u8; 32],
+        max_claims: u32,
+        total_amount: u64,
+    ) -> std::result::Result<(), ProgramError> {
+        let create = anchor_instruction(
+            accounts::CreateMerkleDistribution {
+                program_state: self.program_state,
+                distribution: merkle_distribution_address(distribution_id),
+                authority: self.authority,
+                system_program: system_program::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::CreateMerkleDistribution {
+                distribution_id,
+                mint: *mint,
+                root,
+                max_claims,
+                total_amount,
+            },
+        );
+        self.context.process(&[create])
+    }
+
+    /// Claim leaf `index` of an $OTTO distribution to the claimant's ATA
+    pub fn claim_merkle(
+        &mut self,
+        distribution_id: u64,
+        claimant: &Pubkey,
+        index: u32,
+        amount: u64,
+        proof: Vec<[u8; 32]>,
+    ) -> std::result::Result<(), ProgramError> {
+        let claim = anchor_instruction(
+            accounts::ClaimMerkle {
+                program_state: self.program_state,
+                distribution: merkle_distribution_address(distribution_id),
+                mint: self.otto_mint,
+                rewards_pool: self.rewards_pool,
+                claimant_token_account: ata(claimant, &self.otto_mint),
+                claimant: *claimant,
+                system_program: system_program::ID,
+                token_program: token::spl_token::ID,
+                associated_token_program: associated_token::spl_associated_token_account::ID,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            instruction::ClaimMerkle {
+                index,
+                amount,
+                proof,
+            },
+        );
+        self.context.process(&[claim])
+    }
+}
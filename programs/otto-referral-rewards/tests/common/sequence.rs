@@ -0,0 +1,191 @@
+//! Random instruction sequences against a small fixed cast of referrers and
+//! buyers, checking that no $OTTO is created or lost along the way.
+//!
+//! Shared by the `pool_conservation` property test and the
+//! `instruction_sequence` fuzz target.
+
+use anchor_lang::{prelude::Pubkey, solana_program::program_pack::Pack};
+use anchor_spl::token::spl_token;
+use otto_referral_rewards::ReferrerAccount;
+
+use super::*;
+
+pub const REFERRERS: usize = 3;
+pub const BUYERS: usize = 4;
+
+#[derive(Clone, Debug)]
+pub enum Action {
+    Purchase {
+        buyer: u8,
+        amount: u64,
+        referrer: Option<u8>,
+    },
+    Claim {
+        purchase: u16,
+    },
+    /// A referrer moves tokens out of their ATA, lowering their holdings
+    Sell {
+        referrer: u8,
+        amount: u64,
+    },
+    Warp {
+        seconds: u32,
+    },
+}
+
+impl Action {
+    /// Decode actions from raw fuzzer input, an opcode byte and five parameter bytes at a time
+    pub fn decode_all(data: &[u8]) -> Vec<Action> {
+        data.chunks_exact(6).map(Self::decode).collect()
+    }
+
+    fn decode(chunk: &[u8]) -> Action {
+        let word = u32::from_le_bytes([chunk[2], chunk[3], chunk[4], chunk[5]]);
+        let amount = word as u64;
+        match chunk[0] % 4 {
+            0 => Action::Purchase {
+                buyer: chunk[1],
+                amount: amount * 1_000,
+                referrer: (chunk[1] & 0x80 == 0).then_some(chunk[1] >> 4),
+            },
+            1 => Action::Claim {
+                purchase: u16::from_le_bytes([chunk[1], chunk[2]]),
+            },
+            2 => Action::Sell {
+                referrer: chunk[1],
+                amount,
+            },
+            _ => Action::Warp {
+                seconds: word % 604_800,
+            },
+        }
+    }
+}
+
+pub struct Scenario {
+    pub otto: Otto,
+    referrers: Vec<(String, Pubkey)>,
+    buyers: Vec<Pubkey>,
+    sink: Pubkey,
+    purchases: Vec<(Pubkey, Pubkey)>,
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        let mut otto = Otto::new();
+        let referrers = (0..REFERRERS)
+            .map(|index| {
+                let code = format!("REF{index}");
+                // 1x, 2x and 3x the minimum, spanning the holdings multipliers
+                let authority = otto.holder(MIN_HOLDINGS_FOR_REFERRAL * (index as u64 + 1));
+                otto.register_referrer(&authority, &code).unwrap();
+                (code, authority)
+            })
+            .collect();
+        let buyers = (0..BUYERS).map(|_| otto.holder(0)).collect();
+        let sink = otto.holder(0);
+        Self {
+            otto,
+            referrers,
+            buyers,
+            sink,
+            purchases: Vec::new(),
+        }
+    }
+
+    /// Apply `action`, ignoring instruction failures, which are expected for
+    /// e.g. double claims or purchases below the minimum
+    pub fn apply(&mut self, action: &Action) {
+        match *action {
+            Action::Purchase {
+                buyer,
+                amount,
+                referrer,
+            } => {
+                let buyer = self.buyers[buyer as usize % BUYERS];
+                let referrer =
+                    referrer.map(|index| self.referrers[index as usize % REFERRERS].clone());
+                let result = self.otto.purchase(
+                    &buyer,
+                    amount,
+                    referrer
+                        .as_ref()
+                        .map(|(code, authority)| (code.as_str(), authority)),
+                );
+                if let Ok(purchase_account) = result {
+                    self.purchases.push((buyer, purchase_account));
+                }
+            }
+            Action::Claim { purchase } => {
+                if let Some((buyer, purchase_account)) = self
+                    .purchases
+                    .get(purchase as usize % self.purchases.len().max(1))
+                    .copied()
+                {
+                    let _ = self.otto.claim_rewards(&buyer, &purchase_account);
+                }
+            }
+            Action::Sell { referrer, amount } => {
+                let authority = self.referrers[referrer as usize % REFERRERS].1;
+                let source = ata(&authority, &self.otto.otto_mint);
+                let amount = amount.min(self.otto.context.token_balance(&source));
+                let transfer = spl_token::instruction::transfer(
+                    &spl_token::ID,
+                    &source,
+                    &ata(&self.sink, &self.otto.otto_mint),
+                    &authority,
+                    &[],
+                    amount,
+                )
+                .unwrap();
+                self.otto.context.process(&[transfer]).unwrap();
+            }
+            Action::Warp { seconds } => self.otto.context.warp(seconds as i64),
+        }
+    }
+
+    /// Every $OTTO minted is in the pool or one of the cast's token accounts,
+    /// and everything that left the pool was paid out as a reward
+    pub fn check_invariants(&self) {
+        let context = &self.otto.context;
+        let mint = self.otto.otto_mint;
+        let supply = spl_token::state::Mint::unpack(&context.get_account(&mint).unwrap().data)
+            .unwrap()
+            .supply;
+
+        let pool = context.token_balance(&self.otto.rewards_pool);
+        let wallets = self
+            .referrers
+            .iter()
+            .map(|(_, authority)| authority)
+            .chain(&self.buyers)
+            .chain([&self.sink]);
+        let held: u64 = wallets
+            .map(|wallet| match context.get_account(&ata(wallet, &mint)) {
+                Some(_) => context.token_balance(&ata(wallet, &mint)),
+                None => 0,
+            })
+            .sum();
+        assert_eq!(supply, pool + held, "tokens were created or destroyed");
+
+        let referral_rewards: u64 = self
+            .referrers
+            .iter()
+            .map(|(code, _)| {
+                context
+                    .account::<ReferrerAccount>(&referrer_address(code))
+                    .total_rewards_earned
+            })
+            .sum();
+        let cashback: u64 = self
+            .buyers
+            .iter()
+            .map(|buyer| context.token_balance(&ata(buyer, &mint)))
+            .sum();
+        assert_eq!(
+            REWARDS_POOL_FUNDING - pool,
+            referral_rewards + cashback,
+            "pool outflow does not match rewards paid"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! The heaviest handlers, run on the program built by `cargo build-sbf` so
+//! that the BPF VM's compute metering and 4KB stack frames apply. Each test
+//! is skipped when the program hasn't been built; `cargo test-sbf` builds it.
+
+mod common;
+
+use anchor_lang::prelude::Pubkey;
+use common::*;
+use otto_referral_rewards::{CampaignParams, Leaderboard, PurchaseAccount};
+
+/// Compute units a transaction gets without a compute budget instruction,
+/// as the client and bot send them
+const DEFAULT_COMPUTE_UNITS: u64 = 200_000;
+
+fn compiled_otto() -> Option<Otto> {
+    let otto = Otto::compiled();
+    if otto.is_none() {
+        eprintln!("skipped: build the program with `cargo build-sbf` first");
+    }
+    otto
+}
+
+fn assert_fits_default_budget(otto: &Otto, handler: &str) {
+    let compute_units = otto.context.compute_units();
+    assert!(
+        compute_units <= DEFAULT_COMPUTE_UNITS,
+        "{handler} used {compute_units} compute units"
+    );
+}
+
+/// OTTO1's referrer and a buyer, under an active campaign and leaderboard
+fn setup(otto: &mut Otto) -> (Pubkey, Pubkey) {
+    let (now, otto_mint) = (otto.context.now(), otto.otto_mint);
+    let params = CampaignParams {
+        start_timestamp: now,
+        end_timestamp: now + 86_400,
+        budget: 1_000_000,
+        payout_mint: otto_mint,
+        base_reward_rate: Some(1_000),
+        holdings_multipliers: None,
+        eligible_codes: Vec::new(),
+    };
+    otto.create_campaign(1, params).unwrap();
+    otto.create_leaderboard(1, now, now + 86_400, &otto_mint, 0)
+        .unwrap();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+    (referrer, buyer)
+}
+
+fn campaign_purchase(otto: &mut Otto, buyer: &Pubkey, referrer: &Pubkey) -> Pubkey {
+    let mut accounts = otto.purchase_accounts(buyer, Some(("OTTO1", referrer)));
+    accounts.campaign = Some(campaign_address(1));
+    let purchase_account = otto
+        .purchase_with(accounts, 100_000, Some("OTTO1"))
+        .unwrap();
+    assert_fits_default_budget(otto, "process_purchase");
+    purchase_account
+}
+
+#[test]
+fn referred_purchase_and_claim_fit_the_default_budget() {
+    let Some(mut otto) = compiled_otto() else {
+        return;
+    };
+    let (referrer, buyer) = setup(&mut otto);
+    let referrer_ata = ata(&referrer, &otto.otto_mint);
+
+    let purchase_account = campaign_purchase(&mut otto, &buyer, &referrer);
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.campaign, Some(campaign_address(1)));
+    assert_eq!(purchase.leaderboard_id, Some(1));
+    assert_eq!(
+        otto.context.token_balance(&referrer_ata),
+        MIN_HOLDINGS_FOR_REFERRAL + purchase.referral_reward
+    );
+    let leaderboard: Leaderboard = otto.context.account(&leaderboard_address(1));
+    assert_eq!(leaderboard.entries[0].referrer, referrer);
+    assert_eq!(leaderboard.entries[0].volume, 100_000);
+
+    otto.claim_rewards(&buyer, &purchase_account).unwrap();
+    assert_fits_default_budget(&otto, "claim_rewards");
+    assert_eq!(
+        Some(otto.context.token_balance(&ata(&buyer, &otto.otto_mint))),
+        purchase.cashback
+    );
+}
+
+#[test]
+fn reversal_fits_the_default_budget() {
+    let Some(mut otto) = compiled_otto() else {
+        return;
+    };
+    let (referrer, buyer) = setup(&mut otto);
+
+    let purchase_account = campaign_purchase(&mut otto, &buyer, &referrer);
+    otto.reverse_purchase(&purchase_account).unwrap();
+    assert_fits_default_budget(&otto, "reverse_purchase");
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert!(purchase.reversed);
+    let leaderboard: Leaderboard = otto.context.account(&leaderboard_address(1));
+    assert_eq!(leaderboard.entries[0].volume, 0);
+}
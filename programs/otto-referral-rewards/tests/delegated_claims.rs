@@ -0,0 +1,213 @@
+mod common;
+
+use anchor_lang::{
+    prelude::Pubkey,
+    solana_program::{instruction::Instruction, system_instruction},
+};
+use common::*;
+use otto_referral_rewards::{
+    claim_message, instruction, ErrorCode, PurchaseAccount, RewardsClaimed,
+};
+use solana_sdk::{
+    ed25519_program,
+    signature::{Keypair, Signer},
+};
+
+/// Public key, signature and message offsets in the ed25519 instruction data
+const PUBLIC_KEY_OFFSET: u16 = 16;
+const SIGNATURE_OFFSET: u16 = PUBLIC_KEY_OFFSET + 32;
+const MESSAGE_OFFSET: u16 = SIGNATURE_OFFSET + 64;
+
+/// ed25519 instruction verifying `signer`'s signature of `message`, with its
+/// offsets pointing into instruction `data_ix` (u16::MAX for itself)
+fn signature_instruction(signer: &Keypair, message: &[u8], data_ix: u16) -> Instruction {
+    let signature = signer.sign_message(message);
+    let mut data = vec![1, 0];
+    for field in [
+        SIGNATURE_OFFSET,
+        data_ix,
+        PUBLIC_KEY_OFFSET,
+        data_ix,
+        MESSAGE_OFFSET,
+        message.len() as u16,
+        data_ix,
+    ] {
+        data.extend_from_slice(&field.to_le_bytes());
+    }
+    data.extend_from_slice(signer.pubkey().as_ref());
+    data.extend_from_slice(signature.as_ref());
+    data.extend_from_slice(message);
+    Instruction {
+        program_id: ed25519_program::ID,
+        accounts: vec![],
+        data,
+    }
+}
+
+fn claim_instruction(
+    otto: &Otto,
+    relayer: &Pubkey,
+    buyer: &Pubkey,
+    purchase_account: &Pubkey,
+) -> Instruction {
+    anchor_instruction(
+        otto.delegated_claim_accounts(relayer, buyer, purchase_account),
+        instruction::ClaimRewardsDelegated {},
+    )
+}
+
+/// A buyer's unclaimed purchase and a funded relayer
+fn setup() -> (Otto, Pubkey, Pubkey, Pubkey) {
+    let mut otto = Otto::new();
+    let buyer = otto.context.new_wallet();
+    let purchase_account = otto.purchase(&buyer, 100_000, None).unwrap();
+    let relayer = otto.context.new_wallet();
+    (otto, buyer, purchase_account, relayer)
+}
+
+#[test]
+fn relayer_claims_for_the_buyer_once() {
+    let (mut otto, buyer, purchase_account, relayer) = setup();
+    let buyer_lamports = otto.context.get_account(&buyer).unwrap().lamports;
+    let relayer_lamports = otto.context.get_account(&relayer).unwrap().lamports;
+
+    let signature = signature_instruction(
+        otto.context.keypair(&buyer),
+        &claim_message(&purchase_account),
+        u16::MAX,
+    );
+    let claim = claim_instruction(&otto, &relayer, &buyer, &purchase_account);
+    otto.context
+        .process(&[signature.clone(), claim.clone()])
+        .unwrap();
+
+    // 2% cashback, in an ATA the relayer paid for
+    let buyer_token_account = ata(&buyer, &otto.otto_mint);
+    assert_eq!(otto.context.token_balance(&buyer_token_account), 2_000);
+    assert_eq!(otto.context.events::<RewardsClaimed>()[0].amount, 2_000);
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert!(purchase.rewards_claimed);
+    assert_eq!(
+        otto.context.get_account(&buyer).unwrap().lamports,
+        buyer_lamports
+    );
+    let rent = otto
+        .context
+        .get_account(&buyer_token_account)
+        .unwrap()
+        .lamports;
+    assert_eq!(
+        otto.context.get_account(&relayer).unwrap().lamports,
+        relayer_lamports - rent
+    );
+
+    assert_error(
+        otto.context.process(&[signature, claim]),
+        ErrorCode::RewardsAlreadyClaimed,
+    );
+    assert_eq!(otto.context.token_balance(&buyer_token_account), 2_000);
+}
+
+#[test]
+fn claim_requires_the_signature_directly_before_it() {
+    let (mut otto, buyer, purchase_account, relayer) = setup();
+    let claim = claim_instruction(&otto, &relayer, &buyer, &purchase_account);
+
+    assert_error(
+        otto.context.process(std::slice::from_ref(&claim)),
+        ErrorCode::MissingClaimSignature,
+    );
+
+    let signature = signature_instruction(
+        otto.context.keypair(&buyer),
+        &claim_message(&purchase_account),
+        u16::MAX,
+    );
+    let recipient = otto.context.new_wallet();
+    let transfer = system_instruction::transfer(&relayer, &recipient, 1);
+    assert_error(
+        otto.context.process(&[signature, transfer, claim]),
+        ErrorCode::MissingClaimSignature,
+    );
+}
+
+#[test]
+fn claim_rejects_signatures_that_do_not_authorise_it() {
+    let (mut otto, buyer, purchase_account, relayer) = setup();
+    otto.context.warp(1);
+    let other_purchase = otto.purchase(&buyer, 50_000, None).unwrap();
+    let impostor = Keypair::new();
+    let claim = claim_instruction(&otto, &relayer, &buyer, &purchase_account);
+    let message = claim_message(&purchase_account);
+
+    // Valid for the precompile, but read from instruction 1 (itself, after
+    // the compute budget instruction) rather than inline
+    let explicit_index = signature_instruction(otto.context.keypair(&buyer), &message, 1);
+    let wrong_signer = signature_instruction(&impostor, &message, u16::MAX);
+    let wrong_purchase = signature_instruction(
+        otto.context.keypair(&buyer),
+        &claim_message(&other_purchase),
+        u16::MAX,
+    );
+    for signature in [explicit_index, wrong_signer, wrong_purchase] {
+        assert_error(
+            otto.context.process(&[signature, claim.clone()]),
+            ErrorCode::InvalidClaimSignature,
+        );
+    }
+
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert!(!purchase.rewards_claimed);
+}
+
+#[test]
+fn claim_rejects_reversed_purchases() {
+    let (mut otto, buyer, purchase_account, relayer) = setup();
+    otto.reverse_purchase(&purchase_account).unwrap();
+
+    let signature = signature_instruction(
+        otto.context.keypair(&buyer),
+        &claim_message(&purchase_account),
+        u16::MAX,
+    );
+    let claim = claim_instruction(&otto, &relayer, &buyer, &purchase_account);
+    assert_error(
+        otto.context.process(&[signature, claim]),
+        ErrorCode::PurchaseReversed,
+    );
+}
+
+#[test]
+fn claim_only_pays_the_buyers_payout_wallet() {
+    let (mut otto, buyer, purchase_account, relayer) = setup();
+    let payout_wallet = otto.context.new_wallet();
+    otto.set_payout_address(&buyer, Some(payout_wallet))
+        .unwrap();
+    let signature = signature_instruction(
+        otto.context.keypair(&buyer),
+        &claim_message(&purchase_account),
+        u16::MAX,
+    );
+
+    let mint = otto.otto_mint;
+    let relayer_account = otto.context.create_ata(&relayer, &mint);
+    let mut accounts = otto.delegated_claim_accounts(&relayer, &buyer, &purchase_account);
+    accounts.buyer_token_account = None;
+    accounts.destination = Some(relayer_account);
+    accounts.user_settings = Some(user_settings_address(&buyer));
+    let claim = anchor_instruction(accounts, instruction::ClaimRewardsDelegated {});
+    assert_error(
+        otto.context.process(&[signature.clone(), claim]),
+        ErrorCode::InvalidClaimDestination,
+    );
+
+    let destination = otto.context.create_ata(&payout_wallet, &mint);
+    let mut accounts = otto.delegated_claim_accounts(&relayer, &buyer, &purchase_account);
+    accounts.buyer_token_account = None;
+    accounts.destination = Some(destination);
+    accounts.user_settings = Some(user_settings_address(&buyer));
+    let claim = anchor_instruction(accounts, instruction::ClaimRewardsDelegated {});
+    otto.context.process(&[signature, claim]).unwrap();
+    assert_eq!(otto.context.token_balance(&destination), 2_000);
+    assert_eq!(otto.context.token_balance(&relayer_account), 0);
+}
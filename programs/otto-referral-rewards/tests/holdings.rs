@@ -0,0 +1,335 @@
+mod common;
+
+use anchor_lang::prelude::{AccountMeta, Pubkey};
+use common::*;
+use otto_referral_rewards::{ErrorCode, ReferrerAccount, StakeAccount, Tier};
+
+/// A referrer holding twice the minimum who sold half and bought it back
+/// straight away: ratio down to 50% with a 100% target to recover to
+fn rebought_referrer(otto: &mut Otto) -> Pubkey {
+    let referrer = otto.holder(2 * MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let sink = otto.holder(0);
+
+    otto.transfer(&referrer, &sink, MIN_HOLDINGS_FOR_REFERRAL);
+    otto.update_holdings_ratio(&referrer, "OTTO1").unwrap();
+    otto.transfer(&sink, &referrer, MIN_HOLDINGS_FOR_REFERRAL);
+    otto.update_holdings_ratio(&referrer, "OTTO1").unwrap();
+
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.holding_ratio, 5_000);
+    referrer
+}
+
+#[test]
+fn sale_drops_ratio_and_recovery_is_rate_limited() {
+    let mut otto = Otto::new();
+    // One basis point per minute
+    otto.update_holding_params(None, None, Some(1_440), None, None)
+        .unwrap();
+    let referrer = rebought_referrer(&mut otto);
+
+    otto.context.warp(3_600);
+    otto.update_holdings_ratio(&referrer, "OTTO1").unwrap();
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.holding_ratio, 5_060);
+    assert_eq!(account.ratio_recovery_at, otto.context.now());
+}
+
+#[test]
+fn frequent_updates_do_not_stall_recovery() {
+    let mut otto = Otto::new();
+    otto.update_holding_params(None, None, Some(1_440), None, None)
+        .unwrap();
+    let referrer = rebought_referrer(&mut otto);
+
+    // Each update comes before a whole basis point has been earned
+    for _ in 0..160 {
+        otto.context.warp(45);
+        otto.update_holdings_ratio(&referrer, "OTTO1").unwrap();
+    }
+
+    // 7200 seconds at a basis point per minute, none of it lost to rounding
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.holding_ratio, 5_120);
+    assert_eq!(account.ratio_recovery_at, otto.context.now());
+}
+
+#[test]
+fn recovery_stops_at_the_target() {
+    let mut otto = Otto::new();
+    let referrer = rebought_referrer(&mut otto);
+
+    // 10% a day recovers the 50% gap in five days
+    otto.context.warp(10 * 86_400);
+    otto.update_holdings_ratio(&referrer, "OTTO1").unwrap();
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.holding_ratio, 10_000);
+}
+
+const CRANK_BOUNTY: u64 = 1_000;
+
+/// A Silver referrer holding 6M, a sink to sell into and a cranker with an
+/// ATA for bounties
+fn silver_referrer(otto: &mut Otto) -> (Pubkey, Pubkey, Pubkey) {
+    otto.update_holding_params(None, None, None, Some(CRANK_BOUNTY), None)
+        .unwrap();
+    let referrer = otto.holder(6_000_000);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    otto.update_tier(&referrer, "OTTO1").unwrap();
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert!(account.tier == Tier::Silver);
+    (referrer, otto.holder(0), otto.holder(0))
+}
+
+#[test]
+fn refresh_pays_a_bounty_for_a_sale() {
+    let mut otto = Otto::new();
+    let (referrer, sink, cranker) = silver_referrer(&mut otto);
+
+    otto.transfer(&referrer, &sink, 3_000_000);
+    otto.refresh_referrer(&cranker, &referrer, "OTTO1").unwrap();
+
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert!(account.tier == Tier::Bronze);
+    assert_eq!(account.holding_ratio, 5_000);
+    assert_eq!(account.last_bounty_at, otto.context.now());
+    assert_eq!(
+        otto.context.token_balance(&ata(&cranker, &otto.otto_mint)),
+        CRANK_BOUNTY
+    );
+}
+
+#[test]
+fn refresh_never_raises_the_tier() {
+    let mut otto = Otto::new();
+    otto.update_holding_params(None, None, None, Some(CRANK_BOUNTY), None)
+        .unwrap();
+    let referrer = otto.holder(6_000_000);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let cranker = otto.holder(0);
+
+    otto.refresh_referrer(&cranker, &referrer, "OTTO1").unwrap();
+
+    // Only the referrer's own update_tier moves them up
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert!(account.tier == Tier::None);
+    assert_eq!(
+        otto.context.token_balance(&ata(&cranker, &otto.otto_mint)),
+        0
+    );
+}
+
+#[test]
+fn sell_and_rebuy_cycles_pay_one_bounty_per_window() {
+    let mut otto = Otto::new();
+    let (referrer, sink, cranker) = silver_referrer(&mut otto);
+    let cranker_ata = ata(&cranker, &otto.otto_mint);
+
+    for cycle in 0..3 {
+        otto.context.warp(3_600);
+        otto.transfer(&referrer, &sink, 3_000_000);
+        otto.refresh_referrer(&cranker, &referrer, "OTTO1").unwrap();
+        let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+        assert!(account.tier == Tier::Bronze, "cycle {cycle}");
+
+        otto.transfer(&sink, &referrer, 3_000_000);
+        otto.update_holdings_ratio(&referrer, "OTTO1").unwrap();
+        otto.update_tier(&referrer, "OTTO1").unwrap();
+    }
+    assert_eq!(otto.context.token_balance(&cranker_ata), CRANK_BOUNTY);
+
+    // A sale a full TWAB window after the last bounty pays again
+    otto.context.warp(7 * 86_400);
+    otto.transfer(&referrer, &sink, 3_000_000);
+    otto.refresh_referrer(&cranker, &referrer, "OTTO1").unwrap();
+    assert_eq!(otto.context.token_balance(&cranker_ata), 2 * CRANK_BOUNTY);
+}
+
+#[test]
+fn sale_bought_back_before_the_crank_pays_no_bounty() {
+    let mut otto = Otto::new();
+    let (referrer, sink, cranker) = silver_referrer(&mut otto);
+
+    otto.transfer(&referrer, &sink, 3_000_000);
+    otto.transfer(&sink, &referrer, 3_000_000);
+    otto.refresh_referrer(&cranker, &referrer, "OTTO1").unwrap();
+
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert!(account.tier == Tier::Silver);
+    assert_eq!(
+        otto.context.token_balance(&ata(&cranker, &otto.otto_mint)),
+        0
+    );
+}
+
+/// Purchase referred by `OTTO1`, passing `linked` as the referrer's holdings
+/// accounts
+fn linked_purchase(
+    otto: &mut Otto,
+    buyer: &Pubkey,
+    referrer: &Pubkey,
+    linked: &[Pubkey],
+) -> std::result::Result<(), anchor_lang::prelude::ProgramError> {
+    let mut purchase = anchor_instruction(
+        otto.purchase_accounts(buyer, Some(("OTTO1", referrer))),
+        otto_referral_rewards::instruction::ProcessPurchase {
+            purchase_amount: 100_000,
+            referral_code: Some("OTTO1".to_string()),
+        },
+    );
+    purchase.accounts.extend(
+        linked
+            .iter()
+            .map(|account| AccountMeta::new_readonly(*account, false)),
+    );
+    otto.context.process(&[purchase])
+}
+
+/// A referrer with half the minimum in their ATA and half staked
+fn staked_referrer(otto: &mut Otto) -> Pubkey {
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.stake(&referrer, MIN_HOLDINGS_FOR_REFERRAL / 2)
+        .unwrap();
+    referrer
+}
+
+#[test]
+fn staked_tokens_count_without_being_linked() {
+    let mut otto = Otto::new();
+    let referrer = staked_referrer(&mut otto);
+
+    // Linking the stake account would count it twice
+    assert_error(
+        otto.register_referrer_linked(&referrer, "OTTO1", &[stake_address(&referrer)]),
+        ErrorCode::InvalidHoldingAccount,
+    );
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.current_holdings, MIN_HOLDINGS_FOR_REFERRAL);
+    assert!(account.linked_token_accounts.is_empty());
+    // Its lookup leaves the wallet room for one code
+    assert!(otto.register_referrer(&referrer, "OTTO2").is_err());
+
+    // Purchases read the stake account alongside the ATA
+    let buyer = otto.context.new_wallet();
+    let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO1", &referrer)));
+    accounts.referrer_stake_account = None;
+    assert_error(
+        otto.purchase_with(accounts, 100_000, Some("OTTO1"))
+            .map(|_| ()),
+        ErrorCode::MissingReferrerStakeAccount,
+    );
+    linked_purchase(&mut otto, &buyer, &referrer, &[]).unwrap();
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.current_holdings, MIN_HOLDINGS_FOR_REFERRAL);
+}
+
+#[test]
+fn unstaking_drops_holdings_before_the_tokens_unbond() {
+    let mut otto = Otto::new();
+    let referrer = staked_referrer(&mut otto);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+
+    // A referrer can't unstake without recording it against their ratio
+    assert_error(
+        otto.request_unstake(&referrer, MIN_HOLDINGS_FOR_REFERRAL / 2, None),
+        ErrorCode::ReferrerAccountMismatch,
+    );
+    otto.request_unstake(&referrer, MIN_HOLDINGS_FOR_REFERRAL / 2, Some("OTTO1"))
+        .unwrap();
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.current_holdings, MIN_HOLDINGS_FOR_REFERRAL / 2);
+    assert_eq!(account.holding_ratio, 5_000);
+    let stake: StakeAccount = otto.context.account(&stake_address(&referrer));
+    assert_eq!(
+        (stake.staked_amount, stake.pending_unstake),
+        (0, MIN_HOLDINGS_FOR_REFERRAL / 2)
+    );
+
+    assert_error(
+        otto.withdraw_stake(&referrer),
+        ErrorCode::UnbondingNotElapsed,
+    );
+    otto.context.warp(7 * 86_400);
+    otto.withdraw_stake(&referrer).unwrap();
+    assert_eq!(
+        otto.context.token_balance(&ata(&referrer, &otto.otto_mint)),
+        MIN_HOLDINGS_FOR_REFERRAL
+    );
+    assert_error(otto.withdraw_stake(&referrer), ErrorCode::NothingToWithdraw);
+
+    // Stakers who aren't referrers have no referrer account to pass
+    let staker = staked_referrer(&mut otto);
+    otto.request_unstake(&staker, 1, None).unwrap();
+}
+
+#[test]
+fn holdings_accounts_must_belong_to_the_referrer() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL / 2);
+    let other = staked_referrer(&mut otto);
+
+    for linked in [ata(&other, &otto.otto_mint), stake_address(&other)] {
+        assert_error(
+            otto.register_referrer_linked(&referrer, "OTTO1", &[linked]),
+            ErrorCode::InvalidHoldingAccount,
+        );
+    }
+
+    let (otto_mint, mint_authority) = (otto.otto_mint, otto.mint_authority);
+    let extra = otto.context.create_token_account(&referrer, &otto_mint);
+    otto.context.mint_to(
+        &otto_mint,
+        &mint_authority,
+        &extra,
+        MIN_HOLDINGS_FOR_REFERRAL / 2,
+    );
+    // Counting the ATA or an extra account twice would double its balance
+    for linked in [vec![ata(&referrer, &otto_mint)], vec![extra, extra]] {
+        assert_error(
+            otto.register_referrer_linked(&referrer, "OTTO1", &linked),
+            ErrorCode::DuplicateHoldingAccount,
+        );
+    }
+
+    otto.register_referrer_linked(&referrer, "OTTO1", &[extra])
+        .unwrap();
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.current_holdings, MIN_HOLDINGS_FOR_REFERRAL);
+}
+
+#[test]
+fn closed_or_reassigned_linked_accounts_are_unlinked() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    let (otto_mint, mint_authority) = (otto.otto_mint, otto.mint_authority);
+    let linked = [
+        otto.context.create_token_account(&referrer, &otto_mint),
+        otto.context.create_token_account(&referrer, &otto_mint),
+    ];
+    for account in &linked {
+        otto.context.mint_to(&otto_mint, &mint_authority, account, 1_000);
+    }
+    otto.register_referrer_linked(&referrer, "OTTO1", &linked)
+        .unwrap();
+
+    // One account is closed and the other handed to someone else
+    let [closed, reassigned] = linked;
+    otto.context.set_account(closed, AccountState::default());
+    let new_owner = otto.context.new_wallet();
+    let mut account = otto.context.get_account(&reassigned).unwrap();
+    account.data[32..64].copy_from_slice(new_owner.as_ref());
+    otto.context.set_account(reassigned, account);
+
+    // The crank counts both as empty instead of failing, and unlinks them
+    let cranker = otto.holder(0);
+    otto.refresh_referrer_linked(&cranker, &referrer, "OTTO1", &linked)
+        .unwrap();
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.current_holdings, MIN_HOLDINGS_FOR_REFERRAL);
+    assert!(account.linked_token_accounts.is_empty());
+
+    let buyer = otto.context.new_wallet();
+    linked_purchase(&mut otto, &buyer, &referrer, &[]).unwrap();
+}
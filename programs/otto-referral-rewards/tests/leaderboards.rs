@@ -0,0 +1,209 @@
+mod common;
+
+use anchor_lang::prelude::Pubkey;
+use common::*;
+use otto_referral_rewards::{ErrorCode, Leaderboard, LeaderboardVolume};
+
+/// Purchase referred by OTTO1, ranked on `leaderboard_id`
+fn ranked_purchase(
+    otto: &mut Otto,
+    buyer: &Pubkey,
+    referrer: &Pubkey,
+    leaderboard_id: u64,
+    amount: u64,
+) -> Result<(), anchor_lang::prelude::ProgramError> {
+    let leaderboard = leaderboard_address(leaderboard_id);
+    let mut accounts = otto.purchase_accounts(buyer, Some(("OTTO1", referrer)));
+    accounts.leaderboard = Some(leaderboard);
+    accounts.leaderboard_volume = Some(leaderboard_volume_address(
+        &leaderboard,
+        &referrer_address("OTTO1"),
+    ));
+    otto.purchase_with(accounts, amount, Some("OTTO1"))?;
+    otto.context.warp(1);
+    Ok(())
+}
+
+#[test]
+fn purchases_are_ranked_on_the_active_leaderboard() {
+    let mut otto = Otto::new();
+    let (now, otto_mint) = (otto.context.now(), otto.otto_mint);
+    otto.create_leaderboard(1, now, now + 86_400, &otto_mint, 0)
+        .unwrap();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+
+    // The active leaderboard can't be left out
+    let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO1", &referrer)));
+    accounts.leaderboard = None;
+    accounts.leaderboard_volume = None;
+    assert_error(
+        otto.purchase_with(accounts, 100_000, Some("OTTO1"))
+            .map(|_| ()),
+        ErrorCode::LeaderboardMismatch,
+    );
+    ranked_purchase(&mut otto, &buyer, &referrer, 1, 100_000).unwrap();
+
+    // A new leaderboard takes over ranking, starting from zero, while the
+    // first keeps its volume
+    otto.create_leaderboard(2, now, now + 86_400, &otto_mint, 0)
+        .unwrap();
+    assert_error(
+        ranked_purchase(&mut otto, &buyer, &referrer, 1, 50_000),
+        ErrorCode::LeaderboardMismatch,
+    );
+    ranked_purchase(&mut otto, &buyer, &referrer, 2, 30_000).unwrap();
+
+    let first: Leaderboard = otto.context.account(&leaderboard_address(1));
+    assert_eq!(first.entries[0].referrer, referrer);
+    assert_eq!(first.entries[0].volume, 100_000);
+    let second: Leaderboard = otto.context.account(&leaderboard_address(2));
+    assert_eq!(second.entries[0].volume, 30_000);
+
+    let volume: LeaderboardVolume = otto.context.account(&leaderboard_volume_address(
+        &leaderboard_address(1),
+        &referrer_address("OTTO1"),
+    ));
+    assert_eq!(volume.leaderboard, leaderboard_address(1));
+    assert_eq!(volume.referrer, referrer_address("OTTO1"));
+    assert_eq!(volume.volume, 100_000);
+}
+
+#[test]
+fn ranking_requires_the_volume_account() {
+    let mut otto = Otto::new();
+    let (now, otto_mint) = (otto.context.now(), otto.otto_mint);
+    otto.create_leaderboard(1, now, now + 86_400, &otto_mint, 0)
+        .unwrap();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+
+    let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO1", &referrer)));
+    accounts.leaderboard_volume = None;
+    assert_error(
+        otto.purchase_with(accounts, 100_000, Some("OTTO1"))
+            .map(|_| ()),
+        ErrorCode::MissingLeaderboardVolume,
+    );
+}
+
+#[test]
+fn prize_is_set_aside_at_creation_and_the_rest_returned() {
+    let mut otto = Otto::new();
+    let (now, otto_mint) = (otto.context.now(), otto.otto_mint);
+    otto.create_leaderboard(1, now, now + 86_400, &otto_mint, 100_000)
+        .unwrap();
+    let vault = ata(&leaderboard_address(1), &otto.otto_mint);
+    assert_eq!(otto.context.token_balance(&vault), 100_000);
+    assert_eq!(
+        otto.context.token_balance(&otto.rewards_pool),
+        REWARDS_POOL_FUNDING - 100_000
+    );
+
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+    ranked_purchase(&mut otto, &buyer, &referrer, 1, 100_000).unwrap();
+    let pool_before = otto.context.token_balance(&otto.rewards_pool);
+    let referrer_before = otto.context.token_balance(&ata(&referrer, &otto.otto_mint));
+
+    otto.context.warp(86_400);
+    otto.settle_leaderboard(1, &otto_mint, &[referrer]).unwrap();
+
+    // The only ranked referrer takes half; the unfilled ranks' half goes back
+    assert_eq!(
+        otto.context.token_balance(&ata(&referrer, &otto.otto_mint)),
+        referrer_before + 50_000
+    );
+    assert_eq!(otto.context.token_balance(&vault), 0);
+    assert_eq!(
+        otto.context.token_balance(&otto.rewards_pool),
+        pool_before + 50_000
+    );
+    let leaderboard: Leaderboard = otto.context.account(&leaderboard_address(1));
+    assert!(leaderboard.settled);
+}
+
+#[test]
+fn prize_for_a_missing_ata_is_held_until_claimed() {
+    let mut otto = Otto::new();
+    let mint_authority = otto.mint_authority;
+    let prize_mint = otto.context.create_mint(&mint_authority, 6);
+    otto.configure_reward_pool(&prize_mint, (1, 1), None)
+        .unwrap();
+    let pool = ata(&otto.program_state, &prize_mint);
+    otto.context
+        .mint_to(&prize_mint, &mint_authority, &pool, 1_000_000);
+    let now = otto.context.now();
+    otto.create_leaderboard(1, now, now + 86_400, &prize_mint, 100_000)
+        .unwrap();
+
+    // Only the runner-up has an ATA for the prize mint
+    let leader = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&leader, "OTTO1").unwrap();
+    let runner_up = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&runner_up, "OTTO2").unwrap();
+    otto.context.create_ata(&runner_up, &prize_mint);
+    let buyer = otto.context.new_wallet();
+    ranked_purchase(&mut otto, &buyer, &leader, 1, 100_000).unwrap();
+    let leaderboard = leaderboard_address(1);
+    let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO2", &runner_up)));
+    accounts.leaderboard = Some(leaderboard);
+    accounts.leaderboard_volume = Some(leaderboard_volume_address(
+        &leaderboard,
+        &referrer_address("OTTO2"),
+    ));
+    otto.purchase_with(accounts, 50_000, Some("OTTO2")).unwrap();
+
+    otto.context.warp(86_400);
+    otto.settle_leaderboard(1, &prize_mint, &[leader, runner_up])
+        .unwrap();
+
+    // The runner-up is paid, the leader's half is held and the unfilled
+    // third rank's quarter goes back to the pool
+    let vault = ata(&leaderboard, &prize_mint);
+    assert_eq!(
+        otto.context.token_balance(&ata(&runner_up, &prize_mint)),
+        25_000
+    );
+    assert_eq!(otto.context.token_balance(&vault), 50_000);
+    assert_eq!(otto.context.token_balance(&pool), 925_000);
+
+    // Only the leader can claim it, once
+    assert_error(
+        otto.claim_leaderboard_prize(&runner_up, 1, &prize_mint),
+        ErrorCode::NoLeaderboardPrize,
+    );
+    otto.claim_leaderboard_prize(&leader, 1, &prize_mint)
+        .unwrap();
+    assert_eq!(
+        otto.context.token_balance(&ata(&leader, &prize_mint)),
+        50_000
+    );
+    assert_eq!(otto.context.token_balance(&vault), 0);
+    otto.context.warp(1);
+    assert_error(
+        otto.claim_leaderboard_prize(&leader, 1, &prize_mint),
+        ErrorCode::NoLeaderboardPrize,
+    );
+}
+
+#[test]
+fn prizes_cannot_be_claimed_before_settlement() {
+    let mut otto = Otto::new();
+    let (now, otto_mint) = (otto.context.now(), otto.otto_mint);
+    otto.create_leaderboard(1, now, now + 86_400, &otto_mint, 100_000)
+        .unwrap();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+    ranked_purchase(&mut otto, &buyer, &referrer, 1, 100_000).unwrap();
+
+    otto.context.warp(86_400);
+    assert_error(
+        otto.claim_leaderboard_prize(&referrer, 1, &otto_mint),
+        ErrorCode::LeaderboardNotSettled,
+    );
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use anchor_lang::{prelude::Pubkey, solana_program::keccak};
+use common::*;
+use otto_referral_rewards::{merkle_leaf, ErrorCode, MerkleDistribution};
+
+/// Four claimants owed 1,000, 2,000, 3,000 and 4,000 $OTTO, as
+/// `(claimant, amount, proof)` by index, and the tree's root
+struct Tree {
+    claims: Vec<(Pubkey, u64, Vec<[u8; 32]>)>,
+    root: [u8; 32],
+}
+
+fn node(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+    keccak::hashv(&[&[1], &a.min(b), &a.max(b)]).to_bytes()
+}
+
+fn tree(otto: &mut Otto) -> Tree {
+    let claims: Vec<(Pubkey, u64)> = (1..=4)
+        .map(|amount| (otto.context.new_wallet(), amount * 1_000))
+        .collect();
+    let leaves: Vec<[u8; 32]> = claims
+        .iter()
+        .enumerate()
+        .map(|(index, (claimant, amount))| merkle_leaf(index as u32, claimant, *amount))
+        .collect();
+    let pairs = [node(leaves[0], leaves[1]), node(leaves[2], leaves[3])];
+    Tree {
+        claims: claims
+            .into_iter()
+            .enumerate()
+            .map(|(index, (claimant, amount))| {
+                (
+                    claimant,
+                    amount,
+                    vec![leaves[index ^ 1], pairs[1 - index / 2]],
+                )
+            })
+            .collect(),
+        root: node(pairs[0], pairs[1]),
+    }
+}
+
+#[test]
+fn distribution_requires_a_reward_pool() {
+    let mut otto = Otto::new();
+    let tree = tree(&mut otto);
+    let mint_authority = otto.mint_authority;
+    let mint = otto.context.create_mint(&mint_authority, 6);
+
+    assert_error(
+        otto.create_merkle_distribution(1, &mint, tree.root, 4, 10_000),
+        ErrorCode::UnsupportedPayoutMint,
+    );
+}
+
+#[test]
+fn leaf_is_paid_once() {
+    let mut otto = Otto::new();
+    let tree = tree(&mut otto);
+    let otto_mint = otto.otto_mint;
+    otto.create_merkle_distribution(1, &otto_mint, tree.root, 4, 10_000)
+        .unwrap();
+
+    let (claimant, amount, proof) = tree.claims[2].clone();
+    otto.claim_merkle(1, &claimant, 2, amount, proof.clone())
+        .unwrap();
+    assert_eq!(
+        otto.context.token_balance(&ata(&claimant, &otto_mint)),
+        3_000
+    );
+    let distribution: MerkleDistribution = otto.context.account(&merkle_distribution_address(1));
+    assert_eq!(
+        (distribution.claimed_amount, distribution.num_claimed),
+        (3_000, 1)
+    );
+
+    otto.context.warp(1);
+    assert_error(
+        otto.claim_merkle(1, &claimant, 2, amount, proof),
+        ErrorCode::MerkleAlreadyClaimed,
+    );
+}
+
+#[test]
+fn proof_binds_the_claimant_and_amount() {
+    let mut otto = Otto::new();
+    let tree = tree(&mut otto);
+    let otto_mint = otto.otto_mint;
+    otto.create_merkle_distribution(1, &otto_mint, tree.root, 4, 10_000)
+        .unwrap();
+    let (claimant, amount, proof) = tree.claims[0].clone();
+
+    let thief = otto.context.new_wallet();
+    assert_error(
+        otto.claim_merkle(1, &thief, 0, amount, proof.clone()),
+        ErrorCode::InvalidMerkleProof,
+    );
+    assert_error(
+        otto.claim_merkle(1, &claimant, 0, 4_000, proof.clone()),
+        ErrorCode::InvalidMerkleProof,
+    );
+    // Another leaf's index would claim against the wrong bit
+    assert_error(
+        otto.claim_merkle(1, &claimant, 1, amount, proof.clone()),
+        ErrorCode::InvalidMerkleProof,
+    );
+
+    otto.claim_merkle(1, &claimant, 0, amount, proof).unwrap();
+}
+
+#[test]
+fn index_beyond_max_claims_is_rejected() {
+    let mut otto = Otto::new();
+    let tree = tree(&mut otto);
+    let otto_mint = otto.otto_mint;
+    // The root holds a fourth leaf the distribution wasn't sized for
+    otto.create_merkle_distribution(1, &otto_mint, tree.root, 3, 10_000)
+        .unwrap();
+
+    let (claimant, amount, proof) = tree.claims[3].clone();
+    assert_error(
+        otto.claim_merkle(1, &claimant, 3, amount, proof),
+        ErrorCode::InvalidMerkleProof,
+    );
+}
+
+#[test]
+fn claims_stop_at_the_total_amount() {
+    let mut otto = Otto::new();
+    let tree = tree(&mut otto);
+    let otto_mint = otto.otto_mint;
+    // A root promising more than it was funded for
+    otto.create_merkle_distribution(1, &otto_mint, tree.root, 4, 5_000)
+        .unwrap();
+
+    let (claimant, amount, proof) = tree.claims[3].clone();
+    otto.claim_merkle(1, &claimant, 3, amount, proof).unwrap();
+    let (claimant, amount, proof) = tree.claims[2].clone();
+    assert_error(
+        otto.claim_merkle(1, &claimant, 2, amount, proof),
+        ErrorCode::MerkleDistributionExhausted,
+    );
+    assert_eq!(
+        otto.context.token_balance(&otto.rewards_pool),
+        REWARDS_POOL_FUNDING - 4_000
+    );
+}
@@ -0,0 +1,362 @@
+mod common;
+
+use anchor_lang::{
+    error::ErrorCode as AnchorErrorCode,
+    prelude::{ProgramError, Pubkey, Rent},
+    pubkey,
+    solana_program::program_pack::Pack,
+    system_program, Space,
+};
+use anchor_spl::token::spl_token;
+use common::*;
+use otto_referral_rewards::{
+    accounts, instruction, AccountMigrated, ErrorCode, ProgramState, PurchaseAccount,
+    ReferrerAccount, ReferrerLookup, Tier, ACCOUNT_VERSION,
+};
+use solana_sdk::signature::keypair_from_seed;
+
+// Accounts captured from the program as first deployed, before accounts were
+// versioned: a program state, referrer "OTTO1" and a purchase it referred.
+// The wallets are keypairs seeded with [1; 32] (authority), [2; 32] (mint),
+// [3; 32] (referrer) and [4; 32] (buyer).
+const PROGRAM_STATE_V0: &[u8] = include_bytes!("fixtures/v0/program_state.bin");
+const REFERRER_ACCOUNT_V0: &[u8] = include_bytes!("fixtures/v0/referrer_account.bin");
+const PURCHASE_ACCOUNT_V0: &[u8] = include_bytes!("fixtures/v0/purchase_account.bin");
+
+const FIXTURE_AUTHORITY: Pubkey = pubkey!("AKnL4NNf3DGWZJS6cPknBuEGnVsV4A4m5tgebLHaRSZ9");
+const FIXTURE_MINT: Pubkey = pubkey!("9hSR6S7WPtxmTojgo6GG3k4yDPecgJY292j7xrsUGWBu");
+const FIXTURE_REWARDS_POOL: Pubkey = pubkey!("FMtQgFYRQKPaW4Dc2tRMpk8nYYY1sRfUhenS48yVtpXJ");
+const FIXTURE_REFERRER: Pubkey = pubkey!("GyGKxMyg1p9SsHfm15MkNUu1u9TN2JtTspcdmrtGUdse");
+const FIXTURE_BUYER: Pubkey = pubkey!("EdmxWPmx2WH6WgFfTdu9xfkYf3k1g5wD1zccTVySEEh1");
+const FIXTURE_PURCHASE: Pubkey = pubkey!("2bvc8uyUyBjgZm5862arG8JpT4n2B1akUQZfomt54smX");
+const FIXTURE_TIMESTAMP: i64 = GENESIS_TIMESTAMP + 3600;
+
+/// Install `data` at `key` as a rent-exempt program account
+fn install(context: &mut TestContext, key: Pubkey, data: &[u8]) {
+    context.set_account(
+        key,
+        AccountState {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data: data.to_vec(),
+            owner: otto_referral_rewards::ID,
+            executable: false,
+        },
+    );
+}
+
+/// Fund the fixtures' wallet whose keypair is seeded with `[seed; 32]`
+fn fixture_wallet(context: &mut TestContext, seed: u8) -> Pubkey {
+    context.add_wallet(keypair_from_seed(&[seed; 32]).unwrap())
+}
+
+/// A context holding the three fixtures, with their authority funded
+fn fixture_context() -> TestContext {
+    let mut context = TestContext::new();
+    install(&mut context, program_state_address(), PROGRAM_STATE_V0);
+    install(&mut context, referrer_address("OTTO1"), REFERRER_ACCOUNT_V0);
+    install(&mut context, FIXTURE_PURCHASE, PURCHASE_ACCOUNT_V0);
+    fixture_wallet(&mut context, 1);
+    context
+}
+
+/// The fixtures' deployment: its mint, a funded rewards pool and the
+/// referrer's $OTTO, around the still unmigrated accounts
+fn fixture_otto() -> Otto {
+    let mut context = fixture_context();
+    let mint_authority = context.new_wallet();
+    let mut mint = vec![0; spl_token::state::Mint::LEN];
+    spl_token::state::Mint {
+        mint_authority: Some(mint_authority).into(),
+        supply: 0,
+        decimals: 6,
+        is_initialized: true,
+        freeze_authority: None.into(),
+    }
+    .pack_into_slice(&mut mint);
+    context.set_account(
+        FIXTURE_MINT,
+        AccountState {
+            lamports: Rent::default().minimum_balance(mint.len()),
+            data: mint,
+            owner: spl_token::ID,
+            executable: false,
+        },
+    );
+
+    let mut otto = Otto {
+        context,
+        authority: FIXTURE_AUTHORITY,
+        mint_authority,
+        otto_mint: FIXTURE_MINT,
+        program_state: program_state_address(),
+        rewards_pool: FIXTURE_REWARDS_POOL,
+    };
+    for (wallet, amount) in [
+        (otto.program_state, REWARDS_POOL_FUNDING),
+        (fixture_wallet(&mut otto.context, 3), 2 * MIN_HOLDINGS_FOR_REFERRAL),
+    ] {
+        let token_account = otto.context.create_ata(&wallet, &FIXTURE_MINT);
+        otto.context
+            .mint_to(&FIXTURE_MINT, &mint_authority, &token_account, amount);
+    }
+    fixture_wallet(&mut otto.context, 4);
+    otto
+}
+
+fn migrate_program_state(
+    context: &mut TestContext,
+    authority: &Pubkey,
+) -> std::result::Result<(), ProgramError> {
+    let migrate = anchor_instruction(
+        accounts::MigrateProgramState {
+            program_state: program_state_address(),
+            authority: *authority,
+            system_program: system_program::ID,
+            event_authority: event_authority_address(),
+            program: otto_referral_rewards::ID,
+        },
+        instruction::MigrateProgramState {},
+    );
+    context.process(&[migrate])
+}
+
+fn migrate_referrer(
+    context: &mut TestContext,
+    payer: &Pubkey,
+    referral_code: &str,
+) -> std::result::Result<(), ProgramError> {
+    let migrate = anchor_instruction(
+        accounts::MigrateReferrer {
+            referrer_account: referrer_address(referral_code),
+            referrer_lookup: referrer_lookup_address(&FIXTURE_REFERRER),
+            payer: *payer,
+            system_program: system_program::ID,
+            event_authority: event_authority_address(),
+            program: otto_referral_rewards::ID,
+        },
+        instruction::MigrateReferrer {},
+    );
+    context.process(&[migrate])
+}
+
+fn migrate_purchase(
+    context: &mut TestContext,
+    payer: &Pubkey,
+    purchase_account: &Pubkey,
+) -> std::result::Result<(), ProgramError> {
+    let migrate = anchor_instruction(
+        accounts::MigratePurchase {
+            program_state: program_state_address(),
+            purchase_account: *purchase_account,
+            payer: *payer,
+            system_program: system_program::ID,
+            event_authority: event_authority_address(),
+            program: otto_referral_rewards::ID,
+        },
+        instruction::MigratePurchase {},
+    );
+    context.process(&[migrate])
+}
+
+/// Migration grows the account to the current layout and tops up its rent
+fn assert_resized<T: Space>(context: &TestContext, key: &Pubkey) {
+    let account = context.get_account(key).unwrap();
+    assert_eq!(account.data.len(), 8 + T::INIT_SPACE);
+    assert_eq!(
+        account.lamports,
+        Rent::default().minimum_balance(8 + T::INIT_SPACE)
+    );
+}
+
+#[test]
+fn program_state_fixture_migrates() {
+    let mut context = fixture_context();
+
+    migrate_program_state(&mut context, &FIXTURE_AUTHORITY).unwrap();
+    assert_resized::<ProgramState>(&context, &program_state_address());
+
+    let state: ProgramState = context.account(&program_state_address());
+    assert_eq!(state.version, ACCOUNT_VERSION);
+    assert_eq!(state.authority, FIXTURE_AUTHORITY);
+    assert_eq!(state.otto_token_mint, FIXTURE_MINT);
+    assert_eq!(state.rewards_pool, FIXTURE_REWARDS_POOL);
+    assert_eq!(state.rewards_percentage, REWARDS_PERCENTAGE);
+    assert_eq!(state.min_purchase_amount, MIN_PURCHASE_AMOUNT);
+    assert_eq!(state.min_holdings_for_referral, MIN_HOLDINGS_FOR_REFERRAL);
+    assert_eq!(state.base_reward_rate, BASE_REWARD_RATE);
+    assert_eq!(
+        [
+            state.bronze_threshold,
+            state.silver_threshold,
+            state.gold_threshold
+        ],
+        [1_000_000, 5_000_000, 10_000_000]
+    );
+
+    // New fields take the defaults of a fresh initialize
+    assert_eq!(state.reward_pools.len(), 1);
+    assert_eq!(state.reward_pools[0].mint, FIXTURE_MINT);
+    assert_eq!(state.reward_pools[0].pool, FIXTURE_REWARDS_POOL);
+    assert_eq!(
+        (
+            state.reward_pools[0].rate_numerator,
+            state.reward_pools[0].rate_denominator
+        ),
+        (1, 1)
+    );
+    assert_eq!(state.payout_mint, FIXTURE_MINT);
+    assert!(state.milestones.is_empty());
+    assert_eq!(state.twab_window, 7 * 86_400);
+    assert_eq!(state.ratio_recovery_rate, 1000);
+    assert_eq!(state.unbonding_period, 7 * 86_400);
+    assert!(state.referee_bonus_first_purchase_only);
+    assert_eq!(state.active_campaign, None);
+    assert_eq!(state.active_leaderboard, None);
+    assert_eq!(state.reserved, [0; 64]);
+
+    let [migrated] = &context.events::<AccountMigrated>()[..] else {
+        panic!("expected one AccountMigrated event");
+    };
+    assert_eq!(migrated.account, program_state_address());
+    assert_eq!(migrated.payer, FIXTURE_AUTHORITY);
+    assert_eq!(migrated.from_version, 0);
+    assert_eq!(migrated.to_version, ACCOUNT_VERSION);
+    assert_eq!(migrated.space, (8 + ProgramState::INIT_SPACE) as u64);
+
+    // The authority paid for the extra rent
+    let growth = Rent::default().minimum_balance(8 + ProgramState::INIT_SPACE)
+        - Rent::default().minimum_balance(PROGRAM_STATE_V0.len());
+    assert_eq!(
+        context.get_account(&FIXTURE_AUTHORITY).unwrap().lamports,
+        WALLET_LAMPORTS - growth
+    );
+}
+
+#[test]
+fn referrer_and_purchase_fixtures_migrate_permissionlessly() {
+    let mut context = fixture_context();
+    let payer = context.new_wallet();
+
+    migrate_referrer(&mut context, &payer, "OTTO1").unwrap();
+    assert_resized::<ReferrerAccount>(&context, &referrer_address("OTTO1"));
+    let referrer: ReferrerAccount = context.account(&referrer_address("OTTO1"));
+    assert_eq!(referrer.version, ACCOUNT_VERSION);
+    assert_eq!(referrer.authority, FIXTURE_REFERRER);
+    assert_eq!(referrer.referral_code, "OTTO1");
+    assert_eq!(referrer.total_referrals, 1);
+    assert_eq!(referrer.total_rewards_earned, 5_500);
+    assert!(referrer.tier == Tier::None);
+    assert_eq!(referrer.initial_holdings, 2 * MIN_HOLDINGS_FOR_REFERRAL);
+    assert_eq!(referrer.current_holdings, 2 * MIN_HOLDINGS_FOR_REFERRAL);
+    assert_eq!(referrer.holding_ratio, 10_000);
+    assert_eq!(referrer.last_activity, FIXTURE_TIMESTAMP);
+    assert!(referrer.is_active);
+    // Untracked before versioning
+    assert_eq!(referrer.total_referred_volume, 0);
+    assert_eq!(referrer.unique_referees, 0);
+    assert_eq!(referrer.twab, 2 * MIN_HOLDINGS_FOR_REFERRAL);
+    assert_eq!(referrer.twab_last_update, FIXTURE_TIMESTAMP);
+    assert!(referrer.linked_token_accounts.is_empty());
+    assert_eq!(referrer.ratio_recovery_at, FIXTURE_TIMESTAMP);
+    assert_eq!(referrer.last_bounty_at, 0);
+    // The authority's lookup is created alongside
+    let lookup: ReferrerLookup = context.account(&referrer_lookup_address(&FIXTURE_REFERRER));
+    assert_eq!(lookup.referrer_account, referrer_address("OTTO1"));
+    assert_eq!(referrer.reserved, [0; 64]);
+
+    // Purchases need the migrated program state for their payout mint
+    assert_error(
+        migrate_purchase(&mut context, &payer, &FIXTURE_PURCHASE),
+        AnchorErrorCode::AccountDidNotDeserialize,
+    );
+    migrate_program_state(&mut context, &FIXTURE_AUTHORITY).unwrap();
+    migrate_purchase(&mut context, &payer, &FIXTURE_PURCHASE).unwrap();
+    assert_resized::<PurchaseAccount>(&context, &FIXTURE_PURCHASE);
+    let purchase: PurchaseAccount = context.account(&FIXTURE_PURCHASE);
+    assert_eq!(purchase.version, ACCOUNT_VERSION);
+    assert_eq!(purchase.buyer, FIXTURE_BUYER);
+    assert_eq!(purchase.amount, 100_000);
+    assert_eq!(purchase.timestamp, FIXTURE_TIMESTAMP);
+    assert_eq!(purchase.referral_code.as_deref(), Some("OTTO1"));
+    assert_eq!(purchase.payout_mint, FIXTURE_MINT);
+    assert_eq!(purchase.referrer, None);
+    assert_eq!(purchase.referral_reward, 0);
+    assert!(!purchase.rewards_claimed && !purchase.reversed);
+    assert_eq!(purchase.cashback, None);
+    assert_eq!(purchase.reserved, [0; 32]);
+}
+
+#[test]
+fn migrate_program_state_requires_authority() {
+    let mut context = fixture_context();
+    let impostor = context.new_wallet();
+
+    assert_error(
+        migrate_program_state(&mut context, &impostor),
+        AnchorErrorCode::ConstraintHasOne,
+    );
+    assert_eq!(
+        context.get_account(&program_state_address()).unwrap().data,
+        PROGRAM_STATE_V0
+    );
+}
+
+#[test]
+fn migrating_twice_fails() {
+    let mut context = fixture_context();
+    let payer = context.new_wallet();
+
+    migrate_referrer(&mut context, &payer, "OTTO1").unwrap();
+    assert_error(
+        migrate_referrer(&mut context, &payer, "OTTO1"),
+        ErrorCode::AccountAlreadyMigrated,
+    );
+
+    // Accounts created by this version start out migrated
+    let mut otto = Otto::new();
+    let authority = otto.authority;
+    assert_error(
+        migrate_program_state(&mut otto.context, &authority),
+        ErrorCode::AccountAlreadyMigrated,
+    );
+}
+
+#[test]
+fn migrated_deployment_keeps_working() {
+    let mut otto = fixture_otto();
+
+    // Old layouts no longer deserialize until they are migrated
+    assert_error(
+        otto.claim_rewards(&FIXTURE_BUYER, &FIXTURE_PURCHASE),
+        AnchorErrorCode::AccountDidNotDeserialize,
+    );
+
+    migrate_program_state(&mut otto.context, &FIXTURE_AUTHORITY).unwrap();
+    migrate_referrer(&mut otto.context, &FIXTURE_BUYER, "OTTO1").unwrap();
+    migrate_purchase(&mut otto.context, &FIXTURE_BUYER, &FIXTURE_PURCHASE).unwrap();
+    let init_stats_shards: Vec<_> = (0..otto_referral_rewards::STATS_SHARDS)
+        .map(|index| otto.init_stats_shard_instruction(index))
+        .collect();
+    otto.context.process(&init_stats_shards).unwrap();
+
+    // The cashback owed before the upgrade is still claimable, in $OTTO
+    otto.claim_rewards(&FIXTURE_BUYER, &FIXTURE_PURCHASE)
+        .unwrap();
+    assert_eq!(
+        otto.context.token_balance(&ata(&FIXTURE_BUYER, &FIXTURE_MINT)),
+        2_000
+    );
+
+    otto.context.warp(60);
+    otto.purchase(&FIXTURE_BUYER, 100_000, Some(("OTTO1", &FIXTURE_REFERRER)))
+        .unwrap();
+    let referrer: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(referrer.total_referrals, 2);
+    assert_eq!(referrer.total_referred_volume, 100_000);
+    assert_eq!(referrer.total_rewards_earned, 11_000);
+    assert_eq!(
+        otto.context
+            .token_balance(&ata(&FIXTURE_REFERRER, &FIXTURE_MINT)),
+        2 * MIN_HOLDINGS_FOR_REFERRAL + 5_500
+    );
+}
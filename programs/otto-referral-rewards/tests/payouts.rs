@@ -0,0 +1,329 @@
+mod common;
+
+use anchor_lang::prelude::Pubkey;
+use anchor_spl::token_2022;
+use common::*;
+use otto_referral_rewards::{
+    DynamicReferralProcessed, ErrorCode, HeldPayout, HeldPayoutClaimed, PurchaseAccount,
+    PurchaseReversed, ReferralPayoutHeld, ReferrerAccount, RewardsClaimed,
+};
+
+const MAX_STALENESS: i64 = 3_600;
+
+/// A second payout asset priced by its own oracle at a quarter of a unit per
+/// $OTTO, made the global payout mint. Returns the mint and the oracle updater.
+fn oracle_priced_mint(otto: &mut Otto) -> (Pubkey, Pubkey) {
+    let mint_authority = otto.mint_authority;
+    let mint = otto.context.create_mint(&mint_authority, 6);
+    let updater = otto.context.new_wallet();
+
+    otto.init_price_oracle(&mint, &updater, MAX_STALENESS)
+        .unwrap();
+    otto.update_price_oracle(&mint, &updater, (1, 4)).unwrap();
+    otto.configure_reward_pool(&mint, (1, 1), Some(price_oracle_address(&mint)))
+        .unwrap();
+    otto.set_payout_mint(&mint).unwrap();
+
+    let pool = ata(&otto.program_state, &mint);
+    otto.context
+        .mint_to(&mint, &mint_authority, &pool, REWARDS_POOL_FUNDING);
+    (mint, updater)
+}
+
+/// Purchase accounts paying out in `mint` through its oracle
+fn oracle_purchase(
+    otto: &Otto,
+    mint: &Pubkey,
+    buyer: &Pubkey,
+    referrer: Option<(&str, &Pubkey)>,
+) -> otto_referral_rewards::accounts::ProcessPurchase {
+    let mut accounts = otto.purchase_accounts(buyer, referrer);
+    accounts.payout_mint = *mint;
+    accounts.rewards_pool = ata(&otto.program_state, mint);
+    accounts.referrer_payout_account = referrer.map(|(_, authority)| ata(authority, mint));
+    accounts.price_oracle = Some(price_oracle_address(mint));
+    accounts
+}
+
+#[test]
+fn configure_reward_pool_requires_the_mints_oracle_pda() {
+    let mut otto = Otto::new();
+    let mint_authority = otto.mint_authority;
+    let mint = otto.context.create_mint(&mint_authority, 6);
+
+    assert_error(
+        otto.configure_reward_pool(&mint, (1, 1), Some(Pubkey::new_unique())),
+        ErrorCode::PriceOracleMismatch,
+    );
+    // Another mint's oracle can't price this one
+    let otto_mint = otto.otto_mint;
+    assert_error(
+        otto.configure_reward_pool(&mint, (1, 1), Some(price_oracle_address(&otto_mint))),
+        ErrorCode::PriceOracleMismatch,
+    );
+
+    otto.configure_reward_pool(&mint, (1, 1), Some(price_oracle_address(&mint)))
+        .unwrap();
+}
+
+#[test]
+fn rewards_are_paid_at_the_oracle_rate() {
+    let mut otto = Otto::new();
+    let (mint, _) = oracle_priced_mint(&mut otto);
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    otto.context.create_ata(&referrer, &mint);
+    let buyer = otto.context.new_wallet();
+
+    let accounts = oracle_purchase(&otto, &mint, &buyer, Some(("OTTO1", &referrer)));
+    let purchase_account = otto
+        .purchase_with(accounts, 100_000, Some("OTTO1"))
+        .unwrap();
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.payout_mint, mint);
+    assert_eq!(
+        otto.context.token_balance(&ata(&referrer, &mint)),
+        purchase.referral_reward / 4
+    );
+
+    let mut claim = otto.claim_accounts(&buyer, &purchase_account);
+    claim.payout_mint = mint;
+    claim.rewards_pool = ata(&otto.program_state, &mint);
+    claim.buyer_token_account = Some(ata(&buyer, &mint));
+    claim.price_oracle = Some(price_oracle_address(&mint));
+    otto.context
+        .process(&[anchor_instruction(
+            claim,
+            otto_referral_rewards::instruction::ClaimRewards {},
+        )])
+        .unwrap();
+
+    // 2% cashback on 100,000 $OTTO is 2,000 $OTTO, or 500 units of the mint
+    assert_eq!(otto.context.token_balance(&ata(&buyer, &mint)), 500);
+}
+
+#[test]
+fn cashback_is_fixed_at_the_purchase_rate() {
+    let mut otto = Otto::new();
+    let (mint, updater) = oracle_priced_mint(&mut otto);
+    let buyer = otto.context.new_wallet();
+
+    let accounts = oracle_purchase(&otto, &mint, &buyer, None);
+    let purchase_account = otto.purchase_with(accounts, 100_000, None).unwrap();
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.cashback, Some(500));
+
+    // The mint doubles against $OTTO before the claim, which still pays the
+    // 500 units owed at purchase, without the oracle
+    otto.update_price_oracle(&mint, &updater, (1, 2)).unwrap();
+    let mut claim = otto.claim_accounts(&buyer, &purchase_account);
+    claim.payout_mint = mint;
+    claim.rewards_pool = ata(&otto.program_state, &mint);
+    claim.buyer_token_account = Some(ata(&buyer, &mint));
+    otto.context
+        .process(&[anchor_instruction(
+            claim,
+            otto_referral_rewards::instruction::ClaimRewards {},
+        )])
+        .unwrap();
+    assert_eq!(otto.context.token_balance(&ata(&buyer, &mint)), 500);
+}
+
+#[test]
+fn payouts_are_held_without_a_payout_account() {
+    let mut otto = Otto::new();
+    let (mint, _) = oracle_priced_mint(&mut otto);
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+    let pool = ata(&otto.program_state, &mint);
+    let held_payout = held_payout_address(&referrer_address("OTTO1"), &mint);
+
+    // The referrer has no account for the payout mint, so one can't be passed
+    let mut accounts = oracle_purchase(&otto, &mint, &buyer, Some(("OTTO1", &referrer)));
+    accounts.referrer_payout_account = None;
+    assert_error(
+        otto.purchase_with(accounts, 100_000, Some("OTTO1"))
+            .map(|_| ()),
+        ErrorCode::MissingReferrerPayoutAccount,
+    );
+
+    // Their payout is held in the pool instead of blocking the purchase
+    let mut accounts = oracle_purchase(&otto, &mint, &buyer, Some(("OTTO1", &referrer)));
+    accounts.referrer_payout_account = None;
+    accounts.held_payout = Some(held_payout);
+    let purchase_account = otto
+        .purchase_with(accounts, 100_000, Some("OTTO1"))
+        .unwrap();
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    let reward = purchase.referral_reward / 4;
+    let held: HeldPayout = otto.context.account(&held_payout);
+    assert_eq!(held.amount, reward);
+    let event = &otto.context.events::<ReferralPayoutHeld>()[0];
+    assert_eq!((event.amount, event.total_held), (reward, reward));
+    assert_eq!(otto.context.events::<DynamicReferralProcessed>()[0].transfer_fee, 0);
+    assert_eq!(otto.context.token_balance(&pool), REWARDS_POOL_FUNDING);
+
+    // Claiming creates the referrer's ATA and pays what was held, once
+    otto.claim_held_payout(&referrer, "OTTO1", &mint).unwrap();
+    assert_eq!(otto.context.token_balance(&ata(&referrer, &mint)), reward);
+    assert_eq!(otto.context.account::<HeldPayout>(&held_payout).amount, 0);
+    assert_eq!(otto.context.events::<HeldPayoutClaimed>()[0].amount, reward);
+    assert_error(
+        otto.claim_held_payout(&referrer, "OTTO1", &mint),
+        ErrorCode::NoHeldPayout,
+    );
+
+    // Only the referrer can claim
+    let other = otto.context.new_wallet();
+    assert!(otto.claim_held_payout(&other, "OTTO1", &mint).is_err());
+}
+
+/// Purchase referred by "OTTO1" whose referral reward is held
+fn held_purchase(
+    otto: &mut Otto,
+    mint: &Pubkey,
+    buyer: &Pubkey,
+    referrer: &Pubkey,
+    amount: u64,
+) -> Pubkey {
+    let mut accounts = oracle_purchase(otto, mint, buyer, Some(("OTTO1", referrer)));
+    accounts.referrer_payout_account = None;
+    accounts.held_payout = Some(held_payout_address(&referrer_address("OTTO1"), mint));
+    let purchase_account = otto
+        .purchase_with(accounts, amount, Some("OTTO1"))
+        .unwrap();
+    otto.context.warp(1);
+    purchase_account
+}
+
+#[test]
+fn reversal_releases_held_payouts() {
+    let mut otto = Otto::new();
+    let (mint, _) = oracle_priced_mint(&mut otto);
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+    let held_payout = held_payout_address(&referrer_address("OTTO1"), &mint);
+
+    let reversed = held_purchase(&mut otto, &mint, &buyer, &referrer, 200_000);
+    let kept = held_purchase(&mut otto, &mint, &buyer, &referrer, 100_000);
+    let reversed_reward = otto.context.account::<PurchaseAccount>(&reversed).referral_held;
+    let kept_reward = otto.context.account::<PurchaseAccount>(&kept).referral_held;
+    assert!(reversed_reward > kept_reward && kept_reward > 0);
+    assert_eq!(
+        otto.context.account::<HeldPayout>(&held_payout).amount,
+        reversed_reward + kept_reward
+    );
+
+    // The reversed reward was never paid, so it is released rather than owed
+    otto.reverse_purchase(&reversed).unwrap();
+    let event = &otto.context.events::<PurchaseReversed>()[0];
+    assert_eq!((event.clawback, event.payout_released), (0, reversed_reward));
+    assert_eq!(event.total_rewards_earned, kept_reward);
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.clawback_owed, 0);
+    assert_eq!(otto.context.account::<HeldPayout>(&held_payout).amount, kept_reward);
+
+    otto.claim_held_payout(&referrer, "OTTO1", &mint).unwrap();
+    assert_eq!(otto.context.token_balance(&ata(&referrer, &mint)), kept_reward);
+
+    // Once claimed, the reward was paid and is charged back as usual
+    otto.reverse_purchase(&kept).unwrap();
+    let purchase: PurchaseAccount = otto.context.account(&kept);
+    let event = &otto.context.events::<PurchaseReversed>()[0];
+    assert_eq!((event.clawback, event.payout_released), (purchase.referral_reward, 0));
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.clawback_owed, purchase.referral_reward);
+    assert_error(
+        otto.claim_held_payout(&referrer, "OTTO1", &mint),
+        ErrorCode::NoHeldPayout,
+    );
+}
+
+#[test]
+fn stale_or_missing_oracle_is_rejected() {
+    let mut otto = Otto::new();
+    let (mint, _) = oracle_priced_mint(&mut otto);
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    otto.context.create_ata(&referrer, &mint);
+    let buyer = otto.context.new_wallet();
+
+    let mut accounts = oracle_purchase(&otto, &mint, &buyer, Some(("OTTO1", &referrer)));
+    accounts.price_oracle = None;
+    assert_error(
+        otto.purchase_with(accounts, 100_000, Some("OTTO1"))
+            .map(|_| ()),
+        ErrorCode::MissingPriceOracle,
+    );
+
+    otto.context.warp(MAX_STALENESS + 1);
+    let accounts = oracle_purchase(&otto, &mint, &buyer, Some(("OTTO1", &referrer)));
+    assert_error(
+        otto.purchase_with(accounts, 100_000, Some("OTTO1"))
+            .map(|_| ()),
+        ErrorCode::StalePriceOracle,
+    );
+}
+
+#[test]
+fn transfer_fees_come_out_of_the_payout() {
+    let mut otto = Otto::new();
+    let mint_authority = otto.mint_authority;
+    // 1% fee on every Token-2022 transfer
+    let mint = otto
+        .context
+        .create_fee_mint(&mint_authority, 6, 100, u64::MAX);
+    otto.configure_reward_pool(&mint, (1, 1), None).unwrap();
+    otto.set_payout_mint(&mint).unwrap();
+    let pool = ata_with_program(&otto.program_state, &mint, &token_2022::ID);
+    otto.context
+        .mint_to(&mint, &mint_authority, &pool, REWARDS_POOL_FUNDING);
+
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let referrer_payout = otto.context.create_ata(&referrer, &mint);
+    let buyer = otto.context.new_wallet();
+
+    let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO1", &referrer)));
+    accounts.payout_mint = mint;
+    accounts.rewards_pool = pool;
+    accounts.referrer_payout_account = Some(referrer_payout);
+    accounts.payout_token_program = token_2022::ID;
+    let purchase_account = otto
+        .purchase_with(accounts, 100_000, Some("OTTO1"))
+        .unwrap();
+
+    // The pool sends the full 5,000 reward and the referrer receives 4,950
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.referral_reward, 5_000);
+    let event = &otto.context.events::<DynamicReferralProcessed>()[0];
+    assert_eq!((event.dynamic_reward, event.transfer_fee), (4_950, 50));
+    assert_eq!(otto.context.token_balance(&referrer_payout), 4_950);
+    assert_eq!(
+        otto.context.token_balance(&pool),
+        REWARDS_POOL_FUNDING - 5_000
+    );
+
+    let mut claim = otto.claim_accounts(&buyer, &purchase_account);
+    claim.payout_mint = mint;
+    claim.rewards_pool = pool;
+    claim.buyer_token_account = Some(ata_with_program(&buyer, &mint, &token_2022::ID));
+    claim.token_program = token_2022::ID;
+    otto.context
+        .process(&[anchor_instruction(
+            claim,
+            otto_referral_rewards::instruction::ClaimRewards {},
+        )])
+        .unwrap();
+
+    // 2,000 cashback, less the 1% fee
+    let event = &otto.context.events::<RewardsClaimed>()[0];
+    assert_eq!((event.amount, event.transfer_fee), (1_980, 20));
+    assert_eq!(
+        otto.context
+            .token_balance(&ata_with_program(&buyer, &mint, &token_2022::ID)),
+        1_980
+    );
+}
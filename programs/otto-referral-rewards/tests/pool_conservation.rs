@@ -0,0 +1,34 @@
+mod common;
+
+use common::sequence::{Action, Scenario};
+use proptest::prelude::*;
+
+fn action() -> impl Strategy<Value = Action> {
+    prop_oneof![
+        4 => (any::<u8>(), 0..10_000_000u64, proptest::option::of(any::<u8>())).prop_map(
+            |(buyer, amount, referrer)| Action::Purchase {
+                buyer,
+                amount,
+                referrer,
+            }
+        ),
+        3 => any::<u16>().prop_map(|purchase| Action::Claim { purchase }),
+        1 => (any::<u8>(), 0..2_000_000u64)
+            .prop_map(|(referrer, amount)| Action::Sell { referrer, amount }),
+        2 => (1..86_400u32).prop_map(|seconds| Action::Warp { seconds }),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn random_sequences_conserve_tokens(actions in proptest::collection::vec(action(), 1..40)) {
+        let mut scenario = Scenario::new();
+        scenario.check_invariants();
+        for action in &actions {
+            scenario.apply(action);
+            scenario.check_invariants();
+        }
+    }
+}
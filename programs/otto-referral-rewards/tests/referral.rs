@@ -0,0 +1,562 @@
+mod common;
+
+use anchor_lang::{error::ErrorCode as AnchorErrorCode, prelude::Pubkey};
+use common::*;
+use otto_referral_rewards::{
+    stats_shard_index, BuyerAccount, DynamicReferralProcessed, ErrorCode, Milestone, MilestoneKind,
+    MilestoneReached, ProgramState, PurchaseAccount, PurchaseProcessed, ReferrerAccount,
+    RewardsClaimed, StatsAggregated, StatsShard, Tier, EVENT_VERSION, STATS_AGGREGATION_INTERVAL,
+    STATS_SHARDS,
+};
+
+#[test]
+fn initialize_sets_params_and_creates_rewards_pool() {
+    let otto = Otto::new();
+
+    let state: ProgramState = otto.context.account(&otto.program_state);
+    assert_eq!(state.authority, otto.authority);
+    assert_eq!(state.otto_token_mint, otto.otto_mint);
+    assert_eq!(state.rewards_pool, otto.rewards_pool);
+    assert_eq!(state.rewards_percentage, REWARDS_PERCENTAGE);
+    assert_eq!(state.min_purchase_amount, MIN_PURCHASE_AMOUNT);
+    assert_eq!(state.min_holdings_for_referral, MIN_HOLDINGS_FOR_REFERRAL);
+    assert_eq!(state.base_reward_rate, BASE_REWARD_RATE);
+    assert_eq!(state.payout_mint, otto.otto_mint);
+    assert_eq!(state.reward_pools.len(), 1);
+    assert_eq!(state.total_referrals, 0);
+
+    assert_eq!(
+        otto.context.token_balance(&otto.rewards_pool),
+        REWARDS_POOL_FUNDING
+    );
+}
+
+#[test]
+fn initialize_twice_fails() {
+    let mut otto = Otto::new();
+    let initialize = otto.initialize_instruction();
+    assert!(otto.context.process(&[initialize]).is_err());
+}
+
+#[test]
+fn register_referrer_with_enough_holdings() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.authority, referrer);
+    assert_eq!(account.referral_code, "OTTO1");
+    assert_eq!(account.initial_holdings, MIN_HOLDINGS_FOR_REFERRAL);
+    assert_eq!(account.current_holdings, MIN_HOLDINGS_FOR_REFERRAL);
+    assert_eq!(account.holding_ratio, 10000);
+    assert!(account.tier == Tier::None);
+    assert!(account.is_active);
+}
+
+#[test]
+fn register_referrer_requires_minimum_holdings() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL - 1);
+
+    assert_error(
+        otto.register_referrer(&referrer, "OTTO1"),
+        ErrorCode::InsufficientHoldingsForReferral,
+    );
+    assert!(otto
+        .context
+        .get_account(&referrer_address("OTTO1"))
+        .is_none());
+}
+
+#[test]
+fn register_referrer_rejects_short_code() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+
+    assert_error(
+        otto.register_referrer(&referrer, "OTO"),
+        ErrorCode::ReferralCodeTooShort,
+    );
+}
+
+#[test]
+fn register_referrer_rejects_taken_code() {
+    let mut otto = Otto::new();
+    let first = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    let second = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+
+    otto.register_referrer(&first, "OTTO1").unwrap();
+    assert!(otto.register_referrer(&second, "OTTO1").is_err());
+}
+
+#[test]
+fn purchase_without_referrer() {
+    let mut otto = Otto::new();
+    let buyer = otto.context.new_wallet();
+
+    let purchase_account = otto.purchase(&buyer, 100_000, None).unwrap();
+
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.buyer, buyer);
+    assert_eq!(purchase.amount, 100_000);
+    assert_eq!(purchase.timestamp, otto.context.now());
+    assert_eq!(purchase.referral_code, None);
+    assert_eq!(purchase.referrer, None);
+    assert_eq!(purchase.payout_mint, otto.otto_mint);
+    assert!(!purchase.rewards_claimed);
+
+    let buyer_account: BuyerAccount = otto.context.account(&buyer_address(&buyer));
+    assert_eq!(buyer_account.total_purchases, 1);
+    assert_eq!(buyer_account.referred_purchases, 0);
+    assert_eq!(
+        otto.context.token_balance(&otto.rewards_pool),
+        REWARDS_POOL_FUNDING
+    );
+}
+
+#[test]
+fn purchase_below_minimum_fails() {
+    let mut otto = Otto::new();
+    let buyer = otto.context.new_wallet();
+
+    assert_error(
+        otto.purchase(&buyer, MIN_PURCHASE_AMOUNT - 1, None)
+            .map(|_| ()),
+        ErrorCode::PurchaseAmountTooLow,
+    );
+}
+
+#[test]
+fn purchase_with_referrer_pays_dynamic_reward() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(2 * MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+
+    let purchase_account = otto
+        .purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+
+    // 5% base rate, 100% holding ratio, 1.1x for holding 2x the minimum
+    let reward = 5_500;
+    assert_eq!(
+        otto.context.token_balance(&ata(&referrer, &otto.otto_mint)),
+        2 * MIN_HOLDINGS_FOR_REFERRAL + reward
+    );
+    assert_eq!(
+        otto.context.token_balance(&otto.rewards_pool),
+        REWARDS_POOL_FUNDING - reward
+    );
+
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.total_referrals, 1);
+    assert_eq!(account.total_referred_volume, 100_000);
+    assert_eq!(account.total_rewards_earned, reward);
+    assert_eq!(account.unique_referees, 1);
+
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.referral_code.as_deref(), Some("OTTO1"));
+    assert_eq!(purchase.referrer, Some(referrer_address("OTTO1")));
+    assert_eq!(purchase.referral_reward, reward);
+
+    let buyer_account: BuyerAccount = otto.context.account(&buyer_address(&buyer));
+    assert_eq!(buyer_account.referred_purchases, 1);
+}
+
+#[test]
+fn repeat_purchases_count_one_unique_referee() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+
+    otto.purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+    otto.context.warp(60);
+    otto.purchase(&buyer, 50_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.total_referrals, 2);
+    assert_eq!(account.total_referred_volume, 150_000);
+    assert_eq!(account.unique_referees, 1);
+}
+
+#[test]
+fn referee_bonus_goes_to_the_first_referred_purchase() {
+    let mut otto = Otto::new();
+    otto.update_referee_bonus(Some(100), None).unwrap();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+
+    // A purchase without a code doesn't use up the bonus
+    otto.purchase(&buyer, 100_000, None).unwrap();
+    otto.context.warp(60);
+    let first = otto
+        .purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+    otto.context.warp(60);
+    let second = otto
+        .purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+
+    let purchase: PurchaseAccount = otto.context.account(&first);
+    assert_eq!(purchase.referee_bonus, 1_000);
+    let purchase: PurchaseAccount = otto.context.account(&second);
+    assert_eq!(purchase.referee_bonus, 0);
+}
+
+#[test]
+fn milestone_bonus_is_paid_once_on_crossing() {
+    let mut otto = Otto::new();
+    let milestone = Milestone {
+        kind: MilestoneKind::ReferredVolume,
+        threshold: 150_000,
+        bonus: 3_000,
+    };
+    assert_error(
+        otto.set_milestones(vec![milestone; 17]),
+        ErrorCode::TooManyMilestones,
+    );
+    otto.set_milestones(vec![milestone]).unwrap();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+    let referrer_ata = ata(&referrer, &otto.otto_mint);
+
+    otto.purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+    assert!(otto.context.events::<MilestoneReached>().is_empty());
+    otto.context.warp(60);
+
+    // The purchase taking referred volume past 150,000 pays the bonus on
+    // top of its reward, and records both for any clawback
+    let before = otto.context.token_balance(&referrer_ata);
+    let crossing = otto
+        .purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+    let events = otto.context.events::<MilestoneReached>();
+    assert_eq!(events.len(), 1);
+    assert_eq!((events[0].milestone_index, events[0].bonus), (0, 3_000));
+    let purchase: PurchaseAccount = otto.context.account(&crossing);
+    assert_eq!(
+        otto.context.token_balance(&referrer_ata),
+        before + purchase.referral_reward
+    );
+    assert!(purchase.referral_reward > 3_000);
+    otto.context.warp(60);
+
+    otto.purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+    assert!(otto.context.events::<MilestoneReached>().is_empty());
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.milestones_reached, 1);
+}
+
+#[test]
+fn self_referral_is_rejected() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+
+    assert_error(
+        otto.purchase(&referrer, 100_000, Some(("OTTO1", &referrer)))
+            .map(|_| ()),
+        ErrorCode::SelfReferralNotAllowed,
+    );
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.total_referrals, 0);
+}
+
+#[test]
+fn referral_accounts_are_only_created_for_referrals() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+
+    // Without a referrer they would be derived from the default key
+    let unreferred = [
+        referee_record_address(&Pubkey::default(), &buyer),
+        held_payout_address(&Pubkey::default(), &otto.otto_mint),
+    ];
+    let mut accounts = otto.purchase_accounts(&buyer, None);
+    accounts.referee_record = Some(unreferred[0]);
+    assert_error(
+        otto.purchase_with(accounts, 100_000, None).map(|_| ()),
+        ErrorCode::UnexpectedReferralAccount,
+    );
+    let mut accounts = otto.purchase_accounts(&buyer, None);
+    accounts.held_payout = Some(unreferred[1]);
+    assert_error(
+        otto.purchase_with(accounts, 100_000, None).map(|_| ()),
+        ErrorCode::UnexpectedReferralAccount,
+    );
+
+    // Nor is a referrer passed without a code credited
+    let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO1", &referrer)));
+    accounts.referee_record = Some(referee_record_address(&referrer_address("OTTO1"), &buyer));
+    assert_error(
+        otto.purchase_with(accounts, 100_000, None).map(|_| ()),
+        ErrorCode::UnexpectedReferralAccount,
+    );
+
+    // Without an active leaderboard there is no volume to rank
+    let volume = leaderboard_volume_address(&Pubkey::default(), &referrer_address("OTTO1"));
+    let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO1", &referrer)));
+    accounts.leaderboard_volume = Some(volume);
+    assert_error(
+        otto.purchase_with(accounts, 100_000, Some("OTTO1")).map(|_| ()),
+        ErrorCode::UnexpectedLeaderboardVolume,
+    );
+
+    for account in unreferred.into_iter().chain([volume]) {
+        assert!(otto.context.get_account(&account).is_none());
+    }
+    assert!(otto
+        .context
+        .get_account(&referee_record_address(&referrer_address("OTTO1"), &buyer))
+        .is_none());
+}
+
+#[test]
+fn claim_rewards_pays_cashback_once() {
+    let mut otto = Otto::new();
+    let buyer = otto.context.new_wallet();
+    let purchase_account = otto.purchase(&buyer, 100_000, None).unwrap();
+
+    otto.claim_rewards(&buyer, &purchase_account).unwrap();
+
+    // 2% cashback
+    assert_eq!(
+        otto.context.token_balance(&ata(&buyer, &otto.otto_mint)),
+        2_000
+    );
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert!(purchase.rewards_claimed);
+
+    assert_error(
+        otto.claim_rewards(&buyer, &purchase_account),
+        ErrorCode::RewardsAlreadyClaimed,
+    );
+    assert_eq!(
+        otto.context.token_balance(&ata(&buyer, &otto.otto_mint)),
+        2_000
+    );
+}
+
+#[test]
+fn claim_rewards_rejects_other_buyer() {
+    let mut otto = Otto::new();
+    let buyer = otto.context.new_wallet();
+    let thief = otto.context.new_wallet();
+    let purchase_account = otto.purchase(&buyer, 100_000, None).unwrap();
+
+    assert_error(
+        otto.claim_rewards(&thief, &purchase_account),
+        AnchorErrorCode::ConstraintHasOne,
+    );
+}
+
+#[test]
+fn events_carry_accounts_and_post_state() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(2 * MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+
+    let purchase_account = otto
+        .purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+
+    let [referral] = &otto.context.events::<DynamicReferralProcessed>()[..] else {
+        panic!("expected one DynamicReferralProcessed");
+    };
+    assert_eq!(referral.version, EVENT_VERSION);
+    assert_eq!(referral.purchase, purchase_account);
+    assert_eq!(referral.referrer_account, referrer_address("OTTO1"));
+    assert_eq!(referral.dynamic_reward, 5_500);
+    assert_eq!(referral.total_referrals, 1);
+    assert_eq!(referral.total_rewards_earned, 5_500);
+    assert_eq!(
+        referral.pool_balance,
+        otto.context.token_balance(&otto.rewards_pool)
+    );
+
+    let [purchase] = &otto.context.events::<PurchaseProcessed>()[..] else {
+        panic!("expected one PurchaseProcessed");
+    };
+    assert_eq!(purchase.version, EVENT_VERSION);
+    assert_eq!(purchase.purchase, purchase_account);
+    assert_eq!(purchase.referral_code.as_deref(), Some("OTTO1"));
+    assert_eq!(purchase.referral_reward, 5_500);
+    assert_eq!(purchase.buyer_total_purchases, 1);
+    assert_eq!(purchase.timestamp, otto.context.now());
+
+    otto.claim_rewards(&buyer, &purchase_account).unwrap();
+
+    let [claim] = &otto.context.events::<RewardsClaimed>()[..] else {
+        panic!("expected one RewardsClaimed");
+    };
+    assert_eq!(claim.version, EVENT_VERSION);
+    assert_eq!(claim.purchase, purchase_account);
+    assert_eq!(claim.destination, ata(&buyer, &otto.otto_mint));
+    assert_eq!(claim.amount, 2_000);
+    assert_eq!(
+        claim.pool_balance,
+        otto.context.token_balance(&otto.rewards_pool)
+    );
+    assert!(otto.context.events::<PurchaseProcessed>().is_empty());
+}
+
+#[test]
+fn authority_updates_program_params() {
+    let mut otto = Otto::new();
+    let authority = otto.authority;
+
+    otto.update_program_params(
+        &authority,
+        Some(300),
+        Some(5_000),
+        Some([2_000_000, 6_000_000, 12_000_000]),
+    )
+    .unwrap();
+
+    let state: ProgramState = otto.context.account(&otto.program_state);
+    assert_eq!(state.rewards_percentage, 300);
+    assert_eq!(state.min_purchase_amount, 5_000);
+    assert_eq!(state.bronze_threshold, 2_000_000);
+    assert_eq!(state.silver_threshold, 6_000_000);
+    assert_eq!(state.gold_threshold, 12_000_000);
+
+    // Omitted params are left alone
+    otto.update_program_params(&authority, None, Some(7_000), None)
+        .unwrap();
+    let state: ProgramState = otto.context.account(&otto.program_state);
+    assert_eq!(state.rewards_percentage, 300);
+    assert_eq!(state.min_purchase_amount, 7_000);
+}
+
+#[test]
+fn update_program_params_rejects_excessive_rewards_percentage() {
+    let mut otto = Otto::new();
+    let authority = otto.authority;
+
+    assert_error(
+        otto.update_program_params(&authority, Some(1_001), None, None),
+        ErrorCode::InvalidRewardsPercentage,
+    );
+}
+
+#[test]
+fn update_program_params_requires_authority() {
+    let mut otto = Otto::new();
+    let impostor: Pubkey = otto.context.new_wallet();
+
+    assert_error(
+        otto.update_program_params(&impostor, Some(300), None, None),
+        AnchorErrorCode::ConstraintHasOne,
+    );
+    let state: ProgramState = otto.context.account(&otto.program_state);
+    assert_eq!(state.rewards_percentage, REWARDS_PERCENTAGE);
+}
+
+#[test]
+fn aggregate_stats_sums_every_shard() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(2 * MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let program_state = otto.context.get_account(&otto.program_state).unwrap();
+
+    // Buyers counted in different shards
+    let referred_buyer = otto.context.wallet_in_stats_shard(1);
+    let buyer = otto.context.wallet_in_stats_shard(2);
+    assert_ne!(
+        stats_shard_index(&referred_buyer),
+        stats_shard_index(&buyer)
+    );
+
+    let purchase_account = otto
+        .purchase(&referred_buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+    otto.purchase(&buyer, 50_000, None).unwrap();
+    otto.claim_rewards(&referred_buyer, &purchase_account)
+        .unwrap();
+
+    // Purchases and claims only write their buyer's shard
+    assert_eq!(
+        otto.context.get_account(&otto.program_state).unwrap().data,
+        program_state.data
+    );
+    let shard: StatsShard = otto.context.account(&buyer_stats_shard(&referred_buyer));
+    assert_eq!(shard.total_purchases, 1);
+    assert_eq!(shard.total_referrals, 1);
+    assert_eq!(shard.total_rewards_distributed, 5_500 + 2_000);
+    let shard: StatsShard = otto.context.account(&buyer_stats_shard(&buyer));
+    assert_eq!(shard.total_purchases, 1);
+    assert_eq!(shard.total_purchase_volume, 50_000);
+    assert_eq!(shard.total_referrals, 0);
+
+    otto.aggregate_stats().unwrap();
+    let state: ProgramState = otto.context.account(&otto.program_state);
+    assert_eq!(state.total_purchases, 2);
+    assert_eq!(state.total_purchase_volume, 150_000);
+    assert_eq!(state.total_referrals, 1);
+    assert_eq!(state.total_referred_volume, 100_000);
+    assert_eq!(state.total_rewards_distributed, 5_500 + 2_000);
+    assert_eq!(state.stats_aggregated_at, otto.context.now());
+
+    let [aggregated] = &otto.context.events::<StatsAggregated>()[..] else {
+        panic!("expected one StatsAggregated event");
+    };
+    assert_eq!(aggregated.total_purchases, 2);
+    assert_eq!(aggregated.total_rewards_distributed, 5_500 + 2_000);
+
+    // Totals are recomputed from the shards, not added to
+    otto.context.warp(60);
+    otto.aggregate_stats().unwrap();
+    let state: ProgramState = otto.context.account(&otto.program_state);
+    assert_eq!(state.total_purchases, 2);
+    assert_eq!(state.stats_aggregated_at, otto.context.now());
+}
+
+#[test]
+fn aggregate_stats_is_rate_limited() {
+    let mut otto = Otto::new();
+    otto.aggregate_stats().unwrap();
+
+    otto.context.warp(STATS_AGGREGATION_INTERVAL - 1);
+    assert_error(
+        otto.aggregate_stats(),
+        ErrorCode::StatsAggregatedTooRecently,
+    );
+
+    otto.context.warp(1);
+    otto.aggregate_stats().unwrap();
+}
+
+#[test]
+fn aggregate_stats_requires_every_shard_in_order() {
+    let mut otto = Otto::new();
+
+    let missing = otto.aggregate_stats_instruction(1..STATS_SHARDS);
+    assert_error(
+        otto.context.process(&[missing]),
+        ErrorCode::InvalidStatsShard,
+    );
+
+    let swapped = otto.aggregate_stats_instruction([1, 0].into_iter().chain(2..STATS_SHARDS));
+    assert_error(
+        otto.context.process(&[swapped]),
+        ErrorCode::InvalidStatsShard,
+    );
+
+    let out_of_range = otto.init_stats_shard_instruction(STATS_SHARDS);
+    assert_error(
+        otto.context.process(&[out_of_range]),
+        ErrorCode::InvalidStatsShard,
+    );
+}
@@ -0,0 +1,257 @@
+mod common;
+
+use anchor_lang::prelude::Pubkey;
+use common::*;
+use otto_referral_rewards::{
+    BuyerAccount, Campaign, CampaignParams, DynamicReferralProcessed, ErrorCode, Leaderboard,
+    Milestone, MilestoneKind, PurchaseAccount, RefereeRecord, ReferrerAccount, StatsShard,
+};
+
+/// Purchase referred by `code`, ranked on leaderboard 1
+fn ranked_purchase(
+    otto: &mut Otto,
+    buyer: &Pubkey,
+    (code, referrer): (&str, &Pubkey),
+    amount: u64,
+) -> Pubkey {
+    let leaderboard = leaderboard_address(1);
+    let mut accounts = otto.purchase_accounts(buyer, Some((code, referrer)));
+    accounts.leaderboard = Some(leaderboard);
+    accounts.leaderboard_volume = Some(leaderboard_volume_address(
+        &leaderboard,
+        &referrer_address(code),
+    ));
+    let purchase_account = otto.purchase_with(accounts, amount, Some(code)).unwrap();
+    otto.context.warp(1);
+    purchase_account
+}
+
+#[test]
+fn reversal_takes_back_referral_credit() {
+    let mut otto = Otto::new();
+    otto.set_milestones(vec![Milestone {
+        kind: MilestoneKind::UniqueReferees,
+        threshold: 1,
+        bonus: 3_000,
+    }])
+    .unwrap();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+
+    let purchase_account = otto
+        .purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+    let reward = otto.context.events::<DynamicReferralProcessed>()[0].dynamic_reward;
+    let purchase: PurchaseAccount = otto.context.account(&purchase_account);
+    assert_eq!(purchase.milestones_reached, 1);
+    assert_eq!(purchase.referral_reward, reward + 3_000);
+
+    otto.reverse_purchase(&purchase_account).unwrap();
+
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.total_referrals, 0);
+    assert_eq!(account.total_referred_volume, 0);
+    assert_eq!(account.unique_referees, 0);
+    assert_eq!(account.milestones_reached, 0);
+    assert_eq!(account.clawback_owed, reward + 3_000);
+    let record: RefereeRecord = otto
+        .context
+        .account(&referee_record_address(&referrer_address("OTTO1"), &buyer));
+    assert_eq!((record.purchases, record.volume), (0, 0));
+    let shard: StatsShard = otto.context.account(&buyer_stats_shard(&buyer));
+    assert_eq!(
+        (
+            shard.total_purchases,
+            shard.total_referrals,
+            shard.total_referred_volume
+        ),
+        (0, 0, 0)
+    );
+
+    assert_error(
+        otto.reverse_purchase(&purchase_account),
+        ErrorCode::PurchaseAlreadyReversed,
+    );
+    assert_error(
+        otto.claim_rewards(&buyer, &purchase_account),
+        ErrorCode::PurchaseReversed,
+    );
+}
+
+#[test]
+fn reopened_milestone_pays_against_the_clawback() {
+    let mut otto = Otto::new();
+    otto.set_milestones(vec![Milestone {
+        kind: MilestoneKind::ReferredPurchases,
+        threshold: 1,
+        bonus: 3_000,
+    }])
+    .unwrap();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+    let referrer_ata = ata(&referrer, &otto.otto_mint);
+
+    let purchase_account = otto
+        .purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+    otto.reverse_purchase(&purchase_account).unwrap();
+    otto.context.warp(1);
+
+    // The same purchase again earns the milestone back, while its reward is
+    // withheld against what was charged back
+    let before = otto.context.token_balance(&referrer_ata);
+    otto.purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+    let account: ReferrerAccount = otto.context.account(&referrer_address("OTTO1"));
+    assert_eq!(account.milestones_reached, 1);
+    assert_eq!(account.total_referrals, 1);
+    assert_eq!(account.clawback_owed, 3_000);
+    assert_eq!(otto.context.token_balance(&referrer_ata), before + 3_000);
+}
+
+#[test]
+fn wash_volume_is_taken_off_the_leaderboard() {
+    let mut otto = Otto::new();
+    let (now, otto_mint) = (otto.context.now(), otto.otto_mint);
+    otto.create_leaderboard(1, now, now + 86_400, &otto_mint, 0)
+        .unwrap();
+    let washer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&washer, "OTTO1").unwrap();
+    let honest = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&honest, "OTTO2").unwrap();
+    let buyer = otto.context.new_wallet();
+
+    let wash = ranked_purchase(&mut otto, &buyer, ("OTTO1", &washer), 1_000_000);
+    ranked_purchase(&mut otto, &buyer, ("OTTO2", &honest), 100_000);
+    let leaderboard: Leaderboard = otto.context.account(&leaderboard_address(1));
+    assert_eq!(leaderboard.entries[0].referrer, washer);
+
+    otto.reverse_purchase(&wash).unwrap();
+
+    let leaderboard: Leaderboard = otto.context.account(&leaderboard_address(1));
+    assert_eq!(leaderboard.entries[0].referrer, honest);
+    assert_eq!(leaderboard.entries[0].volume, 100_000);
+    assert_eq!(leaderboard.entries[1].volume, 0);
+}
+
+#[test]
+fn reversal_requires_the_leaderboard_it_ranked_on() {
+    let mut otto = Otto::new();
+    let (now, otto_mint) = (otto.context.now(), otto.otto_mint);
+    otto.create_leaderboard(1, now, now + 86_400, &otto_mint, 0)
+        .unwrap();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+    let purchase_account = ranked_purchase(&mut otto, &buyer, ("OTTO1", &referrer), 100_000);
+
+    // Leaving the leaderboard out would keep the volume ranked
+    let (program_state, authority) = (otto.program_state, otto.authority);
+    let reverse = move |leaderboard| {
+        anchor_instruction(
+            otto_referral_rewards::accounts::ReversePurchase {
+                program_state,
+                purchase_account,
+                referrer_account: Some(referrer_address("OTTO1")),
+                referee_record: Some(referee_record_address(&referrer_address("OTTO1"), &buyer)),
+                buyer_account: Some(buyer_address(&buyer)),
+                campaign: None,
+                leaderboard,
+                leaderboard_volume: None,
+                held_payout: None,
+                stats_shard: buyer_stats_shard(&buyer),
+                authority,
+                event_authority: event_authority_address(),
+                program: otto_referral_rewards::ID,
+            },
+            otto_referral_rewards::instruction::ReversePurchase {},
+        )
+    };
+    assert_error(
+        otto.context.process(&[reverse(None)]),
+        ErrorCode::ReversalAccountMismatch,
+    );
+    assert_error(
+        otto.context
+            .process(&[reverse(Some(leaderboard_address(1)))]),
+        ErrorCode::MissingLeaderboardVolume,
+    );
+}
+
+#[test]
+fn reversal_refunds_the_campaign_budget() {
+    let mut otto = Otto::new();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let params = CampaignParams {
+        start_timestamp: otto.context.now(),
+        end_timestamp: otto.context.now() + 86_400,
+        budget: 1_000_000,
+        payout_mint: otto.otto_mint,
+        base_reward_rate: Some(1_000),
+        holdings_multipliers: None,
+        eligible_codes: Vec::new(),
+    };
+    otto.create_campaign(1, params).unwrap();
+
+    let mut purchases = Vec::new();
+    for _ in 0..2 {
+        let buyer = otto.context.new_wallet();
+        let mut accounts = otto.purchase_accounts(&buyer, Some(("OTTO1", &referrer)));
+        accounts.campaign = Some(campaign_address(1));
+        let purchase_account = otto
+            .purchase_with(accounts, 100_000, Some("OTTO1"))
+            .unwrap();
+        purchases.push((buyer, purchase_account));
+    }
+    // 10,000 reward and 2,000 cashback each
+    let campaign: Campaign = otto.context.account(&campaign_address(1));
+    assert_eq!(campaign.spent, 2 * 12_000);
+
+    // Claimed cashback is gone, so only the reward comes back
+    let (buyer, claimed) = purchases[0];
+    otto.claim_rewards(&buyer, &claimed).unwrap();
+    otto.reverse_purchase(&claimed).unwrap();
+    let campaign: Campaign = otto.context.account(&campaign_address(1));
+    assert_eq!(campaign.spent, 12_000 + 2_000);
+
+    otto.reverse_purchase(&purchases[1].1).unwrap();
+    let campaign: Campaign = otto.context.account(&campaign_address(1));
+    assert_eq!(campaign.spent, 2_000);
+}
+
+#[test]
+fn referee_bonus_follows_the_first_purchase_that_stands() {
+    let mut otto = Otto::new();
+    otto.update_referee_bonus(Some(100), None).unwrap();
+    let referrer = otto.holder(MIN_HOLDINGS_FOR_REFERRAL);
+    otto.register_referrer(&referrer, "OTTO1").unwrap();
+    let buyer = otto.context.new_wallet();
+
+    let first = otto
+        .purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+    let purchase: PurchaseAccount = otto.context.account(&first);
+    assert_eq!(purchase.referee_bonus, 1_000);
+    otto.reverse_purchase(&first).unwrap();
+    let account: BuyerAccount = otto.context.account(&buyer_address(&buyer));
+    assert_eq!(account.referred_purchases, 0);
+    otto.context.warp(1);
+
+    // With the first purchase reversed, the next one is the first referred
+    // purchase that counts
+    let second = otto
+        .purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+    let purchase: PurchaseAccount = otto.context.account(&second);
+    assert_eq!(purchase.referee_bonus, 1_000);
+    otto.context.warp(1);
+
+    let third = otto
+        .purchase(&buyer, 100_000, Some(("OTTO1", &referrer)))
+        .unwrap();
+    let purchase: PurchaseAccount = otto.context.account(&third);
+    assert_eq!(purchase.referee_bonus, 0);
+}
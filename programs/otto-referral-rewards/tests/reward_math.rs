@@ -0,0 +1,158 @@
+use otto_referral_rewards::{apply_bps, calculate_dynamic_reward, DEFAULT_HOLDINGS_MULTIPLIERS};
+use proptest::prelude::*;
+
+const MAX_BPS: u16 = 10_000;
+
+fn max_multiplier() -> u64 {
+    *DEFAULT_HOLDINGS_MULTIPLIERS.iter().max().unwrap() as u64
+}
+
+fn reward(
+    base_rate: u16,
+    holding_ratio: u16,
+    holdings: u64,
+    purchase_amount: u64,
+    min_holdings: u64,
+) -> u64 {
+    calculate_dynamic_reward(
+        base_rate,
+        holding_ratio,
+        holdings,
+        purchase_amount,
+        min_holdings,
+        DEFAULT_HOLDINGS_MULTIPLIERS,
+    )
+    .unwrap()
+}
+
+proptest! {
+    #[test]
+    fn never_panics(
+        base_rate: u16,
+        holding_ratio: u16,
+        holdings: u64,
+        purchase_amount: u64,
+        min_holdings: u64,
+        multipliers: [u16; 3],
+    ) {
+        let _ = calculate_dynamic_reward(
+            base_rate,
+            holding_ratio,
+            holdings,
+            purchase_amount,
+            min_holdings,
+            multipliers,
+        );
+    }
+
+    #[test]
+    fn only_fails_when_a_step_overflows(
+        base_rate: u16,
+        holding_ratio: u16,
+        holdings: u64,
+        purchase_amount: u64,
+        min_holdings: u64,
+        multipliers: [u16; 3],
+    ) {
+        let result = calculate_dynamic_reward(
+            base_rate,
+            holding_ratio,
+            holdings,
+            purchase_amount,
+            min_holdings,
+            multipliers,
+        );
+        // Each step is bounded by its unfloored product, so if none of those
+        // overflow the calculation must succeed
+        let max_multiplier = multipliers.iter().copied().max().unwrap().max(MAX_BPS);
+        let base = purchase_amount as u128 * base_rate as u128 / MAX_BPS as u128;
+        let adjusted = base * holding_ratio as u128 / MAX_BPS as u128;
+        let multiplied = adjusted * max_multiplier as u128 / MAX_BPS as u128;
+        if [base, adjusted, multiplied].iter().all(|step| *step <= u64::MAX as u128) {
+            prop_assert!(result.is_ok());
+        }
+    }
+
+    #[test]
+    fn never_exceeds_max_rate_and_multiplier(
+        base_rate in 0..=MAX_BPS,
+        holding_ratio in 0..=MAX_BPS,
+        holdings: u64,
+        purchase_amount in 0..=u64::MAX / 2,
+        min_holdings: u64,
+    ) {
+        let reward = reward(base_rate, holding_ratio, holdings, purchase_amount, min_holdings);
+        let max_reward = purchase_amount as u128 * base_rate as u128 * max_multiplier() as u128
+            / (MAX_BPS as u128).pow(2);
+        prop_assert!(reward as u128 <= max_reward);
+        prop_assert!(reward <= apply_bps(purchase_amount, max_multiplier()).unwrap());
+    }
+
+    #[test]
+    fn monotonic_in_holdings(
+        base_rate in 0..=MAX_BPS,
+        holding_ratio in 0..=MAX_BPS,
+        holdings: u64,
+        extra_holdings: u64,
+        purchase_amount in 0..=u64::MAX / 2,
+        min_holdings: u64,
+    ) {
+        let more_holdings = holdings.saturating_add(extra_holdings);
+        prop_assert!(
+            reward(base_rate, holding_ratio, holdings, purchase_amount, min_holdings)
+                <= reward(base_rate, holding_ratio, more_holdings, purchase_amount, min_holdings)
+        );
+    }
+
+    #[test]
+    fn monotonic_in_holding_ratio(
+        base_rate in 0..=MAX_BPS,
+        holding_ratio in 0..=MAX_BPS,
+        extra_ratio in 0..=MAX_BPS,
+        holdings: u64,
+        purchase_amount in 0..=u64::MAX / 2,
+        min_holdings: u64,
+    ) {
+        let higher_ratio = holding_ratio.saturating_add(extra_ratio).min(MAX_BPS);
+        prop_assert!(
+            reward(base_rate, holding_ratio, holdings, purchase_amount, min_holdings)
+                <= reward(base_rate, higher_ratio, holdings, purchase_amount, min_holdings)
+        );
+    }
+
+    #[test]
+    fn monotonic_in_purchase_amount(
+        base_rate in 0..=MAX_BPS,
+        holding_ratio in 0..=MAX_BPS,
+        holdings: u64,
+        purchase_amount in 0..=u64::MAX / 4,
+        extra_amount in 0..=u64::MAX / 4,
+        min_holdings: u64,
+    ) {
+        prop_assert!(
+            reward(base_rate, holding_ratio, holdings, purchase_amount, min_holdings)
+                <= reward(base_rate, holding_ratio, holdings, purchase_amount + extra_amount, min_holdings)
+        );
+    }
+
+    #[test]
+    fn zero_ratio_pays_nothing(
+        base_rate: u16,
+        holdings: u64,
+        purchase_amount in 0..=u64::MAX / 7,
+        min_holdings: u64,
+    ) {
+        prop_assert_eq!(reward(base_rate, 0, holdings, purchase_amount, min_holdings), 0);
+    }
+}
+
+#[test]
+fn unreachable_holdings_multiple_gets_no_multiplier() {
+    // 5x and 10x a minimum near u64::MAX overflow and must not count as
+    // met, so only the 2x multiplier applies
+    let min_holdings = u64::MAX / 3;
+    assert_eq!(
+        reward(MAX_BPS, MAX_BPS, u64::MAX, 10_000, min_holdings),
+        11_000
+    );
+}